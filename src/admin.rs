@@ -2,7 +2,15 @@ use serde::{Deserialize, Serialize};
 #[cfg(target_arch = "wasm32")]
 use worker::*;
 
+use crate::dns::{DnsProvider, DnsRecordType};
+#[cfg(target_arch = "wasm32")]
+use crate::dns::CloudflareDns;
+use crate::service::ApiError;
+#[cfg(target_arch = "wasm32")]
+use crate::store::{R2Store, DEBUG_WEBHOOK_KEY, PRICING_KEY};
+use crate::store::{Store, StoreResult};
 use crate::types::*;
+use crate::url_policy::{check_dns_target, check_url, UrlPolicy};
 
 /// BAN record stored in R2 at bans/{username}.json
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,13 +20,6 @@ pub struct BanRecord {
     pub reason: Option<String>,
 }
 
-/// Check if a username is banned by looking up bans/{username}.json in R2
-#[cfg(target_arch = "wasm32")]
-pub async fn is_banned(bucket: &worker::Bucket, username: &str) -> bool {
-    let key = format!("bans/{}.json", username);
-    matches!(bucket.get(&key).execute().await, Ok(Some(_)))
-}
-
 /// Response for GET /admin/stats
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AdminStatsResponse {
@@ -41,8 +42,11 @@ pub struct AdminRentalEntry {
     pub has_email: bool,
     pub has_subdomain: bool,
     pub has_nip05: bool,
+    pub has_lightning_address: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookEndpoint>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub management_token: Option<String>,
 }
@@ -62,6 +66,14 @@ pub struct ExtendRequest {
     pub minutes: u64,
 }
 
+/// Request body for POST /api/admin/orders/:order_id/status
+#[derive(Debug, Deserialize)]
+pub struct AdminOrderStatusRequest {
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
 /// Request body for PUT /api/admin/rentals/:username/webhook
 #[derive(Debug, Deserialize)]
 pub struct AdminWebhookRequest {
@@ -91,7 +103,7 @@ impl Default for DebugWebhookConfig {
 
 /// Verify auth: Bearer token (ADMIN_API_TOKEN) OR session token (X-Admin-Token from NIP-07 login)
 #[cfg(target_arch = "wasm32")]
-async fn verify_session_token(req: &Request, bucket: &worker::Bucket, env: &Env) -> Result<()> {
+async fn verify_session_token(req: &Request, store: &R2Store, env: &Env) -> Result<()> {
     // Check Authorization: Bearer <token> against ADMIN_API_TOKEN secret
     if let Ok(Some(auth_header)) = req.headers().get("Authorization") {
         if let Some(bearer_token) = auth_header.strip_prefix("Bearer ") {
@@ -111,45 +123,19 @@ async fn verify_session_token(req: &Request, bucket: &worker::Bucket, env: &Env)
         .map_err(|_| Error::RustError("Missing X-Admin-Token header".to_string()))?
         .ok_or_else(|| Error::RustError("Missing X-Admin-Token header".to_string()))?;
 
-    let key = format!("sessions/{}.json", token);
-    let obj = bucket.get(&key).execute().await?;
-    match obj {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            let session: crate::types::AdminSession = serde_json::from_str(&text)
-                .map_err(|e| Error::RustError(e.to_string()))?;
-            // Check expiry
-            let now_ms = js_sys::Date::now();
-            let expires_date = js_sys::Date::new(&session.expires_at.clone().into());
-            if expires_date.get_time() < now_ms {
-                return Err(Error::RustError("Session expired".to_string()));
-            }
-            Ok(())
-        }
-        None => Err(Error::RustError("Invalid session token".to_string())),
-    }
+    check_session(store, &token, js_sys::Date::now() as u64)
+        .await
+        .map_err(Error::RustError)
 }
 
 /// POST /api/admin/challenge — generate auth challenge
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_challenge(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let now = js_sys::Date::new_0();
-    let now_ms = js_sys::Date::now();
-    let expires_ms = now_ms + 5.0 * 60.0 * 1000.0; // 5 minutes
-    let expires_date = js_sys::Date::new(&(expires_ms.into()));
-
-    let challenge = format!("ch_{:x}_{:x}", now_ms as u64, (now_ms * 1000.0) as u64);
-    let ch = crate::types::AdminChallenge {
-        challenge: challenge.clone(),
-        created_at: now.to_iso_string().as_string().unwrap_or_default(),
-        expires_at: expires_date.to_iso_string().as_string().unwrap_or_default(),
-    };
-
-    let key = format!("challenges/{}.json", challenge);
-    let json = serde_json::to_string(&ch).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&key, json).execute().await?;
+    let store = R2Store::from_env(&ctx.env)?;
+    let challenge = crate::ids::challenge();
+    issue_challenge(&store, challenge.clone(), js_sys::Date::now() as u64)
+        .await
+        .map_err(Error::RustError)?;
 
     Response::from_json(&serde_json::json!({ "challenge": challenge }))
 }
@@ -157,7 +143,7 @@ pub async fn handle_admin_challenge(_req: Request, ctx: RouteContext<()>) -> Res
 /// POST /api/admin/login — verify NIP-07 signed event and issue session token
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_login(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
+    let store = R2Store::from_env(&ctx.env)?;
     let admin_pubkey = ctx.env
         .secret("ADMIN_PUBKEY")
         .map(|s| s.to_string())
@@ -166,58 +152,24 @@ pub async fn handle_admin_login(mut req: Request, ctx: RouteContext<()>) -> Resu
     let event: crate::types::NostrEvent = req.json().await
         .map_err(|_| Error::RustError("Invalid event JSON".to_string()))?;
 
-    // Verify pubkey matches admin
-    if event.pubkey != admin_pubkey {
-        return Response::error("Unauthorized: invalid pubkey", 403);
-    }
-
-    // Extract challenge from content
-    let challenge = event.content.trim().to_string();
-    if challenge.is_empty() {
-        return Response::error("Missing challenge in event content", 400);
-    }
+    // Verify pubkey, kind, created_at, challenge tag, id and Schnorr signature
+    let now_secs = (js_sys::Date::now() / 1000.0) as u64;
+    let challenge = match crate::nostr::verify_login_event(&event, &admin_pubkey, now_secs) {
+        Ok(c) => c,
+        Err(err) => return Response::error(err, 403),
+    };
 
     // Verify challenge exists in R2 and not expired
-    let ch_key = format!("challenges/{}.json", challenge);
-    let ch_obj = bucket.get(&ch_key).execute().await?;
-    match ch_obj {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            let ch: crate::types::AdminChallenge = serde_json::from_str(&text)
-                .map_err(|e| Error::RustError(e.to_string()))?;
-            let now_ms = js_sys::Date::now();
-            let expires_date = js_sys::Date::new(&ch.expires_at.clone().into());
-            if expires_date.get_time() < now_ms {
-                // Delete expired challenge
-                let _ = bucket.delete(&ch_key).await;
-                return Response::error("Challenge expired", 400);
-            }
-            // Delete used challenge
-            let _ = bucket.delete(&ch_key).await;
-        }
-        None => {
-            return Response::error("Invalid challenge", 400);
-        }
+    let now_ms = js_sys::Date::now() as u64;
+    if let Err(e) = consume_challenge(&store, &challenge, now_ms).await {
+        return e.into_response();
     }
 
     // Issue session token (24h TTL)
-    let now = js_sys::Date::new_0();
-    let now_ms = js_sys::Date::now();
-    let expires_ms = now_ms + 24.0 * 60.0 * 60.0 * 1000.0;
-    let expires_date = js_sys::Date::new(&(expires_ms.into()));
-
-    let token = format!("sess_{:x}", now_ms as u64);
-    let session = crate::types::AdminSession {
-        token: token.clone(),
-        pubkey: event.pubkey,
-        created_at: now.to_iso_string().as_string().unwrap_or_default(),
-        expires_at: expires_date.to_iso_string().as_string().unwrap_or_default(),
-    };
-
-    let sess_key = format!("sessions/{}.json", token);
-    let sess_json = serde_json::to_string(&session).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&sess_key, sess_json).execute().await?;
+    let token = crate::ids::session_token();
+    create_session(&store, token.clone(), event.pubkey, now_ms)
+        .await
+        .map_err(Error::RustError)?;
 
     Response::from_json(&serde_json::json!({ "token": token }))
 }
@@ -225,28 +177,30 @@ pub async fn handle_admin_login(mut req: Request, ctx: RouteContext<()>) -> Resu
 /// GET /api/admin/pricing — get current pricing config
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_pricing_get(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
-    let pricing = load_pricing(&bucket).await;
+    let pricing = store.load_pricing().await;
     Response::from_json(&pricing)
 }
 
 /// PUT /api/admin/pricing — update pricing config
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_pricing_put(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
     let pricing: crate::types::PricingConfig = req.json().await
         .map_err(|_| Error::RustError("Invalid pricing JSON".to_string()))?;
 
-    let json = serde_json::to_string(&pricing).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put("config/pricing.json", json).execute().await?;
+    store
+        .put_json(PRICING_KEY, &pricing)
+        .await
+        .map_err(Error::RustError)?;
 
     Response::from_json(&pricing)
 }
@@ -254,64 +208,28 @@ pub async fn handle_admin_pricing_put(mut req: Request, ctx: RouteContext<()>) -
 /// GET /api/pricing — public pricing data (no auth required)
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_public_pricing(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let pricing = load_pricing(&bucket).await;
+    let store = R2Store::from_env(&ctx.env)?;
+    let pricing = store.load_pricing().await;
     Response::from_json(&pricing)
 }
 
-/// Load pricing config from R2, falling back to defaults
-#[cfg(target_arch = "wasm32")]
-pub async fn load_pricing(bucket: &worker::Bucket) -> crate::types::PricingConfig {
-    match bucket.get("config/pricing.json").execute().await {
-        Ok(Some(obj)) => {
-            if let Some(body) = obj.body() {
-                if let Ok(text) = body.text().await {
-                    if let Ok(config) = serde_json::from_str::<crate::types::PricingConfig>(&text) {
-                        return config;
-                    }
-                }
-            }
-            crate::types::default_pricing()
-        }
-        _ => crate::types::default_pricing(),
-    }
-}
-
-/// Load debug webhook config from R2, falling back to defaults
-#[cfg(target_arch = "wasm32")]
-pub async fn load_debug_webhook_config(bucket: &worker::Bucket) -> DebugWebhookConfig {
-    match bucket.get("config/debug_webhook.json").execute().await {
-        Ok(Some(obj)) => {
-            if let Some(body) = obj.body() {
-                if let Ok(text) = body.text().await {
-                    if let Ok(config) = serde_json::from_str::<DebugWebhookConfig>(&text) {
-                        return config;
-                    }
-                }
-            }
-            DebugWebhookConfig::default()
-        }
-        _ => DebugWebhookConfig::default(),
-    }
-}
-
 /// GET /api/admin/debug-webhook — get current debug webhook config
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_debug_webhook_get(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if verify_session_token(&req, &bucket, &ctx.env).await.is_err() {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
-    let config = load_debug_webhook_config(&bucket).await;
+    let config = store.load_debug_webhook_config().await;
     Response::from_json(&config)
 }
 
 /// PUT /api/admin/debug-webhook — update debug webhook config
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_debug_webhook_put(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if verify_session_token(&req, &bucket, &ctx.env).await.is_err() {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
@@ -324,14 +242,23 @@ pub async fn handle_admin_debug_webhook_put(mut req: Request, ctx: RouteContext<
         return Response::error("Invalid level: must be off, error, warn, info, or debug", 400);
     }
 
+    let webhook_url = body.webhook_url.trim().to_string();
+    if !webhook_url.is_empty() {
+        if let Err(e) = check_url(&webhook_url, crate::url_policy_from_env(&ctx.env)) {
+            return Response::error(format!("Invalid webhook_url: {}", e), 400);
+        }
+    }
+
     let config = DebugWebhookConfig {
         enabled: body.enabled,
-        webhook_url: body.webhook_url.trim().to_string(),
+        webhook_url,
         level,
     };
 
-    let json = serde_json::to_string(&config).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put("config/debug_webhook.json", json).execute().await?;
+    store
+        .put_json(DEBUG_WEBHOOK_KEY, &config)
+        .await
+        .map_err(Error::RustError)?;
 
     Response::from_json(&config)
 }
@@ -345,8 +272,8 @@ pub async fn handle_admin_page(_req: Request, _ctx: RouteContext<()>) -> Result<
 /// GET /admin/rentals?page=1&limit=20&status=active
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_rentals(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
@@ -364,219 +291,62 @@ pub async fn handle_admin_rentals(req: Request, ctx: RouteContext<()>) -> Result
         .min(100);
     let status_filter = params.get("status").cloned();
 
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let now_ms = js_sys::Date::now();
-
-    // Collect all rentals
-    let list = bucket.list().prefix("rentals/").execute().await?;
-    let mut entries: Vec<AdminRentalEntry> = Vec::new();
-
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(rental) = serde_json::from_str::<Rental>(&text) {
-                let expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-                let expires_ms = expires_date.get_time();
-                let minutes_remaining = ((expires_ms - now_ms) / (60.0 * 1000.0)).ceil() as i64;
-
-                // Check if banned
-                let banned = is_banned(&bucket, &rental.username).await;
-                let display_status = if banned {
-                    "banned".to_string()
-                } else {
-                    rental.status.clone()
-                };
-
-                // Apply status filter
-                if let Some(ref filter) = status_filter {
-                    if filter != &display_status {
-                        continue;
-                    }
-                }
-
-                entries.push(AdminRentalEntry {
-                    username: rental.username,
-                    status: display_status,
-                    plan: rental.plan,
-                    created_at: rental.created_at,
-                    expires_at: rental.expires_at,
-                    minutes_remaining,
-                    has_email: rental.services.email.as_ref().map(|e| e.enabled).unwrap_or(false),
-                    has_subdomain: rental.services.subdomain.as_ref().map(|s| s.enabled).unwrap_or(false),
-                    has_nip05: rental.services.nip05.as_ref().map(|n| n.enabled).unwrap_or(false),
-                    webhook_url: rental.webhook_url.clone(),
-                    management_token: rental.management_token.clone(),
-                });
-            }
-        }
-    }
-
-    // Sort by expires_at descending (newest first)
-    entries.sort_by(|a, b| b.expires_at.cmp(&a.expires_at));
-
-    let total = entries.len();
-    let start = (page - 1) * limit;
-    let page_entries: Vec<AdminRentalEntry> = entries.into_iter().skip(start).take(limit).collect();
-
-    Response::from_json(&AdminRentalsResponse {
-        rentals: page_entries,
-        total,
-        page,
-        limit,
-    })
+    let now_ms = js_sys::Date::now() as u64;
+    let resp = list_rentals(&store, now_ms, page, limit, status_filter.as_deref())
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&resp)
 }
 
 /// GET /admin/stats
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_stats(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
-    let now_ms = js_sys::Date::now();
-    let soon_ms = now_ms + 7.0 * 24.0 * 60.0 * 60.0 * 1000.0; // 7 days
-
-    let mut active: u64 = 0;
-    let mut expired: u64 = 0;
-    let mut expiring_soon: u64 = 0;
-
-    let list = bucket.list().prefix("rentals/").execute().await?;
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(rental) = serde_json::from_str::<Rental>(&text) {
-                if rental.status == "active" {
-                    active += 1;
-                    let expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-                    let expires_ms = expires_date.get_time();
-                    if expires_ms <= soon_ms {
-                        expiring_soon += 1;
-                    }
-                } else {
-                    expired += 1;
-                }
-            }
-        }
-    }
 
-    // Count bans
-    let mut banned: u64 = 0;
-    let ban_list = bucket.list().prefix("bans/").execute().await?;
-    for _ in ban_list.objects() {
-        banned += 1;
-    }
-
-    // Calculate total revenue from provisioned orders
-    let mut total_revenue: u64 = 0;
-    let order_list = bucket.list().prefix("orders/").execute().await?;
-    for obj_entry in order_list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(order) = serde_json::from_str::<Order>(&text) {
-                if order.status == OrderStatus::Paid || order.status == OrderStatus::Provisioned {
-                    total_revenue += order.amount_sats;
-                }
-            }
-        }
-    }
-
-    Response::from_json(&AdminStatsResponse {
-        active_rentals: active,
-        expired_rentals: expired,
-        banned_users: banned,
-        expiring_soon,
-        total_revenue_sats: total_revenue,
-    })
+    let now_ms = js_sys::Date::now() as u64;
+    let stats = compute_stats(&store, now_ms).await.map_err(Error::RustError)?;
+    Response::from_json(&stats)
 }
 
 /// POST /admin/ban/{username}
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_ban(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
     let username = ctx.param("username").unwrap().to_string();
-
-    // Check if already banned
-    if is_banned(&bucket, &username).await {
-        return Response::error("User is already banned", 409);
+    let dns = CloudflareDns::from_env(&ctx.env);
+    match ban_user(&store, &dns, &username, js_sys::Date::now() as u64).await {
+        Ok(()) => Response::ok("banned"),
+        Err(e) => e.into_response(),
     }
-
-    // Create ban record
-    let now = js_sys::Date::new_0();
-    let ban = BanRecord {
-        username: username.clone(),
-        banned_at: now.to_iso_string().as_string().unwrap_or_default(),
-        reason: None,
-    };
-    let ban_json = serde_json::to_string(&ban).map_err(|e| Error::RustError(e.to_string()))?;
-    let ban_key = format!("bans/{}.json", username);
-    bucket.put(&ban_key, ban_json).execute().await?;
-
-    // Delete rental services (mark as expired, remove DNS)
-    let rental_key = format!("rentals/{}.json", username);
-    if let Some(obj) = bucket.get(&rental_key).execute().await? {
-        let body = obj.body().unwrap();
-        let text = body.text().await?;
-        if let Ok(mut rental) = serde_json::from_str::<Rental>(&text) {
-            // Delete DNS record if present
-            if let Some(ref sub) = rental.services.subdomain {
-                if let Some(ref record_id) = sub.cf_record_id {
-                    let zone_id = ctx.env.var("CF_ZONE_ID").map(|v| v.to_string()).unwrap_or_default();
-                    if !zone_id.is_empty() {
-                        let is_mock = crate::dns_mock::is_mock_dns_enabled(&ctx.env);
-                        let _ = if is_mock {
-                            crate::dns_mock::delete_dns_record(&zone_id, "", record_id).await
-                        } else {
-                            let token = ctx.env.secret("CF_API_TOKEN")?.to_string();
-                            crate::dns::delete_dns_record(&zone_id, &token, record_id).await
-                        };
-                    }
-                }
-            }
-
-            // Mark rental as expired
-            rental.status = "expired".to_string();
-            let updated = serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-            bucket.put(&rental_key, updated).execute().await?;
-        }
-    }
-
-    Response::ok("banned")
 }
 
 /// POST /admin/unban/{username}
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_unban(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
     let username = ctx.param("username").unwrap().to_string();
-
-    let ban_key = format!("bans/{}.json", username);
-    if !is_banned(&bucket, &username).await {
-        return Response::error("User is not banned", 404);
+    match unban_user(&store, &username).await {
+        Ok(()) => Response::ok("unbanned"),
+        Err(e) => e.into_response(),
     }
-
-    bucket.delete(&ban_key).await?;
-    Response::ok("unbanned")
 }
 
 /// POST /admin/extend/{username}  body: {"minutes": 30}
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_extend(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
@@ -586,47 +356,17 @@ pub async fn handle_admin_extend(mut req: Request, ctx: RouteContext<()>) -> Res
         .await
         .map_err(|_| Error::RustError("Invalid request body, expected {\"minutes\": N}".to_string()))?;
 
-    if body.minutes == 0 || body.minutes > 525600 {
-        return Response::error("Minutes must be between 1 and 525600", 400);
-    }
-    let rental_key = format!("rentals/{}.json", username);
-
-    let obj = bucket.get(&rental_key).execute().await?;
-    match obj {
-        Some(obj) => {
-            let obj_body = obj.body().unwrap();
-            let text = obj_body.text().await?;
-            let mut rental: Rental =
-                serde_json::from_str(&text).map_err(|e| Error::RustError(e.to_string()))?;
-
-            // Extend from current expires_at (or now if already expired)
-            let now_ms = js_sys::Date::now();
-            let current_expires = js_sys::Date::new(&rental.expires_at.clone().into());
-            let base_ms = if current_expires.get_time() > now_ms {
-                current_expires.get_time()
-            } else {
-                now_ms
-            };
-            let extension_ms = body.minutes as f64 * 60.0 * 1000.0;
-            let new_expires = js_sys::Date::new(&(base_ms + extension_ms).into());
-            rental.expires_at = new_expires.to_iso_string().as_string().unwrap_or_default();
-            rental.status = "active".to_string();
-
-            let updated =
-                serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-            bucket.put(&rental_key, updated).execute().await?;
-
-            Response::ok("extended")
-        }
-        None => Response::error("Rental not found", 404),
+    match extend_rental(&store, &username, body.minutes, js_sys::Date::now() as u64).await {
+        Ok(_) => Response::ok("extended"),
+        Err(e) => e.into_response(),
     }
 }
 
 /// PUT /api/admin/rentals/:username/webhook  body: {"webhook_url": "https://..."} or {"webhook_url": null}
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_rental_webhook_put(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
@@ -634,85 +374,93 @@ pub async fn handle_admin_rental_webhook_put(mut req: Request, ctx: RouteContext
     let body: AdminWebhookRequest = req.json().await
         .map_err(|_| Error::RustError("Invalid request body, expected {\"webhook_url\": string|null}".to_string()))?;
 
-    // Validate webhook_url if provided and non-empty
-    if let Some(ref url) = body.webhook_url {
-        if !url.trim().is_empty() && !url.starts_with("https://") && !url.starts_with("http://") {
-            return Response::error("webhook_url must be a valid HTTP(S) URL", 400);
-        }
+    match set_rental_webhook(&store, &username, body.webhook_url, crate::url_policy_from_env(&ctx.env)).await {
+        Ok(_) => Response::ok("updated"),
+        Err(e) => e.into_response(),
     }
+}
 
-    let rental_key = format!("rentals/{}.json", username);
-    let obj = bucket.get(&rental_key).execute().await?;
-    match obj {
-        Some(obj) => {
-            let obj_body = obj.body().unwrap();
-            let text = obj_body.text().await?;
-            let mut rental: Rental =
-                serde_json::from_str(&text).map_err(|e| Error::RustError(e.to_string()))?;
+/// GET /api/admin/rentals/:username/webhooks — recent webhook deliveries for a username
+#[cfg(target_arch = "wasm32")]
+pub async fn handle_admin_webhook_deliveries(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
+        return Response::error("Unauthorized", 401);
+    }
 
-            // Normalize: empty string -> None
-            let new_url = body.webhook_url.as_ref()
-                .and_then(|s| if s.trim().is_empty() { None } else { Some(s.trim().to_string()) });
-            rental.webhook_url = new_url;
+    let username = ctx.param("username").unwrap().to_string();
+    let deliveries = crate::webhook::recent_deliveries(&store, &username, 0, crate::webhook::DELIVERY_LIST_LIMIT)
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&DeliveryListResponse { deliveries })
+}
 
-            let updated =
-                serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-            bucket.put(&rental_key, updated).execute().await?;
+/// POST /api/admin/rentals/:username/webhooks/:delivery_id/replay — send a delivery again now
+#[cfg(target_arch = "wasm32")]
+pub async fn handle_admin_webhook_replay(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
+        return Response::error("Unauthorized", 401);
+    }
 
-            Response::ok("updated")
-        }
-        None => Response::error("Rental not found", 404),
+    let username = ctx.param("username").unwrap().to_string();
+    let delivery_id = ctx.param("delivery_id").unwrap().to_string();
+    let now_ms = js_sys::Date::now() as u64;
+    match crate::webhook::replay_delivery(&store, &crate::http::FetchClient, &username, &delivery_id, now_ms).await {
+        Ok(delivery) => Response::from_json(&delivery),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// POST /api/admin/rentals/:username/rotate-token — replace a leaked management token
+#[cfg(target_arch = "wasm32")]
+pub async fn handle_admin_rotate_token(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let username = ctx.param("username").unwrap().to_string();
+    match force_rotate_token(&store, &username, crate::ids::management_token()).await {
+        Ok(rental) => Response::from_json(&crate::token_rotated(&ctx.env, &rental, "admin").await),
+        Err(e) => e.into_response(),
     }
 }
 
 /// POST /admin/revoke/{username}
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_revoke(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
     let username = ctx.param("username").unwrap().to_string();
-    let rental_key = format!("rentals/{}.json", username);
-
-    let obj = bucket.get(&rental_key).execute().await?;
-    match obj {
-        Some(obj) => {
-            let obj_body = obj.body().unwrap();
-            let text = obj_body.text().await?;
-            let mut rental: Rental =
-                serde_json::from_str(&text).map_err(|e| Error::RustError(e.to_string()))?;
-
-            if rental.status != "active" {
-                return Response::error("Rental is not active", 400);
-            }
+    let dns = CloudflareDns::from_env(&ctx.env);
+    match revoke_rental(&store, &dns, &username).await {
+        Ok(_) => Response::ok("revoked"),
+        Err(e) => e.into_response(),
+    }
+}
 
-            // Delete DNS record if present
-            if let Some(ref sub) = rental.services.subdomain {
-                if let Some(ref record_id) = sub.cf_record_id {
-                    let zone_id = ctx.env.var("CF_ZONE_ID").map(|v| v.to_string()).unwrap_or_default();
-                    if !zone_id.is_empty() {
-                        let is_mock = crate::dns_mock::is_mock_dns_enabled(&ctx.env);
-                        let _ = if is_mock {
-                            crate::dns_mock::delete_dns_record(&zone_id, "", record_id).await
-                        } else {
-                            let token = ctx.env.secret("CF_API_TOKEN")?.to_string();
-                            crate::dns::delete_dns_record(&zone_id, &token, record_id).await
-                        };
-                    }
-                }
-            }
+/// POST /api/admin/orders/:order_id/status — manual cancel/refund/fail with a reason
+#[cfg(target_arch = "wasm32")]
+pub async fn handle_admin_order_status(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
+        return Response::error("Unauthorized", 401);
+    }
 
-            // Mark as expired
-            rental.status = "expired".to_string();
-            let updated =
-                serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-            bucket.put(&rental_key, updated).execute().await?;
+    let order_id = ctx.param("order_id").unwrap().to_string();
+    let body: AdminOrderStatusRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => return Response::error("Invalid request body, expected {\"status\": \"...\"}", 400),
+    };
 
-            Response::ok("revoked")
-        }
-        None => Response::error("Rental not found", 404),
+    let now_ms = js_sys::Date::now() as u64;
+    match set_order_status(&store, &order_id, body.status, body.reason.as_deref(), now_ms).await {
+        Ok(order) => Response::from_json(&order),
+        Err(e) => e.into_response(),
     }
 }
 
@@ -730,60 +478,409 @@ pub struct AdminProvisionRequest {
     pub dns_value: Option<String>,
 }
 
+/// POST /api/admin/backfill-tokens — index management tokens of existing rentals
+#[cfg(target_arch = "wasm32")]
+pub async fn handle_admin_backfill_tokens(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let indexed = crate::service::backfill_token_index(&store)
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&serde_json::json!({ "success": true, "indexed": indexed }))
+}
+
 /// POST /api/admin/provision — directly provision a rental (skip payment)
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_admin_provision(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    if let Err(_) = verify_session_token(&req, &bucket, &ctx.env).await {
+    let store = R2Store::from_env(&ctx.env)?;
+    if verify_session_token(&req, &store, &ctx.env).await.is_err() {
         return Response::error("Unauthorized", 401);
     }
 
     let body: AdminProvisionRequest = req.json().await
         .map_err(|_| Error::RustError("Invalid request body".to_string()))?;
 
-    if let Err(err) = crate::validation::validate_username(&body.username) {
-        return Response::error(err, 400);
+    let dns = CloudflareDns::from_env(&ctx.env);
+    let mgmt_token = crate::ids::management_token();
+    let now_ms = js_sys::Date::now() as u64;
+    match provision_rental(&store, &dns, body, mgmt_token, now_ms).await {
+        Ok(rental) => Response::from_json(&serde_json::json!({
+            "success": true,
+            "username": rental.username,
+            "expires_at": rental.expires_at,
+            "management_token": rental.management_token,
+        })),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Admin sessions are valid for 24 hours
+const SESSION_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Login challenges are valid for 5 minutes
+const CHALLENGE_TTL_MS: u64 = 5 * 60 * 1000;
+
+/// Check an X-Admin-Token session against stored sessions
+pub async fn check_session<S: Store>(store: &S, token: &str, now_ms: u64) -> std::result::Result<(), String> {
+    let session = store
+        .get_session(token)
+        .await?
+        .ok_or_else(|| "Invalid session token".to_string())?;
+    if ms_from_iso(&session.expires_at).is_some_and(|e| e < now_ms) {
+        return Err("Session expired".to_string());
     }
+    Ok(())
+}
+
+/// Store a new login challenge
+pub async fn issue_challenge<S: Store>(
+    store: &S,
+    challenge: String,
+    now_ms: u64,
+) -> StoreResult<AdminChallenge> {
+    let ch = AdminChallenge {
+        challenge,
+        created_at: iso_from_ms(now_ms),
+        expires_at: iso_from_ms(now_ms + CHALLENGE_TTL_MS),
+    };
+    store.put_challenge(&ch).await?;
+    Ok(ch)
+}
 
-    if is_banned(&bucket, &body.username).await {
-        return Response::error("This username is blocked", 403);
+/// Delete a login challenge, failing if it was never issued or has expired
+pub async fn consume_challenge<S: Store>(
+    store: &S,
+    challenge: &str,
+    now_ms: u64,
+) -> std::result::Result<(), ApiError> {
+    let ch = store
+        .get_challenge(challenge)
+        .await?
+        .ok_or_else(|| ApiError::new(400, "Invalid challenge"))?;
+    let _ = store.delete_challenge(challenge).await;
+    if ms_from_iso(&ch.expires_at).is_some_and(|e| e < now_ms) {
+        return Err(ApiError::new(400, "Challenge expired"));
     }
+    Ok(())
+}
+
+/// Store a new admin session
+pub async fn create_session<S: Store>(
+    store: &S,
+    token: String,
+    pubkey: String,
+    now_ms: u64,
+) -> StoreResult<AdminSession> {
+    let session = AdminSession {
+        token,
+        pubkey,
+        created_at: iso_from_ms(now_ms),
+        expires_at: iso_from_ms(now_ms + SESSION_TTL_MS),
+    };
+    store.put_session(&session).await?;
+    Ok(session)
+}
+
+/// Minutes until expiry, rounded up (negative once expired)
+fn minutes_remaining(expires_at: &str, now_ms: u64) -> i64 {
+    let diff = ms_from_iso(expires_at).unwrap_or(now_ms) as i64 - now_ms as i64;
+    if diff > 0 {
+        (diff + 59_999) / 60_000
+    } else {
+        diff / 60_000
+    }
+}
+
+/// Build one page of the admin rental listing, newest expiry first
+pub async fn list_rentals<S: Store>(
+    store: &S,
+    now_ms: u64,
+    page: usize,
+    limit: usize,
+    status_filter: Option<&str>,
+) -> StoreResult<AdminRentalsResponse> {
+    let mut entries: Vec<AdminRentalEntry> = Vec::new();
+
+    for rental in store.list_rentals().await? {
+        let display_status = if store.is_banned(&rental.username).await.unwrap_or(false) {
+            "banned".to_string()
+        } else {
+            rental.status.to_string()
+        };
+
+        if status_filter.is_some_and(|f| f != display_status) {
+            continue;
+        }
 
-    let rental_key = format!("rentals/{}.json", body.username);
-    if let Some(obj) = bucket.get(&rental_key).execute().await? {
-        let obj_body = obj.body().unwrap();
-        let text = obj_body.text().await?;
-        if let Ok(rental) = serde_json::from_str::<Rental>(&text) {
-            let now_ms = js_sys::Date::now();
-            let expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-            if expires_date.get_time() > now_ms {
-                return Response::error("Username is already taken", 409);
+        entries.push(AdminRentalEntry {
+            minutes_remaining: minutes_remaining(&rental.expires_at, now_ms),
+            username: rental.username,
+            status: display_status,
+            plan: rental.plan,
+            created_at: rental.created_at,
+            expires_at: rental.expires_at,
+            has_email: rental.services.email.as_ref().map(|e| e.enabled).unwrap_or(false),
+            has_subdomain: rental.services.subdomain.as_ref().map(|s| s.enabled).unwrap_or(false),
+            has_nip05: rental.services.nip05.as_ref().map(|n| n.enabled).unwrap_or(false),
+            has_lightning_address: rental.services.lightning_address.as_ref().map(|l| l.enabled).unwrap_or(false),
+            webhook_url: rental.webhook_url,
+            webhooks: rental.webhooks,
+            management_token: rental.management_token,
+        });
+    }
+
+    entries.sort_by(|a, b| b.expires_at.cmp(&a.expires_at));
+
+    let total = entries.len();
+    let start = (page.max(1) - 1) * limit;
+    let rentals = entries.into_iter().skip(start).take(limit).collect();
+
+    Ok(AdminRentalsResponse { rentals, total, page, limit })
+}
+
+/// Aggregate rental, ban and revenue counts for the dashboard
+pub async fn compute_stats<S: Store>(store: &S, now_ms: u64) -> StoreResult<AdminStatsResponse> {
+    let soon_ms = now_ms + 7 * 24 * 60 * 60 * 1000; // 7 days
+
+    let mut active: u64 = 0;
+    let mut expired: u64 = 0;
+    let mut expiring_soon: u64 = 0;
+    for rental in store.list_rentals().await? {
+        if rental.status.is_active() {
+            active += 1;
+            if ms_from_iso(&rental.expires_at).is_some_and(|e| e <= soon_ms) {
+                expiring_soon += 1;
             }
+        } else {
+            expired += 1;
         }
     }
 
-    let now_ms = js_sys::Date::now();
-    let now_date = js_sys::Date::new_0();
-    let now_iso = now_date.to_iso_string().as_string().unwrap_or_default();
-    let duration_ms = body.plan.duration_minutes() as f64 * 60.0 * 1000.0;
-    let expires_ms = now_ms + duration_ms;
-    let expires_date = js_sys::Date::new(&(expires_ms.into()));
-    let expires_at = expires_date.to_iso_string().as_string().unwrap_or_default();
+    let banned = store.list_all("bans/").await?.len() as u64;
+
+    let total_revenue_sats = store
+        .list_orders()
+        .await?
+        .iter()
+        .filter(|o| o.status == OrderStatus::Paid || o.status == OrderStatus::Provisioned)
+        .map(|o| o.amount_sats)
+        .sum();
+
+    Ok(AdminStatsResponse {
+        active_rentals: active,
+        expired_rentals: expired,
+        banned_users: banned,
+        expiring_soon,
+        total_revenue_sats,
+    })
+}
+
+/// Remove the rental's DNS record (best effort), drop its management token
+/// from the index and set the given inactive status
+async fn deactivate_rental<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    rental: &mut Rental,
+    status: RentalStatus,
+) -> StoreResult<()> {
+    if let Some(record_id) = rental.services.subdomain.as_ref().and_then(|s| s.cf_record_id.as_deref()) {
+        let _ = dns.delete_record(record_id).await;
+    }
+    if let Some(token) = rental.management_token.as_deref() {
+        store.delete_token_index(token).await?;
+    }
+    rental.status = status;
+    store.put_rental(rental).await
+}
+
+/// Ban a username and shut down its rental, if any
+pub async fn ban_user<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    username: &str,
+    now_ms: u64,
+) -> std::result::Result<(), ApiError> {
+    if store.is_banned(username).await? {
+        return Err(ApiError::new(409, "User is already banned"));
+    }
+
+    store
+        .put_ban(&BanRecord {
+            username: username.to_string(),
+            banned_at: iso_from_ms(now_ms),
+            reason: None,
+        })
+        .await?;
+
+    if let Ok(Some(mut rental)) = store.get_rental(username).await {
+        deactivate_rental(store, dns, &mut rental, RentalStatus::Suspended).await?;
+    }
+    Ok(())
+}
+
+pub async fn unban_user<S: Store>(store: &S, username: &str) -> std::result::Result<(), ApiError> {
+    if !store.is_banned(username).await? {
+        return Err(ApiError::new(404, "User is not banned"));
+    }
+    store.delete_ban(username).await?;
+    Ok(())
+}
+
+/// Extend a rental from its current expiry (or now if already expired)
+pub async fn extend_rental<S: Store>(
+    store: &S,
+    username: &str,
+    minutes: u64,
+    now_ms: u64,
+) -> std::result::Result<Rental, ApiError> {
+    if minutes == 0 || minutes > 525600 {
+        return Err(ApiError::new(400, "Minutes must be between 1 and 525600"));
+    }
+
+    let mut rental = store
+        .get_rental(username)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Rental not found"))?;
+    rental.expires_at = crate::service::extended_expiry(&rental.expires_at, now_ms, minutes);
+    rental.status = RentalStatus::Active;
+    store.put_rental(&rental).await?;
+    Ok(rental)
+}
+
+/// Set or clear (None / empty) a rental's webhook URL
+pub async fn set_rental_webhook<S: Store>(
+    store: &S,
+    username: &str,
+    webhook_url: Option<String>,
+    policy: UrlPolicy,
+) -> std::result::Result<Rental, ApiError> {
+    if let Some(url) = webhook_url.as_deref().filter(|u| !u.trim().is_empty()) {
+        check_url(url, policy).map_err(|e| ApiError::new(400, format!("Invalid webhook_url: {}", e)))?;
+    }
+
+    let mut rental = store
+        .get_rental(username)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Rental not found"))?;
+
+    // Normalize: empty string -> None
+    rental.webhook_url = webhook_url
+        .as_ref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    store.put_rental(&rental).await?;
+    Ok(rental)
+}
+
+/// Replace a rental's management token, e.g. after it leaked
+pub async fn force_rotate_token<S: Store>(
+    store: &S,
+    username: &str,
+    new_token: String,
+) -> std::result::Result<Rental, ApiError> {
+    let mut rental = store
+        .get_rental(username)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Rental not found"))?;
+    crate::service::reissue_management_token(store, &mut rental, new_token).await?;
+    Ok(rental)
+}
 
+/// Revoke an active rental: delete its DNS record and mark it revoked
+pub async fn revoke_rental<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    username: &str,
+) -> std::result::Result<Rental, ApiError> {
+    let mut rental = store
+        .get_rental(username)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Rental not found"))?;
+    if !rental.status.is_active() {
+        return Err(ApiError::new(400, "Rental is not active"));
+    }
+    deactivate_rental(store, dns, &mut rental, RentalStatus::Revoked).await?;
+    Ok(rental)
+}
+
+/// Move an order to a status an admin may set by hand, recording it in the order history
+pub async fn set_order_status<S: Store>(
+    store: &S,
+    order_id: &str,
+    status: OrderStatus,
+    reason: Option<&str>,
+    now_ms: u64,
+) -> std::result::Result<Order, ApiError> {
+    if !matches!(status, OrderStatus::Cancelled | OrderStatus::Refunded | OrderStatus::Failed) {
+        return Err(ApiError::new(400, "Status must be cancelled, refunded or failed"));
+    }
+    let mut order = store
+        .get_order(order_id)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Order not found"))?;
+    order
+        .transition(status, now_ms, Some(reason.unwrap_or("admin")))
+        .map_err(|e| ApiError::new(409, e))?;
+    store.put_order(&order).await?;
+    store.delete_pending_index(&order.order_id).await?;
+    Ok(order)
+}
+
+/// Create a rental directly, without an order or payment
+pub async fn provision_rental<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    body: AdminProvisionRequest,
+    mgmt_token: String,
+    now_ms: u64,
+) -> std::result::Result<Rental, ApiError> {
+    crate::validation::validate_username(&body.username).map_err(|e| ApiError::new(400, e))?;
+    let nip05_profile = body
+        .pubkey
+        .as_deref()
+        .map(crate::nip19::decode_pubkey)
+        .transpose()
+        .map_err(|e| ApiError::new(400, format!("Invalid pubkey: {}", e)))?;
+    if let (Some(dns_type), Some(dns_value)) = (&body.dns_type, &body.dns_value) {
+        let record_type = DnsRecordType::parse(dns_type).map_err(|e| ApiError::new(400, e))?;
+        check_dns_target(&record_type, dns_value)
+            .map_err(|e| ApiError::new(400, format!("Invalid dns_value: {}", e)))?;
+    }
+
+    if store.is_banned(&body.username).await? {
+        return Err(ApiError::new(403, "This username is blocked"));
+    }
+    if let Ok(Some(existing)) = store.get_rental(&body.username).await {
+        if existing.status != RentalStatus::Released && !is_expired_ms(&existing.expires_at, now_ms) {
+            return Err(ApiError::new(409, "Username is already taken"));
+        }
+        // The expired rental is replaced; its token must no longer resolve
+        if let Some(old_token) = existing.management_token.as_deref() {
+            store.delete_token_index(old_token).await?;
+        }
+    }
+
+    let expires_at = iso_from_ms(now_ms + body.plan.duration_minutes() * 60 * 1000);
     let is_bundle = body.service == "bundle";
-    let mgmt_token = format!("mgmt_{:x}", js_sys::Date::now() as u64);
 
-    let nip05_service = if body.service == "nip05" || is_bundle {
-        body.pubkey.as_ref().map(|pk| Nip05Service {
+    let nip05 = if body.service == "nip05" || is_bundle {
+        nip05_profile.map(|profile| Nip05Service {
             enabled: true,
-            pubkey_hex: pk.clone(),
-            relays: vec![],
+            relays: crate::nip05::relay_hints(&profile.relays),
+            pubkey_hex: profile.pubkey_hex,
+            nip46: vec![],
+            names: Default::default(),
+            name_slots: 0,
         })
     } else {
         None
     };
 
-    let email_service = if body.service == "email" || is_bundle {
+    let email = if body.service == "email" || is_bundle {
         Some(EmailService {
             enabled: true,
             cf_rule_id: None,
@@ -792,68 +889,48 @@ pub async fn handle_admin_provision(mut req: Request, ctx: RouteContext<()>) ->
         None
     };
 
-    let subdomain_service = if body.service == "subdomain" || is_bundle {
-        if let (Some(ref dns_type), Some(ref dns_value)) = (&body.dns_type, &body.dns_value) {
-            let zone_id = ctx.env.var("CF_ZONE_ID").map(|v| v.to_string()).unwrap_or_default();
-            let domain = ctx.env.var("DOMAIN").map(|v| v.to_string()).unwrap_or_else(|_| "noscha.io".to_string());
-
-            let record_type = match dns_type.to_uppercase().as_str() {
-                "CNAME" => crate::dns::DnsRecordType::CNAME,
-                "A" => crate::dns::DnsRecordType::A,
-                "AAAA" => crate::dns::DnsRecordType::AAAA,
-                other => return Response::error(format!("Unsupported DNS record type: {}", other), 400),
-            };
-
-            let mut cf_record_id = None;
-            if !zone_id.is_empty() {
-                let is_mock = crate::dns_mock::is_mock_dns_enabled(&ctx.env);
-                let record_id = if is_mock {
-                    crate::dns_mock::create_dns_record(&zone_id, "", &body.username, &record_type, dns_value, false, &body.username, &expires_at, &domain).await?
-                } else {
-                    let token = ctx.env.secret("CF_API_TOKEN")?.to_string();
-                    crate::dns::create_dns_record(&zone_id, &token, &body.username, &record_type, dns_value, false, &body.username, &expires_at, &domain).await?
-                };
-                cf_record_id = Some(record_id);
-            }
-
-            Some(SubdomainService {
+    let mut subdomain = None;
+    if body.service == "subdomain" || is_bundle {
+        if let (Some(dns_type), Some(dns_value)) = (&body.dns_type, &body.dns_value) {
+            let record_type = DnsRecordType::parse(dns_type).map_err(|e| ApiError::new(400, e))?;
+            let cf_record_id = dns
+                .create_record(&body.username, &record_type, dns_value, false, &expires_at)
+                .await?;
+            subdomain = Some(SubdomainService {
                 enabled: true,
                 record_type: dns_type.clone(),
                 target: dns_value.clone(),
                 proxied: false,
                 cf_record_id,
-            })
-        } else {
-            None
+            });
         }
-    } else {
-        None
-    };
+    }
+
+    // Provisioned addresses take payments through the backend; renters can forward them later
+    let lightning_address = (body.service == "lightning_address").then_some(LightningAddressService {
+        enabled: true,
+        forward_to: None,
+    });
 
     let rental = Rental {
-        username: body.username.clone(),
-        status: "active".to_string(),
-        created_at: now_iso,
-        expires_at: expires_at.clone(),
+        username: body.username,
+        status: RentalStatus::Active,
+        created_at: iso_from_ms(now_ms),
+        expires_at,
         plan: body.plan,
-        services: RentalServices {
-            email: email_service,
-            subdomain: subdomain_service,
-            nip05: nip05_service,
-        },
+        services: RentalServices { email, subdomain, nip05, lightning_address },
         management_token: Some(mgmt_token.clone()),
         webhook_url: None,
+        grace_until: None,
+        reminders_sent: Vec::new(),
+        signing_secret: None,
+        webhooks: Vec::new(),
+        nostr_dm: false,
+        owner_pubkey: None,
     };
-
-    let rental_json = serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&rental_key, rental_json).execute().await?;
-
-    Response::from_json(&serde_json::json!({
-        "success": true,
-        "username": body.username,
-        "expires_at": expires_at,
-        "management_token": mgmt_token,
-    }))
+    store.put_token_index(&mgmt_token, &rental.username).await?;
+    store.put_rental(&rental).await?;
+    Ok(rental)
 }
 
 #[cfg(test)]
@@ -930,7 +1007,9 @@ mod tests {
             has_email: true,
             has_subdomain: false,
             has_nip05: true,
+            has_lightning_address: false,
             webhook_url: None,
+            webhooks: Vec::new(),
             management_token: Some("mgmt_abc123".to_string()),
         };
         let json = serde_json::to_value(&entry).unwrap();
@@ -987,11 +1066,173 @@ mod tests {
             has_email: false,
             has_subdomain: false,
             has_nip05: false,
+            has_lightning_address: false,
             webhook_url: None,
+            webhooks: Vec::new(),
             management_token: None,
         };
         let json = serde_json::to_value(&entry).unwrap();
         assert_eq!(json["status"], "banned");
         assert_eq!(json["minutes_remaining"], -5);
     }
+
+    const NOW: u64 = 1_770_811_200_000; // 2026-02-11T12:00:00.000Z
+
+    fn provision_body(username: &str) -> AdminProvisionRequest {
+        AdminProvisionRequest {
+            username: username.to_string(),
+            service: "bundle".to_string(),
+            plan: Plan::OneDay,
+            pubkey: Some("a".repeat(64)),
+            dns_type: Some("cname".to_string()),
+            dns_value: Some("example.com".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_challenge_and_session_flow() {
+        use crate::store::{block_on, MemoryStore};
+        let store = MemoryStore::new();
+        block_on(async {
+            issue_challenge(&store, "ch_1".into(), NOW).await.unwrap();
+            assert!(consume_challenge(&store, "ch_1", NOW + 1000).await.is_ok());
+            // Single use
+            assert_eq!(consume_challenge(&store, "ch_1", NOW).await.unwrap_err().message, "Invalid challenge");
+
+            issue_challenge(&store, "ch_2".into(), NOW).await.unwrap();
+            let err = consume_challenge(&store, "ch_2", NOW + CHALLENGE_TTL_MS + 1).await.unwrap_err();
+            assert_eq!(err.message, "Challenge expired");
+
+            create_session(&store, "sess_1".into(), "pk".into(), NOW).await.unwrap();
+            assert!(check_session(&store, "sess_1", NOW + SESSION_TTL_MS).await.is_ok());
+            assert!(check_session(&store, "sess_1", NOW + SESSION_TTL_MS + 1).await.is_err());
+            assert!(check_session(&store, "sess_x", NOW).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_provision_ban_and_unban() {
+        use crate::service::FakeDns;
+        use crate::store::{block_on, MemoryStore};
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let rental = provision_rental(&store, &dns, provision_body("alice"), "mgmt_a".into(), NOW)
+                .await
+                .unwrap();
+            assert_eq!(rental.expires_at, "2026-02-12T12:00:00.000Z");
+            assert!(rental.services.email.is_some());
+            assert!(rental.services.nip05.is_some());
+            assert_eq!(rental.services.subdomain.unwrap().cf_record_id.as_deref(), Some("rec_1"));
+            assert_eq!(store.get_token_username("mgmt_a").await.unwrap().as_deref(), Some("alice"));
+
+            let err = provision_rental(&store, &dns, provision_body("alice"), "mgmt_b".into(), NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+            let mut private = provision_body("ann");
+            private.dns_type = Some("a".into());
+            private.dns_value = Some("192.168.1.10".into());
+            let err = provision_rental(&store, &dns, private, "mgmt_c".into(), NOW).await.unwrap_err();
+            assert_eq!(err.status, 400);
+            let mut bad_key = provision_body("ann");
+            bad_key.pubkey = Some("npub1invalid".into());
+            assert_eq!(provision_rental(&store, &dns, bad_key, "mgmt_c".into(), NOW).await.unwrap_err().status, 400);
+            let mut npub = provision_body("ann");
+            npub.pubkey = Some("npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg".into());
+            let rental = provision_rental(&store, &dns, npub, "mgmt_c".into(), NOW).await.unwrap();
+            assert_eq!(
+                rental.services.nip05.unwrap().pubkey_hex,
+                "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e"
+            );
+
+            ban_user(&store, &dns, "alice", NOW).await.unwrap();
+            assert_eq!(ban_user(&store, &dns, "alice", NOW).await.unwrap_err().status, 409);
+            assert_eq!(store.get_rental("alice").await.unwrap().unwrap().status, RentalStatus::Suspended);
+            assert_eq!(*dns.deleted.borrow(), vec!["rec_1".to_string()]);
+            assert_eq!(store.get_token_username("mgmt_a").await.unwrap(), None);
+
+            let listing = list_rentals(&store, NOW, 1, 20, Some("banned")).await.unwrap();
+            assert_eq!(listing.total, 1);
+            assert_eq!(listing.rentals[0].minutes_remaining, 1440);
+
+            unban_user(&store, "alice").await.unwrap();
+            assert_eq!(unban_user(&store, "alice").await.unwrap_err().status, 404);
+        });
+    }
+
+    #[test]
+    fn test_extend_revoke_and_stats() {
+        use crate::service::FakeDns;
+        use crate::store::{block_on, MemoryStore};
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            provision_rental(&store, &dns, provision_body("bob"), "mgmt_b".into(), NOW).await.unwrap();
+
+            assert_eq!(extend_rental(&store, "bob", 0, NOW).await.unwrap_err().status, 400);
+            assert_eq!(extend_rental(&store, "nobody", 30, NOW).await.unwrap_err().status, 404);
+            let rental = extend_rental(&store, "bob", 30, NOW).await.unwrap();
+            assert_eq!(rental.expires_at, "2026-02-12T12:30:00.000Z");
+
+            let stats = compute_stats(&store, NOW).await.unwrap();
+            assert_eq!(stats.active_rentals, 1);
+            assert_eq!(stats.expiring_soon, 1);
+
+            let rental = set_rental_webhook(&store, "bob", Some("  ".into()), UrlPolicy::PRODUCTION).await.unwrap();
+            assert_eq!(rental.webhook_url, None);
+            for url in ["ftp://x", "https://localhost/hook", "https://[::1]/hook"] {
+                assert_eq!(
+                    set_rental_webhook(&store, "bob", Some(url.into()), UrlPolicy::PRODUCTION).await.unwrap_err().status,
+                    400
+                );
+            }
+
+            assert_eq!(force_rotate_token(&store, "nobody", "mgmt_x".into()).await.unwrap_err().status, 404);
+            let rental = force_rotate_token(&store, "bob", "mgmt_b2".into()).await.unwrap();
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_b2"));
+            assert!(crate::service::find_rental_by_token(&store, "mgmt_b").await.unwrap().is_none());
+
+            let rental = revoke_rental(&store, &dns, "bob").await.unwrap();
+            assert_eq!(rental.status, RentalStatus::Revoked);
+            assert_eq!(force_rotate_token(&store, "bob", "mgmt_b3".into()).await.unwrap_err().status, 409);
+            assert_eq!(revoke_rental(&store, &dns, "bob").await.unwrap_err().status, 400);
+            let stats = compute_stats(&store, NOW).await.unwrap();
+            assert_eq!(stats.active_rentals, 0);
+            assert_eq!(stats.expired_rentals, 1);
+        });
+    }
+
+    #[test]
+    fn test_set_order_status() {
+        use crate::store::{block_on, MemoryStore};
+        let store = MemoryStore::new();
+        block_on(async {
+            let request: OrderRequest = serde_json::from_value(serde_json::json!({
+                "username": "carol", "plan": "1d", "webhook_url": "https://example.com/hook"
+            }))
+            .unwrap();
+            crate::service::create_order(&store, request, "ord_c".into(), "ch".into(), "whsec_test".into(), crate::service::DEFAULT_GRACE_MINUTES, crate::url_policy::UrlPolicy::PRODUCTION, NOW).await.unwrap();
+
+            let err = set_order_status(&store, "ord_c", OrderStatus::Paid, None, NOW).await.unwrap_err();
+            assert_eq!(err.status, 400);
+            assert_eq!(set_order_status(&store, "nope", OrderStatus::Cancelled, None, NOW).await.unwrap_err().status, 404);
+
+            let order = set_order_status(&store, "ord_c", OrderStatus::Cancelled, Some("duplicate"), NOW).await.unwrap();
+            assert_eq!(order.status, OrderStatus::Cancelled);
+            assert_eq!(order.history[0].reason.as_deref(), Some("duplicate"));
+            assert!(store.list_pending_order_ids().await.unwrap().is_empty());
+
+            // Cancelled orders cannot be refunded: nothing was paid
+            let err = set_order_status(&store, "ord_c", OrderStatus::Refunded, None, NOW).await.unwrap_err();
+            assert_eq!(err.status, 409);
+        });
+    }
+
+    #[test]
+    fn test_minutes_remaining_rounds_up() {
+        assert_eq!(minutes_remaining("2026-02-11T12:00:30.000Z", NOW), 1);
+        assert_eq!(minutes_remaining("2026-02-11T11:59:30.000Z", NOW), 0);
+        assert_eq!(minutes_remaining("2026-02-11T11:55:00.000Z", NOW), -5);
+    }
 }
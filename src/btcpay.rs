@@ -0,0 +1,244 @@
+use crate::hmac::verify_hmac_sha256_hex;
+use crate::http::{HttpClient, HttpRequest, InboundRequest};
+use crate::payment::{CreatedInvoice, InvoiceRequest, InvoiceStatus, PaymentProvider, WebhookNotice};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+struct GreenfieldInvoice {
+    id: String,
+    #[serde(default)]
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GreenfieldPaymentMethod {
+    /// "BTC-LN" on 2.x, "BTC-LightningNetwork" via `paymentMethod` on 1.x
+    #[serde(default, alias = "paymentMethod")]
+    payment_method_id: String,
+    #[serde(default)]
+    destination: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GreenfieldWebhook {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    invoice_id: Option<String>,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+/// BTCPay Server Greenfield API (store-scoped API key)
+pub struct BtcPayProvider<C> {
+    client: C,
+    base_url: String,
+    store_id: String,
+    api_key: String,
+    /// Secret of the store webhook; when set, deliveries must carry a valid BTCPay-Sig
+    webhook_secret: Option<String>,
+}
+
+impl<C: HttpClient> BtcPayProvider<C> {
+    pub fn new(
+        client: C,
+        base_url: String,
+        store_id: String,
+        api_key: String,
+        webhook_secret: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            store_id,
+            api_key,
+            webhook_secret,
+        }
+    }
+
+    fn invoices_url(&self) -> String {
+        format!("{}/api/v1/stores/{}/invoices", self.base_url, self.store_id)
+    }
+
+    async fn send(&self, req: HttpRequest) -> Result<crate::http::HttpResponse, String> {
+        let response = self
+            .client
+            .send(req.header("Authorization", format!("token {}", self.api_key)))
+            .await?;
+        if !response.is_success() {
+            return Err(format!("BTCPay API error ({}): {}", response.status, response.body));
+        }
+        Ok(response)
+    }
+}
+
+impl<C: HttpClient> PaymentProvider for BtcPayProvider<C> {
+    fn name(&self) -> &'static str {
+        "btcpay"
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String> {
+        let body = json!({
+            "amount": req.amount_sats.to_string(),
+            "currency": "SATS",
+            "metadata": { "orderId": req.secret, "itemDesc": req.memo },
+            "checkout": {
+                "expirationMinutes": req.expiry_secs.div_ceil(60).max(1),
+                "paymentMethods": ["BTC-LN"],
+            },
+        });
+        let invoice: GreenfieldInvoice = self
+            .send(HttpRequest::post_json(self.invoices_url(), &body))
+            .await?
+            .json()?;
+
+        let methods: Vec<GreenfieldPaymentMethod> = self
+            .send(HttpRequest::get(format!("{}/{}/payment-methods", self.invoices_url(), invoice.id)))
+            .await?
+            .json()?;
+        let bolt11 = methods
+            .into_iter()
+            .find(|m| matches!(m.payment_method_id.as_str(), "BTC-LN" | "BTC-LightningNetwork"))
+            .map(|m| m.destination)
+            .filter(|d| !d.is_empty())
+            .ok_or("BTCPay invoice has no Lightning payment method")?;
+        Ok(CreatedInvoice { bolt11, payment_id: Some(invoice.id) })
+    }
+
+    async fn check_status(&self, payment_id: &str) -> Result<InvoiceStatus, String> {
+        let invoice: GreenfieldInvoice = self
+            .send(HttpRequest::get(format!("{}/{}", self.invoices_url(), payment_id)))
+            .await?
+            .json()?;
+        Ok(match invoice.status.as_str() {
+            "Settled" => InvoiceStatus::Paid,
+            "Expired" | "Invalid" => InvoiceStatus::Expired,
+            _ => InvoiceStatus::Pending,
+        })
+    }
+
+    /// Verify BTCPay-Sig when a webhook secret is configured; unsigned setups
+    /// fall back to confirming the invoice through the API
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String> {
+        let verified = match &self.webhook_secret {
+            Some(secret) => {
+                let signature = req
+                    .header("BTCPay-Sig")
+                    .and_then(|s| s.strip_prefix("sha256="))
+                    .ok_or("Missing BTCPay-Sig header")?;
+                if !verify_hmac_sha256_hex(secret.as_bytes(), req.body.as_bytes(), signature) {
+                    return Err("Invalid BTCPay-Sig signature".to_string());
+                }
+                true
+            }
+            None => false,
+        };
+
+        let payload: GreenfieldWebhook =
+            serde_json::from_str(&req.body).map_err(|_| "Invalid webhook payload".to_string())?;
+        if !matches!(payload.event_type.as_str(), "InvoiceSettled" | "InvoicePaymentSettled") {
+            return Ok(None);
+        }
+        let Some(invoice_id) = payload.invoice_id else {
+            return Ok(None);
+        };
+        let secret = payload
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get("orderId"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        Ok(Some(WebhookNotice {
+            secret,
+            payment_id: Some(invoice_id),
+            // A single payment can be partial; only a settled invoice is proof
+            paid: verified && payload.event_type == "InvoiceSettled",
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hmac::hmac_sha256_hex;
+    use crate::payment::stand_in::StandInServer;
+    use crate::store::block_on;
+
+    fn provider<'a>(server: &'a StandInServer, secret: Option<&str>) -> BtcPayProvider<&'a StandInServer> {
+        BtcPayProvider::new(
+            server,
+            "https://btcpay.test/".into(),
+            "store1".into(),
+            "key".into(),
+            secret.map(str::to_string),
+        )
+    }
+
+    fn webhook(body: &str, signature: Option<String>) -> InboundRequest {
+        InboundRequest {
+            url: "https://noscha.io/api/webhook/btcpay".into(),
+            headers: signature.map(|s| vec![("BTCPay-Sig".to_string(), s)]).unwrap_or_default(),
+            body: body.into(),
+        }
+    }
+
+    const SETTLED: &str = r#"{"type":"InvoiceSettled","invoiceId":"inv1","storeId":"store1","metadata":{"orderId":"sec_b"}}"#;
+
+    #[test]
+    fn test_create_invoice_uses_lightning_destination() {
+        let server = StandInServer::new();
+        let provider = provider(&server, None);
+        block_on(async {
+            let invoice = provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: 2100,
+                    memo: "noscha.io alice",
+                    webhook_url: "https://noscha.io/api/webhook/btcpay",
+                    secret: "sec_b",
+                    expiry_secs: 900,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(invoice.bolt11, "lnbc2100n1btcpay");
+        });
+        let requests = server.requests.borrow();
+        assert_eq!(requests[0].url, "https://btcpay.test/api/v1/stores/store1/invoices");
+        assert!(requests.iter().all(|r| r.header_value("Authorization") == Some("token key")));
+        let body = server.last_invoice().request_body;
+        assert_eq!(body["amount"], "2100");
+        assert_eq!(body["currency"], "SATS");
+        assert_eq!(body["metadata"]["orderId"], "sec_b");
+        assert_eq!(body["checkout"]["expirationMinutes"], 15);
+    }
+
+    #[test]
+    fn test_signed_webhook() {
+        let server = StandInServer::new();
+        let provider = provider(&server, Some("whsec"));
+        let sig = format!("sha256={}", hmac_sha256_hex(b"whsec", SETTLED.as_bytes()));
+
+        let notice = provider.parse_webhook(&webhook(SETTLED, Some(sig.clone()))).unwrap().unwrap();
+        assert_eq!(notice.secret.as_deref(), Some("sec_b"));
+        assert_eq!(notice.payment_id.as_deref(), Some("inv1"));
+        assert!(notice.paid);
+
+        assert!(provider.parse_webhook(&webhook(SETTLED, None)).is_err());
+        let tampered = SETTLED.replace("sec_b", "sec_z");
+        assert!(provider.parse_webhook(&webhook(&tampered, Some(sig))).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_webhook_and_other_events() {
+        let server = StandInServer::new();
+        let provider = provider(&server, None);
+        let notice = provider.parse_webhook(&webhook(SETTLED, None)).unwrap().unwrap();
+        assert!(!notice.paid);
+
+        let created = r#"{"type":"InvoiceCreated","invoiceId":"inv1"}"#;
+        assert_eq!(provider.parse_webhook(&webhook(created, None)).unwrap(), None);
+    }
+}
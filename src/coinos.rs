@@ -1,30 +1,29 @@
-#![cfg(target_arch = "wasm32")]
-
+use crate::http::{HttpClient, HttpRequest, InboundRequest};
+use crate::payment::{
+    parse_coinos_webhook, CreatedInvoice, InvoiceRequest, InvoiceStatus, PaymentProvider, WebhookNotice,
+};
 use serde::{Deserialize, Serialize};
-use worker::*;
 
-const USER_AGENT: &str = "Mozilla/5.0 (compatible; noscha.io/0.1)";
+const DEFAULT_BASE_URL: &str = "https://coinos.io/api";
 
 #[derive(Debug, Serialize)]
-struct InvoiceInner {
+struct InvoiceInner<'a> {
     amount: u64,
     #[serde(rename = "type")]
-    invoice_type: String,
-    webhook: String,
-    secret: String,
+    invoice_type: &'a str,
+    webhook: &'a str,
+    secret: &'a str,
 }
 
 #[derive(Debug, Serialize)]
-struct CreateInvoiceRequest {
-    invoice: InvoiceInner,
+struct CreateInvoiceRequest<'a> {
+    invoice: InvoiceInner<'a>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CoinosInvoiceResponse {
     #[serde(default)]
-    #[allow(dead_code)]
     pub id: Option<String>,
-    #[allow(dead_code)]
     pub amount: u64,
     /// bolt11 invoice string
     pub text: String,
@@ -32,48 +31,110 @@ pub struct CoinosInvoiceResponse {
     pub hash: Option<String>,
 }
 
-/// Create a Lightning invoice via Coinos API
-pub async fn create_invoice(
-    api_token: &str,
-    amount_sats: u64,
-    webhook_url: &str,
-    order_secret: &str,
-) -> Result<CoinosInvoiceResponse> {
-    let body = CreateInvoiceRequest {
-        invoice: InvoiceInner {
-            amount: amount_sats,
-            invoice_type: "lightning".to_string(),
-            webhook: webhook_url.to_string(),
-            secret: order_secret.to_string(),
-        },
-    };
+#[derive(Debug, Deserialize)]
+struct CoinosInvoiceState {
+    #[serde(default)]
+    amount: u64,
+    #[serde(default)]
+    received: u64,
+}
 
-    let headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
-    headers.set("Authorization", &format!("Bearer {}", api_token))?;
-    headers.set("User-Agent", USER_AGENT)?;
+/// coinos.io custodial wallet API
+pub struct CoinosProvider<C> {
+    client: C,
+    api_token: String,
+    base_url: String,
+}
 
-    let mut init = RequestInit::new();
-    init.with_method(Method::Post)
-        .with_headers(headers)
-        .with_body(Some(
-            serde_json::to_string(&body)
-                .map_err(|e| Error::RustError(e.to_string()))?
-                .into(),
-        ));
+impl<C: HttpClient> CoinosProvider<C> {
+    pub fn new(client: C, api_token: String) -> Self {
+        Self { client, api_token, base_url: DEFAULT_BASE_URL.to_string() }
+    }
 
-    let request = Request::new_with_init("https://coinos.io/api/invoice", &init)?;
-    let mut response = Fetch::Request(request).send().await?;
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
 
-    if response.status_code() != 200 {
-        let text = response.text().await.unwrap_or_default();
-        return Err(Error::RustError(format!(
-            "Coinos API error ({}): {}",
-            response.status_code(),
-            text
-        )));
+    fn authorized(&self, req: HttpRequest) -> HttpRequest {
+        req.header("Authorization", format!("Bearer {}", self.api_token))
     }
+}
 
-    let invoice: CoinosInvoiceResponse = response.json().await?;
-    Ok(invoice)
+impl<C: HttpClient> PaymentProvider for CoinosProvider<C> {
+    fn name(&self) -> &'static str {
+        "coinos"
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String> {
+        let body = CreateInvoiceRequest {
+            invoice: InvoiceInner {
+                amount: req.amount_sats,
+                invoice_type: "lightning",
+                webhook: req.webhook_url,
+                secret: req.secret,
+            },
+        };
+        let body = serde_json::to_value(&body).map_err(|e| e.to_string())?;
+        let request = self.authorized(HttpRequest::post_json(format!("{}/invoice", self.base_url), &body));
+        let response = self.client.send(request).await?;
+        if !response.is_success() {
+            return Err(format!("Coinos API error ({}): {}", response.status, response.body));
+        }
+        let invoice: CoinosInvoiceResponse = response.json()?;
+        Ok(CreatedInvoice { bolt11: invoice.text, payment_id: invoice.hash.or(invoice.id) })
+    }
+
+    async fn check_status(&self, payment_id: &str) -> Result<InvoiceStatus, String> {
+        let request = self.authorized(HttpRequest::get(format!("{}/invoice/{}", self.base_url, payment_id)));
+        let response = self.client.send(request).await?;
+        if !response.is_success() {
+            return Err(format!("Coinos API error ({}): {}", response.status, response.body));
+        }
+        let state: CoinosInvoiceState = response.json()?;
+        if state.received > 0 && state.received >= state.amount {
+            Ok(InvoiceStatus::Paid)
+        } else {
+            Ok(InvoiceStatus::Pending)
+        }
+    }
+
+    /// coinos posts {confirmed, secret, hash}; the per-order secret authenticates it
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String> {
+        parse_coinos_webhook(&req.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payment::stand_in::StandInServer;
+    use crate::store::block_on;
+
+    #[test]
+    fn test_create_invoice_request_shape() {
+        let server = StandInServer::new();
+        let provider = CoinosProvider::new(&server, "tok".into()).with_base_url("https://coinos.test/api/");
+        block_on(async {
+            let invoice = provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: 500,
+                    memo: "m",
+                    webhook_url: "https://noscha.io/api/webhook/coinos",
+                    secret: "sec_x",
+                    expiry_secs: 900,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(invoice.bolt11, "lnbc500n1standin");
+            let sent = server.requests.borrow()[0].clone();
+            assert_eq!(sent.url, "https://coinos.test/api/invoice");
+            assert_eq!(sent.header_value("Authorization"), Some("Bearer tok"));
+            let body = server.last_invoice().request_body;
+            assert_eq!(body["invoice"]["type"], "lightning");
+            assert_eq!(body["invoice"]["secret"], "sec_x");
+            assert_eq!(body["invoice"]["webhook"], "https://noscha.io/api/webhook/coinos");
+        });
+    }
 }
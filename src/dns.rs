@@ -11,6 +11,16 @@ pub enum DnsRecordType {
 }
 
 impl DnsRecordType {
+    /// Parse a record type name (case-insensitive)
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.to_uppercase().as_str() {
+            "CNAME" => Ok(DnsRecordType::CNAME),
+            "A" => Ok(DnsRecordType::A),
+            "AAAA" => Ok(DnsRecordType::AAAA),
+            other => Err(format!("Unsupported DNS record type: {}", other)),
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             DnsRecordType::CNAME => "CNAME",
@@ -26,6 +36,112 @@ impl std::fmt::Display for DnsRecordType {
     }
 }
 
+/// DNS backend used to provision and tear down rental subdomains
+#[allow(async_fn_in_trait)]
+pub trait DnsProvider {
+    /// Create a record for {username}.{domain}. Returns None when DNS is not configured.
+    async fn create_record(
+        &self,
+        username: &str,
+        record_type: &DnsRecordType,
+        content: &str,
+        proxied: bool,
+        expires: &str,
+    ) -> Result<Option<String>, String>;
+
+    async fn delete_record(&self, record_id: &str) -> Result<(), String>;
+
+    /// Point an existing record at a new target
+    async fn update_record(
+        &self,
+        record_id: &str,
+        record_type: &DnsRecordType,
+        content: &str,
+        proxied: bool,
+    ) -> Result<(), String>;
+}
+
+/// Cloudflare DNS (or the MOCK_DNS logger) configured from worker env
+#[cfg(target_arch = "wasm32")]
+pub struct CloudflareDns {
+    zone_id: String,
+    token: Option<String>,
+    domain: String,
+    mock: bool,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl CloudflareDns {
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            zone_id: env.var("CF_ZONE_ID").map(|v| v.to_string()).unwrap_or_default(),
+            token: env.secret("CF_API_TOKEN").map(|s| s.to_string()).ok(),
+            domain: env
+                .var("DOMAIN")
+                .map(|v| v.to_string())
+                .unwrap_or_else(|_| "noscha.io".to_string()),
+            mock: crate::dns_mock::is_mock_dns_enabled(env),
+        }
+    }
+
+    fn token(&self) -> Result<&str, String> {
+        self.token.as_deref().ok_or_else(|| "CF_API_TOKEN not configured".to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl DnsProvider for CloudflareDns {
+    async fn create_record(
+        &self,
+        username: &str,
+        record_type: &DnsRecordType,
+        content: &str,
+        proxied: bool,
+        expires: &str,
+    ) -> Result<Option<String>, String> {
+        if self.zone_id.is_empty() {
+            console_log!("CF_ZONE_ID not set, skipping DNS provisioning");
+            return Ok(None);
+        }
+        let record_id = if self.mock {
+            crate::dns_mock::create_dns_record(&self.zone_id, "", username, record_type, content, proxied, username, expires, &self.domain).await
+        } else {
+            create_dns_record(&self.zone_id, self.token()?, username, record_type, content, proxied, username, expires, &self.domain).await
+        };
+        record_id.map(Some).map_err(|e| e.to_string())
+    }
+
+    async fn delete_record(&self, record_id: &str) -> Result<(), String> {
+        if self.zone_id.is_empty() {
+            return Ok(());
+        }
+        let result = if self.mock {
+            crate::dns_mock::delete_dns_record(&self.zone_id, "", record_id).await
+        } else {
+            delete_dns_record(&self.zone_id, self.token()?, record_id).await
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    async fn update_record(
+        &self,
+        record_id: &str,
+        record_type: &DnsRecordType,
+        content: &str,
+        proxied: bool,
+    ) -> Result<(), String> {
+        if self.zone_id.is_empty() {
+            return Ok(());
+        }
+        let result = if self.mock {
+            crate::dns_mock::update_dns_record(&self.zone_id, "", record_id, record_type, content, proxied).await
+        } else {
+            update_dns_record(&self.zone_id, self.token()?, record_id, record_type, content, proxied).await
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
 /// Request body for creating a DNS record via Cloudflare API
 #[derive(Debug, Serialize)]
 pub struct CreateDnsRecordRequest {
@@ -41,7 +157,10 @@ pub struct CreateDnsRecordRequest {
 /// Request body for updating a DNS record via Cloudflare API
 #[derive(Debug, Serialize)]
 pub struct UpdateDnsRecordRequest {
+    #[serde(rename = "type")]
+    pub record_type: String,
     pub content: String,
+    pub proxied: bool,
 }
 
 /// Cloudflare API response wrapper
@@ -211,13 +330,15 @@ pub async fn delete_dns_record(zone_id: &str, token: &str, record_id: &str) -> R
     Ok(())
 }
 
-/// Update a DNS record's content via Cloudflare API
+/// Update a DNS record's type, content and proxy setting via Cloudflare API
 #[cfg(target_arch = "wasm32")]
 pub async fn update_dns_record(
     zone_id: &str,
     token: &str,
     record_id: &str,
+    record_type: &DnsRecordType,
     content: &str,
+    proxied: bool,
 ) -> Result<()> {
     let url = format!(
         "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
@@ -225,7 +346,9 @@ pub async fn update_dns_record(
     );
 
     let body = UpdateDnsRecordRequest {
+        record_type: record_type.as_str().to_string(),
         content: content.to_string(),
+        proxied,
     };
 
     let headers = Headers::new();
@@ -339,6 +462,13 @@ mod tests {
         assert_eq!(json["proxied"], false);
     }
 
+    #[test]
+    fn test_update_request_json_serialization() {
+        let req = UpdateDnsRecordRequest { record_type: "A".into(), content: "93.184.216.34".into(), proxied: true };
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "A", "content": "93.184.216.34", "proxied": true}));
+    }
+
     #[test]
     fn test_validate_subdomain_valid() {
         assert!(validate_subdomain("alice").is_ok());
@@ -393,6 +523,14 @@ mod tests {
         assert_eq!(DnsRecordType::AAAA.as_str(), "AAAA");
     }
 
+    #[test]
+    fn test_dns_record_type_parse() {
+        assert_eq!(DnsRecordType::parse("cname"), Ok(DnsRecordType::CNAME));
+        assert_eq!(DnsRecordType::parse("A"), Ok(DnsRecordType::A));
+        assert_eq!(DnsRecordType::parse("aaaa"), Ok(DnsRecordType::AAAA));
+        assert!(DnsRecordType::parse("MX").is_err());
+    }
+
     #[test]
     fn test_dns_record_type_serde() {
         let json = serde_json::to_string(&DnsRecordType::CNAME).unwrap();
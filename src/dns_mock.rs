@@ -47,18 +47,21 @@ pub async fn delete_dns_record(_zone_id: &str, _token: &str, record_id: &str) ->
     Ok(())
 }
 
-/// Mock: Update a DNS record's content (logs operation)
-#[allow(dead_code)]
+/// Mock: Update a DNS record (logs operation)
 pub async fn update_dns_record(
     _zone_id: &str,
     _token: &str,
     record_id: &str,
+    record_type: &crate::dns::DnsRecordType,
     content: &str,
+    proxied: bool,
 ) -> Result<()> {
     console_log!(
-        "[MOCK DNS] update_dns_record: {} -> {}",
+        "[MOCK DNS] update_dns_record: {} {} -> {} (proxied={})",
         record_id,
-        content
+        record_type,
+        content,
+        proxied
     );
     Ok(())
 }
@@ -255,6 +255,136 @@ function extractMultipartBodies(contentType, body) {
 const DEBUG_COLORS = { debug: 0x9e9e9e, info: 0x5865f2, warn: 0xfee75c, error: 0xed4245 };
 const LEVEL_PRIORITY = { off: 0, error: 1, warn: 2, info: 3, debug: 4 };
 
+// X-Noscha-Signature value: t=<unix seconds>,v1=<hex HMAC-SHA256(secret, "<t>." + body)> (see src/webhook.rs)
+async function signatureHeader(secret, body) {
+  const t = Math.floor(Date.now() / 1000);
+  const enc = new TextEncoder();
+  const key = await crypto.subtle.importKey("raw", enc.encode(secret), { name: "HMAC", hash: "SHA-256" }, false, ["sign"]);
+  const mac = await crypto.subtle.sign("HMAC", key, enc.encode(`${t}.${body}`));
+  const hex = [...new Uint8Array(mac)].map((b) => b.toString(16).padStart(2, "0")).join("");
+  return `t=${t},v1=${hex}`;
+}
+
+// Webhook formats by URL, mirroring src/webhook_format.rs: recognized services get
+// their native message format, anything else the raw JSON payload (the only
+// format signed with X-Noscha-Signature)
+const WEBHOOK_FORMATS = [
+  ["discord", (u) => /(^|\.)(discord|discordapp)\.com$/.test(u.hostname) && u.pathname.startsWith("/api/webhooks/")],
+  ["slack", (u) => u.hostname === "hooks.slack.com" && u.pathname.startsWith("/services/")],
+  ["telegram", (u) => u.hostname === "api.telegram.org" && u.pathname.startsWith("/bot") && u.pathname.endsWith("/sendMessage")],
+  ["matrix", (u) => (u.hostname.includes("hookshot") || u.pathname.includes("/hookshot/")) && u.pathname.includes("/webhook/")],
+  ["ntfy", (u) => u.hostname === "ntfy.sh" || u.hostname.startsWith("ntfy.")],
+];
+
+function webhookFormat(url) {
+  let u;
+  try {
+    u = new URL(url);
+  } catch {
+    return "json";
+  }
+  const match = WEBHOOK_FORMATS.find(([, test]) => test(u));
+  return match ? match[0] : "json";
+}
+
+function escapeHtml(s) {
+  return String(s).replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;");
+}
+
+// Render n = { title, text, fields: [{name, value, inline}], link: {label, url}, data }
+// for the service behind url; returns { format, url, body }
+function renderNotification(url, n) {
+  const format = webhookFormat(url);
+  const lines = [n.text, ...n.fields.map((f) => `${f.name}: ${f.value}`)];
+  const html = (sep) => [
+    `<b>${escapeHtml(n.title)}</b>`,
+    escapeHtml(n.text),
+    ...n.fields.map((f) => `<b>${escapeHtml(f.name)}:</b> ${escapeHtml(f.value)}`),
+    ...(n.link ? [`<a href="${escapeHtml(n.link.url)}">${escapeHtml(n.link.label)}</a>`] : []),
+  ].join(sep);
+  const slack = (s) => String(s).replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;");
+
+  switch (format) {
+    case "discord": {
+      const fields = n.fields.map((f) => ({ name: f.name, value: f.value, inline: f.inline }));
+      if (n.link) fields.push({ name: "🔗 Link", value: `[${n.link.label}](${n.link.url})`, inline: false });
+      const embed = { title: n.title, description: n.text, color: n.color, fields, footer: { text: "noscha.io" } };
+      if (n.link) embed.url = n.link.url;
+      return { format, url, body: { embeds: [embed] } };
+    }
+    case "slack": {
+      const blocks = [
+        { type: "header", text: { type: "plain_text", text: n.title, emoji: true } },
+        { type: "section", text: { type: "mrkdwn", text: slack(n.text) } },
+      ];
+      if (n.fields.length) {
+        blocks.push({ type: "section", fields: n.fields.slice(0, 10).map((f) => ({ type: "mrkdwn", text: `*${slack(f.name)}*\n${slack(f.value)}` })) });
+      }
+      if (n.link) {
+        blocks.push({ type: "actions", elements: [{ type: "button", text: { type: "plain_text", text: n.link.label }, url: n.link.url }] });
+      }
+      return { format, url, body: { text: `${n.title}: ${n.text}`, blocks, unfurl_links: false, unfurl_media: false } };
+    }
+    case "telegram": {
+      const body = { text: html("\n"), parse_mode: "HTML", disable_web_page_preview: true };
+      const chatId = new URL(url).searchParams.get("chat_id");
+      if (chatId) body.chat_id = chatId;
+      return { format, url, body };
+    }
+    case "matrix": {
+      const text = [n.title, ...lines, ...(n.link ? [`${n.link.label}: ${n.link.url}`] : [])].join("\n");
+      return { format, url, body: { text, html: html("<br>"), username: "noscha.io" } };
+    }
+    case "ntfy": {
+      const u = new URL(url);
+      const body = { topic: u.pathname.replace(/^\/+|\/+$/g, ""), title: n.title, message: lines.join("\n") };
+      if (n.link) {
+        body.click = n.link.url;
+        body.actions = [{ action: "view", label: n.link.label, url: n.link.url }];
+      }
+      return { format, url: `${u.protocol}//${u.hostname}/`, body };
+    }
+    default:
+      return { format, url, body: n.data };
+  }
+}
+
+// "{prefix}_{base64url(12 random bytes)}", like ids::DELIVERY_ID
+function randomId(prefix) {
+  const bytes = crypto.getRandomValues(new Uint8Array(12));
+  const b64 = btoa(String.fromCharCode(...bytes)).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+  return `${prefix}_${b64}`;
+}
+
+// Store a delivery after its first attempt in the layout of webhook::deliver;
+// failures are queued for the cron to retry with backoff
+async function recordDelivery(env, delivery, attempt) {
+  delivery.attempts.push(attempt);
+  const ok = attempt.status_code >= 200 && attempt.status_code < 300;
+  delivery.status = ok ? "delivered" : "pending";
+  if (!ok) delivery.next_attempt_at = new Date(Date.now() + 5 * 60 * 1000).toISOString();
+  try {
+    await env.BUCKET.put(`deliveries/${delivery.username}/${delivery.id}.json`, JSON.stringify(delivery));
+    if (!ok) {
+      await env.BUCKET.put(`delivery_queue/${delivery.id}.json`, JSON.stringify({ username: delivery.username, id: delivery.id }));
+    }
+  } catch (e) {
+    console.error(`Failed to record webhook delivery: ${e.message}`);
+  }
+}
+
+// URLs receiving an event: webhook_url gets everything, extra endpoints only
+// their subscribed events (Rental::webhook_targets in src/types.rs)
+function webhookTargets(rental, event) {
+  const urls = rental.webhook_url ? [rental.webhook_url] : [];
+  for (const endpoint of rental.webhooks || []) {
+    if ((endpoint.events || []).includes(event) && !urls.includes(endpoint.url)) {
+      urls.push(endpoint.url);
+    }
+  }
+  return urls;
+}
+
 async function loadDebugWebhookConfig(env) {
   try {
     const obj = await env.BUCKET.get("config/debug_webhook.json");
@@ -313,7 +443,7 @@ async function debugLog(cfg, level, stage, msg, data = {}) {
 // Email event handler - called by Cloudflare Email Routing
 // Handles incoming emails via webhook notification
 export default {
-  async email(message, env, ctx) {
+  async email(message, env, ctx, dispatch) {
     const recipient = message.to;
     const username = recipient.split("@")[0].toLowerCase();
     const cfg = await loadDebugWebhookConfig(env);
@@ -402,62 +532,108 @@ export default {
 
     await debugLog(cfg, "info", "email_saved", "Email saved to R2", { username, mail_id: mailId, from: message.from, subject });
 
-    // Check if rental has webhook_url - if so, send notification
-    if (rental.webhook_url) {
+    const viewUrl = `https://${env.DOMAIN || "noscha.io"}/api/mail/${username}/${mailId}`;
+
+    // NIP-17 DM to the owner's NIP-05 pubkey; encrypted and signed by the Rust
+    // worker, which holds the service key (mirrors Rental::dm_recipient)
+    const nip05 = rental.services?.nip05;
+    const dmRecipient = rental.nostr_dm && nip05?.enabled && nip05.pubkey_hex;
+    if (dmRecipient && dispatch) {
       try {
-        const viewUrl = `https://${env.DOMAIN || "noscha.io"}/api/mail/${username}/${mailId}`;
-        const isDiscord = /discord\.com\/api\/webhooks|discordapp\.com\/api\/webhooks/i.test(rental.webhook_url);
-
-        let body;
-        if (isDiscord) {
-          body = JSON.stringify({
-            embeds: [{
-              title: "📧 New email",
-              description: `**From:** ${message.from}\n**Subject:** ${subject}`,
-              url: viewUrl,
-              color: 0x5865f2,
-              fields: [
-                { name: "To", value: recipient, inline: true },
-                { name: "View", value: `[Open](${viewUrl})`, inline: true },
-                { name: "Received", value: now.toISOString(), inline: false }
-              ],
-              timestamp: now.toISOString(),
-              footer: { text: "noscha.io" }
-            }]
-          });
-        } else {
-          body = JSON.stringify({
-            event: "email_received",
+        const dmRes = await dispatch(new Request(`https://${env.DOMAIN || "noscha.io"}/internal/nostr-dm/email`, {
+          method: "POST",
+          headers: { "Content-Type": "application/json" },
+          body: JSON.stringify({
             username,
             mail_id: mailId,
             from: message.from,
             to: recipient,
             subject,
-            date: now.toISOString(),
             view_url: viewUrl,
             received_at: now.toISOString()
+          }),
+        }));
+        const { sent } = await dmRes.json();
+        await debugLog(cfg, sent ? "info" : "warn", "nostr_dm", sent ? "Nostr DM sent" : "Nostr DM not sent", { username, mail_id: mailId });
+      } catch (e) {
+        await debugLog(cfg, "error", "nostr_dm_failed", `Nostr DM error: ${e.message}`, { username });
+      }
+    }
+
+    // Notify webhook_url and every endpoint subscribed to email_received
+    const targets = webhookTargets(rental, "email_received");
+    if (targets.length > 0) {
+      for (const webhookUrl of targets) {
+        try {
+          const rendered = renderNotification(webhookUrl, {
+            title: "📧 New email",
+            text: `From ${message.from}: ${subject}`,
+            color: 0x5865f2,
+            fields: [
+              { name: "From", value: message.from, inline: true },
+              { name: "To", value: recipient, inline: true },
+              { name: "Subject", value: subject || "(no subject)", inline: false },
+            ],
+            link: { label: "View email", url: viewUrl },
+            data: {
+              event: "email_received",
+              username,
+              mail_id: mailId,
+              from: message.from,
+              to: recipient,
+              subject,
+              date: now.toISOString(),
+              view_url: viewUrl,
+              received_at: now.toISOString()
+            },
           });
-        }
+          const body = JSON.stringify(rendered.body);
 
-        const webhookRes = await fetch(rental.webhook_url, {
-          method: "POST",
-          headers: { "Content-Type": "application/json" },
-          body,
-        });
-
-        if (!webhookRes.ok) {
-          await debugLog(cfg, "error", "webhook_failed", `Webhook notification failed: ${webhookRes.status}`, { username, status: webhookRes.status });
-          console.error(`Webhook notification failed: ${webhookRes.status}`);
-        } else {
-          await debugLog(cfg, "info", "webhook_sent", "Webhook notification sent", { username, mail_id: mailId });
+          const delivery = {
+            id: randomId("dlv"),
+            username,
+            event: "email_received",
+            url: rendered.url,
+            content_type: "application/json; charset=utf-8",
+            body,
+            signing_secret: rental.signing_secret || undefined,
+            status: "pending",
+            created_at: now.toISOString(),
+            attempts: [],
+          };
+          const attempt = { at: new Date().toISOString() };
+          try {
+            const headers = { "Content-Type": "application/json; charset=utf-8" };
+            if (rendered.format === "json" && rental.signing_secret) {
+              headers["X-Noscha-Signature"] = await signatureHeader(rental.signing_secret, body);
+            }
+            const webhookRes = await fetch(rendered.url, {
+              method: "POST",
+              headers,
+              body,
+            });
+            attempt.status_code = webhookRes.status;
+            if (!webhookRes.ok) attempt.error = (await webhookRes.text()).slice(0, 200);
+          } catch (e) {
+            attempt.error = e.message;
+          }
+          await recordDelivery(env, delivery, attempt);
+
+          if (delivery.status !== "delivered") {
+            await debugLog(cfg, "error", "webhook_failed", `Webhook notification failed: ${attempt.status_code || attempt.error}`, { username, status: attempt.status_code, delivery_id: delivery.id });
+            console.error(`Webhook notification failed, queued for retry: ${attempt.status_code || attempt.error}`);
+          } else {
+            await debugLog(cfg, "info", "webhook_sent", "Webhook notification sent", { username, mail_id: mailId });
+          }
+        } catch (e) {
+          await debugLog(cfg, "error", "webhook_failed", `Webhook notification error: ${e.message}`, { username });
+          console.error(`Webhook notification error: ${e.message}`);
         }
-      } catch (e) {
-        await debugLog(cfg, "error", "webhook_failed", `Webhook notification error: ${e.message}`, { username });
-        console.error(`Webhook notification error: ${e.message}`);
       }
 
-      return; // Done - webhook notified
+      return; // Done - webhooks notified
     }
+    if (dmRecipient) return; // Done - owner notified by DM only
 
     await debugLog(cfg, "warn", "no_webhook_configured", "No webhook configured for this address", { username });
     message.setReject("No webhook configured for this address");
@@ -0,0 +1,80 @@
+use sha2::{Digest, Sha256};
+
+const BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256 (RFC 2104)
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let inner_hash = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner_hash);
+    outer.finalize().into()
+}
+
+/// Lowercase hex HMAC-SHA256
+pub fn hmac_sha256_hex(key: &[u8], message: &[u8]) -> String {
+    hex::encode(hmac_sha256(key, message))
+}
+
+/// Check a hex HMAC-SHA256 signature in constant time
+pub fn verify_hmac_sha256_hex(key: &[u8], message: &[u8], signature_hex: &str) -> bool {
+    let expected = hmac_sha256(key, message);
+    match hex::decode(signature_hex.trim()) {
+        Ok(given) if given.len() == expected.len() => {
+            given.iter().zip(expected.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc4231_case_1() {
+        let key = [0x0b; 20];
+        assert_eq!(
+            hmac_sha256_hex(&key, b"Hi There"),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+    }
+
+    #[test]
+    fn test_rfc4231_case_2() {
+        assert_eq!(
+            hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_rfc4231_case_6_long_key() {
+        let key = [0xaa; 131];
+        assert_eq!(
+            hmac_sha256_hex(&key, b"Test Using Larger Than Block-Size Key - Hash Key First"),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_verify_hex() {
+        let sig = hmac_sha256_hex(b"k", b"body");
+        assert!(verify_hmac_sha256_hex(b"k", b"body", &sig));
+        assert!(verify_hmac_sha256_hex(b"k", b"body", &sig.to_uppercase()));
+        assert!(!verify_hmac_sha256_hex(b"k", b"body2", &sig));
+        assert!(!verify_hmac_sha256_hex(b"k", b"body", &sig[..62]));
+        assert!(!verify_hmac_sha256_hex(b"k", b"body", "zz"));
+    }
+}
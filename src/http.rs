@@ -0,0 +1,166 @@
+//! Minimal outbound HTTP abstraction so API clients can run against
+//! `worker::Fetch` on Workers and an in-process stand-in in native tests.
+
+/// Outbound HTTP request
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: &'static str,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+impl HttpRequest {
+    pub fn get(url: impl Into<String>) -> Self {
+        Self { method: "GET", url: url.into(), headers: Vec::new(), body: None }
+    }
+
+    pub fn post_json(url: impl Into<String>, body: &serde_json::Value) -> Self {
+        Self {
+            method: "POST",
+            url: url.into(),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: Some(body.to_string()),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Case-insensitive header lookup
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Response status and body text
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_str(&self.body).map_err(|e| format!("Invalid JSON response: {}", e))
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait HttpClient {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, String>;
+}
+
+/// Inbound request as seen by webhook parsers: full URL, headers and raw body
+#[derive(Debug, Clone, Default)]
+pub struct InboundRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl InboundRequest {
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// First query parameter with the given name (values are not percent-decoded)
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let query = self.url.split_once('?')?.1;
+        let query = query.split('#').next().unwrap_or(query);
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    }
+}
+
+/// HttpClient backed by the Workers fetch API
+#[cfg(target_arch = "wasm32")]
+pub struct FetchClient;
+
+#[cfg(target_arch = "wasm32")]
+impl HttpClient for FetchClient {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, String> {
+        use worker::{Fetch, Headers, Method, Request, RequestInit};
+
+        let headers = Headers::new();
+        headers
+            .set("User-Agent", "Mozilla/5.0 (compatible; noscha.io/0.1)")
+            .map_err(|e| e.to_string())?;
+        for (name, value) in &req.headers {
+            headers.set(name, value).map_err(|e| e.to_string())?;
+        }
+        let method = match req.method {
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            _ => Method::Get,
+        };
+        let mut init = RequestInit::new();
+        init.with_method(method).with_headers(headers);
+        if let Some(body) = req.body {
+            init.with_body(Some(body.into()));
+        }
+        let request = Request::new_with_init(&req.url, &init).map_err(|e| e.to_string())?;
+        let mut response = Fetch::Request(request).send().await.map_err(|e| e.to_string())?;
+        Ok(HttpResponse {
+            status: response.status_code(),
+            body: response.text().await.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl InboundRequest {
+    /// Capture URL, headers and body of an incoming worker request
+    pub async fn from_worker(req: &mut worker::Request) -> worker::Result<Self> {
+        Ok(Self {
+            url: req.url()?.to_string(),
+            headers: req.headers().entries().collect(),
+            body: req.text().await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inbound_query_and_header() {
+        let req = InboundRequest {
+            url: "https://noscha.io/api/webhook/lnbits?secret=sec_abc&x=1#frag".into(),
+            headers: vec![("BTCPay-Sig".into(), "sha256=00".into())],
+            body: String::new(),
+        };
+        assert_eq!(req.query("secret"), Some("sec_abc"));
+        assert_eq!(req.query("x"), Some("1"));
+        assert_eq!(req.query("missing"), None);
+        assert_eq!(req.header("btcpay-sig"), Some("sha256=00"));
+    }
+
+    #[test]
+    fn test_request_builders() {
+        let req = HttpRequest::post_json("https://example.com", &serde_json::json!({"a": 1}))
+            .header("X-Api-Key", "k");
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.header_value("content-type"), Some("application/json"));
+        assert_eq!(req.header_value("x-api-key"), Some("k"));
+        assert_eq!(req.body.as_deref(), Some(r#"{"a":1}"#));
+    }
+}
@@ -0,0 +1,235 @@
+//! Prefixed, URL-safe random identifiers.
+//! Randomness comes from `crypto.getRandomValues` on Workers; native builds
+//! (tests and tooling) use a per-thread seeded generator that tests can reseed.
+
+/// Prefix and entropy for one kind of identifier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdSpec {
+    pub prefix: &'static str,
+    pub entropy_bytes: usize,
+}
+
+pub const ORDER_ID: IdSpec = IdSpec { prefix: "ord", entropy_bytes: 12 };
+pub const WEBHOOK_SECRET: IdSpec = IdSpec { prefix: "sec", entropy_bytes: 24 };
+pub const MANAGEMENT_TOKEN: IdSpec = IdSpec { prefix: "mgmt", entropy_bytes: 24 };
+pub const SESSION_TOKEN: IdSpec = IdSpec { prefix: "sess", entropy_bytes: 32 };
+pub const CHALLENGE: IdSpec = IdSpec { prefix: "ch", entropy_bytes: 16 };
+pub const SIGNING_SECRET: IdSpec = IdSpec { prefix: "whsec", entropy_bytes: 32 };
+pub const DELIVERY_ID: IdSpec = IdSpec { prefix: "dlv", entropy_bytes: 12 };
+
+impl IdSpec {
+    /// Same prefix with a different amount of entropy
+    pub const fn with_entropy(self, entropy_bytes: usize) -> Self {
+        Self { prefix: self.prefix, entropy_bytes }
+    }
+
+    /// Generate "{prefix}_{base64url(random bytes)}"
+    pub fn generate(&self) -> String {
+        let mut bytes = vec![0u8; self.entropy_bytes.max(1)];
+        fill_random(&mut bytes);
+        format!("{}_{}", self.prefix, base64url(&bytes))
+    }
+
+    /// Length of the encoded random part produced by `generate`
+    pub fn encoded_len(&self) -> usize {
+        (self.entropy_bytes.max(1) * 8).div_ceil(6)
+    }
+}
+
+pub fn order_id() -> String {
+    ORDER_ID.generate()
+}
+
+pub fn webhook_secret() -> String {
+    WEBHOOK_SECRET.generate()
+}
+
+pub fn management_token() -> String {
+    MANAGEMENT_TOKEN.generate()
+}
+
+pub fn session_token() -> String {
+    SESSION_TOKEN.generate()
+}
+
+pub fn challenge() -> String {
+    CHALLENGE.generate()
+}
+
+pub fn signing_secret() -> String {
+    SIGNING_SECRET.generate()
+}
+
+pub fn delivery_id() -> String {
+    DELIVERY_ID.generate()
+}
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Unpadded base64url (RFC 4648 §5)
+pub fn base64url(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        let chars = chunk.len() + 1;
+        for i in 0..chars {
+            out.push(BASE64URL[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Padded standard base64 (RFC 4648 §4)
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard base64; trailing `=` padding is optional
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let data = s.trim_end_matches('=').as_bytes();
+    if s.len() - data.len() > 2 || data.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for &c in chunk {
+            n = (n << 6) | BASE64.iter().position(|&b| b == c)? as u32;
+        }
+        n <<= 6 * (4 - chunk.len()) as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+/// Fill buf from crypto.getRandomValues
+#[cfg(target_arch = "wasm32")]
+pub fn fill_random(buf: &mut [u8]) {
+    use worker::wasm_bindgen::JsCast;
+
+    let crypto = js_sys::Reflect::get(&js_sys::global(), &"crypto".into())
+        .expect("crypto is not available");
+    let get_random_values: js_sys::Function = js_sys::Reflect::get(&crypto, &"getRandomValues".into())
+        .expect("crypto.getRandomValues is not available")
+        .unchecked_into();
+    // getRandomValues accepts at most 65536 bytes per call
+    for chunk in buf.chunks_mut(65536) {
+        let array = js_sys::Uint8Array::new_with_length(chunk.len() as u32);
+        get_random_values
+            .call1(&crypto, &array)
+            .expect("crypto.getRandomValues failed");
+        array.copy_to(chunk);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    static NATIVE_RNG: std::cell::Cell<u64> = std::cell::Cell::new(native_seed());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn native_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(0x6e6f73636861);
+    hasher.finish()
+}
+
+/// Reseed this thread's native generator so generated ids are reproducible
+#[cfg(not(target_arch = "wasm32"))]
+pub fn seed(seed: u64) {
+    NATIVE_RNG.with(|state| state.set(seed));
+}
+
+/// Fill buf from the native SplitMix64 generator
+#[cfg(not(target_arch = "wasm32"))]
+pub fn fill_random(buf: &mut [u8]) {
+    NATIVE_RNG.with(|state| {
+        for chunk in buf.chunks_mut(8) {
+            let mut z = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+            state.set(z);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64url_rfc4648_vectors() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64url(&[0xfb, 0xff, 0xbf]), "-_-_");
+    }
+
+    #[test]
+    fn test_base64_standard() {
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+        for input in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(base64_decode(&base64(input)).unwrap(), input);
+            assert_eq!(base64_decode(base64(input).trim_end_matches('=')).unwrap(), input);
+        }
+        assert!(base64_decode("Z===").is_none());
+        assert!(base64_decode("Zm9v!").is_none());
+    }
+
+    #[test]
+    fn test_generate_format() {
+        for spec in [ORDER_ID, WEBHOOK_SECRET, MANAGEMENT_TOKEN, SESSION_TOKEN, CHALLENGE, SIGNING_SECRET, DELIVERY_ID] {
+            let id = spec.generate();
+            let (prefix, random) = id.split_at(spec.prefix.len() + 1);
+            assert_eq!(prefix, format!("{}_", spec.prefix));
+            assert_eq!(random.len(), spec.encoded_len());
+            assert!(random.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'));
+        }
+    }
+
+    #[test]
+    fn test_configurable_entropy() {
+        let short = ORDER_ID.with_entropy(3);
+        assert_eq!(short.encoded_len(), 4);
+        assert_eq!(short.generate().len(), "ord_".len() + 4);
+        assert_eq!(MANAGEMENT_TOKEN.encoded_len(), 32);
+    }
+
+    #[test]
+    fn test_seeded_generation_is_reproducible() {
+        seed(42);
+        let a = (order_id(), management_token());
+        seed(42);
+        let b = (order_id(), management_token());
+        assert_eq!(a, b);
+        assert_ne!(a.0, order_id());
+    }
+
+    #[test]
+    fn test_ids_do_not_collide() {
+        seed(7);
+        let ids: std::collections::HashSet<String> = (0..1000).map(|_| webhook_secret()).collect();
+        assert_eq!(ids.len(), 1000);
+    }
+}
@@ -1,15 +1,28 @@
 pub mod admin;
+pub mod btcpay;
+pub mod coinos;
 pub mod dns;
 pub mod email;
+pub mod hmac;
+pub mod http;
+pub mod ids;
+pub mod lnbits;
+pub mod lnurl;
 pub mod nip05;
+pub mod nip17;
+pub mod nip19;
+pub mod nip44;
+pub mod nostr;
+pub mod payment;
+pub mod service;
+pub mod store;
 pub mod types;
 pub mod ui;
+pub mod url_policy;
 pub mod validation;
+pub mod webhook;
+pub mod webhook_format;
 
-#[cfg(target_arch = "wasm32")]
-mod coinos;
-#[cfg(target_arch = "wasm32")]
-mod coinos_mock;
 #[cfg(target_arch = "wasm32")]
 mod dns_mock;
 #[cfg(target_arch = "wasm32")]
@@ -21,20 +34,28 @@ use worker::*;
 
 #[cfg(target_arch = "wasm32")]
 use admin::{
-    handle_admin_ban, handle_admin_challenge, handle_admin_debug_webhook_get,
-    handle_admin_debug_webhook_put, handle_admin_extend, handle_admin_login,
+    handle_admin_backfill_tokens, handle_admin_ban, handle_admin_challenge, handle_admin_debug_webhook_get,
+    handle_admin_debug_webhook_put, handle_admin_extend, handle_admin_login, handle_admin_order_status,
     handle_admin_page, handle_admin_pricing_get, handle_admin_pricing_put,
-    handle_admin_rentals, handle_admin_rental_webhook_put, handle_admin_provision, handle_admin_revoke, handle_admin_stats, handle_admin_unban,
+    handle_admin_rentals, handle_admin_rental_webhook_put, handle_admin_webhook_deliveries, handle_admin_webhook_replay, handle_admin_provision, handle_admin_revoke, handle_admin_rotate_token, handle_admin_stats, handle_admin_unban,
     handle_public_pricing,
 };
 #[cfg(target_arch = "wasm32")]
-use dns::DnsRecordType;
+use dns::CloudflareDns;
+#[cfg(target_arch = "wasm32")]
+use http::HttpClient;
 #[cfg(target_arch = "wasm32")]
 use nip05::{handle_nip05, handle_nip05_options};
 #[cfg(target_arch = "wasm32")]
+use payment::{InvoiceRequest, PaymentProvider};
+#[cfg(target_arch = "wasm32")]
+use service::{PaymentResult, Provisioned};
+#[cfg(target_arch = "wasm32")]
+use store::{R2Store, Store};
+#[cfg(target_arch = "wasm32")]
 use types::*;
 #[cfg(target_arch = "wasm32")]
-use validation::validate_username;
+use webhook_format::{Notification, WebhookFormat};
 
 #[cfg(target_arch = "wasm32")]
 #[derive(Serialize)]
@@ -66,45 +87,25 @@ struct HealthResponse {
     version: &'static str,
 }
 
-/// Extract ServiceType list from OrderServicesRequest
+/// POST an operator notification once, unsigned and unrecorded (best effort)
 #[cfg(target_arch = "wasm32")]
-fn services_from_request(services: &Option<OrderServicesRequest>) -> Vec<types::ServiceType> {
-    let mut result = Vec::new();
-    if let Some(ref svc) = services {
-        if svc.subdomain.is_some() {
-            result.push(types::ServiceType::Subdomain);
-        }
-        if svc.email.is_some() {
-            result.push(types::ServiceType::EmailForwarding);
-        }
-        if svc.nip05.is_some() {
-            result.push(types::ServiceType::Nip05);
-        }
-    }
-    result
+async fn post_webhook(url: &str, body: &serde_json::Value) {
+    let now_secs = (js_sys::Date::now() / 1000.0) as u64;
+    let req = webhook::build_request(url, "application/json; charset=utf-8", body.to_string(), None, now_secs);
+    let _ = http::FetchClient.send(req).await;
 }
 
-/// Extract ServiceType list from an existing Rental's services
+/// Record a rental webhook and send it; failed deliveries are retried from the cron
 #[cfg(target_arch = "wasm32")]
-fn services_from_rental(services: &RentalServices) -> Vec<types::ServiceType> {
-    let mut result = Vec::new();
-    if services.subdomain.as_ref().map_or(false, |s| s.enabled) {
-        result.push(types::ServiceType::Subdomain);
-    }
-    if services.email.as_ref().map_or(false, |s| s.enabled) {
-        result.push(types::ServiceType::EmailForwarding);
-    }
-    if services.nip05.as_ref().map_or(false, |s| s.enabled) {
-        result.push(types::ServiceType::Nip05);
+async fn deliver_webhook(env: &Env, webhook: webhook::OutboundWebhook) {
+    let now_ms = js_sys::Date::now() as u64;
+    let result = match R2Store::from_env(env) {
+        Ok(store) => webhook::deliver(&store, &http::FetchClient, webhook, ids::delivery_id(), now_ms).await,
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        console_log!("Failed to record webhook delivery: {}", e);
     }
-    result
-}
-
-/// Generate a simple order ID using timestamp
-#[cfg(target_arch = "wasm32")]
-fn generate_order_id() -> String {
-    let now = js_sys::Date::now() as u64;
-    format!("ord_{:x}", now)
 }
 
 /// Send Discord webhook notification for a paid order (best effort)
@@ -131,6 +132,7 @@ async fn send_discord_notification(env: &Env, order: &Order) {
         if req.email.is_some() { services.push("📧 Email"); }
         if req.subdomain.is_some() { services.push("🌐 Subdomain"); }
         if req.nip05.is_some() { services.push("🔑 NIP-05"); }
+        if req.lightning_address.is_some() { services.push("⚡ Lightning Address"); }
     }
     let services_str = if services.is_empty() {
         "None".to_string()
@@ -138,9 +140,10 @@ async fn send_discord_notification(env: &Env, order: &Order) {
         services.join(", ")
     };
 
-    let is_renewal = order.renewal_for.is_some();
-    let title = if is_renewal {
+    let title = if order.renewal_for.is_some() {
         format!("🔄 Renewal Payment Received - {}", order.username)
+    } else if order.upgrade_for.is_some() {
+        format!("➕ Upgrade Payment Received - {}", order.username)
     } else {
         format!("⚡ New Payment Received - {}", order.username)
     };
@@ -160,98 +163,190 @@ async fn send_discord_notification(env: &Env, order: &Order) {
         }]
     });
 
-    let headers = Headers::new();
-    let _ = headers.set("Content-Type", "application/json; charset=utf-8");
-    let req = Request::new_with_init(
-        &webhook_url,
-        RequestInit::new()
-            .with_method(Method::Post)
-            .with_headers(headers)
-            .with_body(Some(wasm_bindgen::JsValue::from_str(&body.to_string()))),
-    );
-    if let Ok(r) = req {
-        let _ = Fetch::Request(r).send().await;
-    }
+    post_webhook(&webhook_url, &body).await;
 }
 
-/// Send payment_completed webhook to user's webhook_url (best effort)
+/// Base URL of the worker from DOMAIN
 #[cfg(target_arch = "wasm32")]
-async fn send_payment_completed_webhook(
-    env: &Env,
-    webhook_url: &str,
-    order: &Order,
-    username: &str,
-    management_token: &str,
-    expires_at: &str,
-    services: &RentalServices,
-    is_renewal: bool,
-) {
-    let domain = env
-        .var("DOMAIN")
+fn domain(env: &Env) -> String {
+    env.var("DOMAIN")
         .map(|v| v.to_string())
-        .unwrap_or_else(|_| "noscha.io".to_string());
-    let my_page_url = format!("https://{}/my/{}", domain, management_token);
+        .unwrap_or_else(|_| "noscha.io".to_string())
+}
 
-    let services_json = serde_json::json!({
-        "email": services.email.as_ref().map(|e| e.enabled).unwrap_or(false),
-        "subdomain": services.subdomain.is_some(),
-        "nip05": services.nip05.is_some(),
-    });
+/// Deliver a notification to each URL, rendered for the service behind it
+#[cfg(target_arch = "wasm32")]
+async fn notify_rental(env: &Env, rental: &Rental, urls: Vec<&str>, notification: &Notification) {
+    for url in urls {
+        let webhook = webhook::OutboundWebhook::notification(
+            &rental.username,
+            url,
+            notification,
+            rental.signing_secret.as_deref(),
+        );
+        deliver_webhook(env, webhook).await;
+    }
+}
 
-    let body = if webhook_url.to_lowercase().contains("discord.com/api/webhooks")
-        || webhook_url.to_lowercase().contains("discordapp.com/api/webhooks")
-    {
-        let plan_key = order.plan.period_key();
-        serde_json::json!({
-            "embeds": [{
-                "title": "⚡ Payment Complete",
-                "description": format!("**{}** — {}", username, plan_key),
-                "url": my_page_url,
-                "color": 0x00ff88,
-                "fields": [
-                    {"name": "👤 Username", "value": username, "inline": true},
-                    {"name": "🗓️ Plan", "value": plan_key, "inline": true},
-                    {"name": "💰 Amount", "value": format!("{} sats", order.amount_sats), "inline": true},
-                    {"name": "🔗 My Page", "value": format!("[Open]({})", my_page_url), "inline": false},
-                    {"name": "📅 Expires", "value": expires_at, "inline": false}
-                ],
-                "footer": {"text": "noscha.io"}
-            }]
-        })
+/// Service key that signs NIP-17 DMs, from NOSTR_SERVICE_KEY (hex secret key)
+#[cfg(target_arch = "wasm32")]
+fn nostr_service_keys(env: &Env) -> Option<nip17::Keys> {
+    let secret = env.secret("NOSTR_SERVICE_KEY").ok()?.to_string();
+    match nip17::Keys::from_hex(&secret) {
+        Ok(keys) => Some(keys),
+        Err(e) => {
+            console_log!("NOSTR_SERVICE_KEY is invalid: {}", e);
+            None
+        }
+    }
+}
+
+/// Send a notification as a NIP-17 DM to a hex pubkey via the NOSTR_RELAYS relays
+/// (best effort; skipped unless NOSTR_SERVICE_KEY is set)
+#[cfg(target_arch = "wasm32")]
+async fn send_nostr_dm(env: &Env, recipient: &str, notification: &Notification) -> bool {
+    let Some(keys) = nostr_service_keys(env) else {
+        return false;
+    };
+    let relays = nip17::parse_relays(env.var("NOSTR_RELAYS").ok().map(|v| v.to_string()).as_deref());
+    let now_secs = (js_sys::Date::now() / 1000.0) as u64;
+    let text = notification.plain_text();
+    match nip17::send_dm(&nip17::WebSocketRelay, &relays, &keys, recipient, &text, now_secs).await {
+        Ok(accepted) => {
+            console_log!("{} DM published to {}", notification.event, accepted.join(", "));
+            true
+        }
+        Err(e) => {
+            console_log!("{} DM failed: {}", notification.event, e);
+            false
+        }
+    }
+}
+
+/// DM the rental owner if they opted in to NIP-17 notifications
+#[cfg(target_arch = "wasm32")]
+async fn dm_rental(env: &Env, rental: &Rental, notification: &Notification) {
+    if let Some(pubkey) = rental.dm_recipient() {
+        send_nostr_dm(env, pubkey, notification).await;
+    }
+}
+
+/// Send payment_completed webhook to the rental's subscribed endpoints (renewals
+/// also reach `renewed` subscribers), retried from the cron on failure
+#[cfg(target_arch = "wasm32")]
+async fn send_payment_completed_webhook(env: &Env, order: &Order, rental: &Rental, is_renewal: bool) {
+    let Some(management_token) = rental.management_token.as_deref() else {
+        return;
+    };
+    let my_page_url = format!("https://{}/my/{}", domain(env), management_token);
+    let notification = Notification::payment_completed(order, rental, management_token, &my_page_url, is_renewal);
+    let events: &[WebhookEvent] = if is_renewal {
+        &[WebhookEvent::PaymentCompleted, WebhookEvent::Renewed]
     } else {
-        serde_json::json!({
-            "event": "payment_completed",
-            "order_id": order.order_id,
-            "username": username,
-            "management_token": management_token,
-            "my_page_url": my_page_url,
-            "expires_at": expires_at,
-            "plan": order.plan.period_key(),
-            "amount_sats": order.amount_sats,
-            "is_renewal": is_renewal,
-            "services": services_json,
-        })
+        &[WebhookEvent::PaymentCompleted]
     };
+    notify_rental(env, rental, rental.webhook_targets(events), &notification).await;
+    dm_rental(env, rental, &notification).await;
+}
 
-    let headers = Headers::new();
-    let _ = headers.set("Content-Type", "application/json; charset=utf-8");
-    let req = Request::new_with_init(
-        webhook_url,
-        RequestInit::new()
-            .with_method(Method::Post)
-            .with_headers(headers)
-            .with_body(Some(wasm_bindgen::JsValue::from_str(&body.to_string()))),
+/// Tell the rental's token_rotated subscribers (and DM opt-ins) that the management
+/// token changed; the new token is only in the returned rotation response
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn token_rotated(env: &Env, rental: &Rental, rotated_by: &str) -> TokenRotateResponse {
+    let management_token = rental.management_token.clone().unwrap_or_default();
+    let my_page_url = format!("https://{}/my/{}", domain(env), management_token);
+    let notification = Notification::token_rotated(rental, rotated_by);
+    notify_rental(env, rental, rental.webhook_targets(&[WebhookEvent::TokenRotated]), &notification).await;
+    dm_rental(env, rental, &notification).await;
+    TokenRotateResponse { success: true, management_token, my_page_url }
+}
+
+/// Send grace_period_started webhook to the rental's expiring_soon subscribers, retried
+/// from the cron on failure
+#[cfg(target_arch = "wasm32")]
+async fn send_grace_period_webhook(env: &Env, rental: &Rental) {
+    let (Some(management_token), Some(grace_until)) =
+        (rental.management_token.as_deref(), rental.grace_until.as_deref())
+    else {
+        return;
+    };
+    let my_page_url = format!("https://{}/my/{}", domain(env), management_token);
+    let notification = Notification::grace_period_started(rental, grace_until, &my_page_url);
+    notify_rental(env, rental, rental.webhook_targets(&[WebhookEvent::ExpiringSoon]), &notification).await;
+    dm_rental(env, rental, &notification).await;
+}
+
+/// Send expiring_soon webhook to the rental's subscribed endpoints, retried from the cron on failure
+#[cfg(target_arch = "wasm32")]
+async fn send_expiring_soon_webhook(env: &Env, reminder: &service::ExpiryReminder, now_ms: u64) {
+    let rental = &reminder.rental;
+    let Some(management_token) = rental.management_token.as_deref() else {
+        return;
+    };
+    let my_page_url = format!("https://{}/my/{}", domain(env), management_token);
+    let minutes_remaining = ms_from_iso(&rental.expires_at)
+        .map(|e| e.saturating_sub(now_ms).div_ceil(60 * 1000))
+        .unwrap_or(0);
+    let notification = Notification::expiring_soon(
+        rental,
+        reminder.threshold_minutes,
+        &minutes_to_label(reminder.threshold_minutes),
+        minutes_remaining,
+        &my_page_url,
     );
-    if let Ok(r) = req {
-        let _ = Fetch::Request(r).send().await;
-    }
+    notify_rental(env, rental, rental.webhook_targets(&[WebhookEvent::ExpiringSoon]), &notification).await;
+    dm_rental(env, rental, &notification).await;
 }
 
-/// Generate a webhook secret for order verification
+/// Send dns_changed webhook after the subdomain record was removed at expiry or
+/// restored by a renewal, retried from the cron on failure
 #[cfg(target_arch = "wasm32")]
-fn generate_webhook_secret() -> String {
-    let now = js_sys::Date::now() as u64;
-    format!("sec_{:x}", now)
+async fn send_dns_changed_webhook(env: &Env, rental: &Rental, action: &str) {
+    let hostname = format!("{}.{}", rental.username, domain(env));
+    let Some(notification) = Notification::dns_changed(rental, &hostname, action) else {
+        return;
+    };
+    notify_rental(env, rental, rental.webhook_targets(&[WebhookEvent::DnsChanged]), &notification).await;
+}
+
+/// Send settings_changed webhook to the rental's subscribed endpoints
+#[cfg(target_arch = "wasm32")]
+async fn send_settings_changed_webhook(env: &Env, rental: &Rental, changed: &[&str]) {
+    let management_token = rental.management_token.as_deref().unwrap_or_default();
+    let my_page_url = format!("https://{}/my/{}", domain(env), management_token);
+    let notification = Notification::settings_changed(rental, changed, &my_page_url);
+    notify_rental(env, rental, rental.webhook_targets(&[WebhookEvent::SettingsChanged]), &notification).await;
+}
+
+/// Grace period in minutes from GRACE_PERIOD_MINUTES
+#[cfg(target_arch = "wasm32")]
+fn grace_minutes(env: &Env) -> u64 {
+    let value = env.var("GRACE_PERIOD_MINUTES").ok().map(|v| v.to_string());
+    service::grace_minutes_from(value.as_deref())
+}
+
+/// Webhook URL policy; HTTPS is required unless ALLOW_HTTP_WEBHOOKS is "true"
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn url_policy_from_env(env: &Env) -> url_policy::UrlPolicy {
+    let value = env.var("ALLOW_HTTP_WEBHOOKS").ok().map(|v| v.to_string());
+    url_policy::UrlPolicy::from_allow_http(value.as_deref())
+}
+
+/// POST /internal/nostr-dm/email — called by the JS email handler (never routed
+/// from outside; see worker-entry.mjs) to DM the owner about a stored email
+#[cfg(target_arch = "wasm32")]
+async fn handle_internal_email_dm(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let notice: EmailReceivedNotice = match req.json().await {
+        Ok(n) => n,
+        Err(_) => return Response::error("Invalid request body", 400),
+    };
+    let store = R2Store::from_env(&ctx.env)?;
+    let rental = store.get_rental(&notice.username).await.map_err(Error::RustError)?;
+    let sent = match rental.as_ref().and_then(|r| r.dm_recipient()) {
+        Some(pubkey) => send_nostr_dm(&ctx.env, pubkey, &Notification::email_received(&notice)).await,
+        None => false,
+    };
+    Response::from_json(&serde_json::json!({ "sent": sent }))
 }
 
 /// GET /api/check/{username}
@@ -261,51 +356,12 @@ async fn handle_check_username(
     ctx: RouteContext<()>,
 ) -> Result<Response> {
     let username = ctx.param("username").unwrap();
-
-    if let Err(err) = validate_username(username) {
-        return Response::from_json(&CheckUsernameResponse {
-            available: false,
-            username: username.to_string(),
-            error: Some(err),
-        });
-    }
-
-    // Check R2 for existing active rental
-    let bucket = ctx.env.bucket("BUCKET")?;
-
-    // Check if username is banned
-    if admin::is_banned(&bucket, username).await {
-        return Response::from_json(&CheckUsernameResponse {
-            available: false,
-            username: username.to_string(),
-            error: Some("This username is blocked".to_string()),
-        });
-    }
-
-    let key = format!("rentals/{}.json", username);
-    let existing = bucket.get(&key).execute().await?;
-
-    let available = match existing {
-        None => true,
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            match serde_json::from_str::<Rental>(&text) {
-                Ok(rental) => {
-                    let now_ms = js_sys::Date::now();
-                    let expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-                    expires_date.get_time() <= now_ms
-                }
-                Err(_) => false,
-            }
-        }
-    };
-
-    Response::from_json(&CheckUsernameResponse {
-        available,
-        username: username.to_string(),
-        error: None,
-    })
+    let store = R2Store::from_env(&ctx.env)?;
+    let now_ms = js_sys::Date::now() as u64;
+    let resp = service::check_username(&store, username, grace_minutes(&ctx.env), now_ms)
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&resp)
 }
 
 /// POST /api/order
@@ -319,133 +375,62 @@ async fn handle_create_order(
         Err(_) => return Response::error("Invalid request body", 400),
     };
 
-    // Validate username
-    if let Err(err) = validate_username(&body.username) {
-        return Response::error(err, 400);
-    }
-
-    // Validate webhook_url
-    if body.webhook_url.is_empty() {
-        return Response::error("webhook_url is required", 400);
-    }
-    if !body.webhook_url.starts_with("https://") && !body.webhook_url.starts_with("http://") {
-        return Response::error("webhook_url must be a valid HTTP(S) URL", 400);
-    }
-
-    // Check availability
-    let bucket = ctx.env.bucket("BUCKET")?;
-
-    // Check if username is banned
-    if admin::is_banned(&bucket, &body.username).await {
-        return Response::error("This username is blocked", 403);
-    }
-
-    let rental_key = format!("rentals/{}.json", body.username);
-    if bucket.get(&rental_key).execute().await?.is_some() {
-        return Response::error("Username is already taken", 409);
-    }
-
-    let order_id = generate_order_id();
-    let service_types = services_from_request(&body.services);
-    let pricing = admin::load_pricing(&bucket).await;
-    let amount_sats = Plan::calculate_total_dynamic(&body.plan, &service_types, &pricing);
+    let store = R2Store::from_env(&ctx.env)?;
     let domain = ctx
         .env
         .var("DOMAIN")
         .map(|v| v.to_string())
         .unwrap_or_else(|_| "noscha.io".to_string());
+    let webhook_url = body.webhook_url.clone();
+    let is_browser_flow = body.browser_flow == Some(true);
 
     // Generate webhook challenge token
-    let challenge = format!("ch_{:x}_{:x}", js_sys::Date::now() as u64, (js_sys::Math::random() * 1e12) as u64);
-
-    // Calculate expiry (15 min)
-    let now = js_sys::Date::now();
-    let expires_ms = now + 15.0 * 60.0 * 1000.0;
-    let created_at = js_sys::Date::new_0();
-    let expires_at = js_sys::Date::new(&(expires_ms.into()));
-
-    let order = Order {
-        order_id: order_id.clone(),
-        username: body.username.clone(),
-        plan: body.plan,
-        amount_sats,
-        bolt11: String::new(),
-        status: OrderStatus::WebhookPending,
-        created_at: created_at.to_iso_string().as_string().unwrap_or_default(),
-        expires_at: expires_at.to_iso_string().as_string().unwrap_or_default(),
-        coinos_invoice_hash: None,
-        webhook_secret: None,
-        services_requested: body.services,
-        management_token: None,
-        renewal_for: None,
-        webhook_url: Some(body.webhook_url.clone()),
-        webhook_challenge: Some(challenge.clone()),
+    let challenge = ids::challenge();
+    let now_ms = js_sys::Date::now() as u64;
+    let order = match service::create_order(
+        &store,
+        body,
+        ids::order_id(),
+        challenge.clone(),
+        ids::signing_secret(),
+        grace_minutes(&ctx.env),
+        url_policy_from_env(&ctx.env),
+        now_ms,
+    )
+    .await
+    {
+        Ok(o) => o,
+        Err(e) => return e.into_response(),
     };
-
-    // Save order to R2
-    let order_key = format!("orders/{}.json", order_id);
-    let order_json =
-        serde_json::to_string(&order).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&order_key, order_json).execute().await?;
+    let order_id = order.order_id.clone();
 
     // Send challenge to webhook_url (best effort)
     let challenge_url = format!("https://{}/api/order/{}/confirm/{}", domain, order_id, challenge);
-    let is_browser_flow = body.browser_flow == Some(true);
 
-    if is_browser_flow {
-        // Browser flow: Discord expects JSON, others get plain text
-        let webhook_lower = body.webhook_url.to_lowercase();
-        let is_discord = webhook_lower.contains("discord.com/api/webhooks")
-            || webhook_lower.contains("discordapp.com/api/webhooks");
-
-        let (content_type, body_str) = if is_discord {
-            // Wrap URL in angle brackets to suppress Discord link preview (prevents Discord from crawling the URL before the user)
-            let content = format!("<{}>", challenge_url);
-            (
-                "application/json; charset=utf-8",
-                serde_json::json!({ "content": content }).to_string(),
-            )
-        } else {
-            ("text/plain; charset=utf-8", challenge_url.clone())
-        };
-
-        let headers = Headers::new();
-        let _ = headers.set("Content-Type", content_type);
-        let challenge_req = Request::new_with_init(
-            &body.webhook_url,
-            RequestInit::new()
-                .with_method(Method::Post)
-                .with_headers(headers)
-                .with_body(Some(wasm_bindgen::JsValue::from_str(&body_str))),
-        );
-        if let Ok(r) = challenge_req {
-            let _ = Fetch::Request(r).send().await;
+    let notification = Notification::webhook_challenge(&order_id, &order.username, &challenge_url);
+    let webhook = if is_browser_flow && WebhookFormat::detect(&webhook_url) == WebhookFormat::Json {
+        // Browser flow: the renter's own endpoint just gets the URL to open
+        webhook::OutboundWebhook {
+            username: order.username.clone(),
+            event: "webhook_challenge".to_string(),
+            url: webhook_url.clone(),
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: challenge_url.clone(),
+            signing_secret: order.signing_secret.clone(),
+            order_id: Some(order_id.clone()),
         }
     } else {
-        // API flow: send JSON
-        let inner = serde_json::json!({
-            "event": "webhook_challenge",
-            "challenge_url": challenge_url,
-            "order_id": order_id,
-        });
-        let challenge_body = serde_json::json!({
-            "content": inner.to_string(),
-            "event": "webhook_challenge",
-            "challenge_url": challenge_url,
-            "order_id": order_id,
-        });
-        let headers = Headers::new();
-        let _ = headers.set("Content-Type", "application/json; charset=utf-8");
-        let challenge_req = Request::new_with_init(
-            &body.webhook_url,
-            RequestInit::new()
-                .with_method(Method::Post)
-                .with_headers(headers)
-                .with_body(Some(wasm_bindgen::JsValue::from_str(&challenge_body.to_string()))),
-        );
-        if let Ok(r) = challenge_req {
-            let _ = Fetch::Request(r).send().await;
-        }
+        webhook::OutboundWebhook::notification(
+            &order.username,
+            &webhook_url,
+            &notification,
+            order.signing_secret.as_deref(),
+        )
+        .for_order(&order_id)
+    };
+    deliver_webhook(&ctx.env, webhook).await;
+    if let Some(pubkey) = order.dm_recipient() {
+        send_nostr_dm(&ctx.env, pubkey, &notification).await;
     }
 
     Response::from_json(&OrderResponse {
@@ -457,6 +442,7 @@ async fn handle_create_order(
         status: Some(OrderStatus::WebhookPending),
         message: Some("Check your webhook for the challenge URL. Visit it to confirm and get an invoice.".to_string()),
         challenge_url: None,
+        webhook_signing_secret: order.signing_secret,
     })
 }
 
@@ -469,30 +455,13 @@ async fn handle_confirm_webhook(
     let order_id = ctx.param("order_id").unwrap();
     let challenge = ctx.param("challenge").unwrap();
 
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let order_key = format!("orders/{}.json", order_id);
-    let obj = bucket.get(&order_key).execute().await?;
-
-    let obj = match obj {
-        Some(o) => o,
-        None => return Response::error("Order not found", 404),
+    let store = R2Store::from_env(&ctx.env)?;
+    let now_ms = js_sys::Date::now() as u64;
+    let mut order = match service::load_order_for_confirm(&store, order_id, challenge, now_ms).await {
+        Ok(o) => o,
+        Err(e) => return e.into_response(),
     };
 
-    let text = obj.body().unwrap().text().await?;
-    let mut order: Order = serde_json::from_str(&text)
-        .map_err(|e| Error::RustError(e.to_string()))?;
-
-    // Verify challenge (required for all access)
-    if order.webhook_challenge.as_deref() != Some(challenge) {
-        return Response::error("Invalid challenge token", 403);
-    }
-
-    let now_ms = js_sys::Date::now();
-    let expires_date = js_sys::Date::new(&order.expires_at.clone().into());
-    if expires_date.get_time() < now_ms {
-        return Response::error("Order expired", 410);
-    }
-
     // If already Pending, Paid, or Provisioned (revisit/refresh), show the page without creating new invoice
     if order.status == OrderStatus::Pending && !order.bolt11.is_empty() {
         return Ok(render_confirm_response(&req, &order, None, None));
@@ -535,94 +504,52 @@ async fn handle_confirm_webhook(
         .var("DOMAIN")
         .map(|v| v.to_string())
         .unwrap_or_else(|_| "noscha.io".to_string());
-    let webhook_secret = generate_webhook_secret();
-    let coinos_webhook_url = format!("https://{}/api/webhook/coinos", domain);
-
-    let is_mock = coinos_mock::is_mock_enabled(&ctx.env);
-    let invoice = if is_mock {
-        coinos_mock::create_mock_invoice(order.amount_sats, &coinos_webhook_url, &webhook_secret).await?
-    } else {
-        let api_token = ctx.env.secret("COINOS_API_TOKEN")?.to_string();
-        coinos::create_invoice(&api_token, order.amount_sats, &coinos_webhook_url, &webhook_secret).await?
-    };
-
-    // Update order
-    order.bolt11 = invoice.text.clone();
-    order.coinos_invoice_hash = invoice.hash;
-    order.webhook_secret = Some(webhook_secret);
-    if is_mock {
-        order.status = OrderStatus::Paid;
-    } else {
-        order.status = OrderStatus::Pending;
-    }
+    let webhook_secret = ids::webhook_secret();
+    let provider = payment::from_env(&ctx.env).map_err(Error::RustError)?;
+    let payment_webhook_url = format!("https://{}/api/webhook/{}", domain, provider.name());
+    let memo = format!("noscha.io {}", order.username);
+
+    let is_mock = provider.is_mock();
+    let invoice = provider
+        .create_invoice(&InvoiceRequest {
+            amount_sats: order.amount_sats,
+            memo: &memo,
+            webhook_url: &payment_webhook_url,
+            secret: &webhook_secret,
+            expiry_secs: service::ORDER_TTL_MS / 1000,
+            description_hash: None,
+        })
+        .await
+        .map_err(Error::RustError)?;
+
+    service::attach_invoice(
+        &store,
+        &mut order,
+        service::InvoiceDetails {
+            bolt11: invoice.bolt11,
+            hash: invoice.payment_id,
+            webhook_secret,
+        },
+        now_ms,
+    )
+    .await
+    .map_err(Error::RustError)?;
 
     // In mock mode, provision immediately
     let mut mgmt_token: Option<String> = None;
     if is_mock {
-        let duration_ms = order.plan.duration_minutes() as f64 * 60.0 * 1000.0;
-        let rental_expires_ms = now_ms + duration_ms;
-        let rental_expires_date = js_sys::Date::new(&(rental_expires_ms.into()));
-        let rental_expires_at = rental_expires_date.to_iso_string().as_string().unwrap_or_default();
-        let now_date = js_sys::Date::new_0();
-        let now_iso = now_date.to_iso_string().as_string().unwrap_or_default();
-
-        let mut subdomain_service: Option<SubdomainService> = None;
-        if let Some(ref services) = order.services_requested {
-            if let Some(ref sub_req) = services.subdomain {
-                let record_id = provision_dns(&ctx.env, &order.username, sub_req, &rental_expires_at).await?;
-                subdomain_service = Some(SubdomainService {
-                    enabled: true,
-                    record_type: sub_req.record_type.clone(),
-                    target: sub_req.target.clone(),
-                    proxied: sub_req.proxied,
-                    cf_record_id: record_id,
-                });
+        let dns = CloudflareDns::from_env(&ctx.env);
+        let token = ids::management_token();
+        match service::provision_paid_order(&store, &dns, order, token, now_ms).await {
+            Ok(Some(p)) => {
+                mgmt_token = p.order.management_token.clone();
+                order = p.order;
             }
+            Ok(None) => return Response::error("Rental not found", 404),
+            Err(e) => return e.into_response(),
         }
-
-        let email_service = order.services_requested.as_ref().and_then(|s| {
-            s.email.as_ref().map(|_e| EmailService {
-                enabled: true,
-                cf_rule_id: None,
-            })
-        });
-        let nip05_service = order.services_requested.as_ref().and_then(|s| {
-            s.nip05.as_ref().map(|n| Nip05Service {
-                enabled: true,
-                pubkey_hex: n.pubkey.clone(),
-                relays: vec![],
-            })
-        });
-
-        let token = format!("mgmt_{:x}", js_sys::Date::now() as u64);
-        mgmt_token = Some(token.clone());
-
-        let rental = Rental {
-            username: order.username.clone(),
-            status: "active".to_string(),
-            created_at: now_iso,
-            expires_at: rental_expires_at,
-            plan: order.plan.clone(),
-            services: RentalServices {
-                email: email_service,
-                subdomain: subdomain_service,
-                nip05: nip05_service,
-            },
-            management_token: Some(token),
-            webhook_url: order.webhook_url.clone(),
-        };
-
-        let rental_key = format!("rentals/{}.json", order.username);
-        let rental_json = serde_json::to_string(&rental).map_err(|e| Error::RustError(e.to_string()))?;
-        bucket.put(&rental_key, rental_json).execute().await?;
-
-        order.status = OrderStatus::Provisioned;
-        order.management_token = mgmt_token.clone();
     }
 
-    let updated_json = serde_json::to_string(&order).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&order_key, updated_json).execute().await?;
-
     let accept = req
         .headers()
         .get("Accept")
@@ -641,6 +568,7 @@ async fn handle_confirm_webhook(
             status: Some(order.status),
             message: None,
             challenge_url: None,
+            webhook_signing_secret: order.signing_secret.clone(),
         })
     } else if order.status == OrderStatus::Provisioned {
         let domain = ctx
@@ -688,6 +616,7 @@ fn render_confirm_response(
             status: Some(order.status.clone()),
             message: success_msg.map(|s| s.to_string()),
             challenge_url: None,
+            webhook_signing_secret: order.signing_secret.clone(),
         })
         .expect("OrderResponse serialization")
     } else if let Some(msg) = success_msg {
@@ -853,18 +782,29 @@ async fn handle_order_status(
 ) -> Result<Response> {
     let order_id = ctx.param("order_id").unwrap();
 
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let key = format!("orders/{}.json", order_id);
-    let obj = bucket.get(&key).execute().await?;
-
-    match obj {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            let order: Order = serde_json::from_str(&text)
-                .map_err(|e| Error::RustError(e.to_string()))?;
+    let store = R2Store::from_env(&ctx.env)?;
+    match store.get_order(order_id).await.map_err(Error::RustError)? {
+        Some(mut order) => {
+            // Webhook fallback: ask the payment backend directly while the order is unpaid
+            if order.status == OrderStatus::Pending {
+                if let Ok(provider) = payment::from_env(&ctx.env) {
+                    let dns = CloudflareDns::from_env(&ctx.env);
+                    let now_ms = js_sys::Date::now() as u64;
+                    let token = ids::management_token();
+                    match service::poll_order_payment(&store, &dns, &provider, &order, token, now_ms).await {
+                        Ok(PaymentResult::Provisioned(p)) => {
+                            notify_provisioned(&ctx.env, &p).await;
+                            order = p.order;
+                        }
+                        Ok(_) => {}
+                        Err(e) => console_log!("Payment poll failed for {}: {}", order.order_id, e.message),
+                    }
+                }
+            }
 
-            let is_mock = coinos_mock::is_mock_enabled(&ctx.env);
+            let is_mock = payment::config_from_env(&ctx.env)
+                .map(|c| c.kind == payment::ProviderKind::Mock)
+                .unwrap_or(false);
             let status = if is_mock && (order.status == OrderStatus::Paid || order.status == OrderStatus::Provisioned) {
                 OrderStatus::Provisioned
             } else {
@@ -886,526 +826,452 @@ async fn handle_order_status(
     }
 }
 
-/// Provision DNS for a subdomain order
+/// POST /api/webhook/:provider — settlement notification from the payment backend
 #[cfg(target_arch = "wasm32")]
-async fn provision_dns(
-    env: &Env,
-    username: &str,
-    subdomain_req: &OrderSubdomainRequest,
-    expires: &str,
-) -> Result<Option<String>> {
-    let zone_id = env.var("CF_ZONE_ID")
-        .map(|v| v.to_string())
-        .unwrap_or_default();
-    let domain = env.var("DOMAIN")
-        .map(|v| v.to_string())
-        .unwrap_or_else(|_| "noscha.io".to_string());
-
-    if zone_id.is_empty() {
-        console_log!("CF_ZONE_ID not set, skipping DNS provisioning");
-        return Ok(None);
+async fn handle_payment_webhook(
+    mut req: Request,
+    ctx: RouteContext<()>,
+) -> Result<Response> {
+    let provider = payment::from_env(&ctx.env).map_err(Error::RustError)?;
+    if !provider.is_mock() && ctx.param("provider").map(|p| p.as_str()) != Some(provider.name()) {
+        return Response::error("Unknown payment provider", 404);
     }
+    let inbound = http::InboundRequest::from_worker(&mut req).await?;
+
+    let store = R2Store::from_env(&ctx.env)?;
+    let dns = CloudflareDns::from_env(&ctx.env);
+    let now_ms = js_sys::Date::now() as u64;
+    let token = ids::management_token();
+    let provisioned = match service::apply_payment_webhook(&store, &dns, &provider, &inbound, token, now_ms).await {
+        Ok(PaymentResult::Provisioned(p)) => p,
+        Ok(PaymentResult::RentalNotFound) => return Response::ok("rental not found for renewal"),
+        Ok(PaymentResult::NoMatchingOrder) => return settle_lnurl_invoice(&ctx.env, &store, &provider, &inbound, now_ms).await,
+        Ok(PaymentResult::Ignored) => return Response::ok("ignored"),
+        Ok(PaymentResult::NotPaid) => return Response::ok("not paid"),
+        Err(e) => return e.into_response(),
+    };
+
+    notify_provisioned(&ctx.env, &provisioned).await;
+    Response::ok("ok")
+}
 
-    let record_type = match subdomain_req.record_type.to_uppercase().as_str() {
-        "CNAME" => DnsRecordType::CNAME,
-        "A" => DnsRecordType::A,
-        "AAAA" => DnsRecordType::AAAA,
-        other => {
-            return Err(Error::RustError(format!(
-                "Unsupported DNS record type: {}",
-                other
-            )))
+/// Webhook for a Lightning Address invoice rather than an order: mark it paid and
+/// publish the zap receipt when it was a zap (needs NOSTR_SERVICE_KEY)
+#[cfg(target_arch = "wasm32")]
+async fn settle_lnurl_invoice(
+    env: &Env,
+    store: &R2Store,
+    provider: &payment::Provider<http::FetchClient>,
+    inbound: &http::InboundRequest,
+    now_ms: u64,
+) -> Result<Response> {
+    let invoice = match lnurl::settle_invoice(store, provider, inbound, now_ms).await {
+        Ok(Some(invoice)) => invoice,
+        Ok(None) => return Response::ok("no matching order"),
+        Err(e) => return e.into_response(),
+    };
+    if invoice.zap_request.is_some() {
+        if let Some(keys) = nostr_service_keys(env) {
+            match lnurl::publish_zap_receipt(&nip17::WebSocketRelay, &keys, &invoice, now_ms / 1000).await {
+                Ok(accepted) => console_log!("Zap receipt for {} published to {}", invoice.username, accepted.join(", ")),
+                Err(e) => console_log!("Zap receipt for {} not published: {}", invoice.username, e),
+            }
         }
+    }
+    Response::ok("ok")
+}
+
+/// LUD-06 error body, with CORS so wallets in browsers can read it
+#[cfg(target_arch = "wasm32")]
+fn lnurl_error(e: service::ApiError) -> Result<Response> {
+    let body = serde_json::json!({ "status": "ERROR", "reason": e.message });
+    let mut response = Response::from_json(&body)?.with_status(e.status);
+    response.headers_mut().set("Access-Control-Allow-Origin", "*")?;
+    Ok(response)
+}
+
+/// GET /.well-known/lnurlp/:username — LUD-16 Lightning Address payRequest
+#[cfg(target_arch = "wasm32")]
+async fn handle_lnurlp(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let username = ctx.param("username").cloned().unwrap_or_default();
+    let store = R2Store::from_env(&ctx.env)?;
+    let nostr_pubkey = nostr_service_keys(&ctx.env).map(|k| k.pubkey_hex());
+    let now_ms = js_sys::Date::now() as u64;
+    let body = match lnurl::pay_request_for(&store, &http::FetchClient, &username, &domain(&ctx.env), nostr_pubkey.as_deref(), now_ms).await {
+        Ok(body) => body,
+        Err(e) => return lnurl_error(e),
     };
+    let mut response = Response::from_json(&body)?;
+    response.headers_mut().set("Access-Control-Allow-Origin", "*")?;
+    Ok(response)
+}
 
-    let is_mock = dns_mock::is_mock_dns_enabled(env);
+/// GET /api/lnurlp/:username/callback?amount=<msat> — invoice for a Lightning
+/// Address payment or NIP-57 zap
+#[cfg(target_arch = "wasm32")]
+async fn handle_lnurlp_callback(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let username = ctx.param("username").cloned().unwrap_or_default();
+    let url = req.url()?;
+    let query = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.to_string());
+    let Some(amount_msat) = query("amount").and_then(|a| a.parse::<u64>().ok()) else {
+        return lnurl_error(service::ApiError::new(400, "Missing or invalid amount"));
+    };
+    let params = lnurl::CallbackParams { amount_msat, nostr: query("nostr"), comment: query("comment") };
+
+    let provider = payment::from_env(&ctx.env).map_err(Error::RustError)?;
+    let store = R2Store::from_env(&ctx.env)?;
+    let domain = domain(&ctx.env);
+    let webhook_url = format!("https://{}/api/webhook/{}", domain, provider.name());
+    let now_ms = js_sys::Date::now() as u64;
+    let secret = ids::webhook_secret();
+    let body = match lnurl::create_invoice(&store, &provider, &username, params, &domain, &webhook_url, secret, now_ms).await {
+        Ok(body) => body,
+        Err(e) => return lnurl_error(e),
+    };
+    let mut response = Response::from_json(&body)?;
+    response.headers_mut().set("Access-Control-Allow-Origin", "*")?;
+    Ok(response)
+}
 
-    let record_id = if is_mock {
-        dns_mock::create_dns_record(
-            &zone_id,
-            "",
-            username,
-            &record_type,
-            &subdomain_req.target,
-            subdomain_req.proxied,
-            username,
-            expires,
-            &domain,
-        )
-        .await?
-    } else {
-        let token = env.secret("CF_API_TOKEN")?.to_string();
-        dns::create_dns_record(
-            &zone_id,
-            &token,
-            username,
-            &record_type,
-            &subdomain_req.target,
-            subdomain_req.proxied,
-            username,
-            expires,
-            &domain,
-        )
-        .await?
+/// Payment-completed webhook and Discord notification for a freshly provisioned order
+#[cfg(target_arch = "wasm32")]
+async fn notify_provisioned(env: &Env, provisioned: &Provisioned) {
+    let Provisioned { order, rental, is_renewal, dns_restored } = provisioned;
+    send_payment_completed_webhook(env, order, rental, *is_renewal).await;
+    if *dns_restored {
+        send_dns_changed_webhook(env, rental, "restored").await;
+    }
+    send_discord_notification(env, order).await;
+}
+
+/// Pubkey of a verified `Authorization: Nostr` (NIP-98) header, None when the
+/// request carries no Nostr auth
+#[cfg(target_arch = "wasm32")]
+async fn verified_owner(
+    req: &Request,
+    store: &R2Store,
+    body: &[u8],
+) -> Result<std::result::Result<Option<String>, service::ApiError>> {
+    let header = match req.headers().get("Authorization")? {
+        Some(h) if h.starts_with("Nostr ") => h,
+        _ => return Ok(Ok(None)),
     };
+    let url = req.url()?.to_string();
+    let method = req.method().to_string();
+    Ok(service::verify_owner_auth(store, &header, &url, &method, body, js_sys::Date::now() as u64)
+        .await
+        .map(Some))
+}
 
-    Ok(Some(record_id))
+/// POST /api/owner/challenge — challenge for the next NIP-98 owner request
+#[cfg(target_arch = "wasm32")]
+async fn handle_owner_challenge(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let store = R2Store::from_env(&ctx.env)?;
+    let challenge = ids::challenge();
+    admin::issue_challenge(&store, challenge.clone(), js_sys::Date::now() as u64)
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&serde_json::json!({ "challenge": challenge }))
 }
 
-/// POST /api/webhook/coinos
+/// POST /api/renew — renew an existing rental
 #[cfg(target_arch = "wasm32")]
-async fn handle_coinos_webhook(
+async fn handle_renew(
     mut req: Request,
     ctx: RouteContext<()>,
 ) -> Result<Response> {
-    let payload: CoinosWebhookPayload = req.json().await.map_err(|_| {
-        Error::RustError("Invalid webhook payload".to_string())
-    })?;
-
-    // Must have confirmed = true
-    if payload.confirmed != Some(true) {
-        return Response::ok("ignored");
-    }
+    let raw = req.bytes().await?;
+    let body: RenewRequest = match serde_json::from_slice(&raw) {
+        Ok(b) => b,
+        Err(_) => return Response::error("Invalid request body", 400),
+    };
 
-    let secret = match &payload.secret {
-        Some(s) => s.clone(),
-        None => return Response::ok("no secret"),
+    let store = R2Store::from_env(&ctx.env)?;
+    let owner = match verified_owner(&req, &store, &raw).await? {
+        Ok(owner) => owner,
+        Err(e) => return e.into_response(),
+    };
+    let quote = match service::renewal_auth(&body, owner.as_deref()) {
+        Ok(auth) => service::renewal_quote(&store, auth, &body).await,
+        Err(e) => Err(e),
+    };
+    let (rental, amount_sats) = match quote {
+        Ok(q) => q,
+        Err(e) => return e.into_response(),
     };
 
-    // Find order by webhook secret — scan recent orders
-    let bucket = ctx.env.bucket("BUCKET")?;
+    let webhook_secret = ids::webhook_secret();
+    let domain = ctx
+        .env
+        .var("DOMAIN")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "noscha.io".to_string());
+    let provider = payment::from_env(&ctx.env).map_err(Error::RustError)?;
+    let webhook_url = format!("https://{}/api/webhook/{}", domain, provider.name());
+    let memo = format!("noscha.io {} renewal", rental.username);
 
-    let hash = match &payload.hash {
-        Some(h) => h.clone(),
-        None => return Response::ok("no hash"),
-    };
+    // Create invoice (mock or real)
+    let is_mock = provider.is_mock();
+    let invoice = provider
+        .create_invoice(&InvoiceRequest {
+            amount_sats,
+            memo: &memo,
+            webhook_url: &webhook_url,
+            secret: &webhook_secret,
+            expiry_secs: service::ORDER_TTL_MS / 1000,
+            description_hash: None,
+        })
+        .await
+        .map_err(Error::RustError)?;
 
-    // List orders to find the matching one
-    let list = bucket.list().prefix("orders/").execute().await?;
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(mut order) = serde_json::from_str::<Order>(&text) {
-                // Match by webhook secret and pending status
-                if order.webhook_secret.as_deref() == Some(&secret)
-                    && order.status == OrderStatus::Pending
-                {
-                    // Update order status to paid
-                    order.status = OrderStatus::Paid;
-                    order.coinos_invoice_hash = Some(hash.clone());
-
-                    let now_ms = js_sys::Date::now();
-                    let duration_ms =
-                        order.plan.duration_minutes() as f64 * 60.0 * 1000.0;
-
-                    // Check if this is a renewal order
-                    if let Some(ref renewal_username) = order.renewal_for {
-                        // Extend existing rental
-                        let rental_key = format!("rentals/{}.json", renewal_username);
-                        if let Some(rental_obj) = bucket.get(&rental_key).execute().await? {
-                            let rental_body = rental_obj.body().unwrap();
-                            let rental_text = rental_body.text().await?;
-                            if let Ok(mut rental) = serde_json::from_str::<Rental>(&rental_text) {
-                                let current_expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-                                let current_expires_ms = current_expires_date.get_time();
-                                let base_ms = if current_expires_ms > now_ms {
-                                    current_expires_ms
-                                } else {
-                                    now_ms
-                                };
-                                let new_expires_ms = base_ms + duration_ms;
-                                let new_expires_date = js_sys::Date::new(&(new_expires_ms.into()));
-                                rental.expires_at = new_expires_date
-                                    .to_iso_string()
-                                    .as_string()
-                                    .unwrap_or_default();
-                                rental.status = "active".to_string();
-                                rental.plan = order.plan.clone();
-
-                                let rental_json = serde_json::to_string(&rental)
-                                    .map_err(|e| Error::RustError(e.to_string()))?;
-                                bucket.put(&rental_key, rental_json).execute().await?;
-
-                                order.status = OrderStatus::Provisioned;
-                                order.management_token = rental.management_token.clone();
-                                let updated_json = serde_json::to_string(&order)
-                                    .map_err(|e| Error::RustError(e.to_string()))?;
-                                bucket.put(&key, updated_json).execute().await?;
-
-                                if let (Some(ref url), Some(ref mgmt)) =
-                                    (order.webhook_url.as_deref(), rental.management_token.as_deref())
-                                {
-                                    send_payment_completed_webhook(
-                                        &ctx.env,
-                                        url,
-                                        &order,
-                                        &rental.username,
-                                        mgmt,
-                                        &rental.expires_at,
-                                        &rental.services,
-                                        true,
-                                    )
-                                    .await;
-                                }
-                                send_discord_notification(&ctx.env, &order).await;
-                                return Response::ok("ok");
-                            }
-                        }
-                        return Response::ok("rental not found for renewal");
-                    }
+    let now_ms = js_sys::Date::now() as u64;
+    let order = service::create_renewal_order(
+        &store,
+        &rental,
+        body.plan,
+        amount_sats,
+        ids::order_id(),
+        service::InvoiceDetails {
+            bolt11: invoice.bolt11,
+            hash: invoice.payment_id,
+            webhook_secret,
+        },
+        now_ms,
+    )
+    .await
+    .map_err(Error::RustError)?;
 
-                    // New rental — calculate expiry
-                    let rental_expires_ms = now_ms + duration_ms;
-                    let rental_expires_date =
-                        js_sys::Date::new(&(rental_expires_ms.into()));
-                    let rental_expires_at = rental_expires_date
-                        .to_iso_string()
-                        .as_string()
-                        .unwrap_or_default();
-                    let now_date = js_sys::Date::new_0();
-                    let now_iso = now_date
-                        .to_iso_string()
-                        .as_string()
-                        .unwrap_or_default();
-
-                    // Provision DNS if subdomain requested
-                    let mut subdomain_service: Option<SubdomainService> = None;
-                    if let Some(ref services) = order.services_requested {
-                        if let Some(ref sub_req) = services.subdomain {
-                            let record_id = provision_dns(
-                                &ctx.env,
-                                &order.username,
-                                sub_req,
-                                &rental_expires_at,
-                            )
-                            .await?;
-                            subdomain_service = Some(SubdomainService {
-                                enabled: true,
-                                record_type: sub_req.record_type.clone(),
-                                target: sub_req.target.clone(),
-                                proxied: sub_req.proxied,
-                                cf_record_id: record_id,
-                            });
-                        }
-                    }
+    let response = RenewResponse {
+        order_id: order.order_id.clone(),
+        amount_sats: order.amount_sats,
+        bolt11: order.bolt11.clone(),
+        expires_at: order.expires_at.clone(),
+    };
 
-                    // Build rental object
-                    let email_service =
-                        order.services_requested.as_ref().and_then(|s| {
-                            s.email.as_ref().map(|_e| EmailService {
-                                enabled: true,
-                                cf_rule_id: None,
-                            })
-                        });
-                    let nip05_service =
-                        order.services_requested.as_ref().and_then(|s| {
-                            s.nip05.as_ref().map(|n| Nip05Service {
-                                enabled: true,
-                                pubkey_hex: n.pubkey.clone(),
-                                relays: vec![],
-                            })
-                        });
-
-                    let rental = Rental {
-                        username: order.username.clone(),
-                        status: "active".to_string(),
-                        created_at: now_iso,
-                        expires_at: rental_expires_at,
-                        plan: order.plan.clone(),
-                        services: RentalServices {
-                            email: email_service,
-                            subdomain: subdomain_service,
-                            nip05: nip05_service,
-                        },
-                        management_token: Some(format!("mgmt_{:x}", js_sys::Date::now() as u64)),
-                        webhook_url: order.webhook_url.clone(),
-                    };
-
-                    // Save rental to R2
-                    let rental_key =
-                        format!("rentals/{}.json", order.username);
-                    let rental_json = serde_json::to_string(&rental)
-                        .map_err(|e| Error::RustError(e.to_string()))?;
-                    bucket
-                        .put(&rental_key, rental_json)
-                        .execute()
-                        .await?;
-
-                    // Update order status
-                    order.status = OrderStatus::Provisioned;
-                    order.management_token = rental.management_token.clone();
-                    let updated_json = serde_json::to_string(&order)
-                        .map_err(|e| Error::RustError(e.to_string()))?;
-                    bucket.put(&key, updated_json).execute().await?;
-
-                    if let (Some(ref url), Some(ref mgmt)) = (
-                        order.webhook_url.as_deref(),
-                        rental.management_token.as_deref(),
-                    ) {
-                        send_payment_completed_webhook(
-                            &ctx.env,
-                            url,
-                            &order,
-                            &rental.username,
-                            mgmt,
-                            &rental.expires_at,
-                            &rental.services,
-                            false,
-                        )
-                        .await;
-                    }
-                    send_discord_notification(&ctx.env, &order).await;
-                    return Response::ok("ok");
+    // In mock mode, immediately extend the rental
+    if is_mock {
+        let dns = CloudflareDns::from_env(&ctx.env);
+        match service::provision_paid_order(&store, &dns, order, String::new(), now_ms).await {
+            Ok(Some(Provisioned { order, rental, dns_restored, .. })) => {
+                send_payment_completed_webhook(&ctx.env, &order, &rental, true).await;
+                if dns_restored {
+                    send_dns_changed_webhook(&ctx.env, &rental, "restored").await;
                 }
             }
+            Ok(None) => return Response::error("Rental not found", 404),
+            Err(e) => return e.into_response(),
         }
     }
 
-    Response::ok("no matching order")
+    Response::from_json(&response)
 }
 
-/// POST /api/renew — renew an existing rental
+/// POST /api/upgrade — add services to an active rental for a prorated price
 #[cfg(target_arch = "wasm32")]
-async fn handle_renew(
+async fn handle_upgrade(
     mut req: Request,
     ctx: RouteContext<()>,
 ) -> Result<Response> {
-    let body: RenewRequest = match req.json().await {
+    let raw = req.bytes().await?;
+    let body: UpgradeRequest = match serde_json::from_slice(&raw) {
         Ok(b) => b,
         Err(_) => return Response::error("Invalid request body", 400),
     };
 
-    let bucket = ctx.env.bucket("BUCKET")?;
-
-    // Find rental by management_token
-    let list = bucket.list().prefix("rentals/").execute().await?;
-    let mut found_rental: Option<Rental> = None;
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let obj_body = obj.body().unwrap();
-            let text = obj_body.text().await?;
-            if let Ok(rental) = serde_json::from_str::<Rental>(&text) {
-                if rental.management_token.as_deref() == Some(&body.management_token) {
-                    found_rental = Some(rental);
-                    break;
-                }
-            }
-        }
-    }
-
-    let rental = match found_rental {
-        Some(r) => r,
-        None => return Response::error("Rental not found", 404),
+    let store = R2Store::from_env(&ctx.env)?;
+    let owner = match verified_owner(&req, &store, &raw).await? {
+        Ok(owner) => owner,
+        Err(e) => return e.into_response(),
     };
-
-    // Determine services for pricing: use request services if provided, else derive from rental
-    let service_types = if body.services.is_some() {
-        services_from_request(&body.services)
-    } else {
-        services_from_rental(&rental.services)
+    let now_ms = js_sys::Date::now() as u64;
+    let grace = grace_minutes(&ctx.env);
+    let quote = match service::upgrade_auth(&body, owner.as_deref()) {
+        Ok(auth) => service::upgrade_quote(&store, auth, body.services.clone(), grace, now_ms).await,
+        Err(e) => Err(e),
     };
-
-    let order_id = generate_order_id();
-    let pricing = admin::load_pricing(&bucket).await;
-    let amount_sats = Plan::calculate_total_dynamic(&body.plan, &service_types, &pricing);
-    let webhook_secret = generate_webhook_secret();
-    let domain = ctx
-        .env
-        .var("DOMAIN")
-        .map(|v| v.to_string())
-        .unwrap_or_else(|_| "noscha.io".to_string());
-    let webhook_url = format!("https://{}/api/webhook/coinos", domain);
-
-    // Create invoice (mock or real)
-    let is_mock = coinos_mock::is_mock_enabled(&ctx.env);
-    let invoice = if is_mock {
-        coinos_mock::create_mock_invoice(amount_sats, &webhook_url, &webhook_secret).await?
-    } else {
-        let api_token = ctx.env.secret("COINOS_API_TOKEN")?.to_string();
-        coinos::create_invoice(&api_token, amount_sats, &webhook_url, &webhook_secret).await?
+    let (rental, services, amount_sats) = match quote {
+        Ok(q) => q,
+        Err(e) => return e.into_response(),
     };
 
-    // Calculate expiry (15 min for invoice)
-    let now = js_sys::Date::now();
-    let expires_ms = now + 15.0 * 60.0 * 1000.0;
-    let created_at = js_sys::Date::new_0();
-    let expires_at = js_sys::Date::new(&(expires_ms.into()));
+    let webhook_secret = ids::webhook_secret();
+    let provider = payment::from_env(&ctx.env).map_err(Error::RustError)?;
+    let webhook_url = format!("https://{}/api/webhook/{}", domain(&ctx.env), provider.name());
+    let memo = format!("noscha.io {} upgrade", rental.username);
+
+    let is_mock = provider.is_mock();
+    let invoice = provider
+        .create_invoice(&InvoiceRequest {
+            amount_sats,
+            memo: &memo,
+            webhook_url: &webhook_url,
+            secret: &webhook_secret,
+            expiry_secs: service::ORDER_TTL_MS / 1000,
+            description_hash: None,
+        })
+        .await
+        .map_err(Error::RustError)?;
 
-    let order = Order {
-        order_id: order_id.clone(),
-        username: rental.username.clone(),
-        plan: body.plan.clone(),
+    let order = service::create_upgrade_order(
+        &store,
+        &rental,
+        services,
         amount_sats,
-        bolt11: invoice.text.clone(),
-        status: if is_mock {
-            OrderStatus::Paid
-        } else {
-            OrderStatus::Pending
+        ids::order_id(),
+        service::InvoiceDetails {
+            bolt11: invoice.bolt11,
+            hash: invoice.payment_id,
+            webhook_secret,
         },
-        created_at: created_at.to_iso_string().as_string().unwrap_or_default(),
-        expires_at: expires_at.to_iso_string().as_string().unwrap_or_default(),
-        coinos_invoice_hash: invoice.hash,
-        webhook_secret: Some(webhook_secret),
-        services_requested: None,
-        management_token: None,
-        renewal_for: Some(rental.username.clone()),
-        webhook_url: rental.webhook_url.clone(),
-        webhook_challenge: None,
-    };
-
-    // Save order to R2
-    let order_key = format!("orders/{}.json", order_id);
-    let order_json =
-        serde_json::to_string(&order).map_err(|e| Error::RustError(e.to_string()))?;
-    bucket.put(&order_key, order_json).execute().await?;
+        now_ms,
+    )
+    .await
+    .map_err(Error::RustError)?;
 
-    let resp_order_id = order.order_id.clone();
-    let resp_amount_sats = order.amount_sats;
-    let resp_bolt11 = order.bolt11.clone();
-    let resp_expires_at = order.expires_at.clone();
+    let response = UpgradeResponse {
+        order_id: order.order_id.clone(),
+        amount_sats: order.amount_sats,
+        bolt11: order.bolt11.clone(),
+        expires_at: order.expires_at.clone(),
+        rental_expires_at: rental.expires_at.clone(),
+    };
 
-    // In mock mode, immediately extend the rental
+    // In mock mode, immediately add the services
     if is_mock {
-        let now_ms = js_sys::Date::now();
-        let duration_ms = order.plan.duration_minutes() as f64 * 60.0 * 1000.0;
-        let current_expires_date = js_sys::Date::new(&rental.expires_at.clone().into());
-        let current_expires_ms = current_expires_date.get_time();
-        let base_ms = if current_expires_ms > now_ms {
-            current_expires_ms
-        } else {
-            now_ms
-        };
-        let new_expires_ms = base_ms + duration_ms;
-        let new_expires_date = js_sys::Date::new(&(new_expires_ms.into()));
-        let new_expires_at = new_expires_date
-            .to_iso_string()
-            .as_string()
-            .unwrap_or_default();
-
-        let mut updated_rental = rental;
-        updated_rental.expires_at = new_expires_at;
-        updated_rental.status = "active".to_string();
-        updated_rental.plan = order.plan.clone();
-
-        let rental_key = format!("rentals/{}.json", updated_rental.username);
-        let rental_json = serde_json::to_string(&updated_rental)
-            .map_err(|e| Error::RustError(e.to_string()))?;
-        bucket.put(&rental_key, rental_json).execute().await?;
-
-        // Update order to Provisioned
-        let mut order = order;
-        order.status = OrderStatus::Provisioned;
-        order.management_token = updated_rental.management_token.clone();
-        let updated_json = serde_json::to_string(&order)
-            .map_err(|e| Error::RustError(e.to_string()))?;
-        bucket.put(&order_key, updated_json).execute().await?;
-
-        if let (Some(ref url), Some(ref mgmt)) = (
-            order.webhook_url.as_deref(),
-            updated_rental.management_token.as_deref(),
-        ) {
-            send_payment_completed_webhook(
-                &ctx.env,
-                url,
-                &order,
-                &updated_rental.username,
-                mgmt,
-                &updated_rental.expires_at,
-                &updated_rental.services,
-                true,
-            )
-            .await;
+        let dns = CloudflareDns::from_env(&ctx.env);
+        match service::provision_paid_order(&store, &dns, order, String::new(), now_ms).await {
+            Ok(Some(provisioned)) => notify_provisioned(&ctx.env, &provisioned).await,
+            Ok(None) => return Response::error("Rental not found", 404),
+            Err(e) => return e.into_response(),
         }
     }
 
-    Response::from_json(&RenewResponse {
-        order_id: resp_order_id,
-        amount_sats: resp_amount_sats,
-        bolt11: resp_bolt11,
-        expires_at: resp_expires_at,
-    })
+    Response::from_json(&response)
 }
 
-/// PUT /api/settings/{management_token} — update rental settings
+/// PUT /api/settings/{management_token} — update rental settings. With an
+/// `Authorization: Nostr` header the path names the rental instead
 #[cfg(target_arch = "wasm32")]
 async fn handle_settings_update(
     mut req: Request,
     ctx: RouteContext<()>,
 ) -> Result<Response> {
     let token = ctx.param("token").unwrap();
-    let body: SettingsRequest = match req.json().await {
+    let raw = req.bytes().await?;
+    let body: SettingsRequest = match serde_json::from_slice(&raw) {
         Ok(b) => b,
         Err(_) => return Response::error("Invalid request body", 400),
     };
 
-    // Validate webhook_url if provided
-    if let Some(ref url) = body.webhook_url {
-        if !url.is_empty() && !url.starts_with("https://") && !url.starts_with("http://") {
-            return Response::error("webhook_url must be a valid HTTP(S) URL", 400);
-        }
-    }
-
-    let bucket = ctx.env.bucket("BUCKET")?;
-
-    // Scan rentals to find matching management_token
-    let list = bucket.list().prefix("rentals/").execute().await?;
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let obj_body = obj.body().unwrap();
-            let text = obj_body.text().await?;
-            if let Ok(mut rental) = serde_json::from_str::<Rental>(&text) {
-                if rental.management_token.as_deref() == Some(token) {
-                    // Update webhook_url
-                    rental.webhook_url = body.webhook_url.clone();
-                    let updated_json = serde_json::to_string(&rental)
-                        .map_err(|e| Error::RustError(e.to_string()))?;
-                    bucket.put(&key, updated_json).execute().await?;
-
-                    return Response::from_json(&SettingsResponse {
-                        success: true,
-                        webhook_url: rental.webhook_url,
-                    });
+    let store = R2Store::from_env(&ctx.env)?;
+    let owner = match verified_owner(&req, &store, &raw).await? {
+        Ok(owner) => owner,
+        Err(e) => return e.into_response(),
+    };
+    let auth = match owner.as_deref() {
+        Some(pubkey) => service::RentalAuth::Owner { username: token, pubkey },
+        None => service::RentalAuth::Token(token),
+    };
+    let dns = CloudflareDns::from_env(&ctx.env);
+    let now_ms = js_sys::Date::now() as u64;
+    match service::update_settings(
+        &store,
+        &dns,
+        auth,
+        &body,
+        url_policy_from_env(&ctx.env),
+        grace_minutes(&ctx.env),
+        now_ms,
+        ids::signing_secret,
+    )
+    .await
+    {
+        Ok(resp) => {
+            if !resp.changed.is_empty() {
+                if let Ok(Some(rental)) = store.get_rental(&resp.username).await {
+                    send_settings_changed_webhook(&ctx.env, &rental, &resp.changed).await;
                 }
             }
+            Response::from_json(&resp)
         }
+        Err(e) => e.into_response(),
     }
+}
 
-    Response::error("Rental not found", 404)
+/// POST /api/settings/{management_token}/rotate — replace the management token.
+/// With an `Authorization: Nostr` header the path names the rental instead
+#[cfg(target_arch = "wasm32")]
+async fn handle_token_rotate(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let token = ctx.param("token").unwrap();
+    let store = R2Store::from_env(&ctx.env)?;
+    let owner = match verified_owner(&req, &store, &[]).await? {
+        Ok(owner) => owner,
+        Err(e) => return e.into_response(),
+    };
+    let auth = match owner.as_deref() {
+        Some(pubkey) => service::RentalAuth::Owner { username: token, pubkey },
+        None => service::RentalAuth::Token(token),
+    };
+    match service::rotate_token(&store, auth, ids::management_token()).await {
+        Ok(rental) => Response::from_json(&token_rotated(&ctx.env, &rental, "owner").await),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// GET /api/webhooks/deliveries — recent webhook deliveries, authenticated with
+/// `Authorization: Bearer <management_token>`
+#[cfg(target_arch = "wasm32")]
+async fn handle_webhook_deliveries(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let token = req
+        .headers()
+        .get("Authorization")?
+        .and_then(|h| h.strip_prefix("Bearer ").map(|t| t.trim().to_string()));
+    let Some(token) = token else {
+        return Response::error("Missing Authorization: Bearer <management_token>", 401);
+    };
+
+    let store = R2Store::from_env(&ctx.env)?;
+    let rental = match service::find_rental_by_token(&store, &token).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return Response::error("Rental not found", 404),
+        Err(e) => return Err(Error::RustError(e)),
+    };
+    let deliveries = webhook::list_rental_deliveries(&store, &rental, webhook::DELIVERY_LIST_LIMIT)
+        .await
+        .map_err(Error::RustError)?;
+    Response::from_json(&DeliveryListResponse { deliveries })
 }
 
-/// GET /my/{management_token} — user my-page
+/// GET /my/{management_token} — user my-page. With an `Authorization: Nostr`
+/// header the path names the rental instead
 #[cfg(target_arch = "wasm32")]
 async fn handle_my_page(
-    _req: Request,
+    req: Request,
     ctx: RouteContext<()>,
 ) -> Result<Response> {
     let token = ctx.param("token").unwrap();
-    let bucket = ctx.env.bucket("BUCKET")?;
-
-    // Scan rentals to find matching management_token
-    let list = bucket.list().prefix("rentals/").execute().await?;
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(rental) = serde_json::from_str::<Rental>(&text) {
-                if rental.management_token.as_deref() == Some(token) {
-                    return Response::from_html(render_my_page(&rental, &ctx.env, token));
-                }
-            }
+    let store = R2Store::from_env(&ctx.env)?;
+    let owner = match verified_owner(&req, &store, &[]).await? {
+        Ok(owner) => owner,
+        Err(e) => return e.into_response(),
+    };
+    let auth = match owner.as_deref() {
+        Some(pubkey) => service::RentalAuth::Owner { username: token, pubkey },
+        None => service::RentalAuth::Token(token),
+    };
+    match service::find_authorized_rental(&store, auth).await {
+        Ok(rental) => {
+            Response::from_html(render_my_page(&rental, &ctx.env, rental.management_token.as_deref()))
         }
+        Err(e) if e.status == 404 => Response::error("Not found", 404),
+        Err(e) => e.into_response(),
     }
-
-    Response::error("Not found", 404)
 }
 
 #[cfg(target_arch = "wasm32")]
-fn render_my_page(rental: &Rental, env: &Env, management_token: &str) -> String {
+/// Without a management token (an owner-key login to a rental that has none)
+/// the page leaves out the actions that need one
+fn render_my_page(rental: &Rental, env: &Env, management_token: Option<&str>) -> String {
     let domain = env
         .var("DOMAIN")
         .map(|v| v.to_string())
@@ -1418,22 +1284,35 @@ fn render_my_page(rental: &Rental, env: &Env, management_token: &str) -> String
     let days_remaining = ((expires_ms - now_ms) / (24.0 * 60.0 * 60.0 * 1000.0)).ceil() as i64;
     let days_remaining = if days_remaining < 0 { 0 } else { days_remaining };
 
-    let mut status_color = if rental.status == "active" && days_remaining > 0 {
+    let mut status_color = if rental.status.is_active() && days_remaining > 0 {
         "#22c55e"
     } else {
         "#ef4444"
     };
-    let mut display_status = if rental.status == "active" && days_remaining > 0 {
-        "Active"
-    } else {
-        "Expired"
+    let mut display_status = match rental.status {
+        RentalStatus::Active if days_remaining > 0 => "Active",
+        RentalStatus::Grace => "Grace period",
+        RentalStatus::Suspended => "Suspended",
+        RentalStatus::Revoked => "Revoked",
+        RentalStatus::Released => "Released",
+        _ => "Expired",
     };
 
     // Real-time expiry check at millisecond precision
-    if is_expired_iso(&rental.expires_at) {
+    if rental.status.is_active() && is_expired_iso(&rental.expires_at) {
         status_color = "#ef4444";
         display_status = "Expired";
     }
+    if rental.status == RentalStatus::Grace {
+        status_color = "#f59e0b";
+    }
+    let grace_html = match (&rental.status, rental.grace_until.as_deref()) {
+        (RentalStatus::Grace, Some(until)) => format!(
+            "<div class='row'><span class='label'>Grace period</span><span class='val'>Renew before {}</span></div>",
+            until
+        ),
+        _ => String::new(),
+    };
 
     // Build services list
     let mut services_html = String::new();
@@ -1456,11 +1335,22 @@ fn render_my_page(rental: &Rental, env: &Env, management_token: &str) -> String
     }
     if let Some(ref nip) = rental.services.nip05 {
         if nip.enabled {
+            let npub = nip19::encode_npub(&nip.pubkey_hex).unwrap_or_else(|_| nip.pubkey_hex.clone());
+            services_html.push_str(&format!(
+                "<div class='svc'><span class='svc-badge nip'>NIP-05</span> {}@{} &rarr; <span title='{}'>{}...{}</span></div>",
+                rental.username, domain, npub,
+                &npub[..12.min(npub.len())],
+                &npub[npub.len().saturating_sub(8)..]
+            ));
+        }
+    }
+    if let Some(ref ln) = rental.services.lightning_address {
+        if ln.enabled {
             services_html.push_str(&format!(
-                "<div class='svc'><span class='svc-badge nip'>NIP-05</span> {}@{} &rarr; {}...{}</div>",
-                rental.username, domain,
-                &nip.pubkey_hex[..8.min(nip.pubkey_hex.len())],
-                &nip.pubkey_hex[nip.pubkey_hex.len().saturating_sub(8)..]
+                "<div class='svc'><span class='svc-badge ln'>Lightning</span> {}@{}{}</div>",
+                rental.username,
+                domain,
+                ln.forward_to.as_deref().map(|f| format!(" &rarr; {}", ui::escape_html(f))).unwrap_or_default()
             ));
         }
     }
@@ -1468,6 +1358,100 @@ fn render_my_page(rental: &Rental, env: &Env, management_token: &str) -> String
         services_html = "<div class='svc' style='color:#888'>No services configured</div>".to_string();
     }
 
+    // Fields for PUT /api/settings: the NIP-05 pubkey and the subdomain record
+    let mut edit_html = String::new();
+    if let Some(nip) = rental.services.nip05.as_ref().filter(|n| n.enabled) {
+        let npub = nip19::encode_npub(&nip.pubkey_hex).unwrap_or_else(|_| nip.pubkey_hex.clone());
+        edit_html.push_str(&format!(
+            "<div class='edit-row'><label>NIP-05 pubkey</label><input id='edit-pubkey' value='{}' data-orig='{}' placeholder='npub1... or hex'></div>",
+            npub, npub
+        ));
+    }
+    if let Some(sub) = rental.services.subdomain.as_ref().filter(|s| s.enabled) {
+        let options: String = ["CNAME", "A", "AAAA"]
+            .iter()
+            .map(|t| format!("<option{}>{}</option>", if sub.record_type.eq_ignore_ascii_case(t) { " selected" } else { "" }, t))
+            .collect();
+        edit_html.push_str(&format!(
+            "<div class='edit-row'><label>DNS record</label><select id='edit-dns-type'>{}</select><input id='edit-dns-target' value='{}'><label class='check'><input type='checkbox' id='edit-dns-proxied'{}> proxied</label></div>",
+            options,
+            sub.target,
+            if sub.proxied { " checked" } else { "" }
+        ));
+    }
+    let edit_card = if edit_html.is_empty() || management_token.is_none() {
+        String::new()
+    } else {
+        format!(
+            "<div class='card'><h2>Change Settings</h2>{}<div class='renew-form'><button id='save-settings-btn' onclick='saveSettings()'>Save</button></div><div id='settings-status'></div></div>",
+            edit_html
+        )
+    };
+
+    // Services POST /api/upgrade can add, priced for the time left
+    let mut upgrade_html = String::new();
+    let current = service::services_from_rental(&rental.services);
+    if rental.status.is_active() && !is_expired_iso(&rental.expires_at) {
+        if !current.contains(&ServiceType::EmailForwarding) {
+            upgrade_html.push_str("<div class='edit-row'><label class='check'><input type='checkbox' id='add-email'> Email</label></div>");
+        }
+        if !current.contains(&ServiceType::Subdomain) {
+            upgrade_html.push_str("<div class='edit-row'><label class='check'><input type='checkbox' id='add-dns'> Subdomain</label><select id='add-dns-type'><option>CNAME</option><option>A</option><option>AAAA</option></select><input id='add-dns-target' placeholder='target'></div>");
+        }
+        if !current.contains(&ServiceType::Nip05) {
+            upgrade_html.push_str("<div class='edit-row'><label class='check'><input type='checkbox' id='add-nip05'> NIP-05</label><input id='add-nip05-pubkey' placeholder='npub1... or hex'></div>");
+        }
+        if !current.contains(&ServiceType::LightningAddress) {
+            upgrade_html.push_str("<div class='edit-row'><label class='check'><input type='checkbox' id='add-ln'> Lightning Address</label><input id='add-ln-forward' placeholder='forward to (optional)'></div>");
+        }
+    }
+    let upgrade_card = if upgrade_html.is_empty() || management_token.is_none() {
+        String::new()
+    } else {
+        format!(
+            "<div class='card'><h2>Add Services</h2><div style='font-size:.75rem;color:var(--muted)'>Priced for the time left on this rental; the expiry stays the same.</div>{}<div class='renew-form'><button id='upgrade-btn' onclick='doUpgrade()'>Add</button></div><div id='upgrade-status'></div></div>",
+            upgrade_html
+        )
+    };
+
+    // Secret and token rotation and renewal all go through the management token
+    let (rotate_secret, token_actions) = if management_token.is_some() {
+        (
+            r#"<div class="renew-form"><button id="rotate-btn" onclick="rotateSecret()">Rotate secret</button></div>"#,
+            r#"<div class="card">
+<h2>Management Token</h2>
+<div style="font-size:.75rem;color:var(--muted)">This page's URL is your management token. If it leaked, rotate it: the old URL stops working and this page moves to the new one. Keep it safe; <code>token_rotated</code> webhooks only say that it changed.</div>
+<div class="renew-form"><button id="rotate-token-btn" onclick="rotateToken()">Rotate token</button></div>
+</div>
+<div class="renew-form" id="renew-form">
+<select id="renew-plan">
+<option value="5m">5 Minutes (price varies by services)</option>
+<option value="30m">30 Minutes (price varies by services)</option>
+<option value="1h">1 Hour (price varies by services)</option>
+<option value="1d">1 Day (price varies by services)</option>
+<option value="7d">7 Days (price varies by services)</option>
+<option value="30d" selected>30 Days (price varies by services)</option>
+<option value="90d">90 Days (price varies by services)</option>
+<option value="365d">365 Days (price varies by services)</option>
+</select>
+<button id="renew-btn" onclick="doRenew()">Extend</button>
+</div>
+<div id="renew-status"></div>"#
+                .to_string(),
+        )
+    } else {
+        (
+            "",
+            format!(
+                r#"<div class="card">
+<h2>Management Token</h2>
+<div style="font-size:.75rem;color:var(--muted)">This rental has no management token, so changes, renewals and upgrades are not available here. Sign API requests with your owner key, or issue a token with <code>POST /api/settings/{}/rotate</code> and open the my-page it returns. See <a href="/skill.md">skill.md</a>.</div>
+</div>"#,
+                rental.username
+            ),
+        )
+    };
+
     let plan_label = match rental.plan {
         Plan::FiveMinutes => "5 Minutes",
         Plan::ThirtyMinutes => "30 Minutes",
@@ -1507,6 +1491,7 @@ body{{font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',sans-serif;backgro
 .svc-badge.email{{background:#6d28d9;color:#fff}}
 .svc-badge.dns{{background:#c2410c;color:#fff}}
 .svc-badge.nip{{background:#0e7490;color:#fff}}
+.svc-badge.ln{{background:#ca8a04;color:#fff}}
 h2{{font-size:1rem;margin-bottom:.75rem;color:var(--text)}}
 .extend-link{{display:inline-block;margin-top:1rem;color:var(--purple);font-size:.85rem}}
 .renew-form{{display:flex;gap:.5rem;align-items:center;margin-top:1rem}}
@@ -1514,10 +1499,15 @@ h2{{font-size:1rem;margin-bottom:.75rem;color:var(--text)}}
 .renew-form button{{background:var(--purple);border:none;color:#fff;cursor:pointer;font-weight:600}}
 .renew-form button:hover{{opacity:.9}}
 .renew-form button:disabled{{opacity:.5;cursor:not-allowed}}
-#renew-status{{margin-top:.75rem;font-size:.85rem;color:var(--muted)}}
+#renew-status,#upgrade-status{{margin-top:.75rem;font-size:.85rem;color:var(--muted)}}
+.edit-row{{display:flex;gap:.5rem;align-items:center;padding:.4rem 0;font-size:.85rem;flex-wrap:wrap}}
+.edit-row label{{color:var(--muted);min-width:7rem}}
+.edit-row label.check{{min-width:0}}
+.edit-row input:not([type=checkbox]),.edit-row select{{flex:1;padding:.4rem .6rem;border-radius:6px;border:1px solid var(--border);background:var(--bg);color:var(--text);font-size:.8rem}}
+#settings-status{{margin-top:.5rem;font-size:.8rem;color:var(--muted)}}
 #renew-bolt11{{word-break:break-all;background:var(--bg);padding:.5rem;border-radius:6px;margin-top:.5rem;font-family:monospace;font-size:.75rem}}
 .qr-wrap{{text-align:center;margin:1rem 0}}
-.qr-wrap #renew-qrcode{{display:inline-block;padding:12px;background:#fff;border-radius:8px}}
+.qr-wrap #renew-qrcode,.qr-wrap #upgrade-qrcode{{display:inline-block;padding:12px;background:#fff;border-radius:8px}}
 .bolt11-box{{background:var(--bg);border:1px solid var(--border);border-radius:6px;padding:.75rem;word-break:break-all;font-size:.75rem;color:var(--muted);cursor:pointer;position:relative;margin-bottom:.5rem;font-family:monospace}}
 .bolt11-box:hover{{border-color:var(--purple)}}
 .bolt11-box::after{{content:'click to copy';position:absolute;right:.5rem;top:.5rem;font-size:.65rem;color:var(--purple)}}
@@ -1533,25 +1523,21 @@ h2{{font-size:1rem;margin-bottom:.75rem;color:var(--text)}}
 <div class="row"><span class="label">Status</span><span class="badge" style="background:{status_color};color:#fff">{status}</span></div>
 <div class="row"><span class="label">Expires</span><span class="val">{expires}</span></div>
 <div class="row"><span class="label">Remaining</span><span class="val" id="remaining-val">{days}</span></div>
+{grace}
 </div>
 <div class="card">
 <h2>Active Services</h2>
 {services}
 </div>
-<div class="renew-form" id="renew-form">
-<select id="renew-plan">
-<option value="5m">5 Minutes (price varies by services)</option>
-<option value="30m">30 Minutes (price varies by services)</option>
-<option value="1h">1 Hour (price varies by services)</option>
-<option value="1d">1 Day (price varies by services)</option>
-<option value="7d">7 Days (price varies by services)</option>
-<option value="30d" selected>30 Days (price varies by services)</option>
-<option value="90d">90 Days (price varies by services)</option>
-<option value="365d">365 Days (price varies by services)</option>
-</select>
-<button id="renew-btn" onclick="doRenew()">Extend</button>
+{edit_card}
+{upgrade_card}
+<div class="card">
+<h2>Webhook Signing Secret</h2>
+<div class="bolt11-box" id="signing-secret" onclick="navigator.clipboard.writeText(this.textContent)">{signing_secret}</div>
+<div style="font-size:.75rem;color:var(--muted)">Webhooks carry <code>X-Noscha-Signature: t=&lt;unix&gt;,v1=&lt;hex&gt;</code>, an HMAC-SHA256 of <code>&lt;t&gt;.&lt;body&gt;</code> with this secret. See <a href="/skill.md">skill.md</a>.</div>
+{rotate_secret}
 </div>
-<div id="renew-status"></div>
+{token_actions}
 <script>
 const MGMT_TOKEN="{mgmt_token}";
 const EXPIRES_AT="{expires_at}";
@@ -1580,24 +1566,97 @@ async function doRenew(){{
     const r=await fetch('/api/renew',{{method:'POST',headers:{{'Content-Type':'application/json'}},body:JSON.stringify({{management_token:MGMT_TOKEN,plan:plan}})}});
     if(!r.ok){{const t=await r.text();st.textContent='Error: '+t;btn.disabled=false;return;}}
     const d=await r.json();
-    st.innerHTML='<div style="margin-bottom:.75rem">Pay this invoice to extend:</div><div class="qr-wrap"><div id="renew-qrcode"></div></div><div class="bolt11-box" id="renew-bolt11-box">'+d.bolt11+'</div>';
-    if(typeof QRCode!=='undefined'){{new QRCode(document.getElementById('renew-qrcode'),{{text:d.bolt11,width:220,height:220,colorDark:'#000',colorLight:'#fff',correctLevel:QRCode.CorrectLevel.L}});}}
-    document.getElementById('renew-bolt11-box').onclick=function(){{navigator.clipboard.writeText(d.bolt11).then(function(){{var el=document.getElementById('renew-bolt11-box');var orig=el.textContent;el.textContent='Copied!';setTimeout(function(){{el.textContent=orig;}},1500);}});}};
-    pollOrder(d.order_id);
+    showInvoice(st,'renew','Pay this invoice to extend:',d.bolt11);
+    pollOrder(d.order_id,'renew','Renewed!');
   }}catch(e){{st.textContent='Error: '+e.message;btn.disabled=false;}}
 }}
-async function pollOrder(oid){{
-  const st=document.getElementById('renew-status');
+function showInvoice(st,prefix,label,bolt11){{
+  st.innerHTML='<div style="margin-bottom:.75rem">'+label+'</div><div class="qr-wrap"><div id="'+prefix+'-qrcode"></div></div><div class="bolt11-box" id="'+prefix+'-bolt11-box">'+bolt11+'</div>';
+  if(typeof QRCode!=='undefined'){{new QRCode(document.getElementById(prefix+'-qrcode'),{{text:bolt11,width:220,height:220,colorDark:'#000',colorLight:'#fff',correctLevel:QRCode.CorrectLevel.L}});}}
+  document.getElementById(prefix+'-bolt11-box').onclick=function(){{navigator.clipboard.writeText(bolt11).then(function(){{var el=document.getElementById(prefix+'-bolt11-box');var orig=el.textContent;el.textContent='Copied!';setTimeout(function(){{el.textContent=orig;}},1500);}});}};
+}}
+function checked(id){{
+  const el=document.getElementById(id);
+  return !!(el&&el.checked);
+}}
+async function doUpgrade(){{
+  const btn=document.getElementById('upgrade-btn');
+  const st=document.getElementById('upgrade-status');
+  const services={{}};
+  if(checked('add-email'))services.email={{}};
+  if(checked('add-dns'))services.subdomain={{type:document.getElementById('add-dns-type').value,target:document.getElementById('add-dns-target').value.trim()}};
+  if(checked('add-nip05'))services.nip05={{pubkey:document.getElementById('add-nip05-pubkey').value.trim()}};
+  if(checked('add-ln')){{
+    const fwd=document.getElementById('add-ln-forward').value.trim();
+    services.lightning_address=fwd?{{forward_to:fwd}}:{{}};
+  }}
+  if(!Object.keys(services).length){{st.textContent='Pick a service to add.';return;}}
+  btn.disabled=true;st.textContent='Creating invoice...';
+  try{{
+    const r=await fetch('/api/upgrade',{{method:'POST',headers:{{'Content-Type':'application/json'}},body:JSON.stringify({{management_token:MGMT_TOKEN,services:services}})}});
+    if(!r.ok){{st.textContent='Error: '+await r.text();btn.disabled=false;return;}}
+    const d=await r.json();
+    showInvoice(st,'upgrade','Pay '+d.amount_sats+' sats to add the services:',d.bolt11);
+    pollOrder(d.order_id,'upgrade','Services added!');
+  }}catch(e){{st.textContent='Error: '+e.message;btn.disabled=false;}}
+}}
+async function rotateSecret(){{
+  if(!confirm('Rotate the signing secret? Webhook receivers must be updated with the new one.'))return;
+  const btn=document.getElementById('rotate-btn');
+  btn.disabled=true;
+  try{{
+    const r=await fetch('/api/settings/'+MGMT_TOKEN,{{method:'PUT',headers:{{'Content-Type':'application/json'}},body:JSON.stringify({{rotate_signing_secret:true}})}});
+    if(!r.ok){{alert('Error: '+await r.text());return;}}
+    const d=await r.json();
+    document.getElementById('signing-secret').textContent=d.signing_secret;
+  }}catch(e){{alert('Error: '+e.message);}}
+  finally{{btn.disabled=false;}}
+}}
+function dnsState(){{
+  const t=document.getElementById('edit-dns-type');
+  return t?{{type:t.value,target:document.getElementById('edit-dns-target').value.trim(),proxied:document.getElementById('edit-dns-proxied').checked}}:null;
+}}
+const DNS_ORIG=JSON.stringify(dnsState());
+async function saveSettings(){{
+  const btn=document.getElementById('save-settings-btn');
+  const st=document.getElementById('settings-status');
+  const body={{}};
+  const pk=document.getElementById('edit-pubkey');
+  if(pk&&pk.value.trim()!==pk.dataset.orig)body.nip05_pubkey=pk.value.trim();
+  const dns=dnsState();
+  if(dns&&JSON.stringify(dns)!==DNS_ORIG)body.subdomain=dns;
+  if(!Object.keys(body).length){{st.textContent='Nothing changed.';return;}}
+  btn.disabled=true;st.textContent='Saving...';
+  try{{
+    const r=await fetch('/api/settings/'+MGMT_TOKEN,{{method:'PUT',headers:{{'Content-Type':'application/json'}},body:JSON.stringify(body)}});
+    if(!r.ok){{st.textContent='Error: '+await r.text();return;}}
+    st.textContent='Saved. Reloading...';setTimeout(()=>location.reload(),800);
+  }}catch(e){{st.textContent='Error: '+e.message;}}
+  finally{{btn.disabled=false;}}
+}}
+async function rotateToken(){{
+  if(!confirm('Rotate the management token? This page moves to a new URL and the old one stops working.'))return;
+  const btn=document.getElementById('rotate-token-btn');
+  btn.disabled=true;
+  try{{
+    const r=await fetch('/api/settings/'+MGMT_TOKEN+'/rotate',{{method:'POST'}});
+    if(!r.ok){{alert('Error: '+await r.text());btn.disabled=false;return;}}
+    const d=await r.json();
+    location.replace('/my/'+d.management_token);
+  }}catch(e){{alert('Error: '+e.message);btn.disabled=false;}}
+}}
+async function pollOrder(oid,prefix,done){{
+  const st=document.getElementById(prefix+'-status');
   for(let i=0;i<120;i++){{
     await new Promise(r=>setTimeout(r,3000));
     try{{
       const r=await fetch('/api/order/'+oid+'/status');
       const d=await r.json();
-      if(d.status==='provisioned'){{st.textContent='Renewed! Reloading...';setTimeout(()=>location.reload(),1000);return;}}
+      if(d.status==='provisioned'){{st.textContent=done+' Reloading...';setTimeout(()=>location.reload(),1000);return;}}
     }}catch(e){{}}
   }}
   st.textContent='Invoice may have expired. Please try again.';
-  document.getElementById('renew-btn').disabled=false;
+  document.getElementById(prefix+'-btn').disabled=false;
 }}
 </script>
 </div>
@@ -1609,87 +1668,116 @@ async function pollOrder(oid){{
         status = display_status,
         expires = &rental.expires_at[..10.min(rental.expires_at.len())],
         days = days_remaining,
+        grace = grace_html,
         services = services_html,
-        mgmt_token = management_token,
+        edit_card = edit_card,
+        upgrade_card = upgrade_card,
+        signing_secret = rental.signing_secret.as_deref().unwrap_or("Not set — rotate to create one"),
+        rotate_secret = rotate_secret,
+        token_actions = token_actions,
+        mgmt_token = management_token.unwrap_or_default(),
         expires_at = &rental.expires_at,
     )
 }
 
-/// Cleanup expired DNS records by scanning R2 rentals
+/// Query the payment backend for pending orders whose webhook never arrived
 #[cfg(target_arch = "wasm32")]
-async fn cleanup_expired_dns(env: &Env) -> Result<()> {
-    let bucket = env.bucket("BUCKET")?;
-    let now_ms = js_sys::Date::now();
+async fn poll_pending_payments(env: &Env) -> Result<()> {
+    let provider = payment::from_env(env).map_err(Error::RustError)?;
+    if provider.is_mock() {
+        return Ok(());
+    }
+    let store = R2Store::from_env(env)?;
+    let dns = CloudflareDns::from_env(env);
+    let now_ms = js_sys::Date::now() as u64;
 
-    let list = bucket.list().prefix("rentals/").execute().await?;
+    let report = service::poll_pending_orders(&store, &dns, &provider, ids::management_token, now_ms)
+        .await
+        .map_err(Error::RustError)?;
+    for provisioned in &report.provisioned {
+        console_log!("Provisioned order {} via payment poll", provisioned.order.order_id);
+        notify_provisioned(env, provisioned).await;
+    }
+    for err in &report.errors {
+        console_log!("{}", err);
+    }
 
-    for obj_entry in list.objects() {
-        let key = obj_entry.key();
-        if let Some(obj) = bucket.get(&key).execute().await? {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            if let Ok(mut rental) = serde_json::from_str::<Rental>(&text) {
-                if rental.status != "active" {
-                    continue;
-                }
+    Ok(())
+}
 
-                // Parse expires_at as JS Date to compare
-                let expires_date =
-                    js_sys::Date::new(&rental.expires_at.clone().into());
-                let expires_ms = expires_date.get_time();
+/// Mark unconfirmed and unpaid orders expired once they can no longer complete
+#[cfg(target_arch = "wasm32")]
+async fn expire_stale_orders(env: &Env) -> Result<()> {
+    let store = R2Store::from_env(env)?;
+    let now_ms = js_sys::Date::now() as u64;
+    for order_id in service::expire_stale_orders(&store, now_ms).await.map_err(Error::RustError)? {
+        console_log!("Expired unpaid order: {}", order_id);
+    }
+    Ok(())
+}
 
-                if expires_ms > now_ms {
-                    continue; // not yet expired
-                }
+/// Send expiring_soon reminders for rentals that crossed a REMINDER_THRESHOLDS threshold
+#[cfg(target_arch = "wasm32")]
+async fn send_expiry_reminders(env: &Env) -> Result<()> {
+    let store = R2Store::from_env(env)?;
+    let value = env.var("REMINDER_THRESHOLDS").ok().map(|v| v.to_string());
+    let thresholds = service::reminder_thresholds_from(value.as_deref());
+    let now_ms = js_sys::Date::now() as u64;
 
-                console_log!(
-                    "Cleaning up expired rental: {}",
-                    rental.username
-                );
-
-                // Delete DNS record if present
-                if let Some(ref sub) = rental.services.subdomain {
-                    if let Some(ref record_id) = sub.cf_record_id {
-                        let zone_id = env
-                            .var("CF_ZONE_ID")
-                            .map(|v| v.to_string())
-                            .unwrap_or_default();
-
-                        if !zone_id.is_empty() {
-                            let is_mock = dns_mock::is_mock_dns_enabled(env);
-                            let result = if is_mock {
-                                dns_mock::delete_dns_record(
-                                    &zone_id, "", record_id,
-                                )
-                                .await
-                            } else {
-                                let token =
-                                    env.secret("CF_API_TOKEN")?.to_string();
-                                dns::delete_dns_record(
-                                    &zone_id, &token, record_id,
-                                )
-                                .await
-                            };
-
-                            if let Err(e) = result {
-                                console_log!(
-                                    "Failed to delete DNS record {} for {}: {:?}",
-                                    record_id,
-                                    rental.username,
-                                    e
-                                );
-                            }
-                        }
-                    }
-                }
+    let due = service::collect_expiry_reminders(&store, &thresholds, now_ms)
+        .await
+        .map_err(Error::RustError)?;
+    for reminder in &due {
+        console_log!("Expiry reminder ({}m) for {}", reminder.threshold_minutes, reminder.rental.username);
+        send_expiring_soon_webhook(env, reminder, now_ms).await;
+    }
+    Ok(())
+}
 
-                // Mark rental as expired
-                rental.status = "expired".to_string();
-                let updated_json = serde_json::to_string(&rental)
-                    .map_err(|e| Error::RustError(e.to_string()))?;
-                bucket.put(&key, updated_json).execute().await?;
-            }
-        }
+/// Retry failed webhook deliveries whose backoff has elapsed, then prune old records
+#[cfg(target_arch = "wasm32")]
+async fn retry_webhook_deliveries(env: &Env) -> Result<()> {
+    let store = R2Store::from_env(env)?;
+    let now_ms = js_sys::Date::now() as u64;
+
+    let report = webhook::retry_due_deliveries(&store, &http::FetchClient, now_ms)
+        .await
+        .map_err(Error::RustError)?;
+    for id in &report.delivered {
+        console_log!("Webhook delivery {} succeeded on retry", id);
+    }
+    for id in &report.dead {
+        console_log!("Webhook delivery {} dead-lettered", id);
+    }
+    let pruned = webhook::prune_deliveries(&store, now_ms).await.map_err(Error::RustError)?;
+    if pruned > 0 {
+        console_log!("Pruned {} old webhook deliveries", pruned);
+    }
+    Ok(())
+}
+
+/// Cleanup expired DNS records by scanning R2 rentals
+#[cfg(target_arch = "wasm32")]
+async fn cleanup_expired_dns(env: &Env) -> Result<()> {
+    let store = R2Store::from_env(env)?;
+    let dns = CloudflareDns::from_env(env);
+    let now_ms = js_sys::Date::now() as u64;
+
+    let report = service::expire_rentals(&store, &dns, grace_minutes(env), now_ms)
+        .await
+        .map_err(Error::RustError)?;
+    for rental in &report.grace {
+        console_log!("Rental entered grace period: {}", rental.username);
+        send_grace_period_webhook(env, rental).await;
+    }
+    for username in &report.expired {
+        console_log!("Cleaned up expired rental: {}", username);
+    }
+    for rental in &report.dns_removed {
+        send_dns_changed_webhook(env, rental, "removed").await;
+    }
+    for err in &report.dns_errors {
+        console_log!("{}", err);
     }
 
     Ok(())
@@ -1709,21 +1797,6 @@ fn format_sats(n: u64) -> String {
     result.chars().rev().collect()
 }
 
-/// Parse a period key like "5m", "1h", "1d", "7d" into duration in minutes
-#[cfg(target_arch = "wasm32")]
-fn period_to_minutes(period: &str) -> u64 {
-    let s = period.trim();
-    if let Some(num) = s.strip_suffix('m') {
-        num.parse::<u64>().unwrap_or(0)
-    } else if let Some(num) = s.strip_suffix('h') {
-        num.parse::<u64>().unwrap_or(0) * 60
-    } else if let Some(num) = s.strip_suffix('d') {
-        num.parse::<u64>().unwrap_or(0) * 1440
-    } else {
-        s.parse::<u64>().unwrap_or(0)
-    }
-}
-
 /// Generate a human-readable label from duration in minutes
 #[cfg(target_arch = "wasm32")]
 fn minutes_to_label(mins: u64) -> String {
@@ -1750,22 +1823,24 @@ fn minutes_to_label(mins: u64) -> String {
 /// Generate /llms.txt content with dynamic pricing
 #[cfg(target_arch = "wasm32")]
 fn generate_llms_txt(pricing: &std::collections::HashMap<String, std::collections::HashMap<String, u64>>) -> String {
-    let static_part = "# noscha.io\n\n> Disposable email, subdomain & NIP-05 identity - paid via Lightning Network\n\n## API\n\n- Base: https://noscha.io\n- Check username: GET /api/check/{username}\n- Create order: POST /api/order {\"username\",\"plan\",\"services\":{...}}\n- Order status: GET /api/order/{order_id}/status\n- Renew: POST /api/renew {\"management_token\",\"plan\"}\n- Pricing: GET /api/pricing\n- Services: email, subdomain, nip05 (or bundle all 3)\n- Payment: Lightning Network (bolt11)\n- Full docs: https://noscha.io/skill.md\n- OpenAPI spec: https://noscha.io/api/docs\n\n## Pricing (sats)\n\n";
+    let static_part = "# noscha.io\n\n> Disposable email, subdomain & NIP-05 identity - paid via Lightning Network\n\n## API\n\n- Base: https://noscha.io\n- Check username: GET /api/check/{username}\n- Create order: POST /api/order {\"username\",\"plan\",\"services\":{...}}\n- Order status: GET /api/order/{order_id}/status\n- Renew: POST /api/renew {\"management_token\",\"plan\"}\n- Add services: POST /api/upgrade {\"management_token\",\"services\":{...}}\n- Pricing: GET /api/pricing\n- Services: email, subdomain, nip05 (or bundle all 3)\n- Payment: Lightning Network (bolt11)\n- Full docs: https://noscha.io/skill.md\n- OpenAPI spec: https://noscha.io/api/docs\n\n## Pricing (sats)\n\n";
     let mut result = static_part.to_string();
 
     let mut periods: Vec<(&String, &std::collections::HashMap<String, u64>)> = pricing.iter().collect();
-    periods.sort_by_key(|(k, v)| v.get("_duration_minutes").copied().unwrap_or_else(|| period_to_minutes(k)));
+    periods.sort_by_key(|(k, v)| v.get("_duration_minutes").copied().unwrap_or_else(|| service::period_to_minutes(k)));
 
     for (period_key, services) in &periods {
-        let mins = services.get("_duration_minutes").copied().unwrap_or_else(|| period_to_minutes(period_key));
+        let mins = services.get("_duration_minutes").copied().unwrap_or_else(|| service::period_to_minutes(period_key));
         let label = minutes_to_label(mins);
         let subdomain = services.get("subdomain").copied().unwrap_or(0);
         let email = services.get("email").copied().unwrap_or(0);
         let nip05 = services.get("nip05").copied().unwrap_or(0);
+        let nip05_name = services.get("nip05_name").copied().unwrap_or(nip05 / 2);
+        let lightning = services.get("lightning_address").copied().unwrap_or(nip05);
         let bundle = services.get("bundle").copied().unwrap_or(0);
         result.push_str(&format!(
-            "{}: Subdomain={}, Email={}, NIP-05={}, Bundle={}\n",
-            label, subdomain, email, nip05, bundle
+            "{}: Subdomain={}, Email={}, NIP-05={}, Extra NIP-05 name={}, Lightning Address={}, Bundle={}\n",
+            label, subdomain, email, nip05, nip05_name, lightning, bundle
         ));
     }
 
@@ -1816,7 +1891,7 @@ Content-Type: application/json
   "services": {
     "email": {},
     "subdomain": {"type": "CNAME", "target": "mysite.example.com", "proxied": false},
-    "nip05": {"pubkey": "abc123...hex"}
+    "nip05": {"pubkey": "npub1... or hex"}
   }
 }
 ```
@@ -1829,7 +1904,8 @@ Content-Type: application/json
   "bolt11": "",
   "expires_at": "2026-02-11T12:15:00Z",
   "status": "webhook_pending",
-  "message": "Check your webhook for the challenge URL. Visit it to confirm and get an invoice."
+  "message": "Check your webhook for the challenge URL. Visit it to confirm and get an invoice.",
+  "webhook_signing_secret": "whsec_..."
 }
 ```
 
@@ -1840,7 +1916,72 @@ A challenge POST is sent to your `webhook_url`:
 
 After payment is confirmed, a completion notice is POSTed to your `webhook_url`:
 ```json
-{"event": "payment_completed", "order_id": "...", "username": "...", "management_token": "...", "my_page_url": "https://noscha.io/my/mgmt_xxx", "expires_at": "...", "plan": "...", "amount_sats": ..., "is_renewal": false, "services": {"email": true, "subdomain": true, "nip05": true}}
+{"event": "payment_completed", "order_id": "...", "username": "...", "management_token": "...", "my_page_url": "https://noscha.io/my/mgmt_xxx", "expires_at": "...", "plan": "...", "amount_sats": ..., "is_renewal": false, "is_upgrade": false, "services": {"email": true, "subdomain": true, "nip05": true}, "nip05_npub": "npub1..."}
+```
+
+Later lifecycle events go to the same `webhook_url`:
+```json
+{"event": "expiring_soon", "username": "...", "expires_at": "...", "threshold_minutes": 1440, "minutes_remaining": 1432, "my_page_url": "..."}
+{"event": "grace_period_started", "username": "...", "expires_at": "...", "grace_until": "...", "my_page_url": "..."}
+{"event": "dns_changed", "username": "...", "hostname": "alice.noscha.io", "action": "removed"|"restored", "record_type": "A", "target": "...", "expires_at": "..."}
+```
+
+### Webhook endpoints
+
+`webhook_url` receives every event. To route events elsewhere, add up to 10 endpoints with `PUT /api/settings/{management_token}`, each with the events it wants:
+```json
+{"webhooks": [
+  {"url": "https://mail.example.com/hook", "events": ["email_received"]},
+  {"url": "https://billing.example.com/hook", "events": ["payment_completed", "renewed"]}
+]}
+```
+Events: `payment_completed` (every payment), `renewed` (payment notices for renewals only), `email_received`, `expiring_soon` (also `grace_period_started`), `dns_changed`, `token_rotated`, `settings_changed`. Each URL gets one copy of a notification even if several of its events match. The list is replaced as a whole; `[]` removes all endpoints.
+
+### Nostr DMs
+
+Rentals with a NIP-05 pubkey can also get the challenge, payment, expiry and email notifications as NIP-17 encrypted DMs (gift-wrapped kind 14 messages signed by the noscha.io service key) in any Nostr client. Add `"nostr_dm": true` to the order, or set it later with `PUT /api/settings/{management_token}` `{"nostr_dm": true}` (`false` turns DMs off). DMs come in addition to webhooks.
+
+### Owner key (NIP-98)
+
+Add `"owner_pubkey": "<npub or hex pubkey>"` to the order (or set it later with `PUT /api/settings/{management_token}`, `null` to clear) to manage the rental with that Nostr key instead of the management token. Get a one-time challenge from `POST /api/owner/challenge`, then sign a kind 27235 event with tags `["u", "<full request URL>"]`, `["method", "<HTTP method>"]`, `["challenge", "<challenge>"]` and, for requests with a body, `["payload", "<hex sha256 of the body>"]`. Send it base64-encoded as `Authorization: Nostr <base64 event>`, with the username where the management token would go:
+- `PUT /api/settings/{username}`
+- `GET /my/{username}` (read-only for a rental without a management token until `POST /api/settings/{username}/rotate` issues one)
+- `POST /api/renew` with `{"username": "alice", "plan": "30d"}`
+- `POST /api/upgrade` with `{"username": "alice", "services": {...}}`
+
+The event must be signed within 60 seconds of the request.
+
+### Extra NIP-05 names
+
+A NIP-05 rental can carry up to 10 extra names, each mapped to its own pubkey. Add them to the order as `"nip05": {"pubkey": "hex", "names": {"bot": "hex", "news.alice": "hex"}}`; each name costs the "Extra NIP-05 name" price for the plan, at order time and at every renewal. Names use a-z, 0-9, `-`, `_` and `.`, and must not be taken as a username or by another rental. Resolve them as `bot@noscha.io`. On your subdomain, `_@alice.noscha.io` (shown by clients as `alice.noscha.io`) resolves to your main pubkey, and `news@alice.noscha.io` resolves the extra name `news` or `news.alice`. Change the names later with `PUT /api/settings/{management_token}` `{"nip05_names": {...}}`, up to the number of names paid for.
+
+### Lightning Address
+
+Add `"lightning_address": {}` to the services to receive Lightning payments and zaps at `alice@noscha.io` (LUD-16). Invoices are created by the noscha.io payment backend; zaps (NIP-57) get a zap receipt signed by the noscha.io service key. To have payments go straight to your own wallet, set `"lightning_address": {"forward_to": "you@wallet.example"}` (a lightning address, `lnurl1...` or an https LNURL-pay URL); `/.well-known/lnurlp/alice` then serves your wallet's pay request. Priced per plan like the other services and not part of the bundle.
+
+### Chat and push services
+
+Webhook and endpoint URLs for these services get a native message instead of the raw JSON event:
+- Discord: `https://discord.com/api/webhooks/...`
+- Slack: `https://hooks.slack.com/services/...`
+- Telegram: `https://api.telegram.org/bot<token>/sendMessage?chat_id=<id>`
+- Matrix hookshot: `https://hookshot.example.org/webhook/...`
+- ntfy: `https://ntfy.sh/<topic>` (or any `ntfy.*` host)
+
+### Webhook signatures
+
+Raw JSON webhooks carry an `X-Noscha-Signature` header (chat-service formats below are not signed):
+```
+X-Noscha-Signature: t=1760000000,v1=5f2b...e9
+```
+`v1` is the hex HMAC-SHA256 of `"{t}." + raw body`, keyed with your rental's signing secret. The secret is returned as `webhook_signing_secret` when the order is created (so the challenge can be verified), shown on your my-page, and rotated with `PUT /api/settings/{management_token}` `{"rotate_signing_secret": true}`. Reject requests whose `t` is more than 5 minutes from your clock.
+
+Rust receivers can use the crate's verification helper:
+```rust
+use noscha_io::webhook::verify_signature;
+
+let header = headers.get("X-Noscha-Signature").unwrap_or_default();
+verify_signature(&signing_secret, header, &raw_body, unix_now_secs)?; // Err(reason) if invalid
 ```
 
 ### Step 2b: Confirm webhook & get invoice
@@ -1861,7 +2002,9 @@ GET /api/order/{order_id}/confirm/{challenge}
 }
 ```
 
-You can include any combination of services (`email`, `subdomain`, `nip05`). `webhook_url` is **required** for all orders - email notifications are delivered via webhook.
+You can include any combination of services (`email`, `subdomain`, `nip05`, `lightning_address`). `webhook_url` is **required** for all orders - email notifications are delivered via webhook.
+
+Webhook URLs must be `https://` on the default port and point at a public host (no localhost, private, link-local or cloud metadata addresses). Subdomain targets follow the same rule: `A`/`AAAA` need a public IP address, `CNAME` a public hostname.
 
 ### Step 3: Pay the invoice & poll for status
 
@@ -1895,7 +2038,8 @@ Create a new rental order. Returns a Lightning invoice.
 - **plan**: `"1d"` | `"7d"` | `"30d"` | `"90d"` | `"365d"`
 - **services.email**: `{}`
 - **services.subdomain**: `{"type": "A"|"AAAA"|"CNAME", "target": string, "proxied"?: bool}`
-- **services.nip05**: `{"pubkey": "hex_pubkey"}`
+- **services.nip05**: `{"pubkey": "npub1..."}`; the pubkey may be 64-char hex, `npub` or `nprofile` (its relays become nostr.json relay hints, as does an optional `"relays": ["wss://..."]`). Extra name pubkeys and `owner_pubkey` also accept `npub`
+- **services.lightning_address**: `{}`, or `{"forward_to": "you@wallet.example"}` to pass payments on to your own lightning address, `lnurl1...` or https LNURL-pay URL
 - Returns `{"order_id", "amount_sats", "bolt11", "expires_at", "management_token"?}`
 - Invoice expires in 15 minutes
 
@@ -1907,9 +2051,19 @@ Poll order status after payment.
 ### POST /api/renew
 Extend an existing rental.
 - **Body**: `{"management_token": string, "plan": string, "services"?: {...}}`
+- Or authenticate as the owner with `Authorization: Nostr ...` and send `"username"` instead of `"management_token"` (see Owner key)
 - Returns `{"order_id", "amount_sats", "bolt11", "expires_at"}`
 - Time is added on top of current expiry (not from now)
 
+### POST /api/upgrade
+Add services to an active rental without changing its expiry.
+- **Body**: `{"management_token": string, "services": {...}}`, with services as in `POST /api/order` (e.g. `{"subdomain": {"type": "CNAME", "target": "example.com"}}`)
+- Or authenticate as the owner with `Authorization: Nostr ...` and send `"username"` instead of `"management_token"` (see Owner key)
+- Returns `{"order_id", "amount_sats", "bolt11", "expires_at", "rental_expires_at"}`
+- The price is what the services add to the rental's plan price (bundle included), prorated for the time left and rounded up
+- Services the rental already has are rejected with 409, as are rentals that have expired (renew first)
+- Once paid the services are live; `payment_completed` is sent with `"is_upgrade": true`
+
 ### GET /api/pricing
 Get current pricing for all plans and services.
 - Returns pricing matrix: `{"1d": {"subdomain": 500, "email": 1500, "nip05": 200, "bundle": 1800}, ...}`
@@ -1922,6 +2076,8 @@ Health check. Returns `{"status": "ok", "version": "..."}`.
 
 ### GET /.well-known/nostr.json?name={username}
 NIP-05 verification endpoint (Nostr protocol).
+- Returns `{"names": {"alice": "<pubkey>"}, "relays": {"<pubkey>": [...]}, "nip46": {"<pubkey>": [...]}}`; `relays` is empty and `nip46` absent until set with `PUT /api/settings/{management_token}`
+- Extra names a rental paid for resolve the same way; on a rental subdomain (`alice.noscha.io`), `name=_` returns the rental's main pubkey
 
 ### GET /api/mail/{token}
 Retrieve an email from the inbox using the token received via webhook notification.
@@ -1939,12 +2095,28 @@ Send an email via Resend API from your noscha.io email address.
 - Returns `{"success": true, "message_id": "resend_message_id"}` on success
 
 ### PUT /api/settings/{management_token}
-Update rental settings, currently supports setting webhook URL for email notifications.
+Update rental settings. Fields left out are unchanged.
 - **management_token**: Your rental's management token for authentication
-- **Body**: `{"webhook_url": "https://your-server.com/webhook"}` (or null to disable)
-- When webhook_url is set, incoming emails will trigger a POST to this URL
+- **Body**: `{"webhook_url": "https://your-server.com/webhook"}` (or null to disable), `{"webhooks": [{"url": "...", "events": ["email_received"]}]}` to replace the extra endpoints (see Webhook endpoints), `{"rotate_signing_secret": true}` to issue a new webhook signing secret (returned as `signing_secret`), `{"nostr_dm": true}` to also get notifications as NIP-17 DMs to your NIP-05 pubkey, `{"owner_pubkey": "hex"}` (or null) to set the key allowed to manage the rental, `{"nip05_relays": ["wss://..."], "nip46_relays": ["wss://..."]}` to replace the relay hints and NIP-46 bunker relays published in nostr.json (NIP-05 rentals only; up to 5 public `wss://` URLs of at most 200 characters each, `[]` clears), `{"nip05_names": {"bot": "hex"}}` to replace the extra NIP-05 names (up to the number paid for; see Extra NIP-05 names), `{"nip05_pubkey": "npub1..."}` to point your NIP-05 name at a new key (hex, `npub` or `nprofile`), `{"subdomain": {"type": "A"|"AAAA"|"CNAME", "target": "...", "proxied"?: bool}}` to change the subdomain's DNS record (checked as at order time and applied at once)
+- Returns the current settings and `"changed": [...]`, the fields the request changed. Webhook URLs and endpoints subscribed to `settings_changed` then get `{"event": "settings_changed", "username": "...", "changed": ["subdomain"], "my_page_url": "...", "nip05_npub": "npub1..."|null, "subdomain": {"type", "target", "proxied"}|null}`
+- With `Authorization: Nostr ...` from the owner key, use the username in place of the management token
+- When webhook_url is set, incoming emails will trigger a POST to this URL, as they do for endpoints subscribed to `email_received`
 - Webhook payload: `{"event": "email_received", "from": "sender@example.com", "to": "you@noscha.io", "subject": "...", "url": "https://noscha.io/api/mail/{token}", "received_at": "2026-02-11T..."}`
 
+### POST /api/settings/{management_token}/rotate
+Replace a leaked management token.
+- Returns `{"success": true, "management_token": "mgmt_...", "my_page_url": "https://noscha.io/my/mgmt_..."}`
+- The old token and my-page URL stop working immediately
+- Webhook URLs and endpoints subscribed to `token_rotated` get `{"event": "token_rotated", "username": "...", "rotated_by": "owner"|"admin"}`. The new token is never sent in notifications, since whoever held the old one may control the webhooks; keep it from this response (after an admin rotation, ask the admin or open `/my/{username}` with the owner key)
+- Also accepts `Authorization: Nostr ...` from the owner key with the username in the path
+
+### GET /api/webhooks/deliveries
+Recent webhook deliveries (newest first, up to 50) for your rental.
+- **Header**: `Authorization: Bearer <management_token>`
+- Returns `{"deliveries": [{"id", "event", "url", "status": "pending"|"delivered"|"dead", "created_at", "next_attempt_at"?, "attempts": [{"at", "status_code"?, "error"?}], ...}]}`
+- Failed deliveries are retried with exponential backoff (5 minutes, doubling, up to 6 hours) and marked `dead` after 8 attempts
+- Delivered and dead deliveries are kept for 7 days (at most the newest 100)
+
 ## Pricing (sats, Lightning Network)
 
 "#;
@@ -1981,6 +2153,7 @@ Get a NIP-05 identity from noscha.io and use it instantly with nostaro for a com
 6. **GET /api/order/{order_id}/status** - poll until `"provisioned"` (poll every 3s, max ~5 min)
 7. **Store** the `management_token` for future renewals
 8. **POST /api/renew** when rental is nearing expiry
+9. **POST /api/upgrade** to add a service mid-rental
 
 ## Limitations
 
@@ -1989,6 +2162,8 @@ Get a NIP-05 identity from noscha.io and use it instantly with nostaro for a com
 - Lightning Network payments only (Bitcoin)
 - No refunds (disposable service by design)
 - DNS propagation may take up to 5 minutes after provisioning
+- Rentals with a `webhook_url` (or NIP-17 DMs turned on) get an `expiring_soon` reminder 7 days, 1 day and 1 hour before expiry (thresholds shorter than the plan)
+- Expired rentals enter a grace period (3 days by default): services stop, but only the `management_token` holder can renew the username until `grace_until`, after which it is released. A `grace_period_started` webhook is sent when it begins
 - - All services under one username share the same expiry date
 
 ## Terms of Service (Summary)
@@ -2001,21 +2176,23 @@ Get a NIP-05 identity from noscha.io and use it instantly with nostaro for a com
 - Service availability is best-effort
 "#;
 
-    let mut table = String::from("| Plan | Subdomain | Email | NIP-05 | Bundle (all 3) |\n|------|-----------|-------|--------|------------------|\n");
+    let mut table = String::from("| Plan | Subdomain | Email | NIP-05 | Extra NIP-05 name | Lightning Address | Bundle (all 3) |\n|------|-----------|-------|--------|-------------------|-------------------|------------------|\n");
 
     let mut periods: Vec<(&String, &std::collections::HashMap<String, u64>)> = pricing.iter().collect();
-    periods.sort_by_key(|(k, v)| v.get("_duration_minutes").copied().unwrap_or_else(|| period_to_minutes(k)));
+    periods.sort_by_key(|(k, v)| v.get("_duration_minutes").copied().unwrap_or_else(|| service::period_to_minutes(k)));
 
     for (period_key, services) in &periods {
-        let mins = services.get("_duration_minutes").copied().unwrap_or_else(|| period_to_minutes(period_key));
+        let mins = services.get("_duration_minutes").copied().unwrap_or_else(|| service::period_to_minutes(period_key));
         let label = minutes_to_label(mins);
         let subdomain = services.get("subdomain").copied().unwrap_or(0);
         let email = services.get("email").copied().unwrap_or(0);
         let nip05 = services.get("nip05").copied().unwrap_or(0);
+        let nip05_name = services.get("nip05_name").copied().unwrap_or(nip05 / 2);
+        let lightning = services.get("lightning_address").copied().unwrap_or(nip05);
         let bundle = services.get("bundle").copied().unwrap_or(0);
         table.push_str(&format!(
-            "| {} | {} | {} | {} | {} |\n",
-            label, format_sats(subdomain), format_sats(email), format_sats(nip05), format_sats(bundle)
+            "| {} | {} | {} | {} | {} | {} | {} |\n",
+            label, format_sats(subdomain), format_sats(email), format_sats(nip05), format_sats(nip05_name), format_sats(lightning), format_sats(bundle)
         ));
     }
 
@@ -2025,8 +2202,8 @@ Get a NIP-05 identity from noscha.io and use it instantly with nostaro for a com
 /// GET /llms.txt — dynamic pricing
 #[cfg(target_arch = "wasm32")]
 async fn handle_llms_txt(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let pricing = admin::load_pricing(&bucket).await;
+    let store = R2Store::from_env(&ctx.env)?;
+    let pricing = store.load_pricing().await;
     let content = generate_llms_txt(&pricing);
     let headers = Headers::new();
     let _ = headers.set("Content-Type", "text/plain; charset=utf-8");
@@ -2037,11 +2214,9 @@ async fn handle_llms_txt(_req: Request, ctx: RouteContext<()>) -> Result<Respons
 /// GET /favicon.ico — serve favicon from R2
 #[cfg(target_arch = "wasm32")]
 async fn handle_favicon_ico(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    match bucket.get("static/favicon.ico").execute().await? {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let bytes = body.bytes().await?;
+    let store = R2Store::from_env(&ctx.env)?;
+    match store.get("static/favicon.ico").await.map_err(Error::RustError)? {
+        Some(bytes) => {
             let headers = Headers::new();
             let _ = headers.set("Content-Type", "image/x-icon");
             let _ = headers.set("Cache-Control", "public, max-age=604800");
@@ -2054,11 +2229,9 @@ async fn handle_favicon_ico(_req: Request, ctx: RouteContext<()>) -> Result<Resp
 /// GET /favicon.png — serve favicon PNG from R2
 #[cfg(target_arch = "wasm32")]
 async fn handle_favicon_png(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    match bucket.get("static/favicon.png").execute().await? {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let bytes = body.bytes().await?;
+    let store = R2Store::from_env(&ctx.env)?;
+    match store.get("static/favicon.png").await.map_err(Error::RustError)? {
+        Some(bytes) => {
             let headers = Headers::new();
             let _ = headers.set("Content-Type", "image/png");
             let _ = headers.set("Cache-Control", "public, max-age=604800");
@@ -2071,11 +2244,9 @@ async fn handle_favicon_png(_req: Request, ctx: RouteContext<()>) -> Result<Resp
 /// GET /og-image.png — serve OG image from R2
 #[cfg(target_arch = "wasm32")]
 async fn handle_og_image(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    match bucket.get("static/og-image.png").execute().await? {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let bytes = body.bytes().await?;
+    let store = R2Store::from_env(&ctx.env)?;
+    match store.get("static/og-image.png").await.map_err(Error::RustError)? {
+        Some(bytes) => {
             let headers = Headers::new();
             let _ = headers.set("Content-Type", "image/png");
             let _ = headers.set("Cache-Control", "public, max-age=86400");
@@ -2088,8 +2259,8 @@ async fn handle_og_image(_req: Request, ctx: RouteContext<()>) -> Result<Respons
 /// GET /skill.md — dynamic pricing
 #[cfg(target_arch = "wasm32")]
 async fn handle_skill_md(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let pricing = admin::load_pricing(&bucket).await;
+    let store = R2Store::from_env(&ctx.env)?;
+    let pricing = store.load_pricing().await;
     let content = generate_skill_md(&pricing);
     let headers = Headers::new();
     let _ = headers.set("Content-Type", "text/markdown; charset=utf-8");
@@ -2167,19 +2338,28 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         .post_async("/api/order", handle_create_order)
         .get_async("/api/order/:order_id/confirm/:challenge", handle_confirm_webhook)
         .get_async("/api/order/:order_id/status", handle_order_status)
-        .post_async("/api/webhook/coinos", handle_coinos_webhook)
+        .post_async("/api/webhook/:provider", handle_payment_webhook)
+        .post_async("/api/owner/challenge", handle_owner_challenge)
         .post_async("/api/renew", handle_renew)
+        .post_async("/api/upgrade", handle_upgrade)
         .put_async("/api/settings/:token", handle_settings_update)
+        .post_async("/api/settings/:token/rotate", handle_token_rotate)
+        .get_async("/api/webhooks/deliveries", handle_webhook_deliveries)
+        .post_async("/internal/nostr-dm/email", handle_internal_email_dm)
         .get_async("/my/:token", handle_my_page)
         .get_async("/api/pricing", handle_public_pricing)
         .get_async("/.well-known/nostr.json", handle_nip05)
         .options_async("/.well-known/nostr.json", handle_nip05_options)
+        .get_async("/.well-known/lnurlp/:username", handle_lnurlp)
+        .get_async("/api/lnurlp/:username/callback", handle_lnurlp_callback)
         // Admin routes
         .get_async("/admin", handle_admin_page)
         .post_async("/api/admin/challenge", handle_admin_challenge)
         .post_async("/api/admin/login", handle_admin_login)
         .get_async("/api/admin/rentals", handle_admin_rentals)
         .put_async("/api/admin/rentals/:username/webhook", handle_admin_rental_webhook_put)
+        .get_async("/api/admin/rentals/:username/webhooks", handle_admin_webhook_deliveries)
+        .post_async("/api/admin/rentals/:username/webhooks/:delivery_id/replay", handle_admin_webhook_replay)
         .get_async("/api/admin/stats", handle_admin_stats)
         .get_async("/api/admin/pricing", handle_admin_pricing_get)
         .put_async("/api/admin/pricing", handle_admin_pricing_put)
@@ -2189,7 +2369,10 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         .post_async("/api/admin/unban/:username", handle_admin_unban)
         .post_async("/api/admin/extend/:username", handle_admin_extend)
         .post_async("/api/admin/revoke/:username", handle_admin_revoke)
+        .post_async("/api/admin/rentals/:username/rotate-token", handle_admin_rotate_token)
+        .post_async("/api/admin/orders/:order_id/status", handle_admin_order_status)
         .post_async("/api/admin/provision", handle_admin_provision)
+        .post_async("/api/admin/backfill-tokens", handle_admin_backfill_tokens)
         .run(req, env)
         .await
 }
@@ -2197,6 +2380,18 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
 #[cfg(target_arch = "wasm32")]
 #[event(scheduled)]
 async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    if let Err(e) = poll_pending_payments(&env).await {
+        console_log!("Error during payment poll: {:?}", e);
+    }
+    if let Err(e) = expire_stale_orders(&env).await {
+        console_log!("Error during order expiry: {:?}", e);
+    }
+    if let Err(e) = retry_webhook_deliveries(&env).await {
+        console_log!("Error during webhook retries: {:?}", e);
+    }
+    if let Err(e) = send_expiry_reminders(&env).await {
+        console_log!("Error during expiry reminders: {:?}", e);
+    }
     if let Err(e) = cleanup_expired_dns(&env).await {
         console_log!("Error during cleanup: {:?}", e);
     }
@@ -0,0 +1,177 @@
+use crate::http::{HttpClient, HttpRequest, InboundRequest};
+use crate::payment::{CreatedInvoice, InvoiceRequest, InvoiceStatus, PaymentProvider, WebhookNotice};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+struct CreatePaymentResponse {
+    payment_hash: String,
+    /// Older LNbits versions
+    #[serde(default)]
+    payment_request: Option<String>,
+    #[serde(default)]
+    bolt11: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PaymentState {
+    #[serde(default)]
+    paid: bool,
+    #[serde(default)]
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LnbitsWebhookPayload {
+    #[serde(default)]
+    payment_hash: Option<String>,
+}
+
+/// Self-hosted LNbits wallet (invoice/read key)
+pub struct LnbitsProvider<C> {
+    client: C,
+    base_url: String,
+    api_key: String,
+}
+
+impl<C: HttpClient> LnbitsProvider<C> {
+    pub fn new(client: C, base_url: String, api_key: String) -> Self {
+        Self { client, base_url: base_url.trim_end_matches('/').to_string(), api_key }
+    }
+}
+
+impl<C: HttpClient> PaymentProvider for LnbitsProvider<C> {
+    fn name(&self) -> &'static str {
+        "lnbits"
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String> {
+        // LNbits does not echo custom fields back, so the secret rides on the webhook URL
+        let separator = if req.webhook_url.contains('?') { '&' } else { '?' };
+        let mut body = json!({
+            "out": false,
+            "amount": req.amount_sats,
+            "memo": req.memo,
+            "expiry": req.expiry_secs,
+            "webhook": format!("{}{}secret={}", req.webhook_url, separator, req.secret),
+        });
+        if let Some(hash) = req.description_hash {
+            body["description_hash"] = json!(hash);
+        }
+        let request = HttpRequest::post_json(format!("{}/api/v1/payments", self.base_url), &body)
+            .header("X-Api-Key", self.api_key.clone());
+        let response = self.client.send(request).await?;
+        if !response.is_success() {
+            return Err(format!("LNbits API error ({}): {}", response.status, response.body));
+        }
+        let created: CreatePaymentResponse = response.json()?;
+        let bolt11 = created
+            .bolt11
+            .or(created.payment_request)
+            .ok_or("LNbits response missing bolt11")?;
+        Ok(CreatedInvoice { bolt11, payment_id: Some(created.payment_hash) })
+    }
+
+    async fn check_status(&self, payment_id: &str) -> Result<InvoiceStatus, String> {
+        let request = HttpRequest::get(format!("{}/api/v1/payments/{}", self.base_url, payment_id))
+            .header("X-Api-Key", self.api_key.clone());
+        let response = self.client.send(request).await?;
+        if !response.is_success() {
+            return Err(format!("LNbits API error ({}): {}", response.status, response.body));
+        }
+        let state: PaymentState = response.json()?;
+        Ok(if state.paid {
+            InvoiceStatus::Paid
+        } else if matches!(state.status.as_deref(), Some("failed" | "expired")) {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Pending
+        })
+    }
+
+    /// LNbits webhooks are unsigned: take the hash from the body and the secret
+    /// from our own query string, and leave payment to be confirmed via the API
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String> {
+        let payload: LnbitsWebhookPayload =
+            serde_json::from_str(&req.body).map_err(|_| "Invalid webhook payload".to_string())?;
+        let Some(payment_hash) = payload.payment_hash else {
+            return Ok(None);
+        };
+        Ok(Some(WebhookNotice {
+            secret: req.query("secret").map(str::to_string),
+            payment_id: Some(payment_hash),
+            paid: false,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payment::stand_in::StandInServer;
+    use crate::store::block_on;
+
+    #[test]
+    fn test_secret_travels_in_webhook_url() {
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test/".into(), "key".into());
+        block_on(async {
+            provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: 100,
+                    memo: "noscha.io alice",
+                    webhook_url: "https://noscha.io/api/webhook/lnbits",
+                    secret: "sec_l",
+                    expiry_secs: 600,
+                    description_hash: Some("00ff"),
+                })
+                .await
+                .unwrap();
+        });
+        assert_eq!(server.requests.borrow()[0].url, "https://lnbits.test/api/v1/payments");
+        let body = server.last_invoice().request_body;
+        assert_eq!(body["webhook"], "https://noscha.io/api/webhook/lnbits?secret=sec_l");
+        assert_eq!(body["expiry"], 600);
+        assert_eq!(body["out"], false);
+        assert_eq!(body["description_hash"], "00ff");
+    }
+
+    #[test]
+    fn test_parse_webhook_requires_confirmation() {
+        let provider = LnbitsProvider::new(StandInServer::new(), "https://lnbits.test".into(), "key".into());
+        let req = InboundRequest {
+            url: "https://noscha.io/api/webhook/lnbits?secret=sec_l".into(),
+            headers: vec![],
+            body: r#"{"payment_hash":"abc","amount":100000}"#.into(),
+        };
+        let notice = provider.parse_webhook(&req).unwrap().unwrap();
+        assert_eq!(notice.secret.as_deref(), Some("sec_l"));
+        assert_eq!(notice.payment_id.as_deref(), Some("abc"));
+        assert!(!notice.paid);
+
+        let empty = InboundRequest { body: "{}".into(), ..req };
+        assert_eq!(provider.parse_webhook(&empty).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expired_status() {
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+        block_on(async {
+            let invoice = provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: 1,
+                    memo: "",
+                    webhook_url: "https://noscha.io/api/webhook/lnbits",
+                    secret: "s",
+                    expiry_secs: 60,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            let hash = invoice.payment_id.unwrap();
+            server.expire(&hash);
+            assert_eq!(provider.check_status(&hash).await.unwrap(), InvoiceStatus::Expired);
+        });
+    }
+}
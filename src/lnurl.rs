@@ -0,0 +1,668 @@
+//! Lightning Addresses (LUD-16) for rentals. `/.well-known/lnurlp/{username}`
+//! serves a LUD-06 payRequest whose invoices come from the payment backend, or
+//! passes on the renter's own LNURL-pay endpoint when they forward payments.
+//! NIP-57 zap requests are accepted and answered with zap receipts once paid.
+
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::http::{HttpClient, HttpRequest, InboundRequest};
+use crate::nip17::{Keys, Relay};
+use crate::nostr::{find_tag, verify_event};
+use crate::payment::{InvoiceRequest, InvoiceStatus, PaymentProvider};
+use crate::service::ApiError;
+use crate::store::Store;
+use crate::types::{iso_from_ms, LnurlInvoice, NostrEvent, Rental};
+use crate::url_policy::{self, UrlPolicy};
+
+/// Smallest and largest payment offered, in millisatoshis
+pub const MIN_SENDABLE_MSAT: u64 = 1_000;
+pub const MAX_SENDABLE_MSAT: u64 = 10_000_000_000;
+/// Longest LUD-12 comment accepted
+pub const COMMENT_ALLOWED: usize = 255;
+/// Lifetime of Lightning Address invoices
+pub const INVOICE_EXPIRY_SECS: u64 = 600;
+/// Longest accepted forwarding target
+pub const MAX_FORWARD_LEN: usize = 500;
+
+pub const ZAP_REQUEST_KIND: u32 = 9734;
+pub const ZAP_RECEIPT_KIND: u32 = 9735;
+/// Most relays a zap receipt is published to
+const MAX_RECEIPT_RELAYS: usize = 10;
+
+/// LUD-06 payRequest response
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub tag: &'static str,
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    pub metadata: String,
+    pub comment_allowed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allows_nostr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nostr_pubkey: Option<String>,
+}
+
+/// Query parameters of a payRequest callback, already percent-decoded
+#[derive(Debug, Clone, Default)]
+pub struct CallbackParams {
+    pub amount_msat: u64,
+    /// NIP-57 zap request JSON
+    pub nostr: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// Check a forwarding target: a lightning address (`name@host`), a bech32
+/// `lnurl1...` or an https LNURL-pay URL, with a public host. Lightning
+/// addresses are kept as such; lnurl strings are stored as the URL they encode
+pub fn normalize_forward_to(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    let input = input.strip_prefix("lightning:").unwrap_or(input);
+    if input.len() > MAX_FORWARD_LEN {
+        return Err(format!("Must be at most {} characters", MAX_FORWARD_LEN));
+    }
+    let url = if input.to_ascii_lowercase().starts_with("lnurl1") {
+        let (hrp, data) = crate::nip19::decode(input).map_err(|_| "Invalid lnurl".to_string())?;
+        if hrp != "lnurl" {
+            return Err("Invalid lnurl".to_string());
+        }
+        String::from_utf8(data).map_err(|_| "Invalid lnurl".to_string())?
+    } else if input.contains("://") {
+        input.to_string()
+    } else if let Some((name, host)) = input.split_once('@') {
+        let (name, host) = (name.to_ascii_lowercase(), host.to_ascii_lowercase());
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-_.+".contains(&b)) {
+            return Err("Invalid lightning address".to_string());
+        }
+        url_policy::check_host(&host)?;
+        return Ok(format!("{}@{}", name, host));
+    } else {
+        return Err("Expected a lightning address, lnurl or https URL".to_string());
+    };
+    // Only characters a URL may carry unencoded; the target is shown on the my-page
+    if !url.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=%".contains(&b)) {
+        return Err("URL contains invalid characters".to_string());
+    }
+    url_policy::check_url(&url, UrlPolicy::PRODUCTION)?;
+    Ok(url)
+}
+
+/// LNURL-pay URL of a normalized forwarding target
+pub fn forward_url(forward_to: &str) -> String {
+    match forward_to.split_once('@') {
+        Some((name, host)) if !forward_to.contains("://") => format!("https://{}/.well-known/lnurlp/{}", host, name),
+        _ => forward_to.to_string(),
+    }
+}
+
+/// Whether a URL is served by this worker (`domain` or one of its subdomains)
+fn is_own_host(url: &str, domain: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, r)| r);
+    let host = rest.split(['/', '?', '#', ':']).next().unwrap_or_default().to_ascii_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// LUD-06 metadata for `name@domain`
+pub fn metadata(identifier: &str) -> String {
+    json!([["text/plain", format!("Payment to {}", identifier)], ["text/identifier", identifier]]).to_string()
+}
+
+/// payRequest for invoices from the payment backend; zaps are offered when the
+/// service has a Nostr key to sign receipts with
+pub fn pay_request(username: &str, domain: &str, nostr_pubkey: Option<&str>) -> PayRequest {
+    PayRequest {
+        tag: "payRequest",
+        callback: format!("https://{}/api/lnurlp/{}/callback", domain, username),
+        min_sendable: MIN_SENDABLE_MSAT,
+        max_sendable: MAX_SENDABLE_MSAT,
+        metadata: metadata(&format!("{}@{}", username, domain)),
+        comment_allowed: COMMENT_ALLOWED,
+        allows_nostr: nostr_pubkey.map(|_| true),
+        nostr_pubkey: nostr_pubkey.map(str::to_string),
+    }
+}
+
+/// Rental behind a Lightning Address that is currently being served
+async fn lightning_rental<S: Store>(store: &S, username: &str, now_ms: u64) -> Result<Rental, ApiError> {
+    store
+        .get_rental(&username.to_ascii_lowercase())
+        .await?
+        .filter(|r| r.is_serving(now_ms) && r.services.lightning_address.as_ref().is_some_and(|l| l.enabled))
+        .ok_or_else(|| ApiError::new(404, "Lightning address not found"))
+}
+
+/// GET /.well-known/lnurlp/{username}: our payRequest, or the forwarding
+/// target's own payRequest fetched as is
+pub async fn pay_request_for<S: Store, C: HttpClient>(
+    store: &S,
+    client: &C,
+    username: &str,
+    domain: &str,
+    nostr_pubkey: Option<&str>,
+    now_ms: u64,
+) -> Result<serde_json::Value, ApiError> {
+    let rental = lightning_rental(store, username, now_ms).await?;
+    let forward_to = rental.services.lightning_address.as_ref().and_then(|l| l.forward_to.as_deref());
+    let Some(forward_to) = forward_to else {
+        return Ok(serde_json::to_value(pay_request(&rental.username, domain, nostr_pubkey)).map_err(|e| e.to_string())?);
+    };
+
+    let url = forward_url(forward_to);
+    if is_own_host(&url, domain) {
+        return Err(ApiError::new(502, "Forwarding target points back at this service"));
+    }
+    let response = client
+        .send(HttpRequest::get(url))
+        .await
+        .map_err(|e| ApiError::new(502, format!("Forwarding target unreachable: {}", e)))?;
+    if !response.is_success() {
+        return Err(ApiError::new(502, format!("Forwarding target returned {}", response.status)));
+    }
+    let body: serde_json::Value = response.json().map_err(|e| ApiError::new(502, e))?;
+    if body["tag"] != "payRequest" {
+        return Err(ApiError::new(502, "Forwarding target is not an LNURL-pay endpoint"));
+    }
+    Ok(body)
+}
+
+/// Check a NIP-57 zap request (appendix D): a signed kind 9734 event with one
+/// `p` tag, at most one `e` tag, relays, and an `amount` tag matching if present
+pub fn check_zap_request(json: &str, amount_msat: u64) -> Result<NostrEvent, String> {
+    let event: NostrEvent = serde_json::from_str(json).map_err(|_| "Zap request is not a Nostr event".to_string())?;
+    if event.kind != Some(ZAP_REQUEST_KIND) {
+        return Err("Zap request must be kind 9734".to_string());
+    }
+    verify_event(&event)?;
+    let tags = event.tags.as_deref().unwrap_or_default();
+    let count = |name: &str| tags.iter().filter(|t| t.first().map(String::as_str) == Some(name)).count();
+    if count("p") != 1 || !find_tag(&event, "p").is_some_and(crate::nip05::validate_pubkey_hex) {
+        return Err("Zap request must have exactly one p tag".to_string());
+    }
+    if count("e") > 1 {
+        return Err("Zap request must have at most one e tag".to_string());
+    }
+    if count("relays") == 0 {
+        return Err("Zap request must list relays".to_string());
+    }
+    if let Some(amount) = find_tag(&event, "amount") {
+        if amount.parse::<u64>() != Ok(amount_msat) {
+            return Err("Zap request amount does not match".to_string());
+        }
+    }
+    Ok(event)
+}
+
+/// GET /api/lnurlp/{username}/callback: create an invoice committing to the
+/// metadata (or to the zap request) and remember it under `secret`
+#[allow(clippy::too_many_arguments)]
+pub async fn create_invoice<S: Store, P: PaymentProvider>(
+    store: &S,
+    provider: &P,
+    username: &str,
+    params: CallbackParams,
+    domain: &str,
+    webhook_url: &str,
+    secret: String,
+    now_ms: u64,
+) -> Result<serde_json::Value, ApiError> {
+    let rental = lightning_rental(store, username, now_ms).await?;
+    if rental.services.lightning_address.as_ref().is_some_and(|l| l.forward_to.is_some()) {
+        return Err(ApiError::new(404, "Payments to this address are handled by its forwarding target"));
+    }
+    let amount_msat = params.amount_msat;
+    if !(MIN_SENDABLE_MSAT..=MAX_SENDABLE_MSAT).contains(&amount_msat) {
+        return Err(ApiError::new(
+            400,
+            format!("Amount must be between {} and {} msat", MIN_SENDABLE_MSAT, MAX_SENDABLE_MSAT),
+        ));
+    }
+    if !amount_msat.is_multiple_of(1000) {
+        return Err(ApiError::new(400, "Amount must be a whole number of sats"));
+    }
+    let comment = params.comment.filter(|c| !c.is_empty());
+    if comment.as_ref().is_some_and(|c| c.chars().count() > COMMENT_ALLOWED) {
+        return Err(ApiError::new(400, format!("Comment must be at most {} characters", COMMENT_ALLOWED)));
+    }
+
+    let identifier = format!("{}@{}", rental.username, domain);
+    let (description, memo) = match params.nostr.as_deref() {
+        Some(zap) => {
+            check_zap_request(zap, amount_msat).map_err(|e| ApiError::new(400, e))?;
+            (zap.to_string(), format!("Zap to {}", identifier))
+        }
+        None => (metadata(&identifier), format!("Payment to {}", identifier)),
+    };
+    let description_hash = hex::encode(Sha256::digest(description.as_bytes()));
+    let invoice = provider
+        .create_invoice(&InvoiceRequest {
+            amount_sats: amount_msat / 1000,
+            memo: &memo,
+            webhook_url,
+            secret: &secret,
+            expiry_secs: INVOICE_EXPIRY_SECS,
+            description_hash: Some(&description_hash),
+        })
+        .await
+        .map_err(|e| ApiError::new(502, e))?;
+
+    store
+        .put_lnurl_invoice(&LnurlInvoice {
+            secret,
+            username: rental.username,
+            amount_msat,
+            bolt11: invoice.bolt11.clone(),
+            payment_id: invoice.payment_id,
+            zap_request: params.nostr,
+            comment,
+            created_at: iso_from_ms(now_ms),
+            paid_at: None,
+        })
+        .await?;
+    Ok(json!({ "pr": invoice.bolt11, "routes": [] }))
+}
+
+/// Mark a Lightning Address invoice paid from a backend webhook, confirming with
+/// the backend when the payload alone is not proof. None when the webhook is not
+/// for an unpaid Lightning Address invoice
+pub async fn settle_invoice<S: Store, P: PaymentProvider>(
+    store: &S,
+    provider: &P,
+    req: &InboundRequest,
+    now_ms: u64,
+) -> Result<Option<LnurlInvoice>, ApiError> {
+    let Some(notice) = provider.parse_webhook(req).map_err(|e| ApiError::new(400, e))? else {
+        return Ok(None);
+    };
+    let Some(secret) = notice.secret.as_deref() else {
+        return Ok(None);
+    };
+    let Some(mut invoice) = store.get_lnurl_invoice(secret).await?.filter(|i| i.paid_at.is_none()) else {
+        return Ok(None);
+    };
+    if !notice.paid {
+        let Some(payment_id) = invoice.payment_id.as_deref() else {
+            return Ok(None);
+        };
+        if notice.payment_id.as_deref().is_some_and(|id| id != payment_id) {
+            return Ok(None);
+        }
+        let status = provider.check_status(payment_id).await.map_err(|e| ApiError::new(502, e))?;
+        if status != InvoiceStatus::Paid {
+            return Ok(None);
+        }
+    }
+    invoice.paid_at = Some(iso_from_ms(now_ms));
+    store.put_lnurl_invoice(&invoice).await?;
+    Ok(Some(invoice))
+}
+
+/// Unsigned NIP-57 zap receipt for a paid zap invoice, and the relays the zap
+/// request asked for it on
+pub fn zap_receipt(invoice: &LnurlInvoice, paid_at_secs: u64) -> Option<(NostrEvent, Vec<String>)> {
+    let json = invoice.zap_request.as_deref()?;
+    let request: NostrEvent = serde_json::from_str(json).ok()?;
+    let mut tags: Vec<Vec<String>> = ["p", "e", "a"]
+        .into_iter()
+        .filter_map(|name| find_tag(&request, name).map(|value| vec![name.to_string(), value.to_string()]))
+        .collect();
+    tags.push(vec!["P".to_string(), request.pubkey.clone()]);
+    tags.push(vec!["bolt11".to_string(), invoice.bolt11.clone()]);
+    tags.push(vec!["description".to_string(), json.to_string()]);
+
+    let relays = request
+        .tags
+        .iter()
+        .flatten()
+        .find(|t| t.first().map(String::as_str) == Some("relays"))
+        .map(|t| {
+            t[1..]
+                .iter()
+                .filter(|r| url_policy::check_relay_url(r).is_ok())
+                .take(MAX_RECEIPT_RELAYS)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let receipt = NostrEvent {
+        id: None,
+        pubkey: String::new(),
+        created_at: Some(paid_at_secs),
+        kind: Some(ZAP_RECEIPT_KIND),
+        tags: Some(tags),
+        content: String::new(),
+        sig: None,
+    };
+    Some((receipt, relays))
+}
+
+/// Sign the zap receipt for a paid invoice and publish it; Ok with the relays
+/// that accepted it (empty when the invoice was not a zap)
+pub async fn publish_zap_receipt<R: Relay>(
+    relay: &R,
+    keys: &Keys,
+    invoice: &LnurlInvoice,
+    paid_at_secs: u64,
+) -> Result<Vec<String>, String> {
+    let Some((mut receipt, relays)) = zap_receipt(invoice, paid_at_secs) else {
+        return Ok(Vec::new());
+    };
+    keys.sign(&mut receipt)?;
+    let mut accepted = Vec::new();
+    let mut errors = Vec::new();
+    for url in &relays {
+        match relay.publish(url, &receipt).await {
+            Ok(()) => accepted.push(url.clone()),
+            Err(e) => errors.push(format!("{}: {}", url, e)),
+        }
+    }
+    if accepted.is_empty() && !relays.is_empty() {
+        return Err(errors.join("; "));
+    }
+    Ok(accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpResponse;
+    use crate::lnbits::LnbitsProvider;
+    use crate::nip17::MemoryRelay;
+    use crate::payment::stand_in::StandInServer;
+    use crate::store::{block_on, MemoryStore};
+    use crate::types::{LightningAddressService, Plan, RentalServices, RentalStatus};
+    use std::cell::RefCell;
+
+    const NOW: u64 = 1_770_811_200_000;
+
+    fn rental(username: &str, forward_to: Option<&str>) -> Rental {
+        Rental {
+            username: username.to_string(),
+            status: RentalStatus::Active,
+            created_at: "2026-02-01T00:00:00.000Z".to_string(),
+            expires_at: "2026-03-01T00:00:00.000Z".to_string(),
+            plan: Plan::ThirtyDays,
+            services: RentalServices {
+                email: None,
+                subdomain: None,
+                nip05: None,
+                lightning_address: Some(LightningAddressService { enabled: true, forward_to: forward_to.map(str::to_string) }),
+            },
+            management_token: None,
+            webhook_url: None,
+            grace_until: None,
+            reminders_sent: Vec::new(),
+            signing_secret: None,
+            webhooks: Vec::new(),
+            nostr_dm: false,
+            owner_pubkey: None,
+        }
+    }
+
+    /// Upstream LNURL-pay server that answers every GET with `body`
+    struct Upstream {
+        body: serde_json::Value,
+        urls: RefCell<Vec<String>>,
+    }
+
+    impl HttpClient for Upstream {
+        async fn send(&self, req: HttpRequest) -> Result<HttpResponse, String> {
+            self.urls.borrow_mut().push(req.url);
+            Ok(HttpResponse { status: 200, body: self.body.to_string() })
+        }
+    }
+
+    fn zap_request(sender: &Keys, recipient: &str, tags: &[&[&str]]) -> String {
+        let mut tags: Vec<Vec<String>> = tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect();
+        tags.insert(0, vec!["p".to_string(), recipient.to_string()]);
+        let mut event = NostrEvent {
+            id: None,
+            pubkey: String::new(),
+            created_at: Some(NOW / 1000),
+            kind: Some(ZAP_REQUEST_KIND),
+            tags: Some(tags),
+            content: "great post".to_string(),
+            sig: None,
+        };
+        sender.sign(&mut event).unwrap();
+        serde_json::to_string(&event).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_forward_to() {
+        assert_eq!(normalize_forward_to(" Bob@Wallet.Example ").unwrap(), "bob@wallet.example");
+        assert_eq!(normalize_forward_to("lightning:bob@wallet.example").unwrap(), "bob@wallet.example");
+        assert_eq!(
+            normalize_forward_to("https://wallet.example/lnurlp/bob").unwrap(),
+            "https://wallet.example/lnurlp/bob"
+        );
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        assert_eq!(
+            normalize_forward_to(lnurl).unwrap(),
+            "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+        );
+
+        assert!(normalize_forward_to("http://wallet.example/lnurlp/bob").is_err());
+        assert!(normalize_forward_to("bob@localhost").is_err());
+        assert!(normalize_forward_to("bob@127.0.0.1").is_err());
+        assert!(normalize_forward_to("b ob@wallet.example").is_err());
+        assert!(normalize_forward_to("@wallet.example").is_err());
+        assert!(normalize_forward_to("just-a-name").is_err());
+        assert!(normalize_forward_to("lnurl1qqqqqq").is_err());
+        assert!(normalize_forward_to("https://wallet.example/<script>alert(1)</script>").is_err());
+        assert!(normalize_forward_to("https://wallet.example/a b").is_err());
+        assert!(normalize_forward_to("https://wallet.example/\"onmouseover=x").is_err());
+        let bad = crate::nip19::encode("lnurl", b"https://wallet.example/<img src=x>");
+        assert!(normalize_forward_to(&bad).is_err());
+
+        assert_eq!(forward_url("bob@wallet.example"), "https://wallet.example/.well-known/lnurlp/bob");
+        assert_eq!(forward_url("https://wallet.example/lnurlp/bob"), "https://wallet.example/lnurlp/bob");
+    }
+
+    #[test]
+    fn test_pay_request() {
+        let store = MemoryStore::new();
+        let upstream = Upstream { body: json!({}), urls: RefCell::new(Vec::new()) };
+        block_on(async {
+            store.put_rental(&rental("alice", None)).await.unwrap();
+            let body = pay_request_for(&store, &upstream, "Alice", "noscha.io", Some("ab".repeat(32).as_str()), NOW)
+                .await
+                .unwrap();
+            assert_eq!(body["tag"], "payRequest");
+            assert_eq!(body["callback"], "https://noscha.io/api/lnurlp/alice/callback");
+            assert_eq!(body["minSendable"], MIN_SENDABLE_MSAT);
+            assert_eq!(body["commentAllowed"], COMMENT_ALLOWED);
+            assert_eq!(body["allowsNostr"], true);
+            assert_eq!(body["nostrPubkey"], "ab".repeat(32));
+            let metadata: serde_json::Value = serde_json::from_str(body["metadata"].as_str().unwrap()).unwrap();
+            assert_eq!(metadata[1], json!(["text/identifier", "alice@noscha.io"]));
+
+            // Zaps are only offered with a key to sign receipts
+            let body = pay_request_for(&store, &upstream, "alice", "noscha.io", None, NOW).await.unwrap();
+            assert!(body.get("allowsNostr").is_none());
+            assert!(upstream.urls.borrow().is_empty());
+
+            // Expired rentals, unknown names and rentals without the service are 404
+            let err = pay_request_for(&store, &upstream, "alice", "noscha.io", None, NOW + 60 * 86_400_000).await.unwrap_err();
+            assert_eq!(err.status, 404);
+            assert_eq!(pay_request_for(&store, &upstream, "bob", "noscha.io", None, NOW).await.unwrap_err().status, 404);
+            let mut plain = rental("carol", None);
+            plain.services.lightning_address = None;
+            store.put_rental(&plain).await.unwrap();
+            assert_eq!(pay_request_for(&store, &upstream, "carol", "noscha.io", None, NOW).await.unwrap_err().status, 404);
+        });
+    }
+
+    #[test]
+    fn test_pay_request_forwarding() {
+        let store = MemoryStore::new();
+        let upstream_body = json!({"tag": "payRequest", "callback": "https://wallet.example/cb", "minSendable": 1000, "maxSendable": 5000, "metadata": "[]"});
+        let upstream = Upstream { body: upstream_body.clone(), urls: RefCell::new(Vec::new()) };
+        block_on(async {
+            store.put_rental(&rental("alice", Some("bob@wallet.example"))).await.unwrap();
+            let body = pay_request_for(&store, &upstream, "alice", "noscha.io", None, NOW).await.unwrap();
+            assert_eq!(body, upstream_body);
+            assert_eq!(*upstream.urls.borrow(), vec!["https://wallet.example/.well-known/lnurlp/bob".to_string()]);
+
+            // Our own invoices are not issued for forwarding rentals
+            let server = StandInServer::new();
+            let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+            let params = CallbackParams { amount_msat: 21_000, ..Default::default() };
+            let err = create_invoice(&store, &provider, "alice", params, "noscha.io", "https://noscha.io/api/webhook/lnbits", "s1".into(), NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 404);
+
+            // Forwarding back to this service would loop
+            store.put_rental(&rental("loop", Some("alice@noscha.io"))).await.unwrap();
+            assert_eq!(pay_request_for(&store, &upstream, "loop", "noscha.io", None, NOW).await.unwrap_err().status, 502);
+
+            let not_lnurl = Upstream { body: json!({"status": "ERROR"}), urls: RefCell::new(Vec::new()) };
+            assert_eq!(pay_request_for(&store, &not_lnurl, "alice", "noscha.io", None, NOW).await.unwrap_err().status, 502);
+        });
+    }
+
+    #[test]
+    fn test_callback_invoice_and_settlement() {
+        let store = MemoryStore::new();
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+        let webhook_url = "https://noscha.io/api/webhook/lnbits";
+        block_on(async {
+            store.put_rental(&rental("alice", None)).await.unwrap();
+            let params = CallbackParams { amount_msat: 21_000, nostr: None, comment: Some("thanks".into()) };
+            let body = create_invoice(&store, &provider, "alice", params, "noscha.io", webhook_url, "s1".into(), NOW)
+                .await
+                .unwrap();
+            assert_eq!(body["pr"], "lnbc21n1lnbits");
+            assert_eq!(body["routes"], json!([]));
+
+            // The invoice commits to the metadata served in the payRequest
+            let sent = server.last_invoice();
+            assert_eq!(sent.amount, 21);
+            let expected = hex::encode(Sha256::digest(metadata("alice@noscha.io").as_bytes()));
+            assert_eq!(sent.request_body["description_hash"], expected);
+
+            let stored = store.get_lnurl_invoice("s1").await.unwrap().unwrap();
+            assert_eq!(stored.username, "alice");
+            assert_eq!(stored.comment.as_deref(), Some("thanks"));
+            assert!(stored.paid_at.is_none());
+
+            // LNbits webhooks are confirmed with the backend before counting
+            let webhook = InboundRequest {
+                url: format!("{}?secret=s1", webhook_url),
+                body: json!({"payment_hash": sent.id}).to_string(),
+                ..Default::default()
+            };
+            assert!(settle_invoice(&store, &provider, &webhook, NOW).await.unwrap().is_none());
+            server.settle(&sent.id);
+            let settled = settle_invoice(&store, &provider, &webhook, NOW + 1000).await.unwrap().unwrap();
+            assert_eq!(settled.paid_at, Some(iso_from_ms(NOW + 1000)));
+            // A second notice for the same invoice is ignored
+            assert!(settle_invoice(&store, &provider, &webhook, NOW + 2000).await.unwrap().is_none());
+            // Unknown secrets are not Lightning Address invoices
+            let other = InboundRequest { url: format!("{}?secret=nope", webhook_url), ..webhook.clone() };
+            assert!(settle_invoice(&store, &provider, &other, NOW).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_callback_rejects_bad_amounts_and_comments() {
+        let store = MemoryStore::new();
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+        block_on(async {
+            store.put_rental(&rental("alice", None)).await.unwrap();
+            let call = |params: CallbackParams| {
+                let (store, provider) = (&store, &provider);
+                async move {
+                    create_invoice(store, provider, "alice", params, "noscha.io", "https://noscha.io/api/webhook/lnbits", "s".into(), NOW)
+                        .await
+                        .unwrap_err()
+                        .status
+                }
+            };
+            assert_eq!(call(CallbackParams { amount_msat: 999, ..Default::default() }).await, 400);
+            assert_eq!(call(CallbackParams { amount_msat: MAX_SENDABLE_MSAT + 1000, ..Default::default() }).await, 400);
+            assert_eq!(call(CallbackParams { amount_msat: 1500, ..Default::default() }).await, 400);
+            let long = "x".repeat(COMMENT_ALLOWED + 1);
+            assert_eq!(call(CallbackParams { amount_msat: 1000, comment: Some(long), ..Default::default() }).await, 400);
+            assert!(server.invoices.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_check_zap_request() {
+        let sender = Keys::generate();
+        let recipient = "ab".repeat(32);
+        let relays: &[&str] = &["relays", "wss://relay.damus.io"];
+        let ok = zap_request(&sender, &recipient, &[relays, &["amount", "21000"]]);
+        assert!(check_zap_request(&ok, 21_000).is_ok());
+        assert_eq!(check_zap_request(&ok, 1_000).unwrap_err(), "Zap request amount does not match");
+        assert!(check_zap_request(&zap_request(&sender, &recipient, &[relays]), 1_000).is_ok());
+
+        assert!(check_zap_request(&zap_request(&sender, &recipient, &[]), 1_000).unwrap_err().contains("relays"));
+        let two_p = zap_request(&sender, &recipient, &[relays, &["p", &"cd".repeat(32)]]);
+        assert!(check_zap_request(&two_p, 1_000).unwrap_err().contains("p tag"));
+        let two_e = zap_request(&sender, &recipient, &[relays, &["e", "1"], &["e", "2"]]);
+        assert!(check_zap_request(&two_e, 1_000).unwrap_err().contains("e tag"));
+
+        let mut forged: NostrEvent = serde_json::from_str(&ok).unwrap();
+        forged.content = "changed".to_string();
+        assert!(check_zap_request(&serde_json::to_string(&forged).unwrap(), 21_000).is_err());
+        let mut wrong_kind: NostrEvent = serde_json::from_str(&ok).unwrap();
+        wrong_kind.kind = Some(1);
+        sender.sign(&mut wrong_kind).unwrap();
+        assert!(check_zap_request(&serde_json::to_string(&wrong_kind).unwrap(), 21_000).unwrap_err().contains("9734"));
+        assert!(check_zap_request("not json", 1_000).is_err());
+    }
+
+    #[test]
+    fn test_zap_invoice_and_receipt() {
+        let store = MemoryStore::new();
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+        let service = Keys::generate();
+        let sender = Keys::generate();
+        let recipient = "ab".repeat(32);
+        let zap = zap_request(
+            &sender,
+            &recipient,
+            &[&["e", &"ef".repeat(32)], &["relays", "wss://relay.damus.io", "ws://insecure.example", "wss://nos.lol"]],
+        );
+        let relay = MemoryRelay::default();
+        relay.reject("wss://nos.lol");
+        block_on(async {
+            store.put_rental(&rental("alice", None)).await.unwrap();
+            let params = CallbackParams { amount_msat: 5_000, nostr: Some(zap.clone()), comment: None };
+            create_invoice(&store, &provider, "alice", params, "noscha.io", "https://noscha.io/api/webhook/lnbits", "z1".into(), NOW)
+                .await
+                .unwrap();
+            // Zap invoices commit to the zap request instead of the metadata
+            let sent = server.last_invoice();
+            assert_eq!(sent.request_body["description_hash"], hex::encode(Sha256::digest(zap.as_bytes())));
+
+            let mut invoice = store.get_lnurl_invoice("z1").await.unwrap().unwrap();
+            invoice.paid_at = Some(iso_from_ms(NOW));
+            let accepted = publish_zap_receipt(&relay, &service, &invoice, NOW / 1000).await.unwrap();
+            assert_eq!(accepted, vec!["wss://relay.damus.io".to_string()]);
+
+            let (_, receipt) = relay.published().pop().unwrap();
+            assert_eq!(receipt.kind, Some(ZAP_RECEIPT_KIND));
+            assert_eq!(receipt.pubkey, service.pubkey_hex());
+            assert_eq!(receipt.created_at, Some(NOW / 1000));
+            assert_eq!(find_tag(&receipt, "p"), Some(recipient.as_str()));
+            assert_eq!(find_tag(&receipt, "e"), Some("ef".repeat(32).as_str()));
+            assert_eq!(find_tag(&receipt, "P"), Some(sender.pubkey_hex().as_str()));
+            assert_eq!(find_tag(&receipt, "bolt11"), Some("lnbc5n1lnbits"));
+            assert_eq!(find_tag(&receipt, "description"), Some(zap.as_str()));
+
+            // Plain payments have no receipt
+            invoice.zap_request = None;
+            assert!(publish_zap_receipt(&relay, &service, &invoice, NOW / 1000).await.unwrap().is_empty());
+        });
+    }
+}
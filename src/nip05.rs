@@ -1,10 +1,18 @@
-#[cfg(target_arch = "wasm32")]
+use std::collections::BTreeMap;
+
 use serde::Serialize;
 #[cfg(target_arch = "wasm32")]
 use worker::*;
 
 #[cfg(target_arch = "wasm32")]
-use crate::types::{is_expired_iso, Rental};
+use crate::store::R2Store;
+use crate::store::{Store, StoreResult};
+use crate::types::{Nip05Service, Rental};
+
+/// Most relay hints (and, separately, NIP-46 bunker relays) per pubkey
+pub const MAX_RELAYS: usize = 5;
+/// Longest accepted relay URL
+pub const MAX_RELAY_URL_LEN: usize = 200;
 
 /// Validates a hex-encoded pubkey string: must be exactly 64 chars and all hex digits
 pub fn validate_pubkey_hex(s: &str) -> bool {
@@ -14,101 +22,200 @@ pub fn validate_pubkey_hex(s: &str) -> bool {
     s.chars().all(|c| c.is_ascii_hexdigit())
 }
 
-#[cfg(target_arch = "wasm32")]
-#[derive(Serialize)]
-struct Nip05Response {
-    names: std::collections::BTreeMap<String, String>,
-    relays: std::collections::BTreeMap<String, Vec<String>>,
+/// Check relay URLs for nostr.json: wss:// with a public host, trimmed and
+/// deduplicated, at most MAX_RELAYS of at most MAX_RELAY_URL_LEN chars
+pub fn normalize_relays(relays: &[String]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = Vec::new();
+    for url in relays {
+        let url = url.trim();
+        if url.len() > MAX_RELAY_URL_LEN {
+            return Err(format!("Relay URL is longer than {} characters", MAX_RELAY_URL_LEN));
+        }
+        crate::url_policy::check_relay_url(url).map_err(|e| format!("{}: {}", e, url))?;
+        if !out.iter().any(|r| r == url) {
+            out.push(url.to_string());
+        }
+    }
+    if out.len() > MAX_RELAYS {
+        return Err(format!("At most {} relays are allowed", MAX_RELAYS));
+    }
+    Ok(out)
+}
+
+/// The usable relay hints from an nprofile: invalid ones are dropped rather
+/// than rejected, and at most MAX_RELAYS are kept
+pub fn relay_hints(relays: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for url in relays.iter().map(|r| r.trim()) {
+        let usable = url.len() <= MAX_RELAY_URL_LEN && crate::url_policy::check_relay_url(url).is_ok();
+        if usable && out.len() < MAX_RELAYS && !out.iter().any(|r| r == url) {
+            out.push(url.to_string());
+        }
+    }
+    out
+}
+
+/// Most extra names per rental
+pub const MAX_EXTRA_NAMES: usize = 10;
+/// Longest NIP-05 local part
+pub const MAX_NAME_LEN: usize = 64;
+
+/// NIP-05 local parts: a-z, 0-9, '-', '_' and '.', not starting or ending with '.'.
+/// "_" is the root name of a domain and, like reserved usernames, cannot be claimed
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!("Name must be 1-{} characters", MAX_NAME_LEN));
+    }
+    if name == "_" {
+        return Err("\"_\" is reserved for the root name".to_string());
+    }
+    if !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-_.".contains(&b)) {
+        return Err(format!("Name {} may only contain a-z, 0-9, '-', '_' and '.'", name));
+    }
+    if name.starts_with('.') || name.ends_with('.') || name.contains("..") {
+        return Err(format!("Name {} has an empty label", name));
+    }
+    if crate::validation::RESERVED_USERNAMES.contains(&name) {
+        return Err(format!("Name {} is reserved", name));
+    }
+    Ok(())
+}
+
+/// Check a rental's extra names: valid, not the rental's own name, with hex,
+/// npub or nprofile pubkeys, at most MAX_EXTRA_NAMES. Names are lowercased and
+/// pubkeys stored as hex
+pub fn normalize_names(username: &str, names: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+    let mut out = BTreeMap::new();
+    for (name, pubkey) in names {
+        let name = name.trim().to_ascii_lowercase();
+        validate_name(&name)?;
+        if name == username {
+            return Err(format!("{} is already the rental's own name", name));
+        }
+        let pubkey = crate::nip19::pubkey_hex(pubkey).map_err(|e| format!("Pubkey for {}: {}", name, e))?;
+        out.insert(name, pubkey);
+    }
+    if out.len() > MAX_EXTRA_NAMES {
+        return Err(format!("At most {} extra names are allowed", MAX_EXTRA_NAMES));
+    }
+    Ok(out)
+}
+
+/// The rental whose subdomain `host` is (e.g. "alice" for alice.noscha.io), for
+/// `_@alice.noscha.io` style lookups
+pub fn subdomain_username<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    let label = host.strip_suffix(domain)?.strip_suffix('.')?;
+    (!label.is_empty() && !label.contains('.') && label != "www").then_some(label)
+}
+
+/// nostr.json body; relay hints and NIP-46 bunker relays are keyed by pubkey
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Nip05Response {
+    names: BTreeMap<String, String>,
+    relays: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    nip46: BTreeMap<String, Vec<String>>,
+}
+
+impl Nip05Response {
+    /// `name` mapped to `pubkey`; the rental's relays are listed when `pubkey` is
+    /// its main NIP-05 key
+    pub fn for_name(name: &str, pubkey: &str, service: &Nip05Service) -> Self {
+        let mut response = Nip05Response {
+            names: BTreeMap::from([(name.to_string(), pubkey.to_string())]),
+            relays: BTreeMap::new(),
+            nip46: BTreeMap::new(),
+        };
+        if pubkey != service.pubkey_hex {
+            return response;
+        }
+        if !service.relays.is_empty() {
+            response.relays.insert(pubkey.to_string(), service.relays.clone());
+        }
+        if !service.nip46.is_empty() {
+            response.nip46.insert(pubkey.to_string(), service.nip46.clone());
+        }
+        response
+    }
+}
+
+/// Resolve a nostr.json lookup. On the main domain `name` is a username or an
+/// extra name; on a rental's subdomain, `_` is the rental's own key and other
+/// names are its extra names, with or without the ".{username}" suffix
+pub async fn lookup<S: Store>(
+    store: &S,
+    name: &str,
+    subdomain: Option<&str>,
+    now_ms: u64,
+) -> StoreResult<Option<Nip05Response>> {
+    let name = name.to_ascii_lowercase();
+    if let Some(username) = subdomain {
+        return Ok(store.get_rental(username).await?.and_then(|r| resolve(&r, &name, true, now_ms)));
+    }
+    if let Some(found) = store.get_rental(&name).await?.and_then(|r| resolve(&r, &name, false, now_ms)) {
+        return Ok(Some(found));
+    }
+    // Index entries for names a rental has since dropped resolve to nothing
+    let Some(owner) = store.get_nip05_name_owner(&name).await? else {
+        return Ok(None);
+    };
+    Ok(store.get_rental(&owner).await?.and_then(|r| resolve(&r, &name, false, now_ms)))
+}
+
+fn resolve(rental: &Rental, name: &str, on_subdomain: bool, now_ms: u64) -> Option<Nip05Response> {
+    let service = rental.services.nip05.as_ref().filter(|n| n.enabled)?;
+    if !rental.is_serving(now_ms) {
+        return None;
+    }
+    let pubkey = if !on_subdomain {
+        service.pubkey_for(&rental.username, name)
+    } else if name == "_" {
+        Some(service.pubkey_hex.as_str())
+    } else {
+        service
+            .names
+            .get(name)
+            .or_else(|| service.names.get(&format!("{}.{}", name, rental.username)))
+            .map(String::as_str)
+    }?;
+    validate_pubkey_hex(pubkey).then(|| Nip05Response::for_name(name, pubkey, service))
 }
 
 /// GET /.well-known/nostr.json?name={username}
 /// Returns NIP-05 JSON with CORS headers
 #[cfg(target_arch = "wasm32")]
 pub async fn handle_nip05(
-    _req: Request,
+    req: Request,
     ctx: RouteContext<()>,
 ) -> Result<Response> {
-    // Get the ?name query parameter
-    let name = match ctx.param("name") {
-        Some(n) => n.clone(),
-        None => {
-            // Try to get from query string
-            let url = _req.url()?;
-            match url.query_pairs().find(|(k, _)| k == "name") {
-                Some((_, v)) => v.to_string(),
-                None => {
-                    return Response::error("Missing ?name parameter", 400)
-                        .map(|mut res| {
-                            let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                            res
-                        });
-                }
-            }
-        }
+    let url = req.url()?;
+    let Some(name) = url.query_pairs().find(|(k, _)| k == "name").map(|(_, v)| v.to_string()) else {
+        return Response::error("Missing ?name parameter", 400).map(|mut res| {
+            let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
+            res
+        });
     };
+    let domain = ctx
+        .env
+        .var("DOMAIN")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "noscha.io".to_string());
+    let subdomain = url.host_str().and_then(|host| subdomain_username(host, &domain));
 
-    // Get rental from R2
-    let bucket = ctx.env.bucket("BUCKET")?;
-    let rental_key = format!("rentals/{}.json", name);
-    let obj = bucket.get(&rental_key).execute().await?;
-
-    match obj {
-        Some(obj) => {
-            let body = obj.body().unwrap();
-            let text = body.text().await?;
-            let rental: Rental = serde_json::from_str(&text)
-                .map_err(|e| Error::RustError(e.to_string()))?;
-
-            if rental.status != "active" || is_expired_iso(&rental.expires_at) {
-                return Response::error("Username not found", 404)
-                    .map(|mut res| {
-                        let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                        res
-                    });
-            }
-
-            // Check if NIP-05 service is enabled
-            if let Some(nip05_svc) = rental.services.nip05.as_ref() {
-                if !nip05_svc.enabled {
-                    return Response::error("NIP-05 service not enabled for this username", 404)
-                        .map(|mut res| {
-                            let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                            res
-                        });
-                }
-
-                // Validate pubkey format
-                if !validate_pubkey_hex(&nip05_svc.pubkey_hex) {
-                    return Response::error("Invalid pubkey format in rental", 500)
-                        .map(|mut res| {
-                            let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                            res
-                        });
-                }
-
-                // Build NIP-05 response
-                let mut names = std::collections::BTreeMap::new();
-                names.insert(name.clone(), nip05_svc.pubkey_hex.clone());
-
-                let relays = std::collections::BTreeMap::new();
-
-                let response_body = Nip05Response { names, relays };
-                let mut response = Response::from_json(&response_body)?;
-                response.headers_mut().set("Access-Control-Allow-Origin", "*")?;
-                Ok(response)
-            } else {
-                Response::error("NIP-05 service not provisioned for this username", 404)
-                    .map(|mut res| {
-                        let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                        res
-                    })
-            }
+    let store = R2Store::from_env(&ctx.env)?;
+    match lookup(&store, &name, subdomain, js_sys::Date::now() as u64)
+        .await
+        .map_err(Error::RustError)?
+    {
+        Some(body) => {
+            let mut response = Response::from_json(&body)?;
+            response.headers_mut().set("Access-Control-Allow-Origin", "*")?;
+            Ok(response)
         }
-        None => Response::error("Username not found", 404)
-            .map(|mut res| {
-                let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
-                res
-            }),
+        None => Response::error("Name not found", 404).map(|mut res| {
+            let _ = res.headers_mut().set("Access-Control-Allow-Origin", "*");
+            res
+        }),
     }
 }
 
@@ -126,6 +233,8 @@ pub async fn handle_nip05_options(_req: Request, _ctx: RouteContext<()>) -> Resu
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{block_on, MemoryStore};
+    use crate::types::{Plan, RentalServices, RentalStatus};
 
     #[test]
     fn test_validate_pubkey_hex_valid() {
@@ -162,4 +271,120 @@ mod tests {
         let mixed = format!("{}{}", "A".repeat(32), "f".repeat(32));
         assert!(validate_pubkey_hex(&mixed));
     }
+
+    #[test]
+    fn test_normalize_relays() {
+        let relays = vec![" wss://relay.damus.io ".to_string(), "wss://relay.damus.io".to_string(), "wss://nos.lol".to_string()];
+        assert_eq!(normalize_relays(&relays).unwrap(), vec!["wss://relay.damus.io", "wss://nos.lol"]);
+        assert!(normalize_relays(&["ws://relay.damus.io".to_string()]).is_err());
+        assert!(normalize_relays(&[format!("wss://{}.com", "a".repeat(MAX_RELAY_URL_LEN))]).is_err());
+        let many: Vec<String> = (0..=MAX_RELAYS).map(|i| format!("wss://r{}.example.com", i)).collect();
+        assert!(normalize_relays(&many).is_err());
+        assert!(normalize_relays(&many[..MAX_RELAYS]).is_ok());
+    }
+
+    #[test]
+    fn test_nip05_response() {
+        let pubkey = "a".repeat(64);
+        let mut service = Nip05Service { enabled: true, pubkey_hex: pubkey.clone(), relays: vec![], nip46: vec![], names: BTreeMap::new(), name_slots: 0 };
+        let body = serde_json::to_value(Nip05Response::for_name("alice", &pubkey, &service)).unwrap();
+        assert_eq!(body, serde_json::json!({"names": {"alice": pubkey}, "relays": {}}));
+
+        service.relays = vec!["wss://relay.damus.io".into()];
+        service.nip46 = vec!["wss://bunker.example.com".into()];
+        let body = serde_json::to_value(Nip05Response::for_name("alice", &pubkey, &service)).unwrap();
+        assert_eq!(body["relays"][&pubkey], serde_json::json!(["wss://relay.damus.io"]));
+        assert_eq!(body["nip46"][&pubkey], serde_json::json!(["wss://bunker.example.com"]));
+    }
+
+    #[test]
+    fn test_validate_name() {
+        for name in ["bob", "news.alice", "a-b_c", "0"] {
+            assert!(validate_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", "_", "admin", "Bob", "a b", ".a", "a.", "a..b", "a@b", &"a".repeat(MAX_NAME_LEN + 1)] {
+            assert!(validate_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_normalize_names() {
+        let pubkey = "B".repeat(64);
+        let names = BTreeMap::from([(" Bot ".to_string(), pubkey.clone())]);
+        assert_eq!(normalize_names("alice", &names).unwrap(), BTreeMap::from([("bot".to_string(), "b".repeat(64))]));
+        assert!(normalize_names("alice", &BTreeMap::from([("alice".to_string(), pubkey.clone())])).is_err());
+        assert!(normalize_names("alice", &BTreeMap::from([("bot".to_string(), "npub1xyz".to_string())])).is_err());
+        let many: BTreeMap<String, String> = (0..=MAX_EXTRA_NAMES).map(|i| (format!("n{}", i), pubkey.clone())).collect();
+        assert!(normalize_names("alice", &many).is_err());
+    }
+
+    #[test]
+    fn test_subdomain_username() {
+        assert_eq!(subdomain_username("alice.noscha.io", "noscha.io"), Some("alice"));
+        assert_eq!(subdomain_username("noscha.io", "noscha.io"), None);
+        assert_eq!(subdomain_username("www.noscha.io", "noscha.io"), None);
+        assert_eq!(subdomain_username("a.b.noscha.io", "noscha.io"), None);
+        assert_eq!(subdomain_username("evilnoscha.io", "noscha.io"), None);
+    }
+
+    #[test]
+    fn test_lookup_extra_and_root_names() {
+        const NOW: u64 = 1_768_478_400_000; // 2026-01-15T12:00:00.000Z
+        let (main, bot, news) = ("a".repeat(64), "b".repeat(64), "c".repeat(64));
+        let rental = Rental {
+            username: "alice".to_string(),
+            status: RentalStatus::Active,
+            created_at: "2026-01-01T00:00:00.000Z".to_string(),
+            expires_at: "2026-02-01T00:00:00.000Z".to_string(),
+            plan: Plan::ThirtyDays,
+            services: RentalServices {
+                email: None,
+                subdomain: None,
+                nip05: Some(Nip05Service {
+                    enabled: true,
+                    pubkey_hex: main.clone(),
+                    relays: vec!["wss://relay.damus.io".into()],
+                    nip46: vec![],
+                    names: BTreeMap::from([("bot".to_string(), bot.clone()), ("news.alice".to_string(), news.clone())]),
+                    name_slots: 2,
+                }),
+                lightning_address: None,
+            },
+            management_token: None,
+            webhook_url: None,
+            grace_until: None,
+            reminders_sent: Vec::new(),
+            signing_secret: None,
+            webhooks: Vec::new(),
+            nostr_dm: false,
+            owner_pubkey: None,
+        };
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put_rental(&rental).await.unwrap();
+            store.put_nip05_name_index("bot", "alice").await.unwrap();
+            store.put_nip05_name_index("gone", "alice").await.unwrap();
+
+            let found = |name: &'static str, sub: Option<&'static str>| {
+                let store = &store;
+                async move { lookup(store, name, sub, NOW).await.unwrap().map(|r| r.names) }
+            };
+            assert_eq!(found("Alice", None).await, Some(BTreeMap::from([("alice".to_string(), main.clone())])));
+            assert_eq!(found("bot", None).await, Some(BTreeMap::from([("bot".to_string(), bot.clone())])));
+            assert_eq!(found("_", Some("alice")).await, Some(BTreeMap::from([("_".to_string(), main.clone())])));
+            assert_eq!(found("news", Some("alice")).await, Some(BTreeMap::from([("news".to_string(), news.clone())])));
+            assert_eq!(found("bot", Some("alice")).await, Some(BTreeMap::from([("bot".to_string(), bot.clone())])));
+            // Stale index entries and unknown names resolve to nothing
+            assert_eq!(found("gone", None).await, None);
+            assert_eq!(found("_", None).await, None);
+            assert_eq!(found("_", Some("nobody")).await, None);
+
+            // Relay hints belong to the main key only
+            let body = lookup(&store, "bot", None, NOW).await.unwrap().unwrap();
+            assert!(body.relays.is_empty());
+
+            // Expired rentals stop resolving every name
+            assert_eq!(lookup(&store, "bot", None, NOW + 30 * 24 * 3_600_000).await.unwrap(), None);
+        });
+    }
 }
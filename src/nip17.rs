@@ -0,0 +1,369 @@
+//! NIP-17 private direct messages: a kind 14 rumor sealed by the sender (NIP-59
+//! kind 13) and gift-wrapped with a one-time key (kind 1059), then published to
+//! relays over WebSocket on Workers or to an in-process relay in native tests.
+
+use k256::schnorr::SigningKey;
+
+use crate::nip44;
+use crate::nostr::{compute_event_id, sign_event, verify_event};
+use crate::types::NostrEvent;
+
+pub const DM_KIND: u32 = 14;
+pub const SEAL_KIND: u32 = 13;
+pub const GIFT_WRAP_KIND: u32 = 1059;
+
+/// Seal and gift wrap timestamps are backdated by up to two days (NIP-59)
+const MAX_BACKDATE_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// A Nostr key pair
+#[derive(Clone)]
+pub struct Keys {
+    secret: [u8; 32],
+    signing: SigningKey,
+}
+
+impl Keys {
+    pub fn from_bytes(secret: &[u8; 32]) -> Result<Self, String> {
+        let signing = SigningKey::from_bytes(secret).map_err(|_| "Invalid secret key".to_string())?;
+        Ok(Keys { secret: *secret, signing })
+    }
+
+    /// From a 64-character hex secret key
+    pub fn from_hex(secret_hex: &str) -> Result<Self, String> {
+        let bytes: [u8; 32] = hex::decode(secret_hex.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or("Secret key must be 64 hex characters")?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Fresh random keys
+    pub fn generate() -> Self {
+        loop {
+            if let Ok(keys) = Self::from_bytes(&random_32()) {
+                return keys;
+            }
+        }
+    }
+
+    /// x-only public key, lowercase hex
+    pub fn pubkey_hex(&self) -> String {
+        hex::encode(self.signing.verifying_key().to_bytes())
+    }
+
+    /// Set pubkey, id and signature
+    pub fn sign(&self, event: &mut NostrEvent) -> Result<(), String> {
+        sign_event(event, &self.signing, &random_32())
+    }
+
+    fn encrypt_to(&self, pubkey_hex: &str, plaintext: &str) -> Result<String, String> {
+        let key = nip44::conversation_key(&self.secret, pubkey_hex)?;
+        nip44::encrypt(&key, plaintext, &random_32())
+    }
+
+    fn decrypt_from(&self, pubkey_hex: &str, payload: &str) -> Result<String, String> {
+        let key = nip44::conversation_key(&self.secret, pubkey_hex)?;
+        nip44::decrypt(&key, payload)
+    }
+}
+
+fn random_32() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    crate::ids::fill_random(&mut bytes);
+    bytes
+}
+
+fn backdated(now_secs: u64) -> u64 {
+    let mut bytes = [0u8; 8];
+    crate::ids::fill_random(&mut bytes);
+    now_secs.saturating_sub(u64::from_le_bytes(bytes) % MAX_BACKDATE_SECS)
+}
+
+fn to_json(event: &NostrEvent) -> Result<String, String> {
+    serde_json::to_string(event).map_err(|e| e.to_string())
+}
+
+/// Gift-wrap `message` from `sender` to `recipient_pubkey` (hex)
+pub fn gift_wrap(sender: &Keys, recipient_pubkey: &str, message: &str, now_secs: u64) -> Result<NostrEvent, String> {
+    let p_tag = vec![vec!["p".to_string(), recipient_pubkey.to_string()]];
+
+    let mut rumor = NostrEvent {
+        id: None,
+        pubkey: sender.pubkey_hex(),
+        created_at: Some(now_secs),
+        kind: Some(DM_KIND),
+        tags: Some(p_tag.clone()),
+        content: message.to_string(),
+        sig: None,
+    };
+    rumor.id = Some(compute_event_id(&rumor)?);
+
+    let mut seal = NostrEvent {
+        id: None,
+        pubkey: String::new(),
+        created_at: Some(backdated(now_secs)),
+        kind: Some(SEAL_KIND),
+        tags: Some(Vec::new()),
+        content: sender.encrypt_to(recipient_pubkey, &to_json(&rumor)?)?,
+        sig: None,
+    };
+    sender.sign(&mut seal)?;
+
+    let ephemeral = Keys::generate();
+    let mut wrap = NostrEvent {
+        id: None,
+        pubkey: String::new(),
+        created_at: Some(backdated(now_secs)),
+        kind: Some(GIFT_WRAP_KIND),
+        tags: Some(p_tag),
+        content: ephemeral.encrypt_to(recipient_pubkey, &to_json(&seal)?)?,
+        sig: None,
+    };
+    ephemeral.sign(&mut wrap)?;
+    Ok(wrap)
+}
+
+/// Open a gift wrap addressed to `recipient`, returning the rumor once the seal's
+/// signature and author check out
+pub fn unwrap_gift(recipient: &Keys, wrap: &NostrEvent) -> Result<NostrEvent, String> {
+    if wrap.kind != Some(GIFT_WRAP_KIND) {
+        return Err("Not a gift wrap".to_string());
+    }
+    verify_event(wrap)?;
+    let seal: NostrEvent = serde_json::from_str(&recipient.decrypt_from(&wrap.pubkey, &wrap.content)?)
+        .map_err(|e| format!("Invalid seal: {}", e))?;
+    if seal.kind != Some(SEAL_KIND) {
+        return Err("Not a seal".to_string());
+    }
+    verify_event(&seal)?;
+    let rumor: NostrEvent = serde_json::from_str(&recipient.decrypt_from(&seal.pubkey, &seal.content)?)
+        .map_err(|e| format!("Invalid rumor: {}", e))?;
+    if rumor.pubkey != seal.pubkey {
+        return Err("Rumor author does not match seal".to_string());
+    }
+    Ok(rumor)
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Relay {
+    /// Publish an event to one relay; Err with the reason if it was not accepted
+    async fn publish(&self, relay_url: &str, event: &NostrEvent) -> Result<(), String>;
+}
+
+/// Gift-wrap a DM and publish it to every relay; Ok with the relays that accepted
+/// it, Err if none did
+pub async fn send_dm<R: Relay>(
+    relay: &R,
+    relays: &[String],
+    sender: &Keys,
+    recipient_pubkey: &str,
+    message: &str,
+    now_secs: u64,
+) -> Result<Vec<String>, String> {
+    if relays.is_empty() {
+        return Err("No relays configured".to_string());
+    }
+    let wrap = gift_wrap(sender, recipient_pubkey, message, now_secs)?;
+    let mut accepted = Vec::new();
+    let mut errors = Vec::new();
+    for url in relays {
+        match relay.publish(url, &wrap).await {
+            Ok(()) => accepted.push(url.clone()),
+            Err(e) => errors.push(format!("{}: {}", url, e)),
+        }
+    }
+    if accepted.is_empty() {
+        return Err(errors.join("; "));
+    }
+    Ok(accepted)
+}
+
+/// Relay URLs from a comma- or whitespace-separated list; only ws:// and wss:// are kept
+pub fn parse_relays(value: Option<&str>) -> Vec<String> {
+    let mut relays: Vec<String> = Vec::new();
+    for url in value.unwrap_or("").split(|c: char| c == ',' || c.is_whitespace()) {
+        let url = url.trim();
+        if (url.starts_with("wss://") || url.starts_with("ws://")) && !relays.iter().any(|r| r == url) {
+            relays.push(url.to_string());
+        }
+    }
+    relays
+}
+
+/// Interpret a relay message as the answer to publishing `event_id`:
+/// `["OK", id, accepted, reason]`; None for anything else
+pub fn parse_ok(message: &str, event_id: &str) -> Option<Result<(), String>> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    let parts = value.as_array()?;
+    if parts.first()?.as_str()? != "OK" || parts.get(1)?.as_str()? != event_id {
+        return None;
+    }
+    let reason = parts.get(3).and_then(|r| r.as_str()).unwrap_or("");
+    match parts.get(2)?.as_bool()? {
+        true => Some(Ok(())),
+        false => Some(Err(if reason.is_empty() { "rejected".to_string() } else { reason.to_string() })),
+    }
+}
+
+/// In-process relay for native tests: records what it accepts and refuses
+/// events for relays marked with `reject`
+#[derive(Debug, Default)]
+pub struct MemoryRelay {
+    published: std::cell::RefCell<Vec<(String, NostrEvent)>>,
+    rejecting: std::cell::RefCell<Vec<String>>,
+}
+
+impl MemoryRelay {
+    /// Refuse events published to relay_url
+    pub fn reject(&self, relay_url: &str) {
+        self.rejecting.borrow_mut().push(relay_url.to_string());
+    }
+
+    /// (relay URL, event) pairs accepted so far
+    pub fn published(&self) -> Vec<(String, NostrEvent)> {
+        self.published.borrow().clone()
+    }
+}
+
+impl Relay for MemoryRelay {
+    async fn publish(&self, relay_url: &str, event: &NostrEvent) -> Result<(), String> {
+        if self.rejecting.borrow().iter().any(|r| r == relay_url) {
+            return Err("blocked: not accepting events".to_string());
+        }
+        verify_event(event).map_err(|e| format!("invalid: {}", e))?;
+        self.published.borrow_mut().push((relay_url.to_string(), event.clone()));
+        Ok(())
+    }
+}
+
+/// Seconds to wait for a relay's OK before giving up
+#[cfg(target_arch = "wasm32")]
+const PUBLISH_TIMEOUT_SECS: u64 = 5;
+
+/// Publishes over an outbound WebSocket and waits for the relay's OK
+#[cfg(target_arch = "wasm32")]
+pub struct WebSocketRelay;
+
+#[cfg(target_arch = "wasm32")]
+impl Relay for WebSocketRelay {
+    async fn publish(&self, relay_url: &str, event: &NostrEvent) -> Result<(), String> {
+        use futures_util::future::{select, Either};
+        use futures_util::StreamExt;
+        use worker::{Delay, WebSocket, WebsocketEvent};
+
+        let url = worker::Url::parse(relay_url).map_err(|e| e.to_string())?;
+        let ws = WebSocket::connect(url).await.map_err(|e| e.to_string())?;
+        ws.accept().map_err(|e| e.to_string())?;
+        let mut events = ws.events().map_err(|e| e.to_string())?;
+        ws.send(&serde_json::json!(["EVENT", event])).map_err(|e| e.to_string())?;
+
+        let event_id = event.id.clone().unwrap_or_default();
+        let answer = async {
+            while let Some(message) = events.next().await {
+                match message {
+                    Ok(WebsocketEvent::Message(msg)) => {
+                        if let Some(result) = msg.text().and_then(|t| parse_ok(&t, &event_id)) {
+                            return result;
+                        }
+                    }
+                    Ok(WebsocketEvent::Close(_)) => break,
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+            Err("connection closed before OK".to_string())
+        };
+        let timeout = Delay::from(std::time::Duration::from_secs(PUBLISH_TIMEOUT_SECS));
+        let result = match select(std::pin::pin!(answer), std::pin::pin!(timeout)).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err("timed out waiting for OK".to_string()),
+        };
+        let _ = ws.close(Some(1000), Some("done"));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::block_on;
+
+    const NOW: u64 = 1_760_000_000;
+
+    fn keys(byte: u8) -> Keys {
+        Keys::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_gift_wrap_round_trip() {
+        let (service, owner) = (keys(1), keys(2));
+        let wrap = gift_wrap(&service, &owner.pubkey_hex(), "⚡ Payment Complete", NOW).unwrap();
+
+        assert_eq!(wrap.kind, Some(GIFT_WRAP_KIND));
+        assert_ne!(wrap.pubkey, service.pubkey_hex(), "wrapped with a one-time key");
+        assert_eq!(wrap.tags, Some(vec![vec!["p".to_string(), owner.pubkey_hex()]]));
+        assert!(wrap.created_at.unwrap() <= NOW && wrap.created_at.unwrap() > NOW - MAX_BACKDATE_SECS);
+
+        let rumor = unwrap_gift(&owner, &wrap).unwrap();
+        assert_eq!(rumor.kind, Some(DM_KIND));
+        assert_eq!(rumor.pubkey, service.pubkey_hex());
+        assert_eq!(rumor.content, "⚡ Payment Complete");
+        assert_eq!(rumor.created_at, Some(NOW));
+        assert!(rumor.sig.is_none());
+        assert_eq!(rumor.id, Some(compute_event_id(&rumor).unwrap()));
+    }
+
+    #[test]
+    fn test_unwrap_rejects_other_recipients_and_tampering() {
+        let (service, owner) = (keys(1), keys(2));
+        let wrap = gift_wrap(&service, &owner.pubkey_hex(), "hello", NOW).unwrap();
+        assert!(unwrap_gift(&keys(3), &wrap).is_err());
+
+        let mut tampered = wrap.clone();
+        tampered.created_at = Some(NOW);
+        assert!(unwrap_gift(&owner, &tampered).is_err());
+    }
+
+    #[test]
+    fn test_send_dm_publishes_to_accepting_relays() {
+        let relay = MemoryRelay::default();
+        relay.reject("wss://down.example");
+        let relays = parse_relays(Some("wss://relay.example, wss://down.example"));
+        let (service, owner) = (keys(1), keys(2));
+
+        let accepted = block_on(send_dm(&relay, &relays, &service, &owner.pubkey_hex(), "hi", NOW)).unwrap();
+        assert_eq!(accepted, vec!["wss://relay.example".to_string()]);
+        let published = relay.published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(unwrap_gift(&owner, &published[0].1).unwrap().content, "hi");
+
+        relay.reject("wss://relay.example");
+        let err = block_on(send_dm(&relay, &relays, &service, &owner.pubkey_hex(), "hi", NOW)).unwrap_err();
+        assert!(err.contains("wss://down.example: blocked"));
+        assert!(block_on(send_dm(&relay, &[], &service, &owner.pubkey_hex(), "hi", NOW)).is_err());
+    }
+
+    #[test]
+    fn test_parse_relays_and_ok() {
+        assert_eq!(
+            parse_relays(Some("wss://a.example,https://b.example wss://a.example\nws://localhost:7777")),
+            vec!["wss://a.example".to_string(), "ws://localhost:7777".to_string()]
+        );
+        assert!(parse_relays(None).is_empty());
+
+        assert_eq!(parse_ok(r#"["OK","abc",true,""]"#, "abc"), Some(Ok(())));
+        assert_eq!(
+            parse_ok(r#"["OK","abc",false,"rate-limited: slow down"]"#, "abc"),
+            Some(Err("rate-limited: slow down".to_string()))
+        );
+        assert_eq!(parse_ok(r#"["OK","other",true,""]"#, "abc"), None);
+        assert_eq!(parse_ok(r#"["NOTICE","hi"]"#, "abc"), None);
+    }
+
+    #[test]
+    fn test_keys_from_hex() {
+        let owner = Keys::from_hex(&hex::encode([2u8; 32])).unwrap();
+        assert_eq!(owner.pubkey_hex(), keys(2).pubkey_hex());
+        assert!(Keys::from_hex("abcd").is_err());
+        assert!(Keys::from_hex(&"0".repeat(64)).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! NIP-19 bech32 entities: `npub` and `nprofile` keys as pasted from Nostr
+//! clients, decoded to hex for storage and encoded back for display.
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+/// nprofile strings exceed BIP-173's 90 characters; cap input length instead
+const MAX_LEN: usize = 5000;
+
+/// TLV types used by nprofile
+const TLV_SPECIAL: u8 = 0;
+const TLV_RELAY: u8 = 1;
+
+/// A pubkey with the relay hints an nprofile carried (none for hex and npub)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub pubkey_hex: String,
+    pub relays: Vec<String>,
+}
+
+/// Read a pubkey given as 64-char hex, `npub1...` or `nprofile1...`, with or
+/// without a `nostr:` prefix. Hex is returned lowercased
+pub fn decode_pubkey(input: &str) -> Result<Profile, String> {
+    let input = input.trim();
+    let input = input.strip_prefix("nostr:").unwrap_or(input);
+    if crate::nip05::validate_pubkey_hex(input) {
+        return Ok(Profile { pubkey_hex: input.to_ascii_lowercase(), relays: Vec::new() });
+    }
+    let (hrp, data) = decode(input)?;
+    match hrp.as_str() {
+        "npub" => {
+            if data.len() != 32 {
+                return Err("npub must hold a 32-byte key".to_string());
+            }
+            Ok(Profile { pubkey_hex: hex::encode(data), relays: Vec::new() })
+        }
+        "nprofile" => decode_nprofile(&data),
+        "nsec" => Err("That is a secret key (nsec); paste your public key (npub) instead".to_string()),
+        other => Err(format!("Expected a hex pubkey, npub or nprofile, got {}", other)),
+    }
+}
+
+/// Hex pubkey only, for fields where relay hints have no use
+pub fn pubkey_hex(input: &str) -> Result<String, String> {
+    decode_pubkey(input).map(|p| p.pubkey_hex)
+}
+
+/// `npub1...` for a 64-char hex pubkey
+pub fn encode_npub(pubkey_hex: &str) -> Result<String, String> {
+    let bytes = hex::decode(pubkey_hex).map_err(|_| "Invalid pubkey encoding".to_string())?;
+    if bytes.len() != 32 {
+        return Err("Invalid pubkey".to_string());
+    }
+    Ok(encode("npub", &bytes))
+}
+
+/// `nprofile1...` for a hex pubkey and relay hints
+pub fn encode_nprofile(pubkey_hex: &str, relays: &[String]) -> Result<String, String> {
+    let pubkey = hex::decode(pubkey_hex).map_err(|_| "Invalid pubkey encoding".to_string())?;
+    if pubkey.len() != 32 {
+        return Err("Invalid pubkey".to_string());
+    }
+    let mut tlv = vec![TLV_SPECIAL, 32];
+    tlv.extend_from_slice(&pubkey);
+    for relay in relays {
+        let len = u8::try_from(relay.len()).map_err(|_| "Relay URL is too long".to_string())?;
+        tlv.extend_from_slice(&[TLV_RELAY, len]);
+        tlv.extend_from_slice(relay.as_bytes());
+    }
+    Ok(encode("nprofile", &tlv))
+}
+
+fn decode_nprofile(tlv: &[u8]) -> Result<Profile, String> {
+    let mut pubkey = None;
+    let mut relays = Vec::new();
+    let mut rest = tlv;
+    while let [kind, len, tail @ ..] = rest {
+        let len = *len as usize;
+        if tail.len() < len {
+            return Err("Truncated nprofile".to_string());
+        }
+        let (value, next) = tail.split_at(len);
+        match *kind {
+            TLV_SPECIAL if len == 32 => pubkey = pubkey.or(Some(hex::encode(value))),
+            TLV_SPECIAL => return Err("nprofile must hold a 32-byte key".to_string()),
+            TLV_RELAY => {
+                let relay = std::str::from_utf8(value).map_err(|_| "nprofile relay is not UTF-8".to_string())?;
+                relays.push(relay.to_string());
+            }
+            // Unknown TLV types are skipped, as NIP-19 requires
+            _ => {}
+        }
+        rest = next;
+    }
+    if !rest.is_empty() {
+        return Err("Truncated nprofile".to_string());
+    }
+    let pubkey_hex = pubkey.ok_or("nprofile has no pubkey")?;
+    Ok(Profile { pubkey_hex, relays })
+}
+
+/// BIP-173 bech32 (not bech32m) encoding of 8-bit data
+pub(crate) fn encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = convert_bits(data, 8, 5, true).expect("8-to-5 conversion with padding cannot fail");
+    let checksum = {
+        let mut input = hrp_expand(hrp);
+        input.extend_from_slice(&values);
+        input.extend_from_slice(&[0; 6]);
+        let polymod = polymod(&input) ^ 1;
+        (0..6).map(move |i| ((polymod >> (5 * (5 - i))) & 31) as u8)
+    };
+    values.extend(checksum);
+    let mut out = String::with_capacity(hrp.len() + 1 + values.len());
+    out.push_str(hrp);
+    out.push('1');
+    out.extend(values.iter().map(|&v| CHARSET[v as usize] as char));
+    out
+}
+
+/// Decode a bech32 string into its human-readable part and 8-bit data (also
+/// used for LUD-01 `lnurl` strings)
+pub(crate) fn decode(input: &str) -> Result<(String, Vec<u8>), String> {
+    if input.len() > MAX_LEN {
+        return Err("Bech32 string is too long".to_string());
+    }
+    let has_lower = input.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = input.bytes().any(|b| b.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err("Bech32 string mixes upper and lower case".to_string());
+    }
+    let input = input.to_ascii_lowercase();
+    let sep = input.rfind('1').ok_or("Not a hex pubkey, npub or nprofile")?;
+    let (hrp, payload) = (&input[..sep], &input[sep + 1..]);
+    if hrp.is_empty() || payload.len() < 6 || !hrp.bytes().all(|b| (33..=126).contains(&b)) {
+        return Err("Not a hex pubkey, npub or nprofile".to_string());
+    }
+    let values = payload
+        .bytes()
+        .map(|b| CHARSET.iter().position(|&c| c == b).map(|p| p as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("Invalid bech32 character")?;
+
+    let mut check = hrp_expand(hrp);
+    check.extend_from_slice(&values);
+    if polymod(&check) != 1 {
+        return Err("Invalid bech32 checksum".to_string());
+    }
+    let data = convert_bits(&values[..values.len() - 6], 5, 8, false).ok_or("Invalid bech32 padding")?;
+    Ok((hrp.to_string(), data))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for &v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ v as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut out: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    out.push(0);
+    out.extend(hrp.bytes().map(|b| b & 31));
+    out
+}
+
+/// Regroup bits; without padding, leftover bits must be zero and fewer than `from`
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max = (1u32 << to) - 1;
+    let mut out = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for &value in data {
+        acc = (acc << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+    const NPUB: &str = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+
+    #[test]
+    fn test_npub_vector() {
+        assert_eq!(encode_npub(HEX).unwrap(), NPUB);
+        assert_eq!(decode_pubkey(NPUB).unwrap(), Profile { pubkey_hex: HEX.to_string(), relays: vec![] });
+        assert_eq!(pubkey_hex(&format!(" nostr:{} ", NPUB)).unwrap(), HEX);
+        assert_eq!(pubkey_hex(&NPUB.to_ascii_uppercase()).unwrap(), HEX);
+        assert_eq!(pubkey_hex(&HEX.to_ascii_uppercase()).unwrap(), HEX);
+    }
+
+    #[test]
+    fn test_nprofile_vector() {
+        let nprofile = "nprofile1qqsrhuxx8l9ex335q7he0f09aej04zpazpl0ne2cgukyawd24mayt8gpp4mhxue69uhhytnc9e3k7mgpz4mhxue69uhkg6nzv9ejuumpv34kytnrdaksjlyr9p";
+        let profile = decode_pubkey(nprofile).unwrap();
+        assert_eq!(profile.pubkey_hex, "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d");
+        assert_eq!(profile.relays, vec!["wss://r.x.com", "wss://djbas.sadkb.com"]);
+        assert_eq!(encode_nprofile(&profile.pubkey_hex, &profile.relays).unwrap(), nprofile);
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let mut bad_checksum = NPUB.to_string();
+        bad_checksum.pop();
+        bad_checksum.push('q');
+        assert!(decode_pubkey(&bad_checksum).is_err());
+        assert!(decode_pubkey("npub1").is_err());
+        assert!(decode_pubkey("Npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg").is_err());
+        assert!(decode_pubkey(&"a".repeat(63)).is_err());
+        assert!(decode_pubkey(&encode("note", &[1; 32])).is_err());
+        assert!(decode_pubkey(&encode("npub", &[1; 31])).is_err());
+        assert!(decode_pubkey(&encode("nsec", &[1; 32])).unwrap_err().contains("secret key"));
+        // nprofile with a TLV length running past the end
+        assert!(decode_pubkey(&encode("nprofile", &[0, 32, 1, 2])).is_err());
+        assert!(encode_npub("abc").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! NIP-44 v2 payload encryption: secp256k1 ECDH, HKDF-SHA256, ChaCha20 and
+//! HMAC-SHA256 over padded plaintext, base64 encoded.
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hkdf::Hkdf;
+use k256::elliptic_curve::point::AffineCoordinates;
+use k256::{PublicKey, SecretKey};
+use sha2::Sha256;
+
+use crate::hmac::hmac_sha256;
+use crate::ids::{base64, base64_decode};
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+const MIN_PLAINTEXT_LEN: usize = 1;
+const MAX_PLAINTEXT_LEN: usize = 65535;
+
+/// Shared key for messages between a secret key and an x-only pubkey (hex)
+pub fn conversation_key(secret: &[u8; 32], pubkey_hex: &str) -> Result<[u8; 32], String> {
+    let secret = SecretKey::from_slice(secret).map_err(|_| "Invalid secret key".to_string())?;
+    let x = hex::decode(pubkey_hex).map_err(|_| "Invalid pubkey encoding".to_string())?;
+    if x.len() != 32 {
+        return Err("Invalid pubkey".to_string());
+    }
+    let mut sec1 = [0u8; 33];
+    sec1[0] = 0x02;
+    sec1[1..].copy_from_slice(&x);
+    let public = PublicKey::from_sec1_bytes(&sec1).map_err(|_| "Invalid pubkey".to_string())?;
+
+    let shared = (public.to_projective() * *secret.to_nonzero_scalar()).to_affine();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(SALT), &shared.x());
+    Ok(prk.into())
+}
+
+/// Encrypt plaintext with a conversation key and a random 32-byte nonce
+pub fn encrypt(conversation_key: &[u8; 32], plaintext: &str, nonce: &[u8; 32]) -> Result<String, String> {
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, nonce);
+    let mut ciphertext = pad(plaintext.as_bytes())?;
+    chacha20_xor(&chacha_key, &chacha_nonce, &mut ciphertext);
+    let mac = hmac_aad(&hmac_key, nonce, &ciphertext);
+
+    let mut payload = Vec::with_capacity(1 + 32 + ciphertext.len() + 32);
+    payload.push(VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&ciphertext);
+    payload.extend_from_slice(&mac);
+    Ok(base64(&payload))
+}
+
+/// Decrypt a base64 payload, checking version, MAC and padding
+pub fn decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String, String> {
+    if payload.starts_with('#') {
+        return Err("Unsupported encryption version".to_string());
+    }
+    if !(132..=87472).contains(&payload.len()) {
+        return Err("Invalid payload size".to_string());
+    }
+    let data = base64_decode(payload).ok_or("Invalid base64")?;
+    if !(99..=65603).contains(&data.len()) {
+        return Err("Invalid data size".to_string());
+    }
+    if data[0] != VERSION {
+        return Err(format!("Unknown encryption version {}", data[0]));
+    }
+    let nonce: [u8; 32] = data[1..33].try_into().unwrap();
+    let (ciphertext, mac) = data[33..].split_at(data.len() - 33 - 32);
+
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, &nonce);
+    let expected = hmac_aad(&hmac_key, &nonce, ciphertext);
+    if expected.iter().zip(mac).fold(0u8, |acc, (a, b)| acc | (a ^ b)) != 0 {
+        return Err("Invalid MAC".to_string());
+    }
+
+    let mut padded = ciphertext.to_vec();
+    chacha20_xor(&chacha_key, &chacha_nonce, &mut padded);
+    let plaintext = unpad(&padded)?;
+    String::from_utf8(plaintext.to_vec()).map_err(|_| "Plaintext is not UTF-8".to_string())
+}
+
+/// HKDF-expand(conversation_key, nonce, 76) split into ChaCha key, ChaCha nonce and HMAC key
+fn message_keys(conversation_key: &[u8; 32], nonce: &[u8; 32]) -> ([u8; 32], [u8; 12], [u8; 32]) {
+    let mut okm = [0u8; 76];
+    let hkdf = Hkdf::<Sha256>::from_prk(conversation_key).expect("conversation keys are 32 bytes");
+    hkdf.expand(nonce, &mut okm).expect("76 bytes is within the HKDF-SHA256 output limit");
+    (
+        okm[0..32].try_into().unwrap(),
+        okm[32..44].try_into().unwrap(),
+        okm[44..76].try_into().unwrap(),
+    )
+}
+
+fn hmac_aad(key: &[u8; 32], aad: &[u8; 32], message: &[u8]) -> [u8; 32] {
+    let mut input = Vec::with_capacity(32 + message.len());
+    input.extend_from_slice(aad);
+    input.extend_from_slice(message);
+    hmac_sha256(key, &input)
+}
+
+/// Padded length: 32 bytes minimum, then chunks of 32 up to 256 and of 1/8 of the
+/// next power of two beyond that
+pub fn calc_padded_len(unpadded_len: usize) -> usize {
+    if unpadded_len <= 32 {
+        return 32;
+    }
+    // Smallest power of two above unpadded_len - 1
+    let next_power = 1usize << (usize::BITS - (unpadded_len - 1).leading_zeros());
+    let chunk = if next_power <= 256 { 32 } else { next_power / 8 };
+    chunk * ((unpadded_len - 1) / chunk + 1)
+}
+
+fn pad(plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let len = plaintext.len();
+    if !(MIN_PLAINTEXT_LEN..=MAX_PLAINTEXT_LEN).contains(&len) {
+        return Err("Invalid plaintext length".to_string());
+    }
+    let mut padded = Vec::with_capacity(2 + calc_padded_len(len));
+    padded.extend_from_slice(&(len as u16).to_be_bytes());
+    padded.extend_from_slice(plaintext);
+    padded.resize(2 + calc_padded_len(len), 0);
+    Ok(padded)
+}
+
+fn unpad(padded: &[u8]) -> Result<&[u8], String> {
+    let len = u16::from_be_bytes([padded[0], padded[1]]) as usize;
+    if len < MIN_PLAINTEXT_LEN || padded.len() != 2 + calc_padded_len(len) {
+        return Err("Invalid padding".to_string());
+    }
+    Ok(&padded[2..2 + len])
+}
+
+/// ChaCha20 (RFC 8439) keystream XOR, block counter starting at 0
+fn chacha20_xor(key: &[u8; 32], nonce: &[u8; 12], data: &mut [u8]) {
+    ChaCha20::new(key.into(), nonce.into()).apply_keystream(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(hex_str: &str) -> [u8; 32] {
+        hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    fn pubkey_of(secret: &[u8; 32]) -> String {
+        let sk = k256::schnorr::SigningKey::from_bytes(secret).unwrap();
+        hex::encode(sk.verifying_key().to_bytes())
+    }
+
+    #[test]
+    fn test_chacha20_rfc8439_block() {
+        // RFC 8439 §2.3.2
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut keystream = [0u8; 128];
+        chacha20_xor(&key, &nonce, &mut keystream);
+        assert_eq!(
+            hex::encode(&keystream[64..80]),
+            "10f1e7e4d13b5915500fdd1fa32071c4"
+        );
+    }
+
+    #[test]
+    fn test_conversation_key_vector() {
+        let mut sec1 = [0u8; 32];
+        sec1[31] = 1;
+        let mut sec2 = [0u8; 32];
+        sec2[31] = 2;
+        let expected = "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d";
+        assert_eq!(hex::encode(conversation_key(&sec1, &pubkey_of(&sec2)).unwrap()), expected);
+        assert_eq!(hex::encode(conversation_key(&sec2, &pubkey_of(&sec1)).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_encrypt_vector() {
+        let conversation = key("c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d");
+        let mut nonce = [0u8; 32];
+        nonce[31] = 1;
+        let payload = encrypt(&conversation, "a", &nonce).unwrap();
+        assert_eq!(
+            payload,
+            "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb"
+        );
+        assert_eq!(decrypt(&conversation, &payload).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_padded_lengths() {
+        for (len, padded) in [
+            (16, 32), (32, 32), (33, 64), (37, 64), (64, 64), (65, 96), (100, 128), (200, 224),
+            (250, 256), (320, 320), (383, 384), (384, 384), (400, 448), (500, 512), (512, 512),
+            (515, 640), (700, 768), (800, 896), (900, 1024), (1020, 1024), (65536, 65536),
+        ] {
+            assert_eq!(calc_padded_len(len), padded, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_tampering() {
+        let conversation = key("3dfef0ce2a4d80a25e7a328accf73448ef67096f65f79588e358d9a0eb9013f1");
+        let nonce = [7u8; 32];
+        let message = "📧 New email\nFrom: alice@example.com ".repeat(20);
+        let payload = encrypt(&conversation, &message, &nonce).unwrap();
+        assert_eq!(decrypt(&conversation, &payload).unwrap(), message);
+
+        let mut bytes = base64_decode(&payload).unwrap();
+        bytes[40] ^= 1;
+        assert_eq!(decrypt(&conversation, &base64(&bytes)), Err("Invalid MAC".to_string()));
+        assert!(decrypt(&[1u8; 32], &payload).is_err());
+        assert!(encrypt(&conversation, "", &nonce).is_err());
+    }
+}
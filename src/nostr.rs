@@ -0,0 +1,366 @@
+use k256::schnorr::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::types::NostrEvent;
+
+/// Event kind signed by the admin dashboard for NIP-07 login
+pub const ADMIN_AUTH_KIND: u32 = 27235;
+
+/// Maximum allowed difference between event created_at and server time (seconds)
+pub const MAX_CREATED_AT_SKEW_SECS: u64 = 300;
+
+/// NIP-98 HTTP auth event kind (same kind as the admin login event)
+pub const HTTP_AUTH_KIND: u32 = 27235;
+
+/// NIP-98 events must be signed within this many seconds of the request
+pub const HTTP_AUTH_MAX_SKEW_SECS: u64 = 60;
+
+/// NIP-01 serialization: [0, pubkey, created_at, kind, tags, content]
+pub fn serialize_event(event: &NostrEvent) -> Result<String, String> {
+    let created_at = event.created_at.ok_or("Missing created_at")?;
+    let kind = event.kind.ok_or("Missing kind")?;
+    let empty: Vec<Vec<String>> = Vec::new();
+    let tags = event.tags.as_ref().unwrap_or(&empty);
+    let value = serde_json::json!([0, event.pubkey, created_at, kind, tags, event.content]);
+    serde_json::to_string(&value).map_err(|e| e.to_string())
+}
+
+/// Compute the event id (lowercase hex sha256 of the NIP-01 serialization)
+pub fn compute_event_id(event: &NostrEvent) -> Result<String, String> {
+    let serialized = serialize_event(event)?;
+    Ok(hex::encode(Sha256::digest(serialized.as_bytes())))
+}
+
+/// Verify that the event id matches its content and that sig is a valid
+/// BIP-340 Schnorr signature of the id by pubkey
+pub fn verify_event(event: &NostrEvent) -> Result<(), String> {
+    let id = event.id.as_deref().ok_or("Missing event id")?;
+    let sig = event.sig.as_deref().ok_or("Missing event signature")?;
+
+    let computed = compute_event_id(event)?;
+    if !id.eq_ignore_ascii_case(&computed) {
+        return Err("Event id does not match event content".to_string());
+    }
+
+    let pubkey_bytes = decode_hex_32(&event.pubkey).ok_or("Invalid pubkey encoding")?;
+    let verifying_key =
+        VerifyingKey::from_bytes(&pubkey_bytes).map_err(|_| "Invalid pubkey".to_string())?;
+
+    let sig_bytes = hex::decode(sig).map_err(|_| "Invalid signature encoding".to_string())?;
+    let signature =
+        Signature::try_from(sig_bytes.as_slice()).map_err(|_| "Invalid signature".to_string())?;
+
+    let id_bytes = decode_hex_32(&computed).ok_or("Invalid event id")?;
+    verifying_key
+        .verify_raw(&id_bytes, &signature)
+        .map_err(|_| "Invalid signature".to_string())
+}
+
+/// Set pubkey and id from key and sign the id (BIP-340, aux_rand as auxiliary randomness)
+pub fn sign_event(event: &mut NostrEvent, key: &SigningKey, aux_rand: &[u8; 32]) -> Result<(), String> {
+    event.pubkey = hex::encode(key.verifying_key().to_bytes());
+    let id = compute_event_id(event)?;
+    let id_bytes = decode_hex_32(&id).ok_or("Invalid event id")?;
+    let sig = key.sign_raw(&id_bytes, aux_rand).map_err(|_| "Signing failed".to_string())?;
+    event.id = Some(id);
+    event.sig = Some(hex::encode(sig.to_bytes()));
+    Ok(())
+}
+
+/// Return the first value of the first tag with the given name
+pub fn find_tag<'a>(event: &'a NostrEvent, name: &str) -> Option<&'a str> {
+    event
+        .tags
+        .as_ref()?
+        .iter()
+        .find(|t| t.first().map(|n| n == name).unwrap_or(false))
+        .and_then(|t| t.get(1))
+        .map(|v| v.as_str())
+}
+
+/// Validate an admin login event and return its challenge.
+/// Checks pubkey, kind, created_at skew, challenge tag, id and signature.
+pub fn verify_login_event(
+    event: &NostrEvent,
+    expected_pubkey: &str,
+    now_secs: u64,
+) -> Result<String, String> {
+    if !event.pubkey.eq_ignore_ascii_case(expected_pubkey) {
+        return Err("Unauthorized: invalid pubkey".to_string());
+    }
+    if event.kind != Some(ADMIN_AUTH_KIND) {
+        return Err(format!("Invalid event kind: expected {}", ADMIN_AUTH_KIND));
+    }
+    let created_at = event.created_at.ok_or("Missing created_at")?;
+    if created_at.abs_diff(now_secs) > MAX_CREATED_AT_SKEW_SECS {
+        return Err("Event created_at is too far from server time".to_string());
+    }
+    let challenge = find_tag(event, "challenge")
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .ok_or("Missing challenge tag")?;
+    verify_event(event)?;
+    Ok(challenge)
+}
+
+/// Decode an `Authorization: Nostr <base64 event>` header; None for other schemes
+pub fn parse_http_auth_header(header: &str) -> Option<Result<NostrEvent, String>> {
+    let encoded = header.trim().strip_prefix("Nostr ")?;
+    let decoded = crate::ids::base64_decode(encoded.trim());
+    Some(
+        decoded
+            .ok_or_else(|| "Invalid base64 in Authorization header".to_string())
+            .and_then(|json| serde_json::from_slice(&json).map_err(|e| format!("Invalid auth event: {}", e))),
+    )
+}
+
+/// Validate a NIP-98 event for a request and return its challenge. Checks kind,
+/// created_at, the `u` and `method` tags, the `payload` hash when there is a body,
+/// the challenge tag, id and signature.
+pub fn verify_http_auth_event(
+    event: &NostrEvent,
+    url: &str,
+    method: &str,
+    body: &[u8],
+    now_secs: u64,
+) -> Result<String, String> {
+    if event.kind != Some(HTTP_AUTH_KIND) {
+        return Err(format!("Invalid event kind: expected {}", HTTP_AUTH_KIND));
+    }
+    let created_at = event.created_at.ok_or("Missing created_at")?;
+    if created_at.abs_diff(now_secs) > HTTP_AUTH_MAX_SKEW_SECS {
+        return Err("Event created_at is too far from server time".to_string());
+    }
+    if find_tag(event, "u") != Some(url) {
+        return Err("Event u tag does not match the request URL".to_string());
+    }
+    if !find_tag(event, "method").is_some_and(|m| m.eq_ignore_ascii_case(method)) {
+        return Err("Event method tag does not match the request method".to_string());
+    }
+    if !body.is_empty() {
+        let expected = hex::encode(Sha256::digest(body));
+        if !find_tag(event, "payload").is_some_and(|p| p.eq_ignore_ascii_case(&expected)) {
+            return Err("Event payload tag does not match the request body".to_string());
+        }
+    }
+    let challenge = find_tag(event, "challenge")
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .ok_or("Missing challenge tag")?;
+    verify_event(event)?;
+    Ok(challenge)
+}
+
+fn decode_hex_32(s: &str) -> Option<[u8; 32]> {
+    let bytes = hex::decode(s).ok()?;
+    bytes.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef";
+
+    fn signed_event(kind: u32, created_at: u64, tags: Vec<Vec<String>>, content: &str) -> NostrEvent {
+        let sk = SigningKey::from_bytes(&hex::decode(SECRET).unwrap()).unwrap();
+        let mut event = NostrEvent {
+            id: None,
+            pubkey: String::new(),
+            created_at: Some(created_at),
+            kind: Some(kind),
+            tags: Some(tags),
+            content: content.to_string(),
+            sig: None,
+        };
+        sign_event(&mut event, &sk, &[0u8; 32]).unwrap();
+        event
+    }
+
+    fn login_event(challenge: &str, created_at: u64) -> NostrEvent {
+        signed_event(
+            ADMIN_AUTH_KIND,
+            created_at,
+            vec![vec!["challenge".to_string(), challenge.to_string()]],
+            "",
+        )
+    }
+
+    #[test]
+    fn test_bip340_vector() {
+        // BIP-340 test vector #1
+        let pk = decode_hex_32("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659").unwrap();
+        let msg = hex::decode("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89").unwrap();
+        let sig = hex::decode("6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a").unwrap();
+        let vk = VerifyingKey::from_bytes(&pk).unwrap();
+        let sig = Signature::try_from(sig.as_slice()).unwrap();
+        assert!(vk.verify_raw(&msg, &sig).is_ok());
+    }
+
+    #[test]
+    fn test_serialize_event_nip01() {
+        let event = NostrEvent {
+            id: None,
+            pubkey: "ab".into(),
+            created_at: Some(1700000000),
+            kind: Some(1),
+            tags: Some(vec![vec!["p".into(), "cd".into()]]),
+            content: "hi \"there\"\n".into(),
+            sig: None,
+        };
+        assert_eq!(
+            serialize_event(&event).unwrap(),
+            r#"[0,"ab",1700000000,1,[["p","cd"]],"hi \"there\"\n"]"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_event_missing_tags_is_empty_array() {
+        let event = NostrEvent {
+            id: None,
+            pubkey: "ab".into(),
+            created_at: Some(1),
+            kind: Some(1),
+            tags: None,
+            content: String::new(),
+            sig: None,
+        };
+        assert_eq!(serialize_event(&event).unwrap(), r#"[0,"ab",1,1,[],""]"#);
+    }
+
+    #[test]
+    fn test_verify_event_valid() {
+        let event = signed_event(1, 1700000000, vec![], "hello");
+        assert!(verify_event(&event).is_ok());
+    }
+
+    #[test]
+    fn test_verify_event_tampered_content() {
+        let mut event = signed_event(1, 1700000000, vec![], "hello");
+        event.content = "hellO".into();
+        assert!(verify_event(&event).is_err());
+    }
+
+    #[test]
+    fn test_verify_event_tampered_id() {
+        let mut event = signed_event(1, 1700000000, vec![], "hello");
+        event.content = "forged".into();
+        event.id = Some(compute_event_id(&event).unwrap());
+        assert_eq!(verify_event(&event), Err("Invalid signature".to_string()));
+    }
+
+    #[test]
+    fn test_verify_event_tampered_sig() {
+        let mut event = signed_event(1, 1700000000, vec![], "hello");
+        let mut sig = event.sig.clone().unwrap();
+        let last = if sig.ends_with('0') { "1" } else { "0" };
+        sig.replace_range(127..128, last);
+        event.sig = Some(sig);
+        assert!(verify_event(&event).is_err());
+    }
+
+    #[test]
+    fn test_verify_event_missing_sig() {
+        let mut event = signed_event(1, 1700000000, vec![], "hello");
+        event.sig = None;
+        assert!(verify_event(&event).is_err());
+    }
+
+    #[test]
+    fn test_verify_login_event_valid() {
+        let event = login_event("ch_abc", 1700000000);
+        let pubkey = event.pubkey.clone();
+        assert_eq!(
+            verify_login_event(&event, &pubkey, 1700000100),
+            Ok("ch_abc".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_login_event_wrong_pubkey() {
+        let event = login_event("ch_abc", 1700000000);
+        let other = "a".repeat(64);
+        assert!(verify_login_event(&event, &other, 1700000000).is_err());
+    }
+
+    #[test]
+    fn test_verify_login_event_wrong_kind() {
+        let event = signed_event(1, 1700000000, vec![vec!["challenge".into(), "ch_abc".into()]], "");
+        let pubkey = event.pubkey.clone();
+        assert!(verify_login_event(&event, &pubkey, 1700000000).is_err());
+    }
+
+    #[test]
+    fn test_verify_login_event_stale() {
+        let event = login_event("ch_abc", 1700000000);
+        let pubkey = event.pubkey.clone();
+        assert!(verify_login_event(&event, &pubkey, 1700000000 + MAX_CREATED_AT_SKEW_SECS + 1).is_err());
+        assert!(verify_login_event(&event, &pubkey, 1700000000 - MAX_CREATED_AT_SKEW_SECS - 1).is_err());
+    }
+
+    #[test]
+    fn test_verify_login_event_missing_challenge_tag() {
+        let event = signed_event(ADMIN_AUTH_KIND, 1700000000, vec![], "ch_abc");
+        let pubkey = event.pubkey.clone();
+        assert_eq!(
+            verify_login_event(&event, &pubkey, 1700000000),
+            Err("Missing challenge tag".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_login_event_forged_sig() {
+        // Correct pubkey and challenge but signature from a different key
+        let mut event = login_event("ch_abc", 1700000000);
+        let other_sk = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let id = hex::decode(event.id.as_ref().unwrap()).unwrap();
+        event.sig = Some(hex::encode(other_sk.sign_raw(&id, &[0u8; 32]).unwrap().to_bytes()));
+        let pubkey = event.pubkey.clone();
+        assert!(verify_login_event(&event, &pubkey, 1700000000).is_err());
+    }
+
+    fn http_auth_event(url: &str, method: &str, body: &[u8], created_at: u64) -> NostrEvent {
+        let mut tags = vec![
+            vec!["u".to_string(), url.to_string()],
+            vec!["method".to_string(), method.to_string()],
+            vec!["challenge".to_string(), "ch_owner".to_string()],
+        ];
+        if !body.is_empty() {
+            tags.push(vec!["payload".to_string(), hex::encode(Sha256::digest(body))]);
+        }
+        signed_event(HTTP_AUTH_KIND, created_at, tags, "")
+    }
+
+    #[test]
+    fn test_verify_http_auth_event() {
+        let url = "https://noscha.io/api/settings/alice";
+        let body = br#"{"nostr_dm":true}"#;
+        let event = http_auth_event(url, "PUT", body, 1700000000);
+        assert_eq!(verify_http_auth_event(&event, url, "PUT", body, 1700000030), Ok("ch_owner".to_string()));
+
+        assert!(verify_http_auth_event(&event, "https://noscha.io/api/settings/bob", "PUT", body, 1700000000).is_err());
+        assert!(verify_http_auth_event(&event, url, "POST", body, 1700000000).is_err());
+        assert!(verify_http_auth_event(&event, url, "PUT", b"{}", 1700000000).is_err());
+        assert!(verify_http_auth_event(&event, url, "PUT", body, 1700000000 + HTTP_AUTH_MAX_SKEW_SECS + 1).is_err());
+
+        let get = http_auth_event("https://noscha.io/my/alice", "GET", b"", 1700000000);
+        assert!(verify_http_auth_event(&get, "https://noscha.io/my/alice", "get", b"", 1700000000).is_ok());
+    }
+
+    #[test]
+    fn test_parse_http_auth_header() {
+        let event = http_auth_event("https://noscha.io/my/alice", "GET", b"", 1700000000);
+        let header = format!("Nostr {}", crate::ids::base64(serde_json::to_string(&event).unwrap().as_bytes()));
+        let parsed = parse_http_auth_header(&header).unwrap().unwrap();
+        assert_eq!(parsed.id, event.id);
+        assert!(parse_http_auth_header("Bearer mgmt_abc").is_none());
+        assert!(parse_http_auth_header("Nostr !!!").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_find_tag() {
+        let event = login_event("ch_xyz", 1);
+        assert_eq!(find_tag(&event, "challenge"), Some("ch_xyz"));
+        assert_eq!(find_tag(&event, "p"), None);
+    }
+}
@@ -0,0 +1,504 @@
+//! Lightning payment backends behind a common `PaymentProvider` trait.
+//! The backend is chosen with PAYMENT_PROVIDER (coinos, lnbits, btcpay);
+//! MOCK_PAYMENT=true overrides it with an always-paid mock.
+
+use crate::btcpay::BtcPayProvider;
+use crate::coinos::CoinosProvider;
+use crate::http::{HttpClient, InboundRequest};
+use crate::lnbits::LnbitsProvider;
+use crate::types::CoinosWebhookPayload;
+
+/// Parameters for a new invoice
+#[derive(Debug, Clone)]
+pub struct InvoiceRequest<'a> {
+    pub amount_sats: u64,
+    pub memo: &'a str,
+    /// Our webhook endpoint for this backend
+    pub webhook_url: &'a str,
+    /// Per-order secret; backends echo it back (or carry it in the webhook URL)
+    pub secret: &'a str,
+    pub expiry_secs: u64,
+    /// Hex SHA-256 to commit to instead of the memo (LNURL-pay); backends
+    /// without description hash support fall back to the memo
+    pub description_hash: Option<&'a str>,
+}
+
+/// Invoice created by a backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreatedInvoice {
+    pub bolt11: String,
+    /// Backend reference used for status checks (payment hash or invoice id)
+    pub payment_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvoiceStatus {
+    Pending,
+    Paid,
+    Expired,
+}
+
+/// Settlement notification extracted from a backend webhook
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookNotice {
+    pub secret: Option<String>,
+    pub payment_id: Option<String>,
+    /// True when the payload itself is trusted proof of payment;
+    /// otherwise the caller must confirm with `check_status`
+    pub paid: bool,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait PaymentProvider {
+    /// Backend name, also the webhook path segment: /api/webhook/{name}
+    fn name(&self) -> &'static str;
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String>;
+
+    async fn check_status(&self, payment_id: &str) -> Result<InvoiceStatus, String>;
+
+    /// Parse and authenticate a webhook call. Ok(None) means the call is not a
+    /// settlement notification and should be acknowledged and ignored.
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProviderKind {
+    Coinos,
+    Lnbits,
+    BtcPay,
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "coinos" => Ok(Self::Coinos),
+            "lnbits" => Ok(Self::Lnbits),
+            "btcpay" => Ok(Self::BtcPay),
+            "mock" => Ok(Self::Mock),
+            other => Err(format!("Unknown PAYMENT_PROVIDER: {}", other)),
+        }
+    }
+}
+
+/// Backend settings read from env vars/secrets
+#[derive(Debug, Clone)]
+pub struct PaymentConfig {
+    pub kind: ProviderKind,
+    pub coinos_api_token: Option<String>,
+    pub lnbits_url: Option<String>,
+    pub lnbits_api_key: Option<String>,
+    pub btcpay_url: Option<String>,
+    pub btcpay_store_id: Option<String>,
+    pub btcpay_api_key: Option<String>,
+    pub btcpay_webhook_secret: Option<String>,
+}
+
+impl PaymentConfig {
+    /// Build from a name → value lookup (env vars or secrets)
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let get = |name: &str| lookup(name).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let kind = if get("MOCK_PAYMENT").as_deref() == Some("true") {
+            ProviderKind::Mock
+        } else {
+            ProviderKind::parse(&get("PAYMENT_PROVIDER").unwrap_or_default())?
+        };
+        Ok(Self {
+            kind,
+            coinos_api_token: get("COINOS_API_TOKEN"),
+            lnbits_url: get("LNBITS_URL"),
+            lnbits_api_key: get("LNBITS_API_KEY"),
+            btcpay_url: get("BTCPAY_URL"),
+            btcpay_store_id: get("BTCPAY_STORE_ID"),
+            btcpay_api_key: get("BTCPAY_API_KEY"),
+            btcpay_webhook_secret: get("BTCPAY_WEBHOOK_SECRET"),
+        })
+    }
+
+    /// Instantiate the configured backend over the given HTTP client
+    pub fn build<C: HttpClient>(&self, client: C) -> Result<Provider<C>, String> {
+        fn required(value: &Option<String>, name: &str) -> Result<String, String> {
+            value.clone().ok_or_else(|| format!("{} not configured", name))
+        }
+        Ok(match self.kind {
+            ProviderKind::Coinos => Provider::Coinos(CoinosProvider::new(
+                client,
+                required(&self.coinos_api_token, "COINOS_API_TOKEN")?,
+            )),
+            ProviderKind::Lnbits => Provider::Lnbits(LnbitsProvider::new(
+                client,
+                required(&self.lnbits_url, "LNBITS_URL")?,
+                required(&self.lnbits_api_key, "LNBITS_API_KEY")?,
+            )),
+            ProviderKind::BtcPay => Provider::BtcPay(BtcPayProvider::new(
+                client,
+                required(&self.btcpay_url, "BTCPAY_URL")?,
+                required(&self.btcpay_store_id, "BTCPAY_STORE_ID")?,
+                required(&self.btcpay_api_key, "BTCPAY_API_KEY")?,
+                self.btcpay_webhook_secret.clone(),
+            )),
+            ProviderKind::Mock => Provider::Mock(MockProvider),
+        })
+    }
+}
+
+/// Read payment settings from worker vars, falling back to secrets
+#[cfg(target_arch = "wasm32")]
+pub fn config_from_env(env: &worker::Env) -> Result<PaymentConfig, String> {
+    PaymentConfig::from_lookup(|name| {
+        env.var(name)
+            .map(|v| v.to_string())
+            .or_else(|_| env.secret(name).map(|s| s.to_string()))
+            .ok()
+    })
+}
+
+/// The configured backend using the Workers fetch API
+#[cfg(target_arch = "wasm32")]
+pub fn from_env(env: &worker::Env) -> Result<Provider<crate::http::FetchClient>, String> {
+    config_from_env(env)?.build(crate::http::FetchClient)
+}
+
+/// Any configured backend (the trait is not object safe, so dispatch is by enum)
+pub enum Provider<C: HttpClient> {
+    Coinos(CoinosProvider<C>),
+    Lnbits(LnbitsProvider<C>),
+    BtcPay(BtcPayProvider<C>),
+    Mock(MockProvider),
+}
+
+impl<C: HttpClient> Provider<C> {
+    /// Mock invoices are provisioned immediately instead of waiting for a webhook
+    pub fn is_mock(&self) -> bool {
+        matches!(self, Provider::Mock(_))
+    }
+}
+
+impl<C: HttpClient> PaymentProvider for Provider<C> {
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Coinos(p) => p.name(),
+            Provider::Lnbits(p) => p.name(),
+            Provider::BtcPay(p) => p.name(),
+            Provider::Mock(p) => p.name(),
+        }
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String> {
+        match self {
+            Provider::Coinos(p) => p.create_invoice(req).await,
+            Provider::Lnbits(p) => p.create_invoice(req).await,
+            Provider::BtcPay(p) => p.create_invoice(req).await,
+            Provider::Mock(p) => p.create_invoice(req).await,
+        }
+    }
+
+    async fn check_status(&self, payment_id: &str) -> Result<InvoiceStatus, String> {
+        match self {
+            Provider::Coinos(p) => p.check_status(payment_id).await,
+            Provider::Lnbits(p) => p.check_status(payment_id).await,
+            Provider::BtcPay(p) => p.check_status(payment_id).await,
+            Provider::Mock(p) => p.check_status(payment_id).await,
+        }
+    }
+
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String> {
+        match self {
+            Provider::Coinos(p) => p.parse_webhook(req),
+            Provider::Lnbits(p) => p.parse_webhook(req),
+            Provider::BtcPay(p) => p.parse_webhook(req),
+            Provider::Mock(p) => p.parse_webhook(req),
+        }
+    }
+}
+
+/// Parse a coinos-format webhook body; only confirmed payments are notices.
+/// The payload is proof of payment only when it carries the per-order secret:
+/// the payment hash is public (it is in the bolt11), so a hash-only notice must
+/// be confirmed with the backend
+pub fn parse_coinos_webhook(body: &str) -> Result<Option<WebhookNotice>, String> {
+    let payload: CoinosWebhookPayload =
+        serde_json::from_str(body).map_err(|_| "Invalid webhook payload".to_string())?;
+    if payload.confirmed != Some(true) {
+        return Ok(None);
+    }
+    let paid = payload.secret.is_some();
+    Ok(Some(WebhookNotice {
+        secret: payload.secret,
+        payment_id: payload.hash,
+        paid,
+    }))
+}
+
+/// Development backend: fake invoices that count as paid immediately
+pub struct MockProvider;
+
+impl PaymentProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest<'_>) -> Result<CreatedInvoice, String> {
+        Ok(CreatedInvoice {
+            bolt11: format!("lnbc{}n1mock_invoice_for_testing", req.amount_sats),
+            payment_id: Some(crate::ids::IdSpec { prefix: "mock_hash", entropy_bytes: 12 }.generate()),
+        })
+    }
+
+    async fn check_status(&self, _payment_id: &str) -> Result<InvoiceStatus, String> {
+        Ok(InvoiceStatus::Paid)
+    }
+
+    fn parse_webhook(&self, req: &InboundRequest) -> Result<Option<WebhookNotice>, String> {
+        parse_coinos_webhook(&req.body)
+    }
+}
+
+/// Forward through references so tests can keep a handle on a shared client
+impl<T: HttpClient> HttpClient for &T {
+    async fn send(&self, req: crate::http::HttpRequest) -> Result<crate::http::HttpResponse, String> {
+        (**self).send(req).await
+    }
+}
+
+/// In-process stand-in for the coinos, LNbits and BTCPay HTTP APIs, for native tests
+#[cfg(test)]
+pub(crate) mod stand_in {
+    use crate::http::{HttpClient, HttpRequest, HttpResponse};
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    #[derive(Debug, Clone)]
+    pub struct StandInInvoice {
+        pub id: String,
+        pub amount: u64,
+        pub paid: bool,
+        pub expired: bool,
+        pub request_body: serde_json::Value,
+    }
+
+    #[derive(Default)]
+    pub struct StandInServer {
+        pub requests: RefCell<Vec<HttpRequest>>,
+        pub invoices: RefCell<Vec<StandInInvoice>>,
+    }
+
+    impl StandInServer {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn settle(&self, id: &str) {
+            if let Some(inv) = self.invoices.borrow_mut().iter_mut().find(|i| i.id == id) {
+                inv.paid = true;
+            }
+        }
+
+        pub fn expire(&self, id: &str) {
+            if let Some(inv) = self.invoices.borrow_mut().iter_mut().find(|i| i.id == id) {
+                inv.expired = true;
+            }
+        }
+
+        pub fn last_invoice(&self) -> StandInInvoice {
+            self.invoices.borrow().last().cloned().expect("no invoice created")
+        }
+
+        fn create(&self, amount: u64, request_body: serde_json::Value) -> StandInInvoice {
+            let mut invoices = self.invoices.borrow_mut();
+            let inv = StandInInvoice {
+                id: format!("{:064x}", invoices.len() + 1),
+                amount,
+                paid: false,
+                expired: false,
+                request_body,
+            };
+            invoices.push(inv.clone());
+            inv
+        }
+
+        fn find(&self, id: &str) -> Option<StandInInvoice> {
+            self.invoices.borrow().iter().find(|i| i.id == id).cloned()
+        }
+
+        fn route(&self, req: &HttpRequest) -> (u16, serde_json::Value) {
+            let path = req.url.splitn(4, '/').nth(3).map(|p| format!("/{}", p)).unwrap_or_default();
+            let body: serde_json::Value = req
+                .body
+                .as_deref()
+                .and_then(|b| serde_json::from_str(b).ok())
+                .unwrap_or(serde_json::Value::Null);
+            let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+            match (req.method, segments.as_slice()) {
+                // coinos
+                ("POST", ["api", "invoice"]) => {
+                    if req.header_value("Authorization").is_none() {
+                        return (401, json!({"error": "unauthorized"}));
+                    }
+                    let amount = body["invoice"]["amount"].as_u64().unwrap_or(0);
+                    let inv = self.create(amount, body);
+                    (200, json!({"id": inv.id, "amount": amount, "text": format!("lnbc{}n1standin", amount), "hash": inv.id}))
+                }
+                ("GET", ["api", "invoice", hash]) => match self.find(hash) {
+                    Some(inv) => (200, json!({"amount": inv.amount, "received": if inv.paid { inv.amount } else { 0 }})),
+                    None => (404, json!({})),
+                },
+                // LNbits
+                ("POST", ["api", "v1", "payments"]) => {
+                    if req.header_value("X-Api-Key").is_none() {
+                        return (401, json!({"detail": "missing key"}));
+                    }
+                    let amount = body["amount"].as_u64().unwrap_or(0);
+                    let inv = self.create(amount, body);
+                    (201, json!({"payment_hash": inv.id, "payment_request": format!("lnbc{}n1lnbits", amount)}))
+                }
+                ("GET", ["api", "v1", "payments", hash]) => match self.find(hash) {
+                    Some(inv) => (200, json!({"paid": inv.paid, "status": if inv.paid { "success" } else if inv.expired { "failed" } else { "pending" }})),
+                    None => (404, json!({})),
+                },
+                // BTCPay Greenfield
+                ("POST", ["api", "v1", "stores", _, "invoices"]) => {
+                    if !req.header_value("Authorization").is_some_and(|a| a.starts_with("token ")) {
+                        return (401, json!({}));
+                    }
+                    let amount = body["amount"].as_str().and_then(|a| a.parse().ok()).unwrap_or(0);
+                    let inv = self.create(amount, body);
+                    (200, json!({"id": inv.id, "status": "New"}))
+                }
+                ("GET", ["api", "v1", "stores", _, "invoices", id, "payment-methods"]) => match self.find(id) {
+                    Some(inv) => (200, json!([
+                        {"paymentMethodId": "BTC-CHAIN", "destination": "bc1qstandin"},
+                        {"paymentMethodId": "BTC-LN", "destination": format!("lnbc{}n1btcpay", inv.amount)}
+                    ])),
+                    None => (404, json!({})),
+                },
+                ("GET", ["api", "v1", "stores", _, "invoices", id]) => match self.find(id) {
+                    Some(inv) => {
+                        let status = if inv.paid { "Settled" } else if inv.expired { "Expired" } else { "New" };
+                        (200, json!({"id": inv.id, "status": status}))
+                    }
+                    None => (404, json!({})),
+                },
+                _ => (404, json!({"error": "not found"})),
+            }
+        }
+    }
+
+    impl HttpClient for StandInServer {
+        async fn send(&self, req: HttpRequest) -> Result<HttpResponse, String> {
+            let (status, body) = self.route(&req);
+            self.requests.borrow_mut().push(req);
+            Ok(HttpResponse { status, body: body.to_string() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stand_in::StandInServer;
+    use super::*;
+    use crate::store::block_on;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Result<PaymentConfig, String> {
+        let map: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        PaymentConfig::from_lookup(|name| map.get(name).cloned())
+    }
+
+    fn invoice_request<'a>(secret: &'a str) -> InvoiceRequest<'a> {
+        InvoiceRequest {
+            amount_sats: 2100,
+            memo: "noscha.io alice 30d",
+            webhook_url: "https://noscha.io/api/webhook/test",
+            secret,
+            expiry_secs: 900,
+            description_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_provider_selection() {
+        assert_eq!(config(&[]).unwrap().kind, ProviderKind::Coinos);
+        assert_eq!(config(&[("PAYMENT_PROVIDER", "LNbits")]).unwrap().kind, ProviderKind::Lnbits);
+        assert_eq!(config(&[("PAYMENT_PROVIDER", "btcpay")]).unwrap().kind, ProviderKind::BtcPay);
+        assert_eq!(
+            config(&[("PAYMENT_PROVIDER", "btcpay"), ("MOCK_PAYMENT", "true")]).unwrap().kind,
+            ProviderKind::Mock
+        );
+        assert!(config(&[("PAYMENT_PROVIDER", "paypal")]).is_err());
+    }
+
+    #[test]
+    fn test_build_requires_credentials() {
+        let server = StandInServer::new();
+        let err = config(&[("PAYMENT_PROVIDER", "lnbits"), ("LNBITS_URL", "https://lnbits.test")])
+            .unwrap()
+            .build(&server)
+            .err()
+            .unwrap();
+        assert_eq!(err, "LNBITS_API_KEY not configured");
+        assert!(config(&[]).unwrap().build(&server).is_err());
+
+        let provider = config(&[("COINOS_API_TOKEN", "tok")]).unwrap().build(&server).unwrap();
+        assert_eq!(provider.name(), "coinos");
+        assert!(!provider.is_mock());
+    }
+
+    #[test]
+    fn test_all_backends_against_stand_in() {
+        let server = StandInServer::new();
+        let configs = [
+            config(&[("COINOS_API_TOKEN", "tok")]).unwrap(),
+            config(&[
+                ("PAYMENT_PROVIDER", "lnbits"),
+                ("LNBITS_URL", "https://lnbits.test/"),
+                ("LNBITS_API_KEY", "key"),
+            ])
+            .unwrap(),
+            config(&[
+                ("PAYMENT_PROVIDER", "btcpay"),
+                ("BTCPAY_URL", "https://btcpay.test"),
+                ("BTCPAY_STORE_ID", "store1"),
+                ("BTCPAY_API_KEY", "key"),
+            ])
+            .unwrap(),
+        ];
+        block_on(async {
+            for cfg in configs {
+                let provider = cfg.build(&server).unwrap();
+                let invoice = provider.create_invoice(&invoice_request("sec_1")).await.unwrap();
+                assert!(invoice.bolt11.starts_with("lnbc2100n1"), "{}: {}", provider.name(), invoice.bolt11);
+                let id = invoice.payment_id.unwrap();
+                assert_eq!(provider.check_status(&id).await.unwrap(), InvoiceStatus::Pending);
+                server.settle(&id);
+                assert_eq!(provider.check_status(&id).await.unwrap(), InvoiceStatus::Paid);
+            }
+        });
+    }
+
+    #[test]
+    fn test_mock_provider() {
+        block_on(async {
+            let invoice = MockProvider.create_invoice(&invoice_request("s")).await.unwrap();
+            assert_eq!(invoice.bolt11, "lnbc2100n1mock_invoice_for_testing");
+            assert!(invoice.payment_id.unwrap().starts_with("mock_hash_"));
+            assert_eq!(MockProvider.check_status("x").await.unwrap(), InvoiceStatus::Paid);
+        });
+    }
+
+    #[test]
+    fn test_parse_coinos_webhook() {
+        let notice = parse_coinos_webhook(r#"{"confirmed":true,"secret":"sec_1","hash":"h"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(notice.secret.as_deref(), Some("sec_1"));
+        assert_eq!(notice.payment_id.as_deref(), Some("h"));
+        assert!(notice.paid);
+        let hash_only = parse_coinos_webhook(r#"{"confirmed":true,"hash":"h"}"#).unwrap().unwrap();
+        assert!(!hash_only.paid);
+        assert_eq!(parse_coinos_webhook(r#"{"confirmed":false,"secret":"s"}"#).unwrap(), None);
+        assert!(parse_coinos_webhook("nope").is_err());
+    }
+}
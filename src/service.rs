@@ -0,0 +1,3073 @@
+//! Order and rental business logic, independent of the Workers runtime.
+//! Handlers in lib.rs/admin.rs parse requests, call into here with a `Store`,
+//! and perform network side effects (invoices, webhooks) around the result.
+
+use crate::dns::{DnsProvider, DnsRecordType};
+use crate::http::InboundRequest;
+use crate::payment::{InvoiceStatus, PaymentProvider};
+use crate::store::{Store, StoreResult};
+use crate::types::*;
+use crate::url_policy::{self, UrlPolicy};
+use crate::validation::validate_username;
+
+/// Lightning invoice expiry for new and renewal orders
+pub const ORDER_TTL_MS: u64 = 15 * 60 * 1000;
+
+/// Default grace period after a rental expires during which only its owner can renew it
+pub const DEFAULT_GRACE_MINUTES: u64 = 3 * 24 * 60;
+
+/// Parse the GRACE_PERIOD_MINUTES setting; missing or invalid values use the default
+pub fn grace_minutes_from(value: Option<&str>) -> u64 {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_GRACE_MINUTES)
+}
+
+/// Most webhook endpoints a rental can register besides its webhook_url
+pub const MAX_WEBHOOK_ENDPOINTS: usize = 10;
+
+/// Default expiring_soon reminder thresholds before expiry
+pub const DEFAULT_REMINDER_THRESHOLDS: &str = "7d,1d,1h";
+
+/// Parse a period key like "5m", "1h", "1d", "7d" into duration in minutes
+pub fn period_to_minutes(period: &str) -> u64 {
+    let s = period.trim();
+    if let Some(num) = s.strip_suffix('m') {
+        num.parse::<u64>().unwrap_or(0)
+    } else if let Some(num) = s.strip_suffix('h') {
+        num.parse::<u64>().unwrap_or(0) * 60
+    } else if let Some(num) = s.strip_suffix('d') {
+        num.parse::<u64>().unwrap_or(0) * 1440
+    } else {
+        s.parse::<u64>().unwrap_or(0)
+    }
+}
+
+/// Parse the REMINDER_THRESHOLDS setting ("7d,1d,1h") into minutes, largest first.
+/// Missing uses the default; an empty value disables reminders
+pub fn reminder_thresholds_from(value: Option<&str>) -> Vec<u64> {
+    let mut thresholds: Vec<u64> = value
+        .unwrap_or(DEFAULT_REMINDER_THRESHOLDS)
+        .split(',')
+        .map(period_to_minutes)
+        .filter(|m| *m > 0)
+        .collect();
+    thresholds.sort_unstable_by(|a, b| b.cmp(a));
+    thresholds.dedup();
+    thresholds
+}
+
+/// How long past its expiry a pending order keeps being polled, to catch
+/// payments that settled just before the invoice expired
+pub const PAYMENT_POLL_GRACE_MS: u64 = 60 * 60 * 1000;
+
+/// Error returned by business logic, mapped to an HTTP error response by handlers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn into_response(self) -> worker::Result<worker::Response> {
+        worker::Response::error(self.message, self.status)
+    }
+}
+
+/// Storage and other internal failures surface as 500
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        Self::new(500, message)
+    }
+}
+
+/// Invoice details attached to an order once created by the payment backend
+#[derive(Debug, Clone)]
+pub struct InvoiceDetails {
+    pub bolt11: String,
+    pub hash: Option<String>,
+    pub webhook_secret: String,
+}
+
+/// A paid order that has been provisioned into a rental
+#[derive(Debug, Clone)]
+pub struct Provisioned {
+    pub order: Order,
+    pub rental: Rental,
+    pub is_renewal: bool,
+    /// A lapsed rental's subdomain record was recreated by the renewal
+    pub dns_restored: bool,
+}
+
+/// Result of applying a payment notification
+#[derive(Debug, Clone)]
+pub enum PaymentResult {
+    Provisioned(Box<Provisioned>),
+    RentalNotFound,
+    NoMatchingOrder,
+    /// Webhook event that is not a settlement (acknowledged, nothing to do)
+    Ignored,
+    /// The backend does not (yet) report the invoice as paid
+    NotPaid,
+}
+
+/// Result of the pending-order payment poll run from the cron handler
+#[derive(Debug, Clone, Default)]
+pub struct PollReport {
+    pub provisioned: Vec<Provisioned>,
+    /// Orders removed from the pending index (settled elsewhere, gone, or past the grace period)
+    pub dropped: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Result of the expiry sweep run from the cron handler
+#[derive(Debug, Clone, Default)]
+pub struct ExpiryReport {
+    /// Rentals that lapsed and entered the grace period
+    pub grace: Vec<Rental>,
+    /// Rentals released for good (grace over, or no grace configured)
+    pub expired: Vec<String>,
+    /// Rentals whose subdomain record was deleted, for dns_changed webhooks
+    pub dns_removed: Vec<Rental>,
+    pub dns_errors: Vec<String>,
+}
+
+/// Extract ServiceType list from OrderServicesRequest
+pub fn services_from_request(services: &Option<OrderServicesRequest>) -> Vec<ServiceType> {
+    let mut result = Vec::new();
+    if let Some(ref svc) = services {
+        if svc.subdomain.is_some() {
+            result.push(ServiceType::Subdomain);
+        }
+        if svc.email.is_some() {
+            result.push(ServiceType::EmailForwarding);
+        }
+        if svc.nip05.is_some() {
+            result.push(ServiceType::Nip05);
+        }
+        if svc.lightning_address.is_some() {
+            result.push(ServiceType::LightningAddress);
+        }
+    }
+    result
+}
+
+/// Extract ServiceType list from an existing Rental's services
+pub fn services_from_rental(services: &RentalServices) -> Vec<ServiceType> {
+    let mut result = Vec::new();
+    if services.subdomain.as_ref().is_some_and(|s| s.enabled) {
+        result.push(ServiceType::Subdomain);
+    }
+    if services.email.as_ref().is_some_and(|s| s.enabled) {
+        result.push(ServiceType::EmailForwarding);
+    }
+    if services.nip05.as_ref().is_some_and(|s| s.enabled) {
+        result.push(ServiceType::Nip05);
+    }
+    if services.lightning_address.as_ref().is_some_and(|s| s.enabled) {
+        result.push(ServiceType::LightningAddress);
+    }
+    result
+}
+
+/// Extra NIP-05 names requested in an order
+pub fn extra_names_from_request(services: &Option<OrderServicesRequest>) -> usize {
+    services.as_ref().and_then(|s| s.nip05.as_ref()).map_or(0, |n| n.names.len())
+}
+
+/// Price of a plan for these services plus `extra_names` extra NIP-05 names
+pub fn order_total(plan: &Plan, service_types: &[ServiceType], extra_names: usize, pricing: &PricingConfig) -> u64 {
+    Plan::calculate_total_dynamic(plan, service_types, pricing) + extra_names as u64 * plan.nip05_name_price_dynamic(pricing)
+}
+
+/// The rental holding `name` as an extra NIP-05 name, if its claim is still live
+async fn nip05_name_holder<S: Store>(
+    store: &S,
+    name: &str,
+    grace_minutes: u64,
+    now_ms: u64,
+) -> StoreResult<Option<String>> {
+    let Some(owner) = store.get_nip05_name_owner(name).await? else {
+        return Ok(None);
+    };
+    let holds = store.get_rental(&owner).await?.is_some_and(|r| {
+        !r.is_username_free(grace_minutes, now_ms)
+            && r.services.nip05.as_ref().is_some_and(|n| n.names.contains_key(name))
+    });
+    Ok(holds.then_some(owner))
+}
+
+/// Extra names `username` wants must not be taken as usernames or held by another rental
+async fn check_nip05_names_free<S: Store>(
+    store: &S,
+    username: &str,
+    names: impl Iterator<Item = &String>,
+    grace_minutes: u64,
+    now_ms: u64,
+) -> Result<(), ApiError> {
+    for name in names {
+        let taken_as_username = store.is_banned(name).await?
+            || store.get_rental(name).await?.is_some_and(|r| !r.is_username_free(grace_minutes, now_ms));
+        let held = nip05_name_holder(store, name, grace_minutes, now_ms).await?;
+        if taken_as_username || held.is_some_and(|h| h != username) {
+            return Err(ApiError::new(409, format!("NIP-05 name {} is already taken", name)));
+        }
+    }
+    Ok(())
+}
+
+fn validate_webhook_url(url: &str, policy: UrlPolicy) -> Result<(), ApiError> {
+    url_policy::check_url(url, policy).map_err(|e| ApiError::new(400, format!("Invalid webhook_url: {}", e)))
+}
+
+/// Reject subdomain targets that point at private or special addresses
+fn validate_subdomain_target(services: Option<&OrderServicesRequest>) -> Result<(), ApiError> {
+    if let Some(sub) = services.and_then(|s| s.subdomain.as_ref()) {
+        let record_type = DnsRecordType::parse(&sub.record_type).map_err(|e| ApiError::new(400, e))?;
+        url_policy::check_dns_target(&record_type, &sub.target)
+            .map_err(|e| ApiError::new(400, format!("Invalid subdomain target: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Check an endpoint list and drop repeated events within each endpoint
+fn normalize_webhook_endpoints(
+    endpoints: &[WebhookEndpoint],
+    policy: UrlPolicy,
+) -> Result<Vec<WebhookEndpoint>, ApiError> {
+    if endpoints.len() > MAX_WEBHOOK_ENDPOINTS {
+        return Err(ApiError::new(
+            400,
+            format!("At most {} webhook endpoints are allowed", MAX_WEBHOOK_ENDPOINTS),
+        ));
+    }
+    let mut result: Vec<WebhookEndpoint> = Vec::with_capacity(endpoints.len());
+    for endpoint in endpoints {
+        let url = endpoint.url.trim();
+        url_policy::check_url(url, policy)
+            .map_err(|e| ApiError::new(400, format!("Invalid webhooks[].url {}: {}", url, e)))?;
+        if result.iter().any(|e| e.url == url) {
+            return Err(ApiError::new(400, format!("Duplicate webhook endpoint: {}", url)));
+        }
+        let mut events = Vec::new();
+        for event in &endpoint.events {
+            if !events.contains(event) {
+                events.push(*event);
+            }
+        }
+        if events.is_empty() {
+            return Err(ApiError::new(400, "webhooks[].events must name at least one event"));
+        }
+        result.push(WebhookEndpoint { url: url.to_string(), events });
+    }
+    Ok(result)
+}
+
+/// GET /api/check/{username}
+pub async fn check_username<S: Store>(
+    store: &S,
+    username: &str,
+    grace_minutes: u64,
+    now_ms: u64,
+) -> StoreResult<CheckUsernameResponse> {
+    if let Err(err) = validate_username(username) {
+        return Ok(CheckUsernameResponse {
+            available: false,
+            username: username.to_string(),
+            error: Some(err),
+        });
+    }
+
+    if store.is_banned(username).await? {
+        return Ok(CheckUsernameResponse {
+            available: false,
+            username: username.to_string(),
+            error: Some("This username is blocked".to_string()),
+        });
+    }
+
+    let available = match store.get_rental(username).await {
+        Ok(None) => true,
+        Ok(Some(rental)) => rental.is_username_free(grace_minutes, now_ms),
+        Err(_) => false,
+    } && nip05_name_holder(store, username, grace_minutes, now_ms).await?.is_none();
+
+    Ok(CheckUsernameResponse {
+        available,
+        username: username.to_string(),
+        error: None,
+    })
+}
+
+/// POST /api/order — validate and store a new order awaiting webhook verification
+#[allow(clippy::too_many_arguments)]
+pub async fn create_order<S: Store>(
+    store: &S,
+    mut body: OrderRequest,
+    order_id: String,
+    challenge: String,
+    signing_secret: String,
+    grace_minutes: u64,
+    policy: UrlPolicy,
+    now_ms: u64,
+) -> Result<Order, ApiError> {
+    validate_username(&body.username).map_err(|e| ApiError::new(400, e))?;
+
+    if body.webhook_url.is_empty() {
+        return Err(ApiError::new(400, "webhook_url is required"));
+    }
+    validate_webhook_url(&body.webhook_url, policy)?;
+    validate_subdomain_target(body.services.as_ref())?;
+    if body.nostr_dm && body.services.as_ref().and_then(|s| s.nip05.as_ref()).is_none() {
+        return Err(ApiError::new(400, "nostr_dm requires a NIP-05 pubkey"));
+    }
+    let owner_pubkey = body.owner_pubkey.as_deref().map(normalize_owner_pubkey).transpose()?;
+    if let Some(services) = body.services.as_mut() {
+        normalize_services_request(&body.username, services)?;
+    }
+
+    if store.is_banned(&body.username).await? {
+        return Err(ApiError::new(403, "This username is blocked"));
+    }
+    if let Some(existing) = store.get_rental(&body.username).await? {
+        if !existing.is_username_free(grace_minutes, now_ms) {
+            return Err(ApiError::new(409, "Username is already taken"));
+        }
+    }
+    if nip05_name_holder(store, &body.username, grace_minutes, now_ms).await?.is_some() {
+        return Err(ApiError::new(409, "Username is already taken"));
+    }
+    if let Some(nip05) = body.services.as_ref().and_then(|s| s.nip05.as_ref()) {
+        check_nip05_names_free(store, &body.username, nip05.names.keys(), grace_minutes, now_ms).await?;
+    }
+
+    let service_types = services_from_request(&body.services);
+    let pricing = store.load_pricing().await;
+    let amount_sats = order_total(&body.plan, &service_types, extra_names_from_request(&body.services), &pricing);
+
+    let order = Order {
+        order_id,
+        username: body.username,
+        plan: body.plan,
+        amount_sats,
+        bolt11: String::new(),
+        status: OrderStatus::WebhookPending,
+        created_at: iso_from_ms(now_ms),
+        expires_at: iso_from_ms(now_ms + ORDER_TTL_MS),
+        coinos_invoice_hash: None,
+        webhook_secret: None,
+        services_requested: body.services,
+        management_token: None,
+        renewal_for: None,
+        upgrade_for: None,
+        webhook_url: Some(body.webhook_url),
+        webhook_challenge: Some(challenge),
+        history: Vec::new(),
+        signing_secret: Some(signing_secret),
+        nostr_dm: body.nostr_dm,
+        owner_pubkey,
+    };
+    store.put_order(&order).await?;
+    store.put_pending_index(&order.order_id).await?;
+    Ok(order)
+}
+
+/// Load an order for GET /api/order/{order_id}/confirm/{challenge}, checking challenge and expiry
+pub async fn load_order_for_confirm<S: Store>(
+    store: &S,
+    order_id: &str,
+    challenge: &str,
+    now_ms: u64,
+) -> Result<Order, ApiError> {
+    let order = store
+        .get_order(order_id)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Order not found"))?;
+
+    if order.webhook_challenge.as_deref() != Some(challenge) {
+        return Err(ApiError::new(403, "Invalid challenge token"));
+    }
+    if is_expired_ms(&order.expires_at, now_ms) {
+        return Err(ApiError::new(410, "Order expired"));
+    }
+    Ok(order)
+}
+
+/// Attach a freshly created invoice to a verified order and mark it pending payment
+pub async fn attach_invoice<S: Store>(
+    store: &S,
+    order: &mut Order,
+    invoice: InvoiceDetails,
+    now_ms: u64,
+) -> StoreResult<()> {
+    order.transition(OrderStatus::Pending, now_ms, None)?;
+    order.bolt11 = invoice.bolt11;
+    order.coinos_invoice_hash = invoice.hash;
+    order.webhook_secret = Some(invoice.webhook_secret);
+    index_invoice(store, order).await?;
+    store.put_pending_index(&order.order_id).await?;
+    store.put_order(order).await
+}
+
+/// Index an order under its invoice webhook secret and payment hash so the
+/// payment webhook can find it without scanning orders/
+async fn index_invoice<S: Store>(store: &S, order: &Order) -> StoreResult<()> {
+    for key in [order.webhook_secret.as_deref(), order.coinos_invoice_hash.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|k| !k.is_empty())
+    {
+        store.put_invoice_index(key, &order.order_id).await?;
+    }
+    Ok(())
+}
+
+/// Build rental services from an order request, creating the DNS record if a subdomain was requested
+async fn build_rental_services<D: DnsProvider>(
+    dns: &D,
+    username: &str,
+    requested: Option<&OrderServicesRequest>,
+    expires_at: &str,
+) -> Result<RentalServices, ApiError> {
+    let mut subdomain = None;
+    if let Some(sub_req) = requested.and_then(|s| s.subdomain.as_ref()) {
+        let record_type = DnsRecordType::parse(&sub_req.record_type)?;
+        let record_id = dns
+            .create_record(username, &record_type, &sub_req.target, sub_req.proxied, expires_at)
+            .await?;
+        subdomain = Some(SubdomainService {
+            enabled: true,
+            record_type: sub_req.record_type.clone(),
+            target: sub_req.target.clone(),
+            proxied: sub_req.proxied,
+            cf_record_id: record_id,
+        });
+    }
+
+    let email = requested.and_then(|s| s.email.as_ref()).map(|_| EmailService {
+        enabled: true,
+        cf_rule_id: None,
+    });
+    let nip05 = requested.and_then(|s| s.nip05.as_ref()).map(|n| Nip05Service {
+        enabled: true,
+        pubkey_hex: n.pubkey.clone(),
+        relays: n.relays.clone(),
+        nip46: vec![],
+        names: n.names.clone(),
+        name_slots: n.names.len(),
+    });
+    let lightning_address = requested.and_then(|s| s.lightning_address.as_ref()).map(|l| LightningAddressService {
+        enabled: true,
+        forward_to: l.forward_to.clone(),
+    });
+
+    Ok(RentalServices { email, subdomain, nip05, lightning_address })
+}
+
+/// Add an upgrade order's services that the rental does not have yet, keeping its
+/// expiry; returns the services added. A rental that lapsed after the order was
+/// placed gets its subdomain record when renewed
+async fn add_rental_services<D: DnsProvider>(
+    dns: &D,
+    rental: &mut Rental,
+    requested: Option<&OrderServicesRequest>,
+) -> Result<RentalServices, ApiError> {
+    let current = services_from_rental(&rental.services);
+    let missing = |service: ServiceType| !current.contains(&service);
+    let mut wanted = OrderServicesRequest {
+        email: requested.and_then(|s| s.email.clone()).filter(|_| missing(ServiceType::EmailForwarding)),
+        subdomain: requested.and_then(|s| s.subdomain.clone()).filter(|_| missing(ServiceType::Subdomain)),
+        nip05: requested.and_then(|s| s.nip05.clone()).filter(|_| missing(ServiceType::Nip05)),
+        lightning_address: requested
+            .and_then(|s| s.lightning_address.clone())
+            .filter(|_| missing(ServiceType::LightningAddress)),
+    };
+    let deferred_subdomain = if rental.status.is_active() { None } else { wanted.subdomain.take() };
+    let mut added = build_rental_services(dns, &rental.username, Some(&wanted), &rental.expires_at).await?;
+    if let Some(sub) = deferred_subdomain {
+        added.subdomain = Some(SubdomainService {
+            enabled: true,
+            record_type: sub.record_type,
+            target: sub.target,
+            proxied: sub.proxied,
+            cf_record_id: None,
+        });
+    }
+
+    let services = &mut rental.services;
+    if added.email.is_some() {
+        services.email = added.email.clone();
+    }
+    if added.subdomain.is_some() {
+        services.subdomain = added.subdomain.clone();
+    }
+    if added.nip05.is_some() {
+        services.nip05 = added.nip05.clone();
+    }
+    if added.lightning_address.is_some() {
+        services.lightning_address = added.lightning_address.clone();
+    }
+    Ok(added)
+}
+
+/// New expiry when extending: time is added on top of the current expiry, or now if already expired
+pub fn extended_expiry(current_expires_at: &str, now_ms: u64, minutes: u64) -> String {
+    let current = ms_from_iso(current_expires_at).unwrap_or(0);
+    let base = current.max(now_ms);
+    iso_from_ms(base + minutes * 60 * 1000)
+}
+
+/// Recreate the subdomain record removed when a rental lapsed. Returns whether
+/// a record was created
+async fn restore_dns_record<D: DnsProvider>(dns: &D, rental: &mut Rental) -> Result<bool, ApiError> {
+    let username = rental.username.clone();
+    let expires_at = rental.expires_at.clone();
+    if let Some(sub) = rental.services.subdomain.as_mut().filter(|s| s.enabled && s.cf_record_id.is_none()) {
+        let record_type = DnsRecordType::parse(&sub.record_type)?;
+        sub.cf_record_id = dns
+            .create_record(&username, &record_type, &sub.target, sub.proxied, &expires_at)
+            .await?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Record that a paid order could not be provisioned
+async fn fail_order<S: Store>(store: &S, order: &mut Order, reason: &str, now_ms: u64) -> StoreResult<()> {
+    order.transition(OrderStatus::Failed, now_ms, Some(reason))?;
+    store.put_order(order).await?;
+    store.delete_pending_index(&order.order_id).await
+}
+
+/// Provision a paid order: create a new rental, extend the existing one for renewals,
+/// or add services to it for upgrades. A pending order is first moved to paid. Returns
+/// None when a renewal's or upgrade's rental no longer exists; the order is then
+/// marked failed, as it is when DNS setup fails.
+pub async fn provision_paid_order<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    mut order: Order,
+    management_token: String,
+    now_ms: u64,
+) -> Result<Option<Provisioned>, ApiError> {
+    if order.status != OrderStatus::Paid {
+        order.transition(OrderStatus::Paid, now_ms, None)?;
+    }
+
+    if let Some(renewal_username) = order.renewal_for.clone() {
+        // A rental created after the order replaced the one being renewed
+        let rental = store
+            .get_rental(&renewal_username)
+            .await
+            .ok()
+            .flatten()
+            .filter(|r| ms_from_iso(&r.created_at) <= ms_from_iso(&order.created_at));
+        let mut rental = match rental {
+            Some(r) => r,
+            None => {
+                fail_order(store, &mut order, "rental not found for renewal", now_ms).await?;
+                return Ok(None);
+            }
+        };
+        let was_lapsed = !rental.status.is_active();
+        rental.expires_at = extended_expiry(&rental.expires_at, now_ms, order.plan.duration_minutes());
+        rental.status = RentalStatus::Active;
+        rental.grace_until = None;
+        rental.plan = order.plan.clone();
+        let dns_restored = was_lapsed && restore_dns_record(dns, &mut rental).await?;
+        store.put_rental(&rental).await?;
+
+        order.transition(OrderStatus::Provisioned, now_ms, None)?;
+        order.management_token = rental.management_token.clone();
+        store.put_order(&order).await?;
+        store.delete_pending_index(&order.order_id).await?;
+        return Ok(Some(Provisioned { order, rental, is_renewal: true, dns_restored }));
+    }
+
+    if let Some(upgrade_username) = order.upgrade_for.clone() {
+        let rental = store
+            .get_rental(&upgrade_username)
+            .await
+            .ok()
+            .flatten()
+            .filter(|r| ms_from_iso(&r.created_at) <= ms_from_iso(&order.created_at));
+        let mut rental = match rental {
+            Some(r) => r,
+            None => {
+                fail_order(store, &mut order, "rental not found for upgrade", now_ms).await?;
+                return Ok(None);
+            }
+        };
+        let added = match add_rental_services(dns, &mut rental, order.services_requested.as_ref()).await {
+            Ok(added) => added,
+            Err(e) => {
+                fail_order(store, &mut order, &e.message, now_ms).await?;
+                return Err(e);
+            }
+        };
+        for name in added.nip05.iter().flat_map(|n| n.names.keys()) {
+            store.put_nip05_name_index(name, &rental.username).await?;
+        }
+        store.put_rental(&rental).await?;
+
+        order.transition(OrderStatus::Provisioned, now_ms, None)?;
+        order.management_token = rental.management_token.clone();
+        store.put_order(&order).await?;
+        store.delete_pending_index(&order.order_id).await?;
+        return Ok(Some(Provisioned { order, rental, is_renewal: false, dns_restored: false }));
+    }
+
+    let expires_at = iso_from_ms(now_ms + order.plan.duration_minutes() * 60 * 1000);
+    let services =
+        match build_rental_services(dns, &order.username, order.services_requested.as_ref(), &expires_at).await {
+            Ok(s) => s,
+            Err(e) => {
+                fail_order(store, &mut order, &e.message, now_ms).await?;
+                return Err(e);
+            }
+        };
+
+    let rental = Rental {
+        username: order.username.clone(),
+        status: RentalStatus::Active,
+        created_at: iso_from_ms(now_ms),
+        expires_at,
+        plan: order.plan.clone(),
+        services,
+        management_token: Some(management_token.clone()),
+        webhook_url: order.webhook_url.clone(),
+        grace_until: None,
+        reminders_sent: Vec::new(),
+        signing_secret: order.signing_secret.clone(),
+        webhooks: Vec::new(),
+        nostr_dm: order.nostr_dm,
+        owner_pubkey: order.owner_pubkey.clone(),
+    };
+    // A released rental being replaced: its owner's token must no longer resolve
+    if let Some(previous) = store.get_rental(&rental.username).await? {
+        if let Some(old_token) = previous.management_token.as_deref() {
+            store.delete_token_index(old_token).await?;
+        }
+    }
+    store.put_token_index(&management_token, &rental.username).await?;
+    for name in rental.services.nip05.iter().flat_map(|n| n.names.keys()) {
+        store.put_nip05_name_index(name, &rental.username).await?;
+    }
+    store.put_rental(&rental).await?;
+
+    order.transition(OrderStatus::Provisioned, now_ms, None)?;
+    order.management_token = rental.management_token.clone();
+    store.put_order(&order).await?;
+    store.delete_pending_index(&order.order_id).await?;
+    Ok(Some(Provisioned { order, rental, is_renewal: false, dns_restored: false }))
+}
+
+/// Find the pending order whose invoice was created with this webhook secret
+pub async fn find_pending_order_by_secret<S: Store>(
+    store: &S,
+    secret: &str,
+) -> StoreResult<Option<Order>> {
+    let order_id = match store.get_invoice_order_id(secret).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    Ok(store
+        .get_order(&order_id)
+        .await?
+        .filter(|o| o.webhook_secret.as_deref() == Some(secret) && o.status == OrderStatus::Pending))
+}
+
+/// Find the order whose invoice has this payment hash
+pub async fn find_order_by_invoice_hash<S: Store>(store: &S, hash: &str) -> StoreResult<Option<Order>> {
+    let order_id = match store.get_invoice_order_id(hash).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    Ok(store
+        .get_order(&order_id)
+        .await?
+        .filter(|o| o.coinos_invoice_hash.as_deref() == Some(hash)))
+}
+
+/// Mark the pending order with this webhook secret paid and provision it
+pub async fn apply_payment<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    secret: &str,
+    hash: &str,
+    management_token: String,
+    now_ms: u64,
+) -> Result<PaymentResult, ApiError> {
+    let order = match find_pending_order_by_secret(store, secret).await? {
+        Some(o) => o,
+        None => return Ok(PaymentResult::NoMatchingOrder),
+    };
+    settle_order(store, dns, order, Some(hash), management_token, now_ms).await
+}
+
+/// POST /api/webhook/:provider — authenticate a backend notification, confirm
+/// payment with the backend when the payload alone is not proof, and provision
+pub async fn apply_payment_webhook<S: Store, D: DnsProvider, P: PaymentProvider>(
+    store: &S,
+    dns: &D,
+    provider: &P,
+    req: &InboundRequest,
+    management_token: String,
+    now_ms: u64,
+) -> Result<PaymentResult, ApiError> {
+    let notice = match provider.parse_webhook(req).map_err(|e| ApiError::new(400, e))? {
+        Some(n) => n,
+        None => return Ok(PaymentResult::Ignored),
+    };
+
+    let order = match notice.secret.as_deref() {
+        Some(secret) => find_pending_order_by_secret(store, secret).await?,
+        None => match notice.payment_id.as_deref() {
+            Some(id) => find_order_by_invoice_hash(store, id)
+                .await?
+                .filter(|o| o.status == OrderStatus::Pending),
+            None => None,
+        },
+    };
+    let Some(order) = order else {
+        return Ok(PaymentResult::NoMatchingOrder);
+    };
+
+    if !notice.paid {
+        // Only trust the invoice we issued for this order, never one named by the caller
+        let Some(invoice_id) = order.coinos_invoice_hash.clone() else {
+            return Ok(PaymentResult::NoMatchingOrder);
+        };
+        if notice.payment_id.as_deref().is_some_and(|id| id != invoice_id) {
+            return Ok(PaymentResult::NoMatchingOrder);
+        }
+        let status = provider
+            .check_status(&invoice_id)
+            .await
+            .map_err(|e| ApiError::new(502, e))?;
+        if status != InvoiceStatus::Paid {
+            return Ok(PaymentResult::NotPaid);
+        }
+    }
+
+    let payment_id = notice.payment_id.clone();
+    settle_order(store, dns, order, payment_id.as_deref(), management_token, now_ms).await
+}
+
+/// Shared by the webhook and polling paths. The order is re-read and marked
+/// paid before provisioning so that a notification racing a poll (or a
+/// replayed webhook) provisions only once.
+async fn settle_order<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    order: Order,
+    payment_id: Option<&str>,
+    management_token: String,
+    now_ms: u64,
+) -> Result<PaymentResult, ApiError> {
+    let mut order = match store.get_order(&order.order_id).await? {
+        Some(o) if o.status == OrderStatus::Pending => o,
+        _ => return Ok(PaymentResult::NoMatchingOrder),
+    };
+    order.transition(OrderStatus::Paid, now_ms, None)?;
+    if let Some(id) = payment_id {
+        order.coinos_invoice_hash = Some(id.to_string());
+    }
+    store.put_order(&order).await?;
+
+    match provision_paid_order(store, dns, order, management_token, now_ms).await? {
+        Some(p) => Ok(PaymentResult::Provisioned(Box::new(p))),
+        None => Ok(PaymentResult::RentalNotFound),
+    }
+}
+
+/// Ask the payment backend whether a pending order's invoice has been paid,
+/// provisioning it exactly as the webhook would if so
+pub async fn poll_order_payment<S: Store, D: DnsProvider, P: PaymentProvider>(
+    store: &S,
+    dns: &D,
+    provider: &P,
+    order: &Order,
+    management_token: String,
+    now_ms: u64,
+) -> Result<PaymentResult, ApiError> {
+    if order.status != OrderStatus::Pending {
+        return Ok(PaymentResult::NoMatchingOrder);
+    }
+    let Some(invoice_id) = order.coinos_invoice_hash.as_deref() else {
+        return Ok(PaymentResult::NotPaid);
+    };
+    let status = provider
+        .check_status(invoice_id)
+        .await
+        .map_err(|e| ApiError::new(502, e))?;
+    if status != InvoiceStatus::Paid {
+        return Ok(PaymentResult::NotPaid);
+    }
+    settle_order(store, dns, order.clone(), None, management_token, now_ms).await
+}
+
+/// Cron fallback for lost webhooks: poll every pending order in the pending/ index
+pub async fn poll_pending_orders<S: Store, D: DnsProvider, P: PaymentProvider>(
+    store: &S,
+    dns: &D,
+    provider: &P,
+    mut new_token: impl FnMut() -> String,
+    now_ms: u64,
+) -> StoreResult<PollReport> {
+    let mut report = PollReport::default();
+    for order_id in store.list_pending_order_ids().await? {
+        let order = match store.get_order(&order_id).await? {
+            Some(o) if o.status == OrderStatus::Pending => o,
+            Some(o) if o.status.is_open() => continue,
+            _ => {
+                store.delete_pending_index(&order_id).await?;
+                report.dropped.push(order_id);
+                continue;
+            }
+        };
+
+        match poll_order_payment(store, dns, provider, &order, new_token(), now_ms).await {
+            Ok(PaymentResult::Provisioned(p)) => report.provisioned.push(*p),
+            Ok(PaymentResult::NotPaid) => {}
+            Ok(_) => report.dropped.push(order_id),
+            Err(e) => report.errors.push(format!("Payment poll failed for {}: {}", order_id, e.message)),
+        }
+    }
+    Ok(report)
+}
+
+/// Expire open orders that can no longer complete: unconfirmed orders once
+/// past their expiry, and unpaid ones once the payment poll grace has passed.
+/// Returns the ids of orders marked expired.
+pub async fn expire_stale_orders<S: Store>(store: &S, now_ms: u64) -> StoreResult<Vec<String>> {
+    let mut expired = Vec::new();
+    for order_id in store.list_pending_order_ids().await? {
+        let mut order = match store.get_order(&order_id).await? {
+            Some(o) if o.status.is_open() => o,
+            _ => {
+                store.delete_pending_index(&order_id).await?;
+                continue;
+            }
+        };
+
+        let expires_ms = ms_from_iso(&order.expires_at).unwrap_or(0);
+        let reason = match order.status {
+            OrderStatus::WebhookPending if now_ms >= expires_ms => "webhook challenge not confirmed",
+            OrderStatus::Pending if now_ms > expires_ms + PAYMENT_POLL_GRACE_MS => "invoice not paid",
+            _ => continue,
+        };
+        order.transition(OrderStatus::Expired, now_ms, Some(reason))?;
+        store.put_order(&order).await?;
+        store.delete_pending_index(&order_id).await?;
+        expired.push(order_id);
+    }
+    Ok(expired)
+}
+
+/// How a request proves it may manage a rental
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RentalAuth<'a> {
+    /// The rental's management token
+    Token(&'a str),
+    /// A verified NIP-98 event from this pubkey, for the named rental
+    Owner { username: &'a str, pubkey: &'a str },
+}
+
+/// The rental a request may manage: 404 for an unknown token or username,
+/// 403 when the pubkey is not the rental's owner
+pub async fn find_authorized_rental<S: Store>(store: &S, auth: RentalAuth<'_>) -> Result<Rental, ApiError> {
+    match auth {
+        RentalAuth::Token(token) => find_rental_by_token(store, token)
+            .await?
+            .ok_or_else(|| ApiError::new(404, "Rental not found")),
+        RentalAuth::Owner { username, pubkey } => {
+            let rental = store
+                .get_rental(username)
+                .await?
+                .ok_or_else(|| ApiError::new(404, "Rental not found"))?;
+            if !rental.owner_pubkey.as_deref().is_some_and(|o| o.eq_ignore_ascii_case(pubkey)) {
+                return Err(ApiError::new(403, "Not the rental owner"));
+            }
+            Ok(rental)
+        }
+    }
+}
+
+/// Check an `Authorization: Nostr` header (NIP-98) for a request and consume its
+/// login challenge; returns the signer's pubkey
+pub async fn verify_owner_auth<S: Store>(
+    store: &S,
+    header: &str,
+    url: &str,
+    method: &str,
+    body: &[u8],
+    now_ms: u64,
+) -> Result<String, ApiError> {
+    let event = crate::nostr::parse_http_auth_header(header)
+        .ok_or_else(|| ApiError::new(401, "Expected Authorization: Nostr <event>"))?
+        .map_err(|e| ApiError::new(401, e))?;
+    let challenge = crate::nostr::verify_http_auth_event(&event, url, method, body, now_ms / 1000)
+        .map_err(|e| ApiError::new(401, e))?;
+    crate::admin::consume_challenge(store, &challenge, now_ms)
+        .await
+        .map_err(|e| ApiError::new(401, e.message))?;
+    Ok(event.pubkey.to_ascii_lowercase())
+}
+
+/// Owner pubkeys are stored as lowercase hex
+fn normalize_owner_pubkey(pubkey: &str) -> Result<String, ApiError> {
+    crate::nip19::pubkey_hex(pubkey).map_err(|e| ApiError::new(400, format!("Invalid owner_pubkey: {}", e)))
+}
+
+/// Store the NIP-05 pubkey as hex, keeping an nprofile's usable relay hints
+/// after any given explicitly, and check the extra names
+fn normalize_nip05_request(username: &str, nip05: &mut OrderNip05Request) -> Result<(), ApiError> {
+    let profile =
+        crate::nip19::decode_pubkey(&nip05.pubkey).map_err(|e| ApiError::new(400, format!("Invalid NIP-05 pubkey: {}", e)))?;
+    let mut relays = crate::nip05::normalize_relays(&nip05.relays).map_err(|e| ApiError::new(400, e))?;
+    for hint in crate::nip05::relay_hints(&profile.relays) {
+        if relays.len() < crate::nip05::MAX_RELAYS && !relays.contains(&hint) {
+            relays.push(hint);
+        }
+    }
+    nip05.pubkey = profile.pubkey_hex;
+    nip05.relays = relays;
+    nip05.names = crate::nip05::normalize_names(username, &nip05.names).map_err(|e| ApiError::new(400, e))?;
+    Ok(())
+}
+
+/// Normalize the NIP-05 and Lightning Address parts of requested services
+fn normalize_services_request(username: &str, services: &mut OrderServicesRequest) -> Result<(), ApiError> {
+    if let Some(nip05) = services.nip05.as_mut() {
+        normalize_nip05_request(username, nip05)?;
+    }
+    if let Some(lightning) = services.lightning_address.as_mut() {
+        lightning.forward_to = lightning
+            .forward_to
+            .as_deref()
+            .map(crate::lnurl::normalize_forward_to)
+            .transpose()
+            .map_err(|e| ApiError::new(400, format!("Invalid forward_to: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Find the rental owning a management token via the tokens/ index.
+/// An index entry left behind by a rotated token does not match.
+pub async fn find_rental_by_token<S: Store>(store: &S, token: &str) -> StoreResult<Option<Rental>> {
+    let username = match store.get_token_username(token).await? {
+        Some(u) => u,
+        None => return Ok(None),
+    };
+    Ok(store
+        .get_rental(&username)
+        .await?
+        .filter(|r| r.management_token.as_deref() == Some(token)))
+}
+
+/// Replace a rental's management token, moving its index entry
+pub async fn rotate_management_token<S: Store>(
+    store: &S,
+    rental: &mut Rental,
+    new_token: String,
+) -> StoreResult<()> {
+    let old_token = rental.management_token.replace(new_token.clone());
+    store.put_token_index(&new_token, &rental.username).await?;
+    store.put_rental(rental).await?;
+    if let Some(old) = old_token {
+        store.delete_token_index(&old).await?;
+    }
+    Ok(())
+}
+
+/// Issue a new management token for a rental that can still be managed. The old
+/// token stops resolving at once, and with it the old my-page URL
+pub async fn reissue_management_token<S: Store>(
+    store: &S,
+    rental: &mut Rental,
+    new_token: String,
+) -> Result<(), ApiError> {
+    if matches!(rental.status, RentalStatus::Suspended | RentalStatus::Revoked | RentalStatus::Released) {
+        return Err(ApiError::new(409, format!("Rental is {}", rental.status.as_str())));
+    }
+    rotate_management_token(store, rental, new_token).await?;
+    Ok(())
+}
+
+/// POST /api/settings/{management_token}/rotate
+pub async fn rotate_token<S: Store>(store: &S, auth: RentalAuth<'_>, new_token: String) -> Result<Rental, ApiError> {
+    let mut rental = find_authorized_rental(store, auth).await?;
+    reissue_management_token(store, &mut rental, new_token).await?;
+    Ok(rental)
+}
+
+/// Write missing tokens/ index entries for rentals created before the index existed.
+/// Returns the number of entries written.
+pub async fn backfill_token_index<S: Store>(store: &S) -> StoreResult<usize> {
+    let mut written = 0;
+    for rental in store.list_rentals().await? {
+        let token = match rental.management_token.as_deref() {
+            Some(t) if !t.is_empty() => t,
+            _ => continue,
+        };
+        if store.get_token_username(token).await?.as_deref() != Some(rental.username.as_str()) {
+            store.put_token_index(token, &rental.username).await?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// How a renewal is authorized: NIP-98 owner auth (`owner_pubkey`, verified) for
+/// `body.username`, else the management token
+pub fn renewal_auth<'a>(body: &'a RenewRequest, owner_pubkey: Option<&'a str>) -> Result<RentalAuth<'a>, ApiError> {
+    request_auth(&body.management_token, body.username.as_deref(), owner_pubkey)
+}
+
+/// How an upgrade is authorized, as for renewals
+pub fn upgrade_auth<'a>(body: &'a UpgradeRequest, owner_pubkey: Option<&'a str>) -> Result<RentalAuth<'a>, ApiError> {
+    request_auth(&body.management_token, body.username.as_deref(), owner_pubkey)
+}
+
+fn request_auth<'a>(
+    management_token: &'a str,
+    username: Option<&'a str>,
+    owner_pubkey: Option<&'a str>,
+) -> Result<RentalAuth<'a>, ApiError> {
+    match (owner_pubkey, username) {
+        (Some(pubkey), Some(username)) => Ok(RentalAuth::Owner { username, pubkey }),
+        (Some(_), None) => Err(ApiError::new(400, "username is required with Nostr auth")),
+        (None, _) if management_token.is_empty() => Err(ApiError::new(400, "management_token is required")),
+        (None, _) => Ok(RentalAuth::Token(management_token)),
+    }
+}
+
+/// POST /api/renew — resolve the rental and price the renewal
+pub async fn renewal_quote<S: Store>(
+    store: &S,
+    auth: RentalAuth<'_>,
+    body: &RenewRequest,
+) -> Result<(Rental, u64), ApiError> {
+    let rental = find_authorized_rental(store, auth).await?;
+    if matches!(rental.status, RentalStatus::Suspended | RentalStatus::Revoked | RentalStatus::Released) {
+        return Err(ApiError::new(403, format!("Rental is {} and cannot be renewed", rental.status)));
+    }
+
+    // Use request services if provided, else derive from rental
+    let (service_types, extra_names) = if body.services.is_some() {
+        (services_from_request(&body.services), extra_names_from_request(&body.services))
+    } else {
+        let slots = rental.services.nip05.as_ref().filter(|n| n.enabled).map_or(0, |n| n.name_slots);
+        (services_from_rental(&rental.services), slots)
+    };
+    let pricing = store.load_pricing().await;
+    let amount_sats = order_total(&body.plan, &service_types, extra_names, &pricing);
+    Ok((rental, amount_sats))
+}
+
+/// Store a renewal order for an already-invoiced renewal
+pub async fn create_renewal_order<S: Store>(
+    store: &S,
+    rental: &Rental,
+    plan: Plan,
+    amount_sats: u64,
+    order_id: String,
+    invoice: InvoiceDetails,
+    now_ms: u64,
+) -> StoreResult<Order> {
+    let order = Order {
+        order_id,
+        username: rental.username.clone(),
+        plan,
+        amount_sats,
+        bolt11: invoice.bolt11,
+        status: OrderStatus::Pending,
+        created_at: iso_from_ms(now_ms),
+        expires_at: iso_from_ms(now_ms + ORDER_TTL_MS),
+        coinos_invoice_hash: invoice.hash,
+        webhook_secret: Some(invoice.webhook_secret),
+        services_requested: None,
+        management_token: None,
+        renewal_for: Some(rental.username.clone()),
+        upgrade_for: None,
+        webhook_url: rental.webhook_url.clone(),
+        webhook_challenge: None,
+        history: Vec::new(),
+        signing_secret: None,
+        nostr_dm: false,
+        owner_pubkey: None,
+    };
+    index_invoice(store, &order).await?;
+    store.put_pending_index(&order.order_id).await?;
+    store.put_order(&order).await?;
+    Ok(order)
+}
+
+/// Prorated price of adding services to a rental: the difference they make to the
+/// plan's price, scaled by the time left over the plan's duration and rounded up
+pub fn upgrade_price(
+    plan: &Plan,
+    current: &[ServiceType],
+    current_names: usize,
+    added: &[ServiceType],
+    added_names: usize,
+    remaining_ms: u64,
+    pricing: &PricingConfig,
+) -> u64 {
+    let combined: Vec<ServiceType> = current.iter().chain(added).copied().collect();
+    let full = order_total(plan, &combined, current_names + added_names, pricing)
+        .saturating_sub(order_total(plan, current, current_names, pricing));
+    let plan_ms = plan.duration_minutes() * 60 * 1000;
+    let prorated = (full as u128 * remaining_ms as u128).div_ceil(plan_ms as u128);
+    // An invoice needs at least one sat
+    (prorated as u64).max(1)
+}
+
+/// POST /api/upgrade — resolve the rental, check the services to add and price
+/// them for the rental's remaining time. Returns the services normalized for the order
+pub async fn upgrade_quote<S: Store>(
+    store: &S,
+    auth: RentalAuth<'_>,
+    mut services: OrderServicesRequest,
+    grace_minutes: u64,
+    now_ms: u64,
+) -> Result<(Rental, OrderServicesRequest, u64), ApiError> {
+    let rental = find_authorized_rental(store, auth).await?;
+    if matches!(rental.status, RentalStatus::Suspended | RentalStatus::Revoked | RentalStatus::Released) {
+        return Err(ApiError::new(403, format!("Rental is {} and cannot be upgraded", rental.status)));
+    }
+    let remaining_ms = ms_from_iso(&rental.expires_at).unwrap_or(0).saturating_sub(now_ms);
+    if !rental.status.is_active() || remaining_ms == 0 {
+        return Err(ApiError::new(409, "Rental has expired; renew it before adding services"));
+    }
+
+    let added = services_from_request(&Some(services.clone()));
+    if added.is_empty() {
+        return Err(ApiError::new(400, "services must name at least one service to add"));
+    }
+    let current = services_from_rental(&rental.services);
+    if let Some(service) = added.iter().find(|s| current.contains(s)) {
+        return Err(ApiError::new(409, format!("Rental already has {}", service.key())));
+    }
+    validate_subdomain_target(Some(&services))?;
+    normalize_services_request(&rental.username, &mut services)?;
+    if let Some(nip05) = services.nip05.as_ref() {
+        check_nip05_names_free(store, &rental.username, nip05.names.keys(), grace_minutes, now_ms).await?;
+    }
+
+    let current_names = rental.services.nip05.as_ref().filter(|n| n.enabled).map_or(0, |n| n.name_slots);
+    let added_names = services.nip05.as_ref().map_or(0, |n| n.names.len());
+    let pricing = store.load_pricing().await;
+    let amount_sats =
+        upgrade_price(&rental.plan, &current, current_names, &added, added_names, remaining_ms, &pricing);
+    Ok((rental, services, amount_sats))
+}
+
+/// Store an upgrade order for already-invoiced services
+pub async fn create_upgrade_order<S: Store>(
+    store: &S,
+    rental: &Rental,
+    services: OrderServicesRequest,
+    amount_sats: u64,
+    order_id: String,
+    invoice: InvoiceDetails,
+    now_ms: u64,
+) -> StoreResult<Order> {
+    let order = Order {
+        order_id,
+        username: rental.username.clone(),
+        plan: rental.plan.clone(),
+        amount_sats,
+        bolt11: invoice.bolt11,
+        status: OrderStatus::Pending,
+        created_at: iso_from_ms(now_ms),
+        expires_at: iso_from_ms(now_ms + ORDER_TTL_MS),
+        coinos_invoice_hash: invoice.hash,
+        webhook_secret: Some(invoice.webhook_secret),
+        services_requested: Some(services),
+        management_token: None,
+        renewal_for: None,
+        upgrade_for: Some(rental.username.clone()),
+        webhook_url: rental.webhook_url.clone(),
+        webhook_challenge: None,
+        history: Vec::new(),
+        signing_secret: None,
+        nostr_dm: false,
+        owner_pubkey: None,
+    };
+    index_invoice(store, &order).await?;
+    store.put_pending_index(&order.order_id).await?;
+    store.put_order(&order).await?;
+    Ok(order)
+}
+
+/// PUT /api/settings/{management_token}. Absent fields are left unchanged;
+/// `new_signing_secret` is only called when a rotation is requested. A new DNS
+/// record is applied to the live record before anything is saved
+#[allow(clippy::too_many_arguments)]
+pub async fn update_settings<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    auth: RentalAuth<'_>,
+    body: &SettingsRequest,
+    policy: UrlPolicy,
+    grace_minutes: u64,
+    now_ms: u64,
+    new_signing_secret: impl FnOnce() -> String,
+) -> Result<SettingsResponse, ApiError> {
+    let mut rental = find_authorized_rental(store, auth).await?;
+    if matches!(rental.status, RentalStatus::Suspended | RentalStatus::Revoked | RentalStatus::Released) {
+        return Err(ApiError::new(409, format!("Rental is {}", rental.status.as_str())));
+    }
+    if let Some(Some(ref url)) = body.webhook_url {
+        if !url.is_empty() {
+            validate_webhook_url(url, policy)?;
+        }
+    }
+    let webhooks = body
+        .webhooks
+        .as_deref()
+        .map(|w| normalize_webhook_endpoints(w, policy))
+        .transpose()?;
+    let owner_pubkey = match body.owner_pubkey {
+        Some(Some(ref pubkey)) => Some(Some(normalize_owner_pubkey(pubkey)?)),
+        Some(None) => Some(None),
+        None => None,
+    };
+    let nip05_relays = body
+        .nip05_relays
+        .as_deref()
+        .map(crate::nip05::normalize_relays)
+        .transpose()
+        .map_err(|e| ApiError::new(400, e))?;
+    let nip46_relays = body
+        .nip46_relays
+        .as_deref()
+        .map(crate::nip05::normalize_relays)
+        .transpose()
+        .map_err(|e| ApiError::new(400, e))?;
+    let nip05_profile = body
+        .nip05_pubkey
+        .as_deref()
+        .map(|p| crate::nip19::decode_pubkey(p).map_err(|e| ApiError::new(400, format!("Invalid NIP-05 pubkey: {}", e))))
+        .transpose()?;
+    let subdomain = body
+        .subdomain
+        .as_ref()
+        .map(|sub| {
+            let record_type = DnsRecordType::parse(&sub.record_type).map_err(|e| ApiError::new(400, e))?;
+            url_policy::check_dns_target(&record_type, &sub.target)
+                .map_err(|e| ApiError::new(400, format!("Invalid subdomain target: {}", e)))?;
+            Ok::<_, ApiError>((record_type, sub))
+        })
+        .transpose()?;
+
+    // Every field is checked and applied to this copy before anything is written
+    let mut changed = Vec::new();
+    let mut indexed_names = Vec::new();
+    let mut dropped_names = Vec::new();
+    if let Some(ref names) = body.nip05_names {
+        let names = crate::nip05::normalize_names(&rental.username, names).map_err(|e| ApiError::new(400, e))?;
+        let nip05 = rental
+            .services
+            .nip05
+            .as_ref()
+            .ok_or_else(|| ApiError::new(400, "Extra names require the NIP-05 service"))?;
+        if names.len() > nip05.name_slots {
+            return Err(ApiError::new(
+                400,
+                format!("This rental has {} extra name(s) paid for; renew with more names to add others", nip05.name_slots),
+            ));
+        }
+        let added = names.keys().filter(|n| !nip05.names.contains_key(*n));
+        check_nip05_names_free(store, &rental.username, added, grace_minutes, now_ms).await?;
+        dropped_names = nip05.names.keys().filter(|n| !names.contains_key(*n)).cloned().collect();
+        indexed_names = names.keys().cloned().collect();
+        rental.services.nip05.as_mut().unwrap().names = names;
+        changed.push("nip05_names");
+    }
+    if let Some(profile) = nip05_profile {
+        let nip05 = rental
+            .services
+            .nip05
+            .as_mut()
+            .ok_or_else(|| ApiError::new(400, "nip05_pubkey requires the NIP-05 service"))?;
+        nip05.pubkey_hex = profile.pubkey_hex;
+        // As at order time, an nprofile's relays are added as hints
+        for hint in crate::nip05::relay_hints(&profile.relays) {
+            if nip05.relays.len() < crate::nip05::MAX_RELAYS && !nip05.relays.contains(&hint) {
+                nip05.relays.push(hint);
+            }
+        }
+        changed.push("nip05_pubkey");
+    }
+    if nip05_relays.is_some() || nip46_relays.is_some() {
+        let nip05 = rental
+            .services
+            .nip05
+            .as_mut()
+            .ok_or_else(|| ApiError::new(400, "Relays require the NIP-05 service"))?;
+        if let Some(relays) = nip05_relays {
+            nip05.relays = relays;
+            changed.push("nip05_relays");
+        }
+        if let Some(relays) = nip46_relays {
+            nip05.nip46 = relays;
+            changed.push("nip46_relays");
+        }
+    }
+    if let Some(owner_pubkey) = owner_pubkey {
+        rental.owner_pubkey = owner_pubkey;
+        changed.push("owner_pubkey");
+    }
+    if let Some(nostr_dm) = body.nostr_dm {
+        rental.nostr_dm = nostr_dm;
+        if nostr_dm && rental.dm_recipient().is_none() {
+            return Err(ApiError::new(400, "nostr_dm requires a NIP-05 pubkey"));
+        }
+        changed.push("nostr_dm");
+    }
+    if let Some(ref url) = body.webhook_url {
+        rental.webhook_url = url.clone();
+        changed.push("webhook_url");
+    }
+    if let Some(webhooks) = webhooks {
+        rental.webhooks = webhooks;
+        changed.push("webhooks");
+    }
+    let rotated = body.rotate_signing_secret.then(new_signing_secret);
+    if rotated.is_some() {
+        rental.signing_secret = rotated.clone();
+        changed.push("signing_secret");
+    }
+    let mut previous_record = None;
+    if let Some((record_type, sub_req)) = subdomain {
+        let sub = rental
+            .services
+            .subdomain
+            .as_mut()
+            .ok_or_else(|| ApiError::new(400, "DNS changes require the subdomain service"))?;
+        previous_record = Some(sub.clone());
+        sub.record_type = record_type.as_str().to_string();
+        sub.target = sub_req.target.trim().to_string();
+        sub.proxied = sub_req.proxied;
+        changed.push("subdomain");
+    }
+
+    // DNS first, then the rental (undoing the DNS change if that fails), then the
+    // name index. A lapsed rental has no record; the new one is created on renewal
+    let record_change = previous_record
+        .zip(rental.services.subdomain.clone())
+        .and_then(|(old, new)| Some((new.cf_record_id.clone()?, old, new)));
+    if let Some((record_id, _, new)) = &record_change {
+        apply_dns_record(dns, record_id, new).await?;
+    }
+    if let Err(e) = store.put_rental(&rental).await {
+        if let Some((record_id, old, _)) = &record_change {
+            let _ = apply_dns_record(dns, record_id, old).await;
+        }
+        return Err(e.into());
+    }
+    for name in &indexed_names {
+        store.put_nip05_name_index(name, &rental.username).await?;
+    }
+    for name in &dropped_names {
+        if store.get_nip05_name_owner(name).await?.as_deref() == Some(rental.username.as_str()) {
+            store.delete_nip05_name_index(name).await?;
+        }
+    }
+
+    Ok(SettingsResponse {
+        success: true,
+        username: rental.username,
+        webhook_url: rental.webhook_url,
+        webhooks: rental.webhooks,
+        nostr_dm: rental.nostr_dm,
+        owner_pubkey: rental.owner_pubkey,
+        nip05_relays: rental.services.nip05.as_ref().map(|n| n.relays.clone()).unwrap_or_default(),
+        nip46_relays: rental.services.nip05.as_ref().map(|n| n.nip46.clone()).unwrap_or_default(),
+        nip05_names: rental.services.nip05.as_ref().map(|n| n.names.clone()).unwrap_or_default(),
+        nip05_pubkey: rental.services.nip05.as_ref().map(|n| n.pubkey_hex.clone()),
+        subdomain: rental.services.subdomain.as_ref().map(|s| OrderSubdomainRequest {
+            record_type: s.record_type.clone(),
+            target: s.target.clone(),
+            proxied: s.proxied,
+        }),
+        signing_secret: rotated,
+        changed,
+    })
+}
+
+/// Point a subdomain's existing DNS record at the service's type, target and proxy setting
+async fn apply_dns_record<D: DnsProvider>(dns: &D, record_id: &str, sub: &SubdomainService) -> Result<(), ApiError> {
+    let record_type = DnsRecordType::parse(&sub.record_type)?;
+    dns.update_record(record_id, &record_type, &sub.target, sub.proxied)
+        .await
+        .map_err(|e| ApiError::new(502, format!("DNS update failed: {}", e)))
+}
+
+/// Mark expired active rentals as expired and delete their DNS records
+pub async fn expire_rentals<S: Store, D: DnsProvider>(
+    store: &S,
+    dns: &D,
+    grace_minutes: u64,
+    now_ms: u64,
+) -> StoreResult<ExpiryReport> {
+    let mut report = ExpiryReport::default();
+    for mut rental in store.list_rentals().await? {
+        match rental.status {
+            RentalStatus::Active if is_expired_ms(&rental.expires_at, now_ms) => {
+                // Services stop at expiry; the record is recreated if the owner renews
+                let mut dns_removed = false;
+                if let Some(sub) = rental.services.subdomain.as_mut() {
+                    if let Some(record_id) = sub.cf_record_id.clone() {
+                        match dns.delete_record(&record_id).await {
+                            Ok(()) => {
+                                sub.cf_record_id = None;
+                                dns_removed = true;
+                            }
+                            Err(e) => report.dns_errors.push(format!(
+                                "Failed to delete DNS record {} for {}: {}",
+                                record_id, rental.username, e
+                            )),
+                        }
+                    }
+                }
+
+                if grace_minutes > 0 {
+                    rental.status = RentalStatus::Grace;
+                    rental.grace_until = Some(iso_from_ms(rental.released_at_ms(grace_minutes)));
+                } else {
+                    rental.status = RentalStatus::Expired;
+                }
+                store.put_rental(&rental).await?;
+                if dns_removed {
+                    report.dns_removed.push(rental.clone());
+                }
+                if rental.status == RentalStatus::Grace {
+                    report.grace.push(rental);
+                } else {
+                    report.expired.push(rental.username);
+                }
+            }
+            RentalStatus::Grace if rental.is_username_free(grace_minutes, now_ms) => {
+                rental.status = RentalStatus::Expired;
+                store.put_rental(&rental).await?;
+                report.expired.push(rental.username);
+            }
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// An expiring_soon reminder due for a rental
+#[derive(Debug, Clone)]
+pub struct ExpiryReminder {
+    pub rental: Rental,
+    pub threshold_minutes: u64,
+}
+
+/// Find active rentals with a webhook or NIP-17 DMs that crossed a reminder threshold and record
+/// the reminder as sent, so each threshold fires once per expiry date. Thresholds
+/// not shorter than the rental's plan are skipped, and when several are crossed
+/// at once only the nearest one is reported.
+pub async fn collect_expiry_reminders<S: Store>(
+    store: &S,
+    thresholds: &[u64],
+    now_ms: u64,
+) -> StoreResult<Vec<ExpiryReminder>> {
+    let mut due = Vec::new();
+    for mut rental in store.list_rentals().await? {
+        let has_webhook = !rental.webhook_targets(&[WebhookEvent::ExpiringSoon]).is_empty();
+        if !rental.status.is_active() || (!has_webhook && rental.dm_recipient().is_none()) {
+            continue;
+        }
+        let remaining_ms = match ms_from_iso(&rental.expires_at) {
+            Some(expires) if expires > now_ms => expires - now_ms,
+            _ => continue,
+        };
+        let plan_minutes = rental.plan.duration_minutes();
+        let crossed: Vec<u64> = thresholds
+            .iter()
+            .copied()
+            .filter(|t| *t < plan_minutes && remaining_ms <= t * 60 * 1000)
+            .filter(|t| !rental.reminder_sent(*t))
+            .collect();
+        let Some(nearest) = crossed.iter().copied().min() else {
+            continue;
+        };
+
+        let expires_at = rental.expires_at.clone();
+        rental.reminders_sent.retain(|r| r.expires_at == expires_at);
+        rental.reminders_sent.extend(crossed.into_iter().map(|threshold_minutes| SentReminder {
+            threshold_minutes,
+            expires_at: expires_at.clone(),
+        }));
+        store.put_rental(&rental).await?;
+        due.push(ExpiryReminder { rental, threshold_minutes: nearest });
+    }
+    Ok(due)
+}
+
+/// In-memory DNS provider recording calls, for native tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct FakeDns {
+    pub created: std::cell::RefCell<Vec<(String, String)>>,
+    pub deleted: std::cell::RefCell<Vec<String>>,
+    /// (record id, type, content, proxied)
+    pub updated: std::cell::RefCell<Vec<(String, String, String, bool)>>,
+    /// Make update_record fail, as on a Cloudflare outage
+    pub fail_updates: std::cell::Cell<bool>,
+}
+
+#[cfg(test)]
+impl DnsProvider for FakeDns {
+    async fn create_record(
+        &self,
+        username: &str,
+        _record_type: &DnsRecordType,
+        content: &str,
+        _proxied: bool,
+        _expires: &str,
+    ) -> Result<Option<String>, String> {
+        let mut created = self.created.borrow_mut();
+        created.push((username.to_string(), content.to_string()));
+        Ok(Some(format!("rec_{}", created.len())))
+    }
+
+    async fn delete_record(&self, record_id: &str) -> Result<(), String> {
+        self.deleted.borrow_mut().push(record_id.to_string());
+        Ok(())
+    }
+
+    async fn update_record(
+        &self,
+        record_id: &str,
+        record_type: &DnsRecordType,
+        content: &str,
+        proxied: bool,
+    ) -> Result<(), String> {
+        if self.fail_updates.get() {
+            return Err("unavailable".into());
+        }
+        self.updated
+            .borrow_mut()
+            .push((record_id.to_string(), record_type.to_string(), content.to_string(), proxied));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{block_on, MemoryStore};
+    use crate::admin::BanRecord;
+    use std::collections::BTreeMap;
+
+    const NOW: u64 = 1_770_811_200_000; // 2026-02-11T12:00:00.000Z
+    const GRACE: u64 = 24 * 60;
+    const MINUTE: u64 = 60 * 1000;
+
+    fn order_request(username: &str) -> OrderRequest {
+        serde_json::from_value(serde_json::json!({
+            "username": username,
+            "plan": "30d",
+            "webhook_url": "https://example.com/hook",
+            "services": {
+                "email": {},
+                "subdomain": {"type": "CNAME", "target": "example.com"},
+                "nip05": {"pubkey": "a".repeat(64)}
+            }
+        }))
+        .unwrap()
+    }
+
+    fn invoice(secret: &str) -> InvoiceDetails {
+        InvoiceDetails {
+            bolt11: "lnbc1test".into(),
+            hash: Some("hash1".into()),
+            webhook_secret: secret.into(),
+        }
+    }
+
+    /// Order → webhook confirm → invoice → payment → provisioned rental
+    #[test]
+    fn test_full_order_flow() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let order = create_order(&store, order_request("alice"), "ord_1".into(), "ch_1".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            assert_eq!(order.status, OrderStatus::WebhookPending);
+            assert_eq!(order.amount_sats, 6500);
+            assert_eq!(order.expires_at, "2026-02-11T12:15:00.000Z");
+
+            assert_eq!(
+                load_order_for_confirm(&store, "ord_1", "wrong", NOW).await.unwrap_err().status,
+                403
+            );
+            let mut order = load_order_for_confirm(&store, "ord_1", "ch_1", NOW + MINUTE).await.unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_1"), NOW).await.unwrap();
+            assert_eq!(store.get_order("ord_1").await.unwrap().unwrap().status, OrderStatus::Pending);
+
+            let result = apply_payment(&store, &dns, "sec_1", "hash_paid", "mgmt_1".into(), NOW + 2 * MINUTE)
+                .await
+                .unwrap();
+            let provisioned = match result {
+                PaymentResult::Provisioned(p) => p,
+                other => panic!("unexpected {:?}", other),
+            };
+            assert!(!provisioned.is_renewal);
+
+            let rental = store.get_rental("alice").await.unwrap().unwrap();
+            assert_eq!(rental.status, RentalStatus::Active);
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_1"));
+            assert_eq!(rental.webhook_url.as_deref(), Some("https://example.com/hook"));
+            assert_eq!(rental.expires_at, iso_from_ms(NOW + 2 * MINUTE + 43200 * MINUTE));
+            assert!(rental.services.email.is_some());
+            assert_eq!(rental.services.subdomain.as_ref().unwrap().cf_record_id.as_deref(), Some("rec_1"));
+            assert_eq!(dns.created.borrow()[0], ("alice".to_string(), "example.com".to_string()));
+
+            let order = store.get_order("ord_1").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Provisioned);
+            assert_eq!(order.coinos_invoice_hash.as_deref(), Some("hash_paid"));
+            assert_eq!(order.management_token.as_deref(), Some("mgmt_1"));
+
+            // Replayed webhook finds no pending order
+            let replay = apply_payment(&store, &dns, "sec_1", "hash_paid", "mgmt_2".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(replay, PaymentResult::NoMatchingOrder));
+
+            // Username is now taken
+            let err = create_order(&store, order_request("alice"), "ord_2".into(), "ch_2".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+        });
+    }
+
+    #[test]
+    fn test_renewal_flow_extends_from_current_expiry() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("bob"), "ord_1".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_1"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_1", "h", "mgmt_bob".into(), NOW).await.unwrap();
+            let before = store.get_rental("bob").await.unwrap().unwrap();
+
+            let body = RenewRequest {
+                management_token: "mgmt_bob".into(),
+                username: None,
+                plan: Plan::SevenDays,
+                services: None,
+            };
+            let (rental, amount) = renewal_quote(&store, renewal_auth(&body, None).unwrap(), &body).await.unwrap();
+            assert_eq!(amount, 3300); // bundle for 7d
+            let renewal = create_renewal_order(&store, &rental, Plan::SevenDays, amount, "ord_2".into(), invoice("sec_2"), NOW)
+                .await
+                .unwrap();
+            assert_eq!(renewal.renewal_for.as_deref(), Some("bob"));
+
+            let result = apply_payment(&store, &dns, "sec_2", "h2", "unused".into(), NOW + MINUTE)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(ref p) if p.is_renewal));
+
+            let after = store.get_rental("bob").await.unwrap().unwrap();
+            assert_eq!(after.plan, Plan::SevenDays);
+            assert_eq!(after.management_token.as_deref(), Some("mgmt_bob"));
+            assert_eq!(
+                ms_from_iso(&after.expires_at).unwrap(),
+                ms_from_iso(&before.expires_at).unwrap() + 10080 * MINUTE
+            );
+            // Only the original subdomain record was created
+            assert_eq!(dns.created.borrow().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_renewal_for_missing_rental() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let rental = Rental {
+                username: "ghost".into(),
+                status: RentalStatus::Active,
+                created_at: iso_from_ms(NOW),
+                expires_at: iso_from_ms(NOW),
+                plan: Plan::OneDay,
+                services: RentalServices { email: None, subdomain: None, nip05: None, lightning_address: None },
+                management_token: Some("mgmt_g".into()),
+                webhook_url: None,
+                grace_until: None,
+                reminders_sent: Vec::new(),
+                signing_secret: None,
+                webhooks: Vec::new(),
+                nostr_dm: false,
+                owner_pubkey: None,
+            };
+            create_renewal_order(&store, &rental, Plan::OneDay, 1, "ord_g".into(), invoice("sec_g"), NOW)
+                .await
+                .unwrap();
+            let result = apply_payment(&store, &dns, "sec_g", "h", "x".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::RentalNotFound));
+            let order = store.get_order("ord_g").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Failed);
+            assert_eq!(order.history.last().unwrap().reason.as_deref(), Some("rental not found for renewal"));
+            assert!(store.list_pending_order_ids().await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_payment_lookup_spans_list_pages() {
+        let store = MemoryStore::with_page_size(2);
+        let dns = FakeDns::default();
+        block_on(async {
+            for i in 0..5 {
+                let mut order = create_order(&store, order_request(&format!("user{}", i)), format!("ord_{}", i), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                    .await
+                    .unwrap();
+                attach_invoice(&store, &mut order, invoice(&format!("sec_{}", i)), NOW).await.unwrap();
+            }
+            let result = apply_payment(&store, &dns, "sec_4", "h", "mgmt_4".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(_)));
+            assert!(store.get_rental("user4").await.unwrap().is_some());
+        });
+    }
+
+    #[test]
+    fn test_create_order_validation() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let mut req = order_request("ab");
+            assert_eq!(create_order(&store, req, "o".into(), "c".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap_err().status, 400);
+
+            req = order_request("carol");
+            req.webhook_url = String::new();
+            assert_eq!(create_order(&store, req, "o".into(), "c".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap_err().status, 400);
+
+            req = order_request("carol");
+            req.webhook_url = "ftp://example.com".into();
+            assert_eq!(create_order(&store, req, "o".into(), "c".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap_err().status, 400);
+
+            store
+                .put_ban(&crate::admin::BanRecord {
+                    username: "carol".into(),
+                    banned_at: iso_from_ms(NOW),
+                    reason: None,
+                })
+                .await
+                .unwrap();
+            let err = create_order(&store, order_request("carol"), "o".into(), "c".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 403);
+            assert!(store.get_order("o").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_create_order_accepts_nip19_pubkeys() {
+        let store = MemoryStore::new();
+        let nprofile = "nprofile1qqsrhuxx8l9ex335q7he0f09aej04zpazpl0ne2cgukyawd24mayt8gpp4mhxue69uhhytnc9e3k7mgpz4mhxue69uhkg6nzv9ejuumpv34kytnrdaksjlyr9p";
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        block_on(async {
+            let mut req = order_request("nora");
+            let nip05 = req.services.as_mut().unwrap().nip05.as_mut().unwrap();
+            nip05.pubkey = format!("nostr:{}", nprofile);
+            nip05.relays = vec!["wss://nos.lol".into()];
+            nip05.names = BTreeMap::from([("bot".to_string(), npub.to_string())]);
+            req.owner_pubkey = Some(npub.into());
+            let order = create_order(&store, req, "ord_n".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            let nip05 = order.services_requested.unwrap().nip05.unwrap();
+            assert_eq!(nip05.pubkey, "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d");
+            assert_eq!(nip05.relays, vec!["wss://nos.lol", "wss://r.x.com", "wss://djbas.sadkb.com"]);
+            let hex = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+            assert_eq!(nip05.names["bot"], hex);
+            assert_eq!(order.owner_pubkey.as_deref(), Some(hex));
+
+            // The main NIP-05 pubkey is checked too
+            let mut req = order_request("nora");
+            req.services.as_mut().unwrap().nip05.as_mut().unwrap().pubkey = "not-a-key".into();
+            let err = create_order(&store, req, "ord_x".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 400);
+        });
+    }
+
+    #[test]
+    fn test_confirm_expired_order() {
+        let store = MemoryStore::new();
+        block_on(async {
+            create_order(&store, order_request("dave"), "ord_d".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            let err = load_order_for_confirm(&store, "ord_d", "ch", NOW + ORDER_TTL_MS + 1).await.unwrap_err();
+            assert_eq!(err.status, 410);
+            let err = load_order_for_confirm(&store, "missing", "ch", NOW).await.unwrap_err();
+            assert_eq!(err.status, 404);
+        });
+    }
+
+    #[test]
+    fn test_check_username() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            assert!(check_username(&store, "erin", GRACE, NOW).await.unwrap().available);
+            assert!(!check_username(&store, "admin", GRACE, NOW).await.unwrap().available);
+
+            let mut order = create_order(&store, order_request("erin"), "ord_e".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_e"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_e", "h", "mgmt_e".into(), NOW).await.unwrap();
+            assert!(!check_username(&store, "erin", GRACE, NOW).await.unwrap().available);
+            assert!(!check_username(&store, "erin", GRACE, NOW + 43200 * MINUTE).await.unwrap().available);
+            assert!(check_username(&store, "erin", GRACE, NOW + (43200 + GRACE) * MINUTE).await.unwrap().available);
+
+            let mut rental = store.get_rental("erin").await.unwrap().unwrap();
+            rental.status = RentalStatus::Released;
+            store.put_rental(&rental).await.unwrap();
+            assert!(check_username(&store, "erin", GRACE, NOW).await.unwrap().available);
+        });
+    }
+
+    #[test]
+    fn test_update_settings() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("fred"), "ord_f".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_f"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_f", "h", "mgmt_f".into(), NOW).await.unwrap();
+
+            let body = SettingsRequest { webhook_url: Some(Some("https://new.example.com".into())), ..Default::default() };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_f"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, || unreachable!()).await.unwrap();
+            assert_eq!(resp.webhook_url.as_deref(), Some("https://new.example.com"));
+            assert_eq!(resp.signing_secret, None);
+            assert_eq!(
+                store.get_rental("fred").await.unwrap().unwrap().webhook_url.as_deref(),
+                Some("https://new.example.com")
+            );
+
+            let bad = SettingsRequest { webhook_url: Some(Some("javascript:alert(1)".into())), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_f"), &bad, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_nope"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 404);
+
+            // Rotation alone keeps the webhook; null disables it
+            let rental = store.get_rental("fred").await.unwrap().unwrap();
+            assert_eq!(rental.signing_secret.as_deref(), Some("whsec_test"));
+            let rotate = SettingsRequest { rotate_signing_secret: true, ..Default::default() };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_f"), &rotate, UrlPolicy::PRODUCTION, GRACE, NOW, || "whsec_new".into()).await.unwrap();
+            assert_eq!(resp.signing_secret.as_deref(), Some("whsec_new"));
+            let rental = store.get_rental("fred").await.unwrap().unwrap();
+            assert_eq!(rental.signing_secret.as_deref(), Some("whsec_new"));
+            assert_eq!(rental.webhook_url.as_deref(), Some("https://new.example.com"));
+
+            let disable = SettingsRequest { webhook_url: Some(None), ..Default::default() };
+            update_settings(&store, &dns, RentalAuth::Token("mgmt_f"), &disable, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(store.get_rental("fred").await.unwrap().unwrap().webhook_url, None);
+        });
+    }
+
+    #[test]
+    fn test_update_settings_pubkey_and_dns() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("gwen"), "ord_g".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_g"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_g", "h", "mgmt_g".into(), NOW).await.unwrap();
+            let settings = |body: serde_json::Value| serde_json::from_value::<SettingsRequest>(body).unwrap();
+            let auth = RentalAuth::Token("mgmt_g");
+
+            let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+            let body = settings(serde_json::json!({
+                "nip05_pubkey": npub,
+                "subdomain": {"type": "a", "target": "93.184.216.34", "proxied": true}
+            }));
+            let resp = update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            let hex = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+            assert_eq!(resp.nip05_pubkey.as_deref(), Some(hex));
+            assert_eq!(resp.changed, vec!["nip05_pubkey", "subdomain"]);
+            let rental = store.get_rental("gwen").await.unwrap().unwrap();
+            assert_eq!(rental.services.nip05.unwrap().pubkey_hex, hex);
+            let sub = rental.services.subdomain.unwrap();
+            assert_eq!((sub.record_type.as_str(), sub.target.as_str(), sub.proxied), ("A", "93.184.216.34", true));
+            assert_eq!(
+                *dns.updated.borrow(),
+                vec![("rec_1".to_string(), "A".to_string(), "93.184.216.34".to_string(), true)]
+            );
+
+            // Targets are saved and sent to Cloudflare as they were checked, trimmed
+            let body = settings(serde_json::json!({"subdomain": {"type": "CNAME", "target": " example.com "}}));
+            update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(dns.updated.borrow()[1].2, "example.com");
+            assert_eq!(store.get_rental("gwen").await.unwrap().unwrap().services.subdomain.unwrap().target, "example.com");
+
+            // Targets and pubkeys are checked as at order time, before anything changes
+            for body in [
+                serde_json::json!({"subdomain": {"type": "A", "target": "10.0.0.1"}}),
+                serde_json::json!({"subdomain": {"type": "MX", "target": "mail.example.com"}}),
+                serde_json::json!({"nip05_pubkey": "nsec1xyz"}),
+                serde_json::json!({"webhook_url": "https://new.example.com", "nip05_pubkey": "abc"}),
+            ] {
+                let err = update_settings(&store, &dns, auth, &settings(body), UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err();
+                assert_eq!(err.status, 400);
+            }
+            assert_eq!(dns.updated.borrow().len(), 2);
+            assert_eq!(store.get_rental("gwen").await.unwrap().unwrap().webhook_url.as_deref(), Some("https://example.com/hook"));
+
+            // A lapsed rental keeps the new target for the record restored on renewal
+            let mut rental = store.get_rental("gwen").await.unwrap().unwrap();
+            rental.services.subdomain.as_mut().unwrap().cf_record_id = None;
+            rental.services.nip05 = None;
+            store.put_rental(&rental).await.unwrap();
+            let body = settings(serde_json::json!({"subdomain": {"type": "CNAME", "target": "new.example.com"}}));
+            update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(dns.updated.borrow().len(), 2);
+            assert_eq!(store.get_rental("gwen").await.unwrap().unwrap().services.subdomain.unwrap().target, "new.example.com");
+
+            let body = settings(serde_json::json!({"nip05_pubkey": "a".repeat(64)}));
+            let err = update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err();
+            assert_eq!(err.status, 400);
+        });
+    }
+
+    #[test]
+    fn test_update_settings_nip05_relays() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("rita"), "ord_r".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_r"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_r", "h", "mgmt_r".into(), NOW).await.unwrap();
+
+            let body = SettingsRequest {
+                nip05_relays: Some(vec!["wss://relay.damus.io".into(), "wss://relay.damus.io".into()]),
+                nip46_relays: Some(vec!["wss://bunker.example.com".into()]),
+                ..Default::default()
+            };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_r"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(resp.nip05_relays, vec!["wss://relay.damus.io"]);
+            let nip05 = store.get_rental("rita").await.unwrap().unwrap().services.nip05.unwrap();
+            assert_eq!(nip05.relays, vec!["wss://relay.damus.io"]);
+            assert_eq!(nip05.nip46, vec!["wss://bunker.example.com"]);
+
+            let bad = SettingsRequest { nip05_relays: Some(vec!["https://relay.damus.io".into()]), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_r"), &bad, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+            let clear = SettingsRequest { nip46_relays: Some(Vec::new()), ..Default::default() };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_r"), &clear, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert!(resp.nip46_relays.is_empty());
+            assert_eq!(resp.nip05_relays, vec!["wss://relay.damus.io"]);
+
+            // Relays only make sense with a NIP-05 name
+            let mut rental = store.get_rental("rita").await.unwrap().unwrap();
+            rental.services.nip05 = None;
+            store.put_rental(&rental).await.unwrap();
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_r"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+        });
+    }
+
+    #[test]
+    fn test_lightning_address_order() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut body = order_request("lena");
+            body.services.as_mut().unwrap().lightning_address =
+                Some(OrderLightningAddressRequest { forward_to: Some("Lena@Wallet.Example".into()) });
+            let mut order = create_order(&store, body, "ord_l".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            assert_eq!(order.amount_sats, 6500 + 1000);
+            attach_invoice(&store, &mut order, invoice("sec_l"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_l", "h", "mgmt_l".into(), NOW).await.unwrap();
+            let lightning = store.get_rental("lena").await.unwrap().unwrap().services.lightning_address.unwrap();
+            assert!(lightning.enabled);
+            assert_eq!(lightning.forward_to.as_deref(), Some("lena@wallet.example"));
+
+            let mut body = order_request("lars");
+            body.services.as_mut().unwrap().lightning_address =
+                Some(OrderLightningAddressRequest { forward_to: Some("http://wallet.example/pay".into()) });
+            let err = create_order(&store, body, "ord_x".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 400);
+            assert!(err.message.starts_with("Invalid forward_to"));
+        });
+    }
+
+    #[test]
+    fn test_upgrade_adds_services_prorated() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut body = order_request("uma");
+            body.services = Some(OrderServicesRequest { email: Some(OrderEmailRequest {}), ..Default::default() });
+            let mut order = create_order(&store, body, "ord_u".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            assert_eq!(order.amount_sats, 5000);
+            attach_invoice(&store, &mut order, invoice("sec_u"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_u", "h", "mgmt_uma".into(), NOW).await.unwrap();
+            let before = store.get_rental("uma").await.unwrap().unwrap();
+
+            // Half of the 30d plan left: half the 1500 sats the bundle adds over email alone
+            let halfway = NOW + 21600 * MINUTE;
+            let body: UpgradeRequest = serde_json::from_value(serde_json::json!({
+                "management_token": "mgmt_uma",
+                "services": {
+                    "subdomain": {"type": "CNAME", "target": "example.com"},
+                    "nip05": {"pubkey": "a".repeat(64)}
+                }
+            }))
+            .unwrap();
+            let auth = upgrade_auth(&body, None).unwrap();
+            let (rental, services, amount) =
+                upgrade_quote(&store, auth, body.services.clone(), GRACE, halfway).await.unwrap();
+            assert_eq!(amount, 750);
+            let upgrade = create_upgrade_order(&store, &rental, services, amount, "ord_up".into(), invoice("sec_up"), halfway)
+                .await
+                .unwrap();
+            assert_eq!(upgrade.upgrade_for.as_deref(), Some("uma"));
+
+            let result = apply_payment(&store, &dns, "sec_up", "h2", "unused".into(), halfway + MINUTE)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(ref p) if !p.is_renewal));
+            let after = store.get_rental("uma").await.unwrap().unwrap();
+            assert_eq!(after.expires_at, before.expires_at);
+            assert_eq!(after.management_token.as_deref(), Some("mgmt_uma"));
+            assert!(after.services.email.is_some());
+            assert_eq!(after.services.subdomain.as_ref().unwrap().cf_record_id.as_deref(), Some("rec_1"));
+            assert_eq!(after.services.nip05.as_ref().unwrap().pubkey_hex, "a".repeat(64));
+            assert_eq!(dns.created.borrow().len(), 1);
+            let order = store.get_order("ord_up").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Provisioned);
+
+            // Services the rental already has, or none at all, are rejected
+            let auth = RentalAuth::Token("mgmt_uma");
+            let nip05 = OrderNip05Request { pubkey: "b".repeat(64), relays: vec![], names: BTreeMap::new() };
+            let services = OrderServicesRequest { nip05: Some(nip05), ..Default::default() };
+            let err = upgrade_quote(&store, auth, services, GRACE, halfway).await.unwrap_err();
+            assert_eq!(err.status, 409);
+            let err = upgrade_quote(&store, auth, OrderServicesRequest::default(), GRACE, halfway)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 400);
+
+            // Past expiry the rental must be renewed first
+            let services = OrderServicesRequest { lightning_address: Some(Default::default()), ..Default::default() };
+            let err = upgrade_quote(&store, auth, services, GRACE, NOW + 43200 * MINUTE).await.unwrap_err();
+            assert_eq!(err.status, 409);
+        });
+    }
+
+    #[test]
+    fn test_upgrade_price_rounds_up() {
+        let pricing = PricingConfig::new();
+        let email = [ServiceType::EmailForwarding];
+        let lightning = [ServiceType::LightningAddress];
+        let month_ms = 43200 * MINUTE;
+        assert_eq!(upgrade_price(&Plan::ThirtyDays, &email, 0, &lightning, 0, month_ms, &pricing), 1000);
+        assert_eq!(upgrade_price(&Plan::ThirtyDays, &email, 0, &lightning, 0, month_ms / 3, &pricing), 334);
+        assert_eq!(upgrade_price(&Plan::ThirtyDays, &email, 0, &lightning, 0, 1, &pricing), 1);
+    }
+
+    #[test]
+    fn test_extra_nip05_names() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let (bot, news) = ("b".repeat(64), "c".repeat(64));
+        block_on(async {
+            let mut body = order_request("nina");
+            body.services.as_mut().unwrap().nip05.as_mut().unwrap().names =
+                BTreeMap::from([("Bot".to_string(), bot.clone()), ("news.nina".to_string(), news.clone())]);
+            let mut order = create_order(&store, body, "ord_n".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            assert_eq!(order.amount_sats, 6500 + 2 * 500);
+            attach_invoice(&store, &mut order, invoice("sec_n"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_n", "h", "mgmt_n".into(), NOW).await.unwrap();
+            let nip05 = store.get_rental("nina").await.unwrap().unwrap().services.nip05.unwrap();
+            assert_eq!(nip05.name_slots, 2);
+            assert_eq!(nip05.names.get("bot"), Some(&bot));
+            assert_eq!(store.get_nip05_name_owner("bot").await.unwrap().as_deref(), Some("nina"));
+
+            // Held names are taken both as usernames and as extra names
+            assert!(!check_username(&store, "bot", GRACE, NOW).await.unwrap().available);
+            let err = create_order(&store, order_request("bot"), "ord_b".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+            let mut body = order_request("otto");
+            body.services.as_mut().unwrap().nip05.as_mut().unwrap().names = BTreeMap::from([("nina".to_string(), bot.clone())]);
+            let err = create_order(&store, body, "ord_o".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+
+            // Renewals keep paying for the slots
+            let renew: RenewRequest = serde_json::from_value(serde_json::json!({"management_token": "mgmt_n", "plan": "30d"})).unwrap();
+            let (_, amount) = renewal_quote(&store, RentalAuth::Token("mgmt_n"), &renew).await.unwrap();
+            assert_eq!(amount, 6500 + 2 * 500);
+
+            // Settings can swap names within the paid slots and release dropped ones
+            let swap = SettingsRequest { nip05_names: Some(BTreeMap::from([("bot".to_string(), bot.clone()), ("shop".to_string(), news.clone())])), ..Default::default() };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_n"), &swap, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(resp.nip05_names.keys().collect::<Vec<_>>(), vec!["bot", "shop"]);
+            assert_eq!(store.get_nip05_name_owner("news.nina").await.unwrap(), None);
+            assert_eq!(store.get_nip05_name_owner("shop").await.unwrap().as_deref(), Some("nina"));
+            let three = SettingsRequest {
+                nip05_names: Some(BTreeMap::from([("a1".to_string(), bot.clone()), ("a2".to_string(), bot.clone()), ("a3".to_string(), bot.clone())])),
+                ..Default::default()
+            };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_n"), &three, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+            store
+                .put_ban(&BanRecord { username: "spam".into(), banned_at: "2026-01-01T00:00:00.000Z".into(), reason: None })
+                .await
+                .unwrap();
+            let taken = SettingsRequest { nip05_names: Some(BTreeMap::from([("spam".to_string(), bot.clone())])), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_n"), &taken, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 409);
+
+            // Once the rental is released its names are free again
+            assert!(check_username(&store, "bot", GRACE, NOW + (43200 + GRACE) * MINUTE).await.unwrap().available);
+        });
+    }
+
+    #[test]
+    fn test_update_settings_writes_nothing_until_checked() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let (bot, news) = ("b".repeat(64), "c".repeat(64));
+        block_on(async {
+            let mut body = order_request("nell");
+            body.services.as_mut().unwrap().nip05.as_mut().unwrap().names = BTreeMap::from([("bot".to_string(), bot.clone())]);
+            let mut order = create_order(&store, body, "ord_n".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_n"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_n", "h", "mgmt_n".into(), NOW).await.unwrap();
+            store
+                .put_ban(&BanRecord { username: "spam".into(), banned_at: "2026-01-01T00:00:00.000Z".into(), reason: None })
+                .await
+                .unwrap();
+            let auth = RentalAuth::Token("mgmt_n");
+            let subdomain = serde_json::json!({"type": "CNAME", "target": "new.example.com"});
+
+            // A taken name fails the request before the DNS record is touched
+            let body: SettingsRequest =
+                serde_json::from_value(serde_json::json!({"nip05_names": {"spam": bot}, "subdomain": subdomain})).unwrap();
+            let err = update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err();
+            assert_eq!(err.status, 409);
+            assert!(dns.updated.borrow().is_empty());
+
+            // A failed DNS update leaves the rental and the name index as they were
+            dns.fail_updates.set(true);
+            let body: SettingsRequest =
+                serde_json::from_value(serde_json::json!({"nip05_names": {"shop": news}, "subdomain": subdomain})).unwrap();
+            let err = update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err();
+            assert_eq!(err.status, 502);
+            assert_eq!(store.get_nip05_name_owner("shop").await.unwrap(), None);
+            assert_eq!(store.get_nip05_name_owner("bot").await.unwrap().as_deref(), Some("nell"));
+            let rental = store.get_rental("nell").await.unwrap().unwrap();
+            assert_eq!(rental.services.nip05.unwrap().names.keys().collect::<Vec<_>>(), vec!["bot"]);
+            assert_ne!(rental.services.subdomain.unwrap().target, "new.example.com");
+
+            dns.fail_updates.set(false);
+            update_settings(&store, &dns, auth, &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(store.get_nip05_name_owner("shop").await.unwrap().as_deref(), Some("nell"));
+            assert_eq!(store.get_nip05_name_owner("bot").await.unwrap(), None);
+            assert_eq!(store.get_rental("nell").await.unwrap().unwrap().services.subdomain.unwrap().target, "new.example.com");
+        });
+    }
+
+    #[test]
+    fn test_update_settings_rejects_suspended_rental() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("sami"), "ord_s".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_s"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_s", "h", "mgmt_s".into(), NOW).await.unwrap();
+            let mut rental = store.get_rental("sami").await.unwrap().unwrap();
+            rental.status = RentalStatus::Suspended;
+            store.put_rental(&rental).await.unwrap();
+
+            let body: SettingsRequest = serde_json::from_value(serde_json::json!({
+                "webhook_url": "https://new.example.com/hook",
+                "subdomain": {"type": "CNAME", "target": "new.example.com"}
+            }))
+            .unwrap();
+            let err = update_settings(&store, &dns, RentalAuth::Token("mgmt_s"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+            assert!(dns.updated.borrow().is_empty());
+            let rental = store.get_rental("sami").await.unwrap().unwrap();
+            assert_eq!(rental.webhook_url.as_deref(), Some("https://example.com/hook"));
+            assert_ne!(rental.services.subdomain.unwrap().target, "new.example.com");
+        });
+    }
+
+    #[test]
+    fn test_update_settings_webhook_endpoints() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let endpoint = |url: &str, events: Vec<WebhookEvent>| WebhookEndpoint { url: url.into(), events };
+        block_on(async {
+            let mut order = create_order(&store, order_request("gail"), "ord_g".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_g"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_g", "h", "mgmt_g".into(), NOW).await.unwrap();
+
+            let body = SettingsRequest {
+                webhooks: Some(vec![
+                    endpoint(" https://mail.example.com ", vec![WebhookEvent::EmailReceived, WebhookEvent::EmailReceived]),
+                    endpoint("https://billing.example.com", vec![WebhookEvent::PaymentCompleted, WebhookEvent::Renewed]),
+                ]),
+                ..Default::default()
+            };
+            let resp = update_settings(&store, &dns, RentalAuth::Token("mgmt_g"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(resp.webhooks.len(), 2);
+            assert_eq!(resp.webhooks[0], endpoint("https://mail.example.com", vec![WebhookEvent::EmailReceived]));
+            let rental = store.get_rental("gail").await.unwrap().unwrap();
+            assert_eq!(rental.webhooks, resp.webhooks);
+            assert_eq!(rental.webhook_url.as_deref(), Some("https://example.com/hook"));
+
+            // Rejected lists leave the stored endpoints alone
+            let bad = [
+                vec![endpoint("ftp://example.com", vec![WebhookEvent::DnsChanged])],
+                vec![endpoint("https://10.0.0.1/hook", vec![WebhookEvent::DnsChanged])],
+                vec![endpoint("https://example.com", Vec::new())],
+                vec![
+                    endpoint("https://example.com", vec![WebhookEvent::DnsChanged]),
+                    endpoint("https://example.com", vec![WebhookEvent::Renewed]),
+                ],
+                vec![endpoint("https://example.com", vec![WebhookEvent::DnsChanged]); MAX_WEBHOOK_ENDPOINTS + 1],
+            ];
+            for webhooks in bad {
+                let body = SettingsRequest { webhooks: Some(webhooks), ..Default::default() };
+                assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_g"), &body, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+            }
+            assert_eq!(store.get_rental("gail").await.unwrap().unwrap().webhooks.len(), 2);
+
+            let clear = SettingsRequest { webhooks: Some(Vec::new()), ..Default::default() };
+            update_settings(&store, &dns, RentalAuth::Token("mgmt_g"), &clear, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert!(store.get_rental("gail").await.unwrap().unwrap().webhooks.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_nostr_dm_opt_in() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut request = order_request("hank");
+            request.nostr_dm = true;
+            request.services.as_mut().unwrap().nip05 = None;
+            let err = create_order(&store, request, "ord_h".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 400);
+
+            let mut request = order_request("hank");
+            request.nostr_dm = true;
+            let mut order = create_order(&store, request, "ord_h".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            assert_eq!(order.dm_recipient(), Some("a".repeat(64).as_str()));
+            attach_invoice(&store, &mut order, invoice("sec_h"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_h", "h", "mgmt_h".into(), NOW).await.unwrap();
+            let mut rental = store.get_rental("hank").await.unwrap().unwrap();
+            assert_eq!(rental.dm_recipient(), Some("a".repeat(64).as_str()));
+
+            let off = SettingsRequest { nostr_dm: Some(false), ..Default::default() };
+            assert!(!update_settings(&store, &dns, RentalAuth::Token("mgmt_h"), &off, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap().nostr_dm);
+            assert_eq!(store.get_rental("hank").await.unwrap().unwrap().dm_recipient(), None);
+
+            // Turning DMs on needs a NIP-05 pubkey to send them to
+            rental.nostr_dm = false;
+            rental.services.nip05 = None;
+            store.put_rental(&rental).await.unwrap();
+            let on = SettingsRequest { nostr_dm: Some(true), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_h"), &on, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 400);
+            assert!(!store.get_rental("hank").await.unwrap().unwrap().nostr_dm);
+        });
+    }
+
+    #[test]
+    fn test_owner_pubkey_auth() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        use sha2::Digest;
+        let owner = k256::schnorr::SigningKey::from_bytes(&[3u8; 32]).unwrap();
+        let owner_hex = hex::encode(owner.verifying_key().to_bytes());
+        block_on(async {
+            let mut request = order_request("iris");
+            request.owner_pubkey = Some("not-a-key".into());
+            let err = create_order(&store, request, "ord_i".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 400);
+
+            let mut request = order_request("iris");
+            request.owner_pubkey = Some(owner_hex.to_uppercase());
+            let mut order = create_order(&store, request, "ord_i".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_i"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_i", "h", "mgmt_i".into(), NOW).await.unwrap();
+            assert_eq!(store.get_rental("iris").await.unwrap().unwrap().owner_pubkey.as_deref(), Some(owner_hex.as_str()));
+
+            // A signed request with a fresh challenge yields the signer's pubkey, once
+            let url = "https://noscha.io/api/settings/iris";
+            let body = br#"{"nostr_dm":true}"#;
+            crate::admin::issue_challenge(&store, "ch_owner".into(), NOW).await.unwrap();
+            let mut event = NostrEvent {
+                id: None,
+                pubkey: String::new(),
+                created_at: Some(NOW / 1000),
+                kind: Some(crate::nostr::HTTP_AUTH_KIND),
+                tags: Some(vec![
+                    vec!["u".into(), url.into()],
+                    vec!["method".into(), "PUT".into()],
+                    vec!["payload".into(), hex::encode(sha2::Sha256::digest(body))],
+                    vec!["challenge".into(), "ch_owner".into()],
+                ]),
+                content: String::new(),
+                sig: None,
+            };
+            crate::nostr::sign_event(&mut event, &owner, &[0u8; 32]).unwrap();
+            let header = format!("Nostr {}", crate::ids::base64(serde_json::to_string(&event).unwrap().as_bytes()));
+            let pubkey = verify_owner_auth(&store, &header, url, "PUT", body, NOW).await.unwrap();
+            assert_eq!(pubkey, owner_hex);
+            assert_eq!(verify_owner_auth(&store, &header, url, "PUT", body, NOW).await.unwrap_err().status, 401);
+            assert_eq!(verify_owner_auth(&store, "Bearer x", url, "PUT", body, NOW).await.unwrap_err().status, 401);
+
+            let owner_auth = RentalAuth::Owner { username: "iris", pubkey: &pubkey };
+            let stranger = "b".repeat(64);
+            assert!(find_authorized_rental(&store, owner_auth).await.is_ok());
+            let err = find_authorized_rental(&store, RentalAuth::Owner { username: "iris", pubkey: &stranger }).await.unwrap_err();
+            assert_eq!(err.status, 403);
+            let err = find_authorized_rental(&store, RentalAuth::Owner { username: "nobody", pubkey: &pubkey }).await.unwrap_err();
+            assert_eq!(err.status, 404);
+
+            // Renewal picks owner auth only together with a username
+            let renew = RenewRequest { management_token: String::new(), username: Some("iris".into()), plan: Plan::SevenDays, services: None };
+            assert_eq!(renewal_auth(&renew, Some(&pubkey)).unwrap(), owner_auth);
+            assert_eq!(renewal_auth(&renew, None).unwrap_err().status, 400);
+            let (rental, _) = renewal_quote(&store, owner_auth, &renew).await.unwrap();
+            assert_eq!(rental.username, "iris");
+
+            // The owner can hand the rental to another key or clear it
+            let handover = SettingsRequest { owner_pubkey: Some(Some(stranger.clone())), ..Default::default() };
+            let response = update_settings(&store, &dns, owner_auth, &handover, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap();
+            assert_eq!(response.owner_pubkey.as_deref(), Some(stranger.as_str()));
+            assert_eq!(update_settings(&store, &dns, owner_auth, &handover, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 403);
+            let clear = SettingsRequest { owner_pubkey: Some(None), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_i"), &clear, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap().owner_pubkey, None);
+        });
+    }
+
+    #[test]
+    fn test_expire_rentals_deletes_dns() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("gina"), "ord_g".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_g"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_g", "h", "mgmt_g".into(), NOW).await.unwrap();
+
+            let report = expire_rentals(&store, &dns, 0, NOW + MINUTE).await.unwrap();
+            assert!(report.expired.is_empty());
+
+            let report = expire_rentals(&store, &dns, 0, NOW + 43200 * MINUTE).await.unwrap();
+            assert_eq!(report.expired, vec!["gina".to_string()]);
+            assert_eq!(*dns.deleted.borrow(), vec!["rec_1".to_string()]);
+            assert_eq!(store.get_rental("gina").await.unwrap().unwrap().status, RentalStatus::Expired);
+        });
+    }
+
+    /// Lapsed rentals wait out the grace period; only the owner can bring them back
+    #[test]
+    fn test_grace_period() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("gwen"), "ord_g".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_g"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_g", "h", "mgmt_g".into(), NOW).await.unwrap();
+
+            let expiry = NOW + 43200 * MINUTE;
+            let report = expire_rentals(&store, &dns, GRACE, expiry).await.unwrap();
+            assert!(report.expired.is_empty());
+            assert_eq!(report.grace.len(), 1);
+            assert_eq!(report.dns_removed.len(), 1);
+            let rental = store.get_rental("gwen").await.unwrap().unwrap();
+            assert_eq!(rental.status, RentalStatus::Grace);
+            assert_eq!(rental.grace_until, Some(iso_from_ms(expiry + GRACE * MINUTE)));
+            assert!(!rental.is_serving(expiry));
+            assert_eq!(*dns.deleted.borrow(), vec!["rec_1".to_string()]);
+            assert_eq!(rental.services.subdomain.as_ref().unwrap().cf_record_id, None);
+
+            // Nobody else can take the name during grace
+            assert!(!check_username(&store, "gwen", GRACE, expiry + MINUTE).await.unwrap().available);
+            let err = create_order(&store, order_request("gwen"), "ord_x".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, expiry + MINUTE)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status, 409);
+            // A second sweep during grace changes nothing
+            let report = expire_rentals(&store, &dns, GRACE, expiry + MINUTE).await.unwrap();
+            assert!(report.grace.is_empty() && report.expired.is_empty());
+
+            // The owner renews: back to active from now, DNS restored
+            let renew = RenewRequest { management_token: "mgmt_g".into(), username: None, plan: Plan::OneDay, services: None };
+            let (rental, amount) = renewal_quote(&store, renewal_auth(&renew, None).unwrap(), &renew).await.unwrap();
+            let order = create_renewal_order(&store, &rental, Plan::OneDay, amount, "ord_r".into(), invoice("sec_r"), expiry + MINUTE)
+                .await
+                .unwrap();
+            let provisioned = provision_paid_order(&store, &dns, order, String::new(), expiry + 2 * MINUTE).await.unwrap().unwrap();
+            assert!(provisioned.is_renewal && provisioned.dns_restored);
+            let rental = store.get_rental("gwen").await.unwrap().unwrap();
+            assert_eq!(rental.status, RentalStatus::Active);
+            assert_eq!(rental.grace_until, None);
+            assert_eq!(rental.expires_at, iso_from_ms(expiry + 2 * MINUTE + 1440 * MINUTE));
+            assert_eq!(rental.services.subdomain.as_ref().unwrap().cf_record_id.as_deref(), Some("rec_2"));
+        });
+    }
+
+    #[test]
+    fn test_grace_ends_and_name_is_released() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("hank"), "ord_h".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_h"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_h", "h", "mgmt_h".into(), NOW).await.unwrap();
+
+            // Renewal started by the old owner before the name was released
+            let renew = RenewRequest { management_token: "mgmt_h".into(), username: None, plan: Plan::OneDay, services: None };
+            let (old, amount) = renewal_quote(&store, renewal_auth(&renew, None).unwrap(), &renew).await.unwrap();
+            let stale = create_renewal_order(&store, &old, Plan::OneDay, amount, "ord_s".into(), invoice("sec_s"), NOW)
+                .await
+                .unwrap();
+
+            let expiry = NOW + 43200 * MINUTE;
+            expire_rentals(&store, &dns, GRACE, expiry).await.unwrap();
+            let released = expiry + GRACE * MINUTE;
+            let report = expire_rentals(&store, &dns, GRACE, released).await.unwrap();
+            assert_eq!(report.expired, vec!["hank".to_string()]);
+            assert!(check_username(&store, "hank", GRACE, released).await.unwrap().available);
+
+            // Someone else registers the name; the old token stops resolving
+            let mut order = create_order(&store, order_request("hank"), "ord_n".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, released)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_n"), released).await.unwrap();
+            apply_payment(&store, &dns, "sec_n", "h", "mgmt_new".into(), released).await.unwrap();
+            assert!(find_rental_by_token(&store, "mgmt_h").await.unwrap().is_none());
+            assert_eq!(renewal_quote(&store, renewal_auth(&renew, None).unwrap(), &renew).await.unwrap_err().status, 404);
+
+            // The old owner's stale renewal must not extend the new owner's rental
+            assert!(provision_paid_order(&store, &dns, stale, String::new(), released).await.unwrap().is_none());
+            let rental = store.get_rental("hank").await.unwrap().unwrap();
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_new"));
+            assert_eq!(rental.expires_at, iso_from_ms(released + 43200 * MINUTE));
+        });
+    }
+
+    #[test]
+    fn test_expiry_reminders_fire_once_per_threshold() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let thresholds = reminder_thresholds_from(None);
+        block_on(async {
+            let mut order = create_order(&store, order_request("ivy"), "ord_i".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_i"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_i", "h", "mgmt_i".into(), NOW).await.unwrap();
+            // Rentals without a webhook or DMs are never reminded
+            let mut order = create_order(&store, order_request("jay"), "ord_j".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_j"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_j", "h", "mgmt_j".into(), NOW).await.unwrap();
+            let mut jay = store.get_rental("jay").await.unwrap().unwrap();
+            jay.webhook_url = None;
+            store.put_rental(&jay).await.unwrap();
+
+            let expiry = NOW + 43200 * MINUTE;
+            assert!(collect_expiry_reminders(&store, &thresholds, NOW).await.unwrap().is_empty());
+
+            let due = collect_expiry_reminders(&store, &thresholds, expiry - 7 * 1440 * MINUTE).await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].rental.username, "ivy");
+            assert_eq!(due[0].threshold_minutes, 7 * 1440);
+            assert!(collect_expiry_reminders(&store, &thresholds, expiry - 2 * 1440 * MINUTE).await.unwrap().is_empty());
+
+            // A missed sweep crossing both remaining thresholds sends only the nearest
+            let due = collect_expiry_reminders(&store, &thresholds, expiry - 30 * MINUTE).await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].threshold_minutes, 60);
+            assert!(collect_expiry_reminders(&store, &thresholds, expiry - MINUTE).await.unwrap().is_empty());
+            assert!(collect_expiry_reminders(&store, &thresholds, expiry).await.unwrap().is_empty());
+
+            // Extending the rental re-arms the reminders for the new expiry
+            let mut rental = store.get_rental("ivy").await.unwrap().unwrap();
+            assert_eq!(rental.reminders_sent.len(), 3);
+            rental.expires_at = iso_from_ms(expiry + 43200 * MINUTE);
+            store.put_rental(&rental).await.unwrap();
+            let due = collect_expiry_reminders(&store, &thresholds, expiry + 43200 * MINUTE - 1440 * MINUTE).await.unwrap();
+            assert_eq!(due[0].threshold_minutes, 1440);
+            assert_eq!(store.get_rental("ivy").await.unwrap().unwrap().reminders_sent.len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_expiry_reminders_reach_dm_only_rentals() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let thresholds = reminder_thresholds_from(None);
+        block_on(async {
+            let mut order = create_order(&store, order_request("dee"), "ord_d".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_d"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_d", "h", "mgmt_d".into(), NOW).await.unwrap();
+            let mut dee = store.get_rental("dee").await.unwrap().unwrap();
+            dee.webhook_url = None;
+            dee.nostr_dm = true;
+            store.put_rental(&dee).await.unwrap();
+
+            let due = collect_expiry_reminders(&store, &thresholds, NOW + (43200 - 1440) * MINUTE).await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].rental.username, "dee");
+            assert!(due[0].rental.webhook_targets(&[WebhookEvent::ExpiringSoon]).is_empty());
+        });
+    }
+
+    #[test]
+    fn test_reminder_thresholds_skip_plans_shorter_than_threshold() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut body = order_request("kim");
+            body.plan = Plan::OneDay;
+            let mut order = create_order(&store, body, "ord_k".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_k"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_k", "h", "mgmt_k".into(), NOW).await.unwrap();
+
+            let thresholds = reminder_thresholds_from(None);
+            assert!(collect_expiry_reminders(&store, &thresholds, NOW + MINUTE).await.unwrap().is_empty());
+            let due = collect_expiry_reminders(&store, &thresholds, NOW + 1400 * MINUTE).await.unwrap();
+            assert_eq!(due[0].threshold_minutes, 60);
+        });
+    }
+
+    #[test]
+    fn test_reminder_thresholds_from() {
+        assert_eq!(reminder_thresholds_from(None), vec![10080, 1440, 60]);
+        assert_eq!(reminder_thresholds_from(Some("1h, 3d,1h,bogus,30m")), vec![4320, 60, 30]);
+        assert!(reminder_thresholds_from(Some("")).is_empty());
+    }
+
+    #[test]
+    fn test_grace_minutes_from() {
+        assert_eq!(grace_minutes_from(None), DEFAULT_GRACE_MINUTES);
+        assert_eq!(grace_minutes_from(Some(" 60 ")), 60);
+        assert_eq!(grace_minutes_from(Some("0")), 0);
+        assert_eq!(grace_minutes_from(Some("soon")), DEFAULT_GRACE_MINUTES);
+    }
+
+    #[test]
+    fn test_extended_expiry() {
+        // Active: added on top of current expiry
+        assert_eq!(
+            extended_expiry("2026-02-11T13:00:00.000Z", NOW, 60),
+            "2026-02-11T14:00:00.000Z"
+        );
+        // Already expired: added from now
+        assert_eq!(
+            extended_expiry("2026-02-01T00:00:00.000Z", NOW, 60),
+            "2026-02-11T13:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_token_index_lookup_and_rotation() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("hana"), "ord_h".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_h"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_h", "h", "mgmt_h1".into(), NOW).await.unwrap();
+            assert_eq!(store.get_token_username("mgmt_h1").await.unwrap().as_deref(), Some("hana"));
+
+            let mut rental = find_rental_by_token(&store, "mgmt_h1").await.unwrap().unwrap();
+            rotate_management_token(&store, &mut rental, "mgmt_h2".into()).await.unwrap();
+            assert!(find_rental_by_token(&store, "mgmt_h1").await.unwrap().is_none());
+            assert_eq!(find_rental_by_token(&store, "mgmt_h2").await.unwrap().unwrap().username, "hana");
+
+            // A stale index entry pointing at a rental with a different token does not match
+            store.put_token_index("mgmt_h1", "hana").await.unwrap();
+            assert!(find_rental_by_token(&store, "mgmt_h1").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_rotate_token() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("joan"), "ord_j".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_j"), NOW).await.unwrap();
+            apply_payment(&store, &dns, "sec_j", "h", "mgmt_j1".into(), NOW).await.unwrap();
+
+            let rental = rotate_token(&store, RentalAuth::Token("mgmt_j1"), "mgmt_j2".into()).await.unwrap();
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_j2"));
+            assert_eq!(rotate_token(&store, RentalAuth::Token("mgmt_j1"), "mgmt_j3".into()).await.unwrap_err().status, 404);
+            let settings = SettingsRequest { nostr_dm: Some(false), ..Default::default() };
+            assert_eq!(update_settings(&store, &dns, RentalAuth::Token("mgmt_j1"), &settings, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.unwrap_err().status, 404);
+            assert!(update_settings(&store, &dns, RentalAuth::Token("mgmt_j2"), &settings, UrlPolicy::PRODUCTION, GRACE, NOW, String::new).await.is_ok());
+
+            // Shut-down rentals keep their token out of the index
+            let mut rental = store.get_rental("joan").await.unwrap().unwrap();
+            rental.status = RentalStatus::Revoked;
+            store.put_rental(&rental).await.unwrap();
+            assert_eq!(reissue_management_token(&store, &mut rental, "mgmt_j4".into()).await.unwrap_err().status, 409);
+            assert_eq!(store.get_token_username("mgmt_j4").await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_backfill_token_index() {
+        let store = MemoryStore::with_page_size(1);
+        block_on(async {
+            for name in ["ivan", "jane"] {
+                let rental = Rental {
+                    username: name.into(),
+                    status: RentalStatus::Active,
+                    created_at: iso_from_ms(NOW),
+                    expires_at: iso_from_ms(NOW + MINUTE),
+                    plan: Plan::OneDay,
+                    services: RentalServices { email: None, subdomain: None, nip05: None, lightning_address: None },
+                    management_token: Some(format!("mgmt_{}", name)),
+                    webhook_url: None,
+                    grace_until: None,
+                    reminders_sent: Vec::new(),
+                    signing_secret: None,
+                    webhooks: Vec::new(),
+                    nostr_dm: false,
+                    owner_pubkey: None,
+                };
+                store.put_rental(&rental).await.unwrap();
+            }
+            assert!(find_rental_by_token(&store, "mgmt_jane").await.unwrap().is_none());
+
+            assert_eq!(backfill_token_index(&store).await.unwrap(), 2);
+            assert_eq!(find_rental_by_token(&store, "mgmt_jane").await.unwrap().unwrap().username, "jane");
+            // Idempotent
+            assert_eq!(backfill_token_index(&store).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_invoice_index_lookup() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let mut order = create_order(&store, order_request("kate"), "ord_k".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_k"), NOW).await.unwrap();
+            assert_eq!(store.get_invoice_order_id("sec_k").await.unwrap().as_deref(), Some("ord_k"));
+            assert_eq!(store.get_invoice_order_id("hash1").await.unwrap().as_deref(), Some("ord_k"));
+            assert_eq!(find_order_by_invoice_hash(&store, "hash1").await.unwrap().unwrap().order_id, "ord_k");
+            assert!(find_pending_order_by_secret(&store, "sec_k").await.unwrap().is_some());
+
+            // Lookup goes through the index only: an unindexed order is not found by scanning
+            store.delete(&crate::store::invoice_key("sec_k")).await.unwrap();
+            assert!(find_pending_order_by_secret(&store, "sec_k").await.unwrap().is_none());
+
+            // An index entry whose order does not carry the secret is ignored
+            store.put_invoice_index("sec_forged", "ord_k").await.unwrap();
+            assert!(find_pending_order_by_secret(&store, "sec_forged").await.unwrap().is_none());
+        });
+    }
+
+    /// LNbits-style webhook: unsigned, so payment is confirmed against the backend
+    #[test]
+    fn test_payment_webhook_confirms_with_backend() {
+        use crate::lnbits::LnbitsProvider;
+        use crate::payment::stand_in::StandInServer;
+        use crate::payment::{CreatedInvoice, InvoiceRequest};
+
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let server = StandInServer::new();
+        let provider = LnbitsProvider::new(&server, "https://lnbits.test".into(), "key".into());
+        block_on(async {
+            let mut order = create_order(&store, order_request("lena"), "ord_l".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            let CreatedInvoice { bolt11, payment_id } = provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: order.amount_sats,
+                    memo: "noscha.io lena",
+                    webhook_url: "https://noscha.io/api/webhook/lnbits",
+                    secret: "sec_l",
+                    expiry_secs: 900,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            let hash = payment_id.unwrap();
+            let details = InvoiceDetails { bolt11, hash: Some(hash.clone()), webhook_secret: "sec_l".into() };
+            attach_invoice(&store, &mut order, details, NOW).await.unwrap();
+
+            let webhook = |secret: &str, hash: &str| InboundRequest {
+                url: format!("https://noscha.io/api/webhook/lnbits?secret={}", secret),
+                headers: vec![],
+                body: serde_json::json!({ "payment_hash": hash }).to_string(),
+            };
+
+            // Not settled yet
+            let result = apply_payment_webhook(&store, &dns, &provider, &webhook("sec_l", &hash), "mgmt_l".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::NotPaid));
+
+            // A different (paid) invoice cannot be used to settle this order
+            server.settle(&hash);
+            let result = apply_payment_webhook(&store, &dns, &provider, &webhook("sec_l", "other"), "mgmt_l".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::NoMatchingOrder));
+            let result = apply_payment_webhook(&store, &dns, &provider, &webhook("sec_bad", &hash), "mgmt_l".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::NoMatchingOrder));
+
+            let result = apply_payment_webhook(&store, &dns, &provider, &webhook("sec_l", &hash), "mgmt_l".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(_)));
+            assert_eq!(store.get_rental("lena").await.unwrap().unwrap().management_token.as_deref(), Some("mgmt_l"));
+
+            let ignored = InboundRequest { body: "{}".into(), ..webhook("sec_l", &hash) };
+            let result = apply_payment_webhook(&store, &dns, &provider, &ignored, "x".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::Ignored));
+            let bad = InboundRequest { body: "nope".into(), ..ignored };
+            let err = apply_payment_webhook(&store, &dns, &provider, &bad, "x".into(), NOW).await.unwrap_err();
+            assert_eq!(err.status, 400);
+        });
+    }
+
+    /// coinos-style webhook: the per-order secret is proof, no status call is made
+    #[test]
+    fn test_payment_webhook_trusted_payload() {
+        use crate::coinos::CoinosProvider;
+        use crate::payment::stand_in::StandInServer;
+
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let server = StandInServer::new();
+        let provider = CoinosProvider::new(&server, "tok".into());
+        block_on(async {
+            let mut order = create_order(&store, order_request("cora"), "ord_c".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_c"), NOW).await.unwrap();
+            let req = InboundRequest {
+                url: "https://noscha.io/api/webhook/coinos".into(),
+                headers: vec![],
+                body: r#"{"confirmed":true,"secret":"sec_c","hash":"hash1"}"#.into(),
+            };
+            let result = apply_payment_webhook(&store, &dns, &provider, &req, "mgmt_c".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(_)));
+            assert!(server.requests.borrow().is_empty());
+
+            let unconfirmed = InboundRequest { body: r#"{"confirmed":false,"secret":"sec_c"}"#.into(), ..req };
+            let result = apply_payment_webhook(&store, &dns, &provider, &unconfirmed, "x".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::Ignored));
+        });
+    }
+
+    /// The payment hash is public, so a coinos notice without the secret is
+    /// checked with the backend rather than trusted
+    #[test]
+    fn test_payment_webhook_forged_hash_only_notice() {
+        use crate::coinos::CoinosProvider;
+        use crate::payment::stand_in::StandInServer;
+        use crate::payment::{CreatedInvoice, InvoiceRequest};
+
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let server = StandInServer::new();
+        let provider = CoinosProvider::new(&server, "tok".into());
+        block_on(async {
+            let mut order = create_order(&store, order_request("fay"), "ord_f".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            let CreatedInvoice { bolt11, payment_id } = provider
+                .create_invoice(&InvoiceRequest {
+                    amount_sats: order.amount_sats,
+                    memo: "noscha.io fay",
+                    webhook_url: "https://noscha.io/api/webhook/coinos",
+                    secret: "sec_f",
+                    expiry_secs: 900,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            let hash = payment_id.unwrap();
+            let details = InvoiceDetails { bolt11, hash: Some(hash.clone()), webhook_secret: "sec_f".into() };
+            attach_invoice(&store, &mut order, details, NOW).await.unwrap();
+
+            let forged = InboundRequest {
+                url: "https://noscha.io/api/webhook/coinos".into(),
+                headers: vec![],
+                body: serde_json::json!({ "confirmed": true, "hash": hash }).to_string(),
+            };
+            let result = apply_payment_webhook(&store, &dns, &provider, &forged, "mgmt_f".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::NotPaid));
+            assert_eq!(store.get_order("ord_f").await.unwrap().unwrap().status, OrderStatus::Pending);
+            assert!(store.get_rental("fay").await.unwrap().is_none());
+
+            // Once the backend reports the invoice paid, the same notice settles it
+            server.settle(&hash);
+            let result = apply_payment_webhook(&store, &dns, &provider, &forged, "mgmt_f".into(), NOW).await.unwrap();
+            assert!(matches!(result, PaymentResult::Provisioned(_)));
+        });
+    }
+
+    /// Pending order provisioned by the cron poll; a late webhook is then a no-op
+    #[test]
+    fn test_poll_pending_orders_is_idempotent_with_webhook() {
+        use crate::coinos::CoinosProvider;
+        use crate::payment::stand_in::StandInServer;
+        use crate::payment::PaymentProvider;
+
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let server = StandInServer::new();
+        let provider = CoinosProvider::new(&server, "tok".into());
+        block_on(async {
+            let mut order = create_order(&store, order_request("polly"), "ord_p".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            let created = provider
+                .create_invoice(&crate::payment::InvoiceRequest {
+                    amount_sats: order.amount_sats,
+                    memo: "",
+                    webhook_url: "https://noscha.io/api/webhook/coinos",
+                    secret: "sec_p",
+                    expiry_secs: 900,
+                    description_hash: None,
+                })
+                .await
+                .unwrap();
+            let hash = created.payment_id.unwrap();
+            let details = InvoiceDetails { bolt11: created.bolt11, hash: Some(hash.clone()), webhook_secret: "sec_p".into() };
+            attach_invoice(&store, &mut order, details, NOW).await.unwrap();
+            assert_eq!(store.list_pending_order_ids().await.unwrap(), vec!["ord_p"]);
+
+            let mut tokens = vec!["mgmt_p2".to_string(), "mgmt_p1".to_string()];
+            let report = poll_pending_orders(&store, &dns, &provider, || tokens.pop().unwrap(), NOW + MINUTE)
+                .await
+                .unwrap();
+            assert!(report.provisioned.is_empty() && report.dropped.is_empty());
+            assert_eq!(store.get_order("ord_p").await.unwrap().unwrap().status, OrderStatus::Pending);
+
+            server.settle(&hash);
+            let report = poll_pending_orders(&store, &dns, &provider, || tokens.pop().unwrap(), NOW + 2 * MINUTE)
+                .await
+                .unwrap();
+            assert_eq!(report.provisioned.len(), 1);
+            assert_eq!(report.provisioned[0].rental.management_token.as_deref(), Some("mgmt_p2"));
+            assert!(store.list_pending_order_ids().await.unwrap().is_empty());
+            assert_eq!(dns.created.borrow().len(), 1);
+
+            // The webhook arriving afterwards must not provision again
+            let result = apply_payment(&store, &dns, "sec_p", &hash, "mgmt_late".into(), NOW + 3 * MINUTE)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::NoMatchingOrder));
+            // Nor does a status poll holding a stale copy of the order
+            let result = poll_order_payment(&store, &dns, &provider, &order, "mgmt_stale".into(), NOW)
+                .await
+                .unwrap();
+            assert!(matches!(result, PaymentResult::NoMatchingOrder));
+            let rental = store.get_rental("polly").await.unwrap().unwrap();
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_p2"));
+            assert_eq!(dns.created.borrow().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_poll_skips_unconfirmed_and_reports_errors() {
+        use crate::payment::stand_in::StandInServer;
+        use crate::payment::MockProvider;
+
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        let server = StandInServer::new();
+        let provider = crate::lnbits::LnbitsProvider::new(&server, "https://lnbits.test".into(), "k".into());
+        block_on(async {
+            create_order(&store, order_request("una"), "ord_u".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            let mut order = create_order(&store, order_request("gina"), "ord_g".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_g"), NOW).await.unwrap();
+
+            // Status checks for an unknown invoice fail; both orders stay indexed
+            let report = poll_pending_orders(&store, &dns, &provider, || "t".into(), NOW).await.unwrap();
+            assert_eq!(report.errors.len(), 1);
+            assert!(report.dropped.is_empty());
+            assert_eq!(store.list_pending_order_ids().await.unwrap(), vec!["ord_g", "ord_u"]);
+
+            // Index entries for orders that are no longer open are cleaned up
+            store.put_pending_index("ord_gone").await.unwrap();
+            let report = poll_pending_orders(&store, &dns, &MockProvider, || "t".into(), NOW).await.unwrap();
+            assert_eq!(report.dropped, vec!["ord_gone"]);
+        });
+    }
+
+    #[test]
+    fn test_expire_stale_orders() {
+        let store = MemoryStore::new();
+        block_on(async {
+            create_order(&store, order_request("una"), "ord_u".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            let mut order = create_order(&store, order_request("pia"), "ord_p".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_p"), NOW + MINUTE).await.unwrap();
+
+            assert!(expire_stale_orders(&store, NOW + MINUTE).await.unwrap().is_empty());
+
+            // Unconfirmed orders expire with their challenge; unpaid ones wait out the poll grace
+            let after_ttl = NOW + ORDER_TTL_MS;
+            assert_eq!(expire_stale_orders(&store, after_ttl).await.unwrap(), vec!["ord_u"]);
+            let order = store.get_order("ord_u").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Expired);
+            assert_eq!(order.history[0].from, OrderStatus::WebhookPending);
+            assert_eq!(order.history[0].at, iso_from_ms(after_ttl));
+
+            let after_grace = NOW + ORDER_TTL_MS + PAYMENT_POLL_GRACE_MS + MINUTE;
+            assert_eq!(expire_stale_orders(&store, after_grace).await.unwrap(), vec!["ord_p"]);
+            let order = store.get_order("ord_p").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Expired);
+            let steps: Vec<_> = order.history.iter().map(|t| t.to.clone()).collect();
+            assert_eq!(steps, vec![OrderStatus::Pending, OrderStatus::Expired]);
+            assert!(store.list_pending_order_ids().await.unwrap().is_empty());
+
+            // A late webhook for an expired order is not applied
+            let dns = FakeDns::default();
+            let result = apply_payment(&store, &dns, "sec_p", "h", "t".into(), after_grace).await.unwrap();
+            assert!(matches!(result, PaymentResult::NoMatchingOrder));
+        });
+    }
+
+    /// Every status change along the happy path is recorded
+    #[test]
+    fn test_order_history_on_provision() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("hana"), "ord_h".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW)
+                .await
+                .unwrap();
+            attach_invoice(&store, &mut order, invoice("sec_h"), NOW).await.unwrap();
+            // Confirming twice is an illegal pending -> pending move
+            assert!(attach_invoice(&store, &mut order, invoice("sec_h2"), NOW).await.is_err());
+
+            apply_payment(&store, &dns, "sec_h", "h", "mgmt_h".into(), NOW + MINUTE).await.unwrap();
+            let order = store.get_order("ord_h").await.unwrap().unwrap();
+            let steps: Vec<_> = order.history.iter().map(|t| (t.from.clone(), t.to.clone())).collect();
+            assert_eq!(
+                steps,
+                vec![
+                    (OrderStatus::WebhookPending, OrderStatus::Pending),
+                    (OrderStatus::Pending, OrderStatus::Paid),
+                    (OrderStatus::Paid, OrderStatus::Provisioned),
+                ]
+            );
+        });
+    }
+
+    /// DNS failures leave the paid order marked failed rather than pending
+    #[test]
+    fn test_provision_failure_marks_order_failed() {
+        let store = MemoryStore::new();
+        let dns = FakeDns::default();
+        block_on(async {
+            let mut order = create_order(&store, order_request("ivy"), "ord_i".into(), "ch".into(), "whsec_test".into(), GRACE, UrlPolicy::PRODUCTION, NOW).await.unwrap();
+            // A record type the DNS layer rejects, as on orders stored before targets were validated
+            order.services_requested.as_mut().unwrap().subdomain.as_mut().unwrap().record_type = "MX".into();
+            attach_invoice(&store, &mut order, invoice("sec_i"), NOW).await.unwrap();
+
+            assert!(apply_payment(&store, &dns, "sec_i", "h", "t".into(), NOW).await.is_err());
+            let order = store.get_order("ord_i").await.unwrap().unwrap();
+            assert_eq!(order.status, OrderStatus::Failed);
+            assert!(order.history.last().unwrap().reason.is_some());
+            assert!(store.get_rental("ivy").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_create_order_applies_url_policy() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let create = |request: OrderRequest, policy: UrlPolicy| {
+                create_order(&store, request, "ord_p".into(), "ch".into(), "whsec_test".into(), GRACE, policy, NOW)
+            };
+            for url in ["http://example.com/hook", "https://127.0.0.1/hook", "https://169.254.169.254/", "https://example.com:8080/"] {
+                let mut request = order_request("pia");
+                request.webhook_url = url.into();
+                assert_eq!(create(request, UrlPolicy::PRODUCTION).await.unwrap_err().status, 400, "{}", url);
+            }
+            let mut request = order_request("pia");
+            request.webhook_url = "http://example.com/hook".into();
+            assert!(create(request, UrlPolicy { allow_http: true }).await.is_ok());
+
+            for (record_type, target) in [("A", "10.0.0.1"), ("AAAA", "::1"), ("CNAME", "localhost"), ("CNAME", "192.168.0.1"), ("MX", "example.com")] {
+                let mut request = order_request("quin");
+                let sub = request.services.as_mut().unwrap().subdomain.as_mut().unwrap();
+                sub.record_type = record_type.into();
+                sub.target = target.into();
+                assert_eq!(create(request, UrlPolicy::PRODUCTION).await.unwrap_err().status, 400, "{}", target);
+            }
+            assert!(store.get_order("ord_p").await.unwrap().is_some());
+        });
+    }
+}
@@ -0,0 +1,652 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::admin::{BanRecord, DebugWebhookConfig};
+use crate::types::*;
+
+/// Result type for storage operations (errors are human-readable messages)
+pub type StoreResult<T> = Result<T, String>;
+
+/// One page of keys returned by `Store::list`
+#[derive(Debug, Clone, Default)]
+pub struct ListPage {
+    pub keys: Vec<String>,
+    /// Pass back to `Store::list` to fetch the next page; None when exhausted
+    pub cursor: Option<String>,
+}
+
+pub fn order_key(order_id: &str) -> String {
+    format!("orders/{}.json", order_id)
+}
+
+pub fn rental_key(username: &str) -> String {
+    format!("rentals/{}.json", username)
+}
+
+pub fn ban_key(username: &str) -> String {
+    format!("bans/{}.json", username)
+}
+
+pub fn session_key(token: &str) -> String {
+    format!("sessions/{}.json", token)
+}
+
+pub fn challenge_key(challenge: &str) -> String {
+    format!("challenges/{}.json", challenge)
+}
+
+pub fn token_key(management_token: &str) -> String {
+    format!("tokens/{}.json", management_token)
+}
+
+/// Extra NIP-05 names are indexed to the rental that claims them
+pub fn nip05_name_key(name: &str) -> String {
+    format!("nip05names/{}.json", name)
+}
+
+/// Invoices are indexed by webhook secret and by payment hash
+pub fn invoice_key(secret_or_hash: &str) -> String {
+    format!("invoices/{}.json", secret_or_hash)
+}
+
+/// Lightning Address invoices, by the webhook secret they were created with
+pub fn lnurl_invoice_key(secret: &str) -> String {
+    format!("lnurl_invoices/{}.json", secret)
+}
+
+/// Marker for an order awaiting payment, polled by the cron fallback
+pub fn pending_key(order_id: &str) -> String {
+    format!("pending/{}.json", order_id)
+}
+
+pub fn delivery_key(username: &str, id: &str) -> String {
+    format!("deliveries/{}/{}.json", username, id)
+}
+
+/// Marker for a webhook delivery awaiting a retry from the cron
+pub fn delivery_queue_key(id: &str) -> String {
+    format!("delivery_queue/{}.json", id)
+}
+
+/// Index entry stored at delivery_queue/{id}.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeliveryRef {
+    pub username: String,
+    pub id: String,
+}
+
+/// Index entry stored at invoices/{secret_or_hash}.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InvoiceIndexEntry {
+    pub order_id: String,
+}
+
+/// Index entry stored at tokens/{management_token}.json and nip05names/{name}.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenIndexEntry {
+    pub username: String,
+}
+
+pub const PRICING_KEY: &str = "config/pricing.json";
+pub const DEBUG_WEBHOOK_KEY: &str = "config/debug_webhook.json";
+
+/// Key/value object storage used by all handlers.
+/// Implemented by `R2Store` on Workers and `MemoryStore` for native tests.
+#[allow(async_fn_in_trait)]
+pub trait Store {
+    async fn get(&self, key: &str) -> StoreResult<Option<Vec<u8>>>;
+    async fn put(&self, key: &str, value: Vec<u8>) -> StoreResult<()>;
+    async fn delete(&self, key: &str) -> StoreResult<()>;
+    /// List keys under prefix, one page at a time
+    async fn list(&self, prefix: &str, cursor: Option<String>) -> StoreResult<ListPage>;
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    /// List every key under prefix, following cursors across pages
+    async fn list_all(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self.list(prefix, cursor).await?;
+            keys.extend(page.keys);
+            match page.cursor {
+                Some(c) => cursor = Some(c),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, key: &str) -> StoreResult<Option<T>> {
+        match self.get(key).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| format!("Invalid JSON at {}: {}", key, e)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_json<T: Serialize>(&self, key: &str, value: &T) -> StoreResult<()> {
+        let json = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+        self.put(key, json).await
+    }
+
+    /// Load every object under prefix that parses as T, skipping invalid entries
+    async fn list_json<T: DeserializeOwned>(&self, prefix: &str) -> StoreResult<Vec<(String, T)>> {
+        let mut items = Vec::new();
+        for key in self.list_all(prefix).await? {
+            if let Ok(Some(item)) = self.get_json::<T>(&key).await {
+                items.push((key, item));
+            }
+        }
+        Ok(items)
+    }
+
+    async fn get_order(&self, order_id: &str) -> StoreResult<Option<Order>> {
+        self.get_json(&order_key(order_id)).await
+    }
+
+    async fn put_order(&self, order: &Order) -> StoreResult<()> {
+        self.put_json(&order_key(&order.order_id), order).await
+    }
+
+    async fn list_orders(&self) -> StoreResult<Vec<Order>> {
+        Ok(self.list_json("orders/").await?.into_iter().map(|(_, o)| o).collect())
+    }
+
+    async fn get_rental(&self, username: &str) -> StoreResult<Option<Rental>> {
+        self.get_json(&rental_key(username)).await
+    }
+
+    async fn put_rental(&self, rental: &Rental) -> StoreResult<()> {
+        self.put_json(&rental_key(&rental.username), rental).await
+    }
+
+    async fn list_rentals(&self) -> StoreResult<Vec<Rental>> {
+        Ok(self.list_json("rentals/").await?.into_iter().map(|(_, r)| r).collect())
+    }
+
+    /// Resolve a management token to its rental's username via the token index
+    async fn get_token_username(&self, management_token: &str) -> StoreResult<Option<String>> {
+        Ok(self
+            .get_json::<TokenIndexEntry>(&token_key(management_token))
+            .await?
+            .map(|e| e.username))
+    }
+
+    async fn put_token_index(&self, management_token: &str, username: &str) -> StoreResult<()> {
+        let entry = TokenIndexEntry { username: username.to_string() };
+        self.put_json(&token_key(management_token), &entry).await
+    }
+
+    async fn delete_token_index(&self, management_token: &str) -> StoreResult<()> {
+        self.delete(&token_key(management_token)).await
+    }
+
+    /// Resolve an extra NIP-05 name to the username of the rental claiming it
+    async fn get_nip05_name_owner(&self, name: &str) -> StoreResult<Option<String>> {
+        Ok(self
+            .get_json::<TokenIndexEntry>(&nip05_name_key(name))
+            .await?
+            .map(|e| e.username))
+    }
+
+    async fn put_nip05_name_index(&self, name: &str, username: &str) -> StoreResult<()> {
+        let entry = TokenIndexEntry { username: username.to_string() };
+        self.put_json(&nip05_name_key(name), &entry).await
+    }
+
+    async fn delete_nip05_name_index(&self, name: &str) -> StoreResult<()> {
+        self.delete(&nip05_name_key(name)).await
+    }
+
+    async fn get_lnurl_invoice(&self, secret: &str) -> StoreResult<Option<LnurlInvoice>> {
+        self.get_json(&lnurl_invoice_key(secret)).await
+    }
+
+    async fn put_lnurl_invoice(&self, invoice: &LnurlInvoice) -> StoreResult<()> {
+        self.put_json(&lnurl_invoice_key(&invoice.secret), invoice).await
+    }
+
+    /// Resolve an invoice webhook secret or payment hash to its order id
+    async fn get_invoice_order_id(&self, secret_or_hash: &str) -> StoreResult<Option<String>> {
+        Ok(self
+            .get_json::<InvoiceIndexEntry>(&invoice_key(secret_or_hash))
+            .await?
+            .map(|e| e.order_id))
+    }
+
+    async fn put_invoice_index(&self, secret_or_hash: &str, order_id: &str) -> StoreResult<()> {
+        let entry = InvoiceIndexEntry { order_id: order_id.to_string() };
+        self.put_json(&invoice_key(secret_or_hash), &entry).await
+    }
+
+    async fn put_pending_index(&self, order_id: &str) -> StoreResult<()> {
+        let entry = InvoiceIndexEntry { order_id: order_id.to_string() };
+        self.put_json(&pending_key(order_id), &entry).await
+    }
+
+    async fn delete_pending_index(&self, order_id: &str) -> StoreResult<()> {
+        self.delete(&pending_key(order_id)).await
+    }
+
+    /// Ids of all orders in the pending/ index
+    async fn list_pending_order_ids(&self) -> StoreResult<Vec<String>> {
+        Ok(self
+            .list_all("pending/")
+            .await?
+            .iter()
+            .filter_map(|k| k.strip_prefix("pending/")?.strip_suffix(".json"))
+            .map(str::to_string)
+            .collect())
+    }
+
+    async fn get_delivery(&self, username: &str, id: &str) -> StoreResult<Option<WebhookDelivery>> {
+        self.get_json(&delivery_key(username, id)).await
+    }
+
+    async fn put_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        self.put_json(&delivery_key(&delivery.username, &delivery.id), delivery).await
+    }
+
+    /// All deliveries recorded for a username, in no particular order
+    async fn list_deliveries(&self, username: &str) -> StoreResult<Vec<WebhookDelivery>> {
+        let prefix = format!("deliveries/{}/", username);
+        Ok(self.list_json(&prefix).await?.into_iter().map(|(_, d)| d).collect())
+    }
+
+    async fn delete_delivery(&self, username: &str, id: &str) -> StoreResult<()> {
+        self.delete(&delivery_key(username, id)).await
+    }
+
+    /// Every recorded delivery, for the retention sweep
+    async fn list_all_deliveries(&self) -> StoreResult<Vec<WebhookDelivery>> {
+        Ok(self.list_json("deliveries/").await?.into_iter().map(|(_, d)| d).collect())
+    }
+
+    async fn put_delivery_queue(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        let entry = DeliveryRef { username: delivery.username.clone(), id: delivery.id.clone() };
+        self.put_json(&delivery_queue_key(&delivery.id), &entry).await
+    }
+
+    async fn delete_delivery_queue(&self, id: &str) -> StoreResult<()> {
+        self.delete(&delivery_queue_key(id)).await
+    }
+
+    /// Deliveries in the delivery_queue/ index
+    async fn list_queued_deliveries(&self) -> StoreResult<Vec<DeliveryRef>> {
+        Ok(self.list_json("delivery_queue/").await?.into_iter().map(|(_, r)| r).collect())
+    }
+
+    async fn is_banned(&self, username: &str) -> StoreResult<bool> {
+        self.exists(&ban_key(username)).await
+    }
+
+    async fn put_ban(&self, ban: &BanRecord) -> StoreResult<()> {
+        self.put_json(&ban_key(&ban.username), ban).await
+    }
+
+    async fn delete_ban(&self, username: &str) -> StoreResult<()> {
+        self.delete(&ban_key(username)).await
+    }
+
+    async fn get_session(&self, token: &str) -> StoreResult<Option<AdminSession>> {
+        self.get_json(&session_key(token)).await
+    }
+
+    async fn put_session(&self, session: &AdminSession) -> StoreResult<()> {
+        self.put_json(&session_key(&session.token), session).await
+    }
+
+    async fn get_challenge(&self, challenge: &str) -> StoreResult<Option<AdminChallenge>> {
+        self.get_json(&challenge_key(challenge)).await
+    }
+
+    async fn put_challenge(&self, challenge: &AdminChallenge) -> StoreResult<()> {
+        self.put_json(&challenge_key(&challenge.challenge), challenge).await
+    }
+
+    async fn delete_challenge(&self, challenge: &str) -> StoreResult<()> {
+        self.delete(&challenge_key(challenge)).await
+    }
+
+    /// Load pricing config, falling back to defaults when missing or invalid
+    async fn load_pricing(&self) -> PricingConfig {
+        match self.get_json::<PricingConfig>(PRICING_KEY).await {
+            Ok(Some(config)) => config,
+            _ => default_pricing(),
+        }
+    }
+
+    /// Load debug webhook config, falling back to defaults when missing or invalid
+    async fn load_debug_webhook_config(&self) -> DebugWebhookConfig {
+        match self.get_json::<DebugWebhookConfig>(DEBUG_WEBHOOK_KEY).await {
+            Ok(Some(config)) => config,
+            _ => DebugWebhookConfig::default(),
+        }
+    }
+}
+
+/// In-memory store for native tests and local tooling
+#[derive(Debug)]
+pub struct MemoryStore {
+    objects: RefCell<BTreeMap<String, Vec<u8>>>,
+    page_size: usize,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::with_page_size(1000)
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store whose list() returns at most page_size keys per page
+    pub fn with_page_size(page_size: usize) -> Self {
+        Self {
+            objects: RefCell::new(BTreeMap::new()),
+            page_size: page_size.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.borrow().is_empty()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.borrow().keys().cloned().collect()
+    }
+}
+
+impl Store for MemoryStore {
+    async fn get(&self, key: &str) -> StoreResult<Option<Vec<u8>>> {
+        Ok(self.objects.borrow().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> StoreResult<()> {
+        self.objects.borrow_mut().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.objects.borrow_mut().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>) -> StoreResult<ListPage> {
+        let objects = self.objects.borrow();
+        let mut keys: Vec<String> = objects
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .filter(|k| cursor.as_ref().map(|c| k.as_str() > c.as_str()).unwrap_or(true))
+            .take(self.page_size + 1)
+            .cloned()
+            .collect();
+        let cursor = if keys.len() > self.page_size {
+            keys.truncate(self.page_size);
+            keys.last().cloned()
+        } else {
+            None
+        };
+        Ok(ListPage { keys, cursor })
+    }
+}
+
+/// Store backed by the R2 bucket bound as BUCKET
+#[cfg(target_arch = "wasm32")]
+pub struct R2Store {
+    bucket: worker::Bucket,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl R2Store {
+    pub fn new(bucket: worker::Bucket) -> Self {
+        Self { bucket }
+    }
+
+    pub fn from_env(env: &worker::Env) -> worker::Result<Self> {
+        Ok(Self::new(env.bucket("BUCKET")?))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Store for R2Store {
+    async fn get(&self, key: &str) -> StoreResult<Option<Vec<u8>>> {
+        match self.bucket.get(key).execute().await.map_err(|e| e.to_string())? {
+            Some(obj) => match obj.body() {
+                Some(body) => body.bytes().await.map(Some).map_err(|e| e.to_string()),
+                None => Ok(Some(Vec::new())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> StoreResult<()> {
+        self.bucket
+            .put(key, value)
+            .execute()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.bucket.delete(key).await.map_err(|e| e.to_string())
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<String>) -> StoreResult<ListPage> {
+        let mut builder = self.bucket.list().prefix(prefix);
+        if let Some(c) = cursor {
+            builder = builder.cursor(c);
+        }
+        let objects = builder.execute().await.map_err(|e| e.to_string())?;
+        let keys = objects.objects().iter().map(|o| o.key()).collect();
+        let cursor = if objects.truncated() { objects.cursor() } else { None };
+        Ok(ListPage { keys, cursor })
+    }
+}
+
+/// Drive a future to completion on the current thread (MemoryStore futures never pend)
+#[cfg(test)]
+pub(crate) fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    use std::task::{Context, Poll, Waker};
+    let mut fut = std::pin::pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(v) = fut.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_rental(username: &str) -> Rental {
+        Rental {
+            username: username.to_string(),
+            status: RentalStatus::Active,
+            created_at: "2026-01-01T00:00:00.000Z".to_string(),
+            expires_at: "2026-02-01T00:00:00.000Z".to_string(),
+            plan: Plan::ThirtyDays,
+            services: RentalServices { email: None, subdomain: None, nip05: None, lightning_address: None },
+            management_token: Some(format!("mgmt_{}", username)),
+            webhook_url: None,
+            grace_until: None,
+            reminders_sent: Vec::new(),
+            signing_secret: None,
+            webhooks: Vec::new(),
+            nostr_dm: false,
+            owner_pubkey: None,
+        }
+    }
+
+    #[test]
+    fn test_memory_store_get_put_delete() {
+        let store = MemoryStore::new();
+        block_on(async {
+            assert_eq!(store.get("a").await.unwrap(), None);
+            store.put("a", b"1".to_vec()).await.unwrap();
+            assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+            assert!(store.exists("a").await.unwrap());
+            store.delete("a").await.unwrap();
+            assert!(!store.exists("a").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_memory_store_list_pagination() {
+        let store = MemoryStore::with_page_size(2);
+        block_on(async {
+            for name in ["a", "b", "c", "d", "e"] {
+                store.put(&rental_key(name), b"{}".to_vec()).await.unwrap();
+            }
+            store.put("orders/x.json", b"{}".to_vec()).await.unwrap();
+
+            let first = store.list("rentals/", None).await.unwrap();
+            assert_eq!(first.keys, vec!["rentals/a.json", "rentals/b.json"]);
+            assert!(first.cursor.is_some());
+
+            let all = store.list_all("rentals/").await.unwrap();
+            assert_eq!(all.len(), 5);
+            assert!(all.iter().all(|k| k.starts_with("rentals/")));
+        });
+    }
+
+    #[test]
+    fn test_typed_rental_roundtrip() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put_rental(&sample_rental("alice")).await.unwrap();
+            let rental = store.get_rental("alice").await.unwrap().unwrap();
+            assert_eq!(rental.management_token.as_deref(), Some("mgmt_alice"));
+            assert!(store.get_rental("bob").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_list_rentals_skips_invalid_json() {
+        let store = MemoryStore::with_page_size(1);
+        block_on(async {
+            store.put_rental(&sample_rental("alice")).await.unwrap();
+            store.put_rental(&sample_rental("bob")).await.unwrap();
+            store.put(&rental_key("broken"), b"not json".to_vec()).await.unwrap();
+            let rentals = store.list_rentals().await.unwrap();
+            assert_eq!(rentals.len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_get_json_invalid_is_error() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put(&order_key("ord_x"), b"{".to_vec()).await.unwrap();
+            assert!(store.get_order("ord_x").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_ban_helpers() {
+        let store = MemoryStore::new();
+        block_on(async {
+            assert!(!store.is_banned("spammer").await.unwrap());
+            store
+                .put_ban(&BanRecord {
+                    username: "spammer".into(),
+                    banned_at: "2026-01-01T00:00:00.000Z".into(),
+                    reason: None,
+                })
+                .await
+                .unwrap();
+            assert!(store.is_banned("spammer").await.unwrap());
+            store.delete_ban("spammer").await.unwrap();
+            assert!(!store.is_banned("spammer").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_load_pricing_fallback() {
+        let store = MemoryStore::new();
+        block_on(async {
+            assert_eq!(store.load_pricing().await, default_pricing());
+            store.put(PRICING_KEY, b"garbage".to_vec()).await.unwrap();
+            assert_eq!(store.load_pricing().await, default_pricing());
+            let mut custom = default_pricing();
+            custom.get_mut("1d").unwrap().insert("bundle".into(), 1);
+            store.put_json(PRICING_KEY, &custom).await.unwrap();
+            assert_eq!(store.load_pricing().await, custom);
+        });
+    }
+
+    #[test]
+    fn test_token_index_helpers() {
+        let store = MemoryStore::new();
+        block_on(async {
+            assert_eq!(store.get_token_username("mgmt_x").await.unwrap(), None);
+            store.put_token_index("mgmt_x", "alice").await.unwrap();
+            assert_eq!(store.get_token_username("mgmt_x").await.unwrap().as_deref(), Some("alice"));
+            assert!(store.exists("tokens/mgmt_x.json").await.unwrap());
+            store.delete_token_index("mgmt_x").await.unwrap();
+            assert_eq!(store.get_token_username("mgmt_x").await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_pending_index_helpers() {
+        let store = MemoryStore::with_page_size(1);
+        block_on(async {
+            store.put_pending_index("ord_a").await.unwrap();
+            store.put_pending_index("ord_b").await.unwrap();
+            assert_eq!(store.list_pending_order_ids().await.unwrap(), vec!["ord_a", "ord_b"]);
+            store.delete_pending_index("ord_a").await.unwrap();
+            assert_eq!(store.list_pending_order_ids().await.unwrap(), vec!["ord_b"]);
+        });
+    }
+
+    #[test]
+    fn test_deliveries_are_listed_per_username() {
+        let store = MemoryStore::new();
+        let delivery = |username: &str, id: &str| WebhookDelivery {
+            id: id.to_string(),
+            username: username.to_string(),
+            event: "payment_completed".to_string(),
+            url: "https://example.com/hook".to_string(),
+            content_type: "application/json".to_string(),
+            body: "{}".to_string(),
+            order_id: None,
+            status: DeliveryStatus::Pending,
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            next_attempt_at: None,
+            attempts: Vec::new(),
+        };
+        block_on(async {
+            store.put_delivery(&delivery("al", "dlv_1")).await.unwrap();
+            store.put_delivery(&delivery("alice", "dlv_2")).await.unwrap();
+            store.put_delivery_queue(&delivery("alice", "dlv_2")).await.unwrap();
+
+            let listed = store.list_deliveries("al").await.unwrap();
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].id, "dlv_1");
+            assert_eq!(store.list_all_deliveries().await.unwrap().len(), 2);
+            store.delete_delivery("al", "dlv_1").await.unwrap();
+            assert!(store.list_deliveries("al").await.unwrap().is_empty());
+            assert_eq!(
+                store.list_queued_deliveries().await.unwrap(),
+                vec![DeliveryRef { username: "alice".into(), id: "dlv_2".into() }]
+            );
+            store.delete_delivery_queue("dlv_2").await.unwrap();
+            assert!(store.list_queued_deliveries().await.unwrap().is_empty());
+        });
+    }
+}
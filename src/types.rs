@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Service types that can be individually selected
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -7,8 +7,24 @@ pub enum ServiceType {
     Subdomain,
     EmailForwarding,
     Nip05,
+    LightningAddress,
 }
 
+impl ServiceType {
+    /// Name used for the service in pricing config and request bodies
+    pub fn key(&self) -> &'static str {
+        match self {
+            ServiceType::Subdomain => "subdomain",
+            ServiceType::EmailForwarding => "email",
+            ServiceType::Nip05 => "nip05",
+            ServiceType::LightningAddress => "lightning_address",
+        }
+    }
+}
+
+/// Services covered by the bundle price; others are added on top
+const BUNDLE_SERVICES: [ServiceType; 3] = [ServiceType::Subdomain, ServiceType::EmailForwarding, ServiceType::Nip05];
+
 /// Supported rental plans with pricing in sats
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Plan {
@@ -34,6 +50,7 @@ impl Plan {
     /// Price for a single service type
     pub fn service_price(&self, service: &ServiceType) -> u64 {
         match (self, service) {
+            (_, ServiceType::LightningAddress) => self.service_price(&ServiceType::Nip05),
             (Plan::FiveMinutes, ServiceType::Subdomain) => 500,
             (Plan::FiveMinutes, ServiceType::EmailForwarding) => 1500,
             (Plan::FiveMinutes, ServiceType::Nip05) => 200,
@@ -78,8 +95,9 @@ impl Plan {
     /// Calculate total price based on selected services
     pub fn calculate_total(plan: &Plan, services: &[ServiceType]) -> u64 {
         let unique: HashSet<&ServiceType> = services.iter().collect();
-        if unique.len() == 3 {
+        if BUNDLE_SERVICES.iter().all(|s| unique.contains(s)) {
             plan.bundle_price()
+                + unique.iter().filter(|s| !BUNDLE_SERVICES.contains(s)).map(|s| plan.service_price(s)).sum::<u64>()
         } else {
             unique.iter().map(|s| plan.service_price(s)).sum()
         }
@@ -100,12 +118,17 @@ impl Plan {
 
     pub fn service_price_dynamic(&self, service: &ServiceType, pricing: &PricingConfig) -> u64 {
         let key = self.period_key();
-        let svc_key = match service {
-            ServiceType::Subdomain => "subdomain",
-            ServiceType::EmailForwarding => "email",
-            ServiceType::Nip05 => "nip05",
-        };
-        pricing.get(key).and_then(|m| m.get(svc_key)).copied().unwrap_or_else(|| self.service_price(service))
+        pricing.get(key).and_then(|m| m.get(service.key())).copied().unwrap_or_else(|| self.service_price(service))
+    }
+
+    /// Price of one extra NIP-05 name (half the NIP-05 price unless configured)
+    pub fn nip05_name_price_dynamic(&self, pricing: &PricingConfig) -> u64 {
+        let key = self.period_key();
+        pricing
+            .get(key)
+            .and_then(|m| m.get("nip05_name"))
+            .copied()
+            .unwrap_or_else(|| self.service_price_dynamic(&ServiceType::Nip05, pricing) / 2)
     }
 
     pub fn bundle_price_dynamic(&self, pricing: &PricingConfig) -> u64 {
@@ -115,8 +138,13 @@ impl Plan {
 
     pub fn calculate_total_dynamic(plan: &Plan, services: &[ServiceType], pricing: &PricingConfig) -> u64 {
         let unique: HashSet<&ServiceType> = services.iter().collect();
-        if unique.len() == 3 {
+        if BUNDLE_SERVICES.iter().all(|s| unique.contains(s)) {
             plan.bundle_price_dynamic(pricing)
+                + unique
+                    .iter()
+                    .filter(|s| !BUNDLE_SERVICES.contains(s))
+                    .map(|s| plan.service_price_dynamic(s, pricing))
+                    .sum::<u64>()
         } else {
             unique.iter().map(|s| plan.service_price_dynamic(s, pricing)).sum()
         }
@@ -149,7 +177,9 @@ impl Plan {
     }
 }
 
-/// Order status lifecycle
+/// Order status lifecycle:
+/// webhook_pending → pending → paid → provisioned, with expired/cancelled for
+/// unpaid orders, failed when a paid order cannot be provisioned, and refunded
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
@@ -159,6 +189,58 @@ pub enum OrderStatus {
     Paid,
     Provisioned,
     Expired,
+    Failed,
+    Refunded,
+    Cancelled,
+}
+
+impl OrderStatus {
+    /// Whether an order may move from this status to `next`
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (WebhookPending, Pending | Expired | Cancelled)
+                | (Pending, Paid | Expired | Cancelled)
+                | (Paid, Provisioned | Failed | Refunded)
+                | (Failed, Provisioned | Refunded)
+                | (Provisioned, Refunded)
+        )
+    }
+
+    /// Unpaid orders that still accept a webhook confirmation or payment
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::WebhookPending | OrderStatus::Pending)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::WebhookPending => "webhook_pending",
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Provisioned => "provisioned",
+            OrderStatus::Expired => "expired",
+            OrderStatus::Failed => "failed",
+            OrderStatus::Refunded => "refunded",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One recorded status change of an order
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OrderTransition {
+    pub from: OrderStatus,
+    pub to: OrderStatus,
+    pub at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 /// Order stored in R2 at orders/{order_id}.json
@@ -187,12 +269,50 @@ pub struct Order {
     /// If set, this order is a renewal for an existing rental
     #[serde(skip_serializing_if = "Option::is_none")]
     pub renewal_for: Option<String>,
+    /// If set, this order adds `services_requested` to an existing rental
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade_for: Option<String>,
     /// Webhook URL for notifications
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webhook_url: Option<String>,
     /// Webhook challenge token for verification
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webhook_challenge: Option<String>,
+    /// Status changes, oldest first (orders created before this field have none)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<OrderTransition>,
+    /// Secret for X-Noscha-Signature; becomes the rental's signing secret
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub signing_secret: Option<String>,
+    /// Also send notifications as NIP-17 DMs to the requested NIP-05 pubkey
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nostr_dm: bool,
+    /// Pubkey (hex) that may manage the rental with NIP-98 auth; becomes the rental's owner
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub owner_pubkey: Option<String>,
+}
+
+impl Order {
+    /// NIP-05 pubkey that gets this order's challenge as a NIP-17 DM, if requested
+    pub fn dm_recipient(&self) -> Option<&str> {
+        let nip05 = self.services_requested.as_ref()?.nip05.as_ref()?;
+        self.nostr_dm.then_some(nip05.pubkey.as_str())
+    }
+
+    /// Move to `to`, recording the change in `history`; illegal moves are rejected
+    pub fn transition(&mut self, to: OrderStatus, now_ms: u64, reason: Option<&str>) -> Result<(), String> {
+        if !self.status.can_transition_to(&to) {
+            return Err(format!("Invalid order transition {} -> {}", self.status, to));
+        }
+        self.history.push(OrderTransition {
+            from: self.status.clone(),
+            to: to.clone(),
+            at: iso_from_ms(now_ms),
+            reason: reason.map(str::to_string),
+        });
+        self.status = to;
+        Ok(())
+    }
 }
 
 /// Services requested in an order
@@ -204,6 +324,8 @@ pub struct OrderServicesRequest {
     pub subdomain: Option<OrderSubdomainRequest>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nip05: Option<OrderNip05Request>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lightning_address: Option<OrderLightningAddressRequest>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -220,7 +342,22 @@ pub struct OrderSubdomainRequest {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderNip05Request {
+    /// Hex, npub or nprofile; stored as hex
     pub pubkey: String,
+    /// Relay hints for nostr.json; an nprofile pubkey adds its own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<String>,
+    /// Extra names (e.g. "bot.alice") and their pubkeys, priced per name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub names: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OrderLightningAddressRequest {
+    /// Lightning address, lnurl or https LNURL-pay URL to pass payments on to;
+    /// without it invoices come from the payment backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_to: Option<String>,
 }
 
 /// POST /api/order request body
@@ -233,6 +370,12 @@ pub struct OrderRequest {
     pub services: Option<OrderServicesRequest>,
     #[serde(default)]
     pub browser_flow: Option<bool>,
+    /// Also send notifications as NIP-17 DMs to the NIP-05 pubkey
+    #[serde(default)]
+    pub nostr_dm: bool,
+    /// Pubkey (hex) allowed to manage the rental with NIP-98 auth instead of the token
+    #[serde(default)]
+    pub owner_pubkey: Option<String>,
 }
 
 /// POST /api/order response
@@ -250,6 +393,9 @@ pub struct OrderResponse {
     pub message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub challenge_url: Option<String>,
+    /// Secret for verifying X-Noscha-Signature on webhooks, returned on order creation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_signing_secret: Option<String>,
 }
 
 /// GET /api/check/{username} response
@@ -298,8 +444,58 @@ pub struct EmailService {
 pub struct Nip05Service {
     pub enabled: bool,
     pub pubkey_hex: String,
+    /// Relay hints published in nostr.json
     #[serde(default)]
     pub relays: Vec<String>,
+    /// NIP-46 bunker relays published in nostr.json
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nip46: Vec<String>,
+    /// Extra names and their pubkeys, indexed under nip05names/
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub names: BTreeMap<String, String>,
+    /// Extra names paid for; `names` may be changed up to this many
+    #[serde(default)]
+    pub name_slots: usize,
+}
+
+impl Nip05Service {
+    /// Pubkey for a name on the main domain: the rental's own name or an extra name
+    pub fn pubkey_for(&self, username: &str, name: &str) -> Option<&str> {
+        if name == username {
+            Some(self.pubkey_hex.as_str())
+        } else {
+            self.names.get(name).map(String::as_str)
+        }
+    }
+}
+
+/// Lightning Address (LUD-16) service configuration stored in rental
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightningAddressService {
+    pub enabled: bool,
+    /// LNURL-pay endpoint served in place of our own; None when invoices come
+    /// from the payment backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_to: Option<String>,
+}
+
+/// Invoice issued to a Lightning Address payer, stored at lnurl_invoices/{secret}.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LnurlInvoice {
+    pub secret: String,
+    pub username: String,
+    pub amount_msat: u64,
+    pub bolt11: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_id: Option<String>,
+    /// NIP-57 zap request (kind 9734 JSON) the invoice pays for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zap_request: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paid_at: Option<String>,
 }
 
 /// Services configured for a rental
@@ -311,13 +507,71 @@ pub struct RentalServices {
     pub subdomain: Option<SubdomainService>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nip05: Option<Nip05Service>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lightning_address: Option<LightningAddressService>,
+}
+
+/// Rental lifecycle status
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase", from = "String")]
+pub enum RentalStatus {
+    /// Paid for and serving
+    #[default]
+    Active,
+    /// Past expiry but still renewable by its owner; not serving
+    Grace,
+    /// Lapsed without renewal
+    Expired,
+    /// Shut down because the username was banned
+    Suspended,
+    /// Shut down by an admin
+    Revoked,
+    /// Given up; the username is free for anyone to register
+    Released,
+}
+
+impl RentalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RentalStatus::Active => "active",
+            RentalStatus::Grace => "grace",
+            RentalStatus::Expired => "expired",
+            RentalStatus::Suspended => "suspended",
+            RentalStatus::Revoked => "revoked",
+            RentalStatus::Released => "released",
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self == RentalStatus::Active
+    }
+}
+
+/// Stored rentals predate the enum; unknown values are treated as expired
+impl From<String> for RentalStatus {
+    fn from(s: String) -> Self {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "active" => RentalStatus::Active,
+            "grace" => RentalStatus::Grace,
+            "suspended" | "banned" => RentalStatus::Suspended,
+            "revoked" => RentalStatus::Revoked,
+            "released" => RentalStatus::Released,
+            _ => RentalStatus::Expired,
+        }
+    }
+}
+
+impl std::fmt::Display for RentalStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// Rental object stored in R2 at rentals/{username}.json
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rental {
     pub username: String,
-    pub status: String,
+    pub status: RentalStatus,
     pub created_at: String,
     pub expires_at: String,
     pub plan: Plan,
@@ -326,6 +580,115 @@ pub struct Rental {
     pub management_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub webhook_url: Option<String>,
+    /// While in grace: when the username is released if not renewed
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub grace_until: Option<String>,
+    /// Expiry reminders already sent to the webhook
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub reminders_sent: Vec<SentReminder>,
+    /// HMAC key for the X-Noscha-Signature header on outbound webhooks
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub signing_secret: Option<String>,
+    /// Extra endpoints, each receiving only the events it subscribes to
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub webhooks: Vec<WebhookEndpoint>,
+    /// Also send notifications as NIP-17 DMs to the NIP-05 pubkey
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nostr_dm: bool,
+    /// Pubkey (hex) allowed to manage the rental with NIP-98 auth instead of the token
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub owner_pubkey: Option<String>,
+}
+
+/// Event a webhook endpoint can subscribe to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    PaymentCompleted,
+    EmailReceived,
+    /// Expiry reminders and the start of the grace period
+    ExpiringSoon,
+    DnsChanged,
+    /// Payment notices for renewals only
+    Renewed,
+    /// The management token was replaced (the notice carries the new one)
+    TokenRotated,
+    /// Rental settings were changed with PUT /api/settings
+    SettingsChanged,
+}
+
+/// A webhook endpoint and the events delivered to it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+impl WebhookEndpoint {
+    pub fn subscribes_to(&self, events: &[WebhookEvent]) -> bool {
+        self.events.iter().any(|e| events.contains(e))
+    }
+}
+
+/// An expiring_soon reminder sent for a threshold; tied to the expiry date it
+/// warned about so renewals and extensions re-arm it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SentReminder {
+    pub threshold_minutes: u64,
+    pub expires_at: String,
+}
+
+impl Rental {
+    /// Active and not yet past its expiry
+    pub fn is_serving(&self, now_ms: u64) -> bool {
+        self.status.is_active() && !is_expired_ms(&self.expires_at, now_ms)
+    }
+
+    /// When the username becomes available to others, given the grace period
+    /// that applies to rentals the expiry sweep has not reached yet
+    pub fn released_at_ms(&self, grace_minutes: u64) -> u64 {
+        let expires = ms_from_iso(&self.expires_at).unwrap_or(0);
+        match self.status {
+            RentalStatus::Released => 0,
+            RentalStatus::Active => expires + grace_minutes * 60 * 1000,
+            RentalStatus::Grace => self
+                .grace_until
+                .as_deref()
+                .and_then(ms_from_iso)
+                .unwrap_or(expires + grace_minutes * 60 * 1000),
+            RentalStatus::Expired | RentalStatus::Suspended | RentalStatus::Revoked => expires,
+        }
+    }
+
+    /// Whether the reminder for this threshold went out for the current expiry
+    pub fn reminder_sent(&self, threshold_minutes: u64) -> bool {
+        self.reminders_sent
+            .iter()
+            .any(|r| r.threshold_minutes == threshold_minutes && r.expires_at == self.expires_at)
+    }
+
+    /// Whether someone other than the owner may register this username
+    pub fn is_username_free(&self, grace_minutes: u64, now_ms: u64) -> bool {
+        now_ms >= self.released_at_ms(grace_minutes)
+    }
+
+    /// URLs that receive a notification matching any of `events`: the primary
+    /// webhook_url (which gets everything) and each subscribed endpoint, once each
+    pub fn webhook_targets(&self, events: &[WebhookEvent]) -> Vec<&str> {
+        let mut urls: Vec<&str> = self.webhook_url.as_deref().into_iter().collect();
+        for endpoint in self.webhooks.iter().filter(|w| w.subscribes_to(events)) {
+            if !urls.contains(&endpoint.url.as_str()) {
+                urls.push(&endpoint.url);
+            }
+        }
+        urls
+    }
+
+    /// NIP-05 pubkey that gets notifications as NIP-17 DMs, if the owner opted in
+    pub fn dm_recipient(&self) -> Option<&str> {
+        let nip05 = self.services.nip05.as_ref().filter(|n| n.enabled && !n.pubkey_hex.is_empty())?;
+        self.nostr_dm.then_some(nip05.pubkey_hex.as_str())
+    }
 }
 
 /// Webhook verification stored in R2 at verify/{token}.json
@@ -342,7 +705,12 @@ pub struct WebhookVerification {
 /// POST /api/renew request body
 #[derive(Debug, Deserialize)]
 pub struct RenewRequest {
+    /// Empty when authenticating as the owner with NIP-98
+    #[serde(default)]
     pub management_token: String,
+    /// Rental to renew with NIP-98 owner auth
+    #[serde(default)]
+    pub username: Option<String>,
     pub plan: Plan,
     #[serde(default)]
     pub services: Option<OrderServicesRequest>,
@@ -357,6 +725,30 @@ pub struct RenewResponse {
     pub expires_at: String,
 }
 
+/// POST /api/upgrade request body
+#[derive(Debug, Deserialize)]
+pub struct UpgradeRequest {
+    /// Empty when authenticating as the owner with NIP-98
+    #[serde(default)]
+    pub management_token: String,
+    /// Rental to upgrade with NIP-98 owner auth
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Services to add; the rental must not have them yet
+    pub services: OrderServicesRequest,
+}
+
+/// POST /api/upgrade response
+#[derive(Debug, Serialize)]
+pub struct UpgradeResponse {
+    pub order_id: String,
+    pub amount_sats: u64,
+    pub bolt11: String,
+    pub expires_at: String,
+    /// The rental's expiry, which the upgrade does not change
+    pub rental_expires_at: String,
+}
+
 /// Coinos webhook payload
 #[derive(Debug, Deserialize)]
 pub struct CoinosWebhookPayload {
@@ -379,15 +771,15 @@ pub type PricingConfig = HashMap<String, HashMap<String, u64>>;
 /// Get default pricing config
 pub fn default_pricing() -> PricingConfig {
     let mut config = HashMap::new();
-    let periods: [(&str, [(&str, u64); 5]); 8] = [
-        ("5m", [("subdomain",100),("email",300),("nip05",50),("bundle",400),("_duration_minutes",5)]),
-        ("30m", [("subdomain",200),("email",600),("nip05",100),("bundle",800),("_duration_minutes",30)]),
-        ("1h", [("subdomain",300),("email",900),("nip05",150),("bundle",1200),("_duration_minutes",60)]),
-        ("1d", [("subdomain",500),("email",1500),("nip05",200),("bundle",1800),("_duration_minutes",1440)]),
-        ("7d", [("subdomain",1000),("email",2500),("nip05",500),("bundle",3300),("_duration_minutes",10080)]),
-        ("30d", [("subdomain",2000),("email",5000),("nip05",1000),("bundle",6500),("_duration_minutes",43200)]),
-        ("90d", [("subdomain",5000),("email",12000),("nip05",2500),("bundle",16000),("_duration_minutes",129600)]),
-        ("365d", [("subdomain",15000),("email",40000),("nip05",8000),("bundle",50000),("_duration_minutes",525600)]),
+    let periods: [(&str, [(&str, u64); 7]); 8] = [
+        ("5m", [("subdomain",100),("email",300),("nip05",50),("nip05_name",25),("lightning_address",50),("bundle",400),("_duration_minutes",5)]),
+        ("30m", [("subdomain",200),("email",600),("nip05",100),("nip05_name",50),("lightning_address",100),("bundle",800),("_duration_minutes",30)]),
+        ("1h", [("subdomain",300),("email",900),("nip05",150),("nip05_name",75),("lightning_address",150),("bundle",1200),("_duration_minutes",60)]),
+        ("1d", [("subdomain",500),("email",1500),("nip05",200),("nip05_name",100),("lightning_address",200),("bundle",1800),("_duration_minutes",1440)]),
+        ("7d", [("subdomain",1000),("email",2500),("nip05",500),("nip05_name",250),("lightning_address",500),("bundle",3300),("_duration_minutes",10080)]),
+        ("30d", [("subdomain",2000),("email",5000),("nip05",1000),("nip05_name",500),("lightning_address",1000),("bundle",6500),("_duration_minutes",43200)]),
+        ("90d", [("subdomain",5000),("email",12000),("nip05",2500),("nip05_name",1250),("lightning_address",2500),("bundle",16000),("_duration_minutes",129600)]),
+        ("365d", [("subdomain",15000),("email",40000),("nip05",8000),("nip05_name",4000),("lightning_address",8000),("bundle",50000),("_duration_minutes",525600)]),
     ];
     for (period, prices) in periods {
         let mut m = HashMap::new();
@@ -414,6 +806,87 @@ pub fn is_expired_at(expires_at: &str, now_iso: &str) -> bool {
     expires_at <= now_iso
 }
 
+/// Check if expires_at is at or before now_ms. Unparseable timestamps are treated as not expired.
+pub fn is_expired_ms(expires_at: &str, now_ms: u64) -> bool {
+    ms_from_iso(expires_at).map(|e| e <= now_ms).unwrap_or(false)
+}
+
+/// Format milliseconds since the Unix epoch like JS Date.toISOString ("2026-02-11T12:15:00.000Z")
+pub fn iso_from_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (y, m, d) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        y,
+        m,
+        d,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60,
+        ms % 1000
+    )
+}
+
+/// Parse an ISO 8601 UTC timestamp ("YYYY-MM-DDTHH:MM:SS[.sss]Z") into milliseconds since the Unix epoch
+pub fn ms_from_iso(s: &str) -> Option<u64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-');
+    let y: i64 = date_parts.next()?.parse().ok()?;
+    let m: u32 = date_parts.next()?.parse().ok()?;
+    let d: u32 = date_parts.next()?.parse().ok()?;
+    let (hms, frac) = match time.split_once('.') {
+        Some((hms, frac)) => (hms, frac),
+        None => (time, ""),
+    };
+    let mut time_parts = hms.splitn(3, ':');
+    let hh: u64 = time_parts.next()?.parse().ok()?;
+    let mm: u64 = time_parts.next()?.parse().ok()?;
+    let ss: u64 = time_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || hh > 23 || mm > 59 || ss > 60 {
+        return None;
+    }
+    let millis: u64 = if frac.is_empty() {
+        0
+    } else {
+        if !frac.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let padded = format!("{:0<3}", &frac[..frac.len().min(3)]);
+        padded.parse().ok()?
+    };
+    let days = days_from_civil(y, m, d);
+    if days < 0 {
+        return None;
+    }
+    Some(((days as u64 * 86400) + hh * 3600 + mm * 60 + ss) * 1000 + millis)
+}
+
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
 /// Admin session stored in R2 at sessions/{token}.json
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AdminSession {
@@ -431,7 +904,20 @@ pub struct AdminChallenge {
     pub expires_at: String,
 }
 
-/// Nostr event from NIP-07 signing (simplified, no crypto verification)
+/// An email the JS email handler stored, passed to the worker for NIP-17 DMs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailReceivedNotice {
+    pub username: String,
+    pub mail_id: String,
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub subject: String,
+    pub view_url: String,
+    pub received_at: String,
+}
+
+/// Nostr event from NIP-07 signing (verified in crate::nostr)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NostrEvent {
     #[serde(default)]
@@ -444,23 +930,138 @@ pub struct NostrEvent {
     #[serde(default)]
     pub tags: Option<Vec<Vec<String>>>,
     pub content: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sig: Option<String>,
 }
 
-/// PUT /api/settings/{management_token} request body
-#[derive(Debug, Deserialize)]
+/// Webhook delivery state: pending until delivered or out of attempts (dead)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+/// One POST of a webhook delivery
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeliveryAttempt {
+    pub at: String,
+    /// HTTP status, when a response was received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outbound webhook stored in R2 at deliveries/{username}/{id}.json
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub username: String,
+    pub event: String,
+    pub url: String,
+    pub content_type: String,
+    pub body: String,
+    /// Order whose signing secret signs retries of its challenge; other
+    /// deliveries are signed with the rental's current secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    pub status: DeliveryStatus,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<String>,
+    #[serde(default)]
+    pub attempts: Vec<DeliveryAttempt>,
+}
+
+/// GET /api/webhooks/deliveries response
+#[derive(Debug, Serialize)]
+pub struct DeliveryListResponse {
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+/// PUT /api/settings/{management_token} request body. Absent fields are left
+/// unchanged; `"webhook_url": null` disables the webhook and `"webhooks"`
+/// replaces the whole endpoint list (`[]` removes them all)
+#[derive(Debug, Default, Deserialize)]
 pub struct SettingsRequest {
+    #[serde(default, deserialize_with = "present_or_null")]
+    pub webhook_url: Option<Option<String>>,
     #[serde(default)]
-    pub webhook_url: Option<String>,
+    pub webhooks: Option<Vec<WebhookEndpoint>>,
+    #[serde(default)]
+    pub rotate_signing_secret: bool,
+    #[serde(default)]
+    pub nostr_dm: Option<bool>,
+    /// Bind an owner pubkey for NIP-98 auth, or null to unbind
+    #[serde(default, deserialize_with = "present_or_null")]
+    pub owner_pubkey: Option<Option<String>>,
+    /// Replace the NIP-05 relay hints
+    #[serde(default)]
+    pub nip05_relays: Option<Vec<String>>,
+    /// Replace the NIP-46 bunker relays
+    #[serde(default)]
+    pub nip46_relays: Option<Vec<String>>,
+    /// Replace the extra NIP-05 names, up to the number paid for
+    #[serde(default)]
+    pub nip05_names: Option<BTreeMap<String, String>>,
+    /// New main NIP-05 pubkey (hex, npub or nprofile)
+    #[serde(default)]
+    pub nip05_pubkey: Option<String>,
+    /// New DNS record for the subdomain
+    #[serde(default)]
+    pub subdomain: Option<OrderSubdomainRequest>,
+}
+
+/// Deserialize a field that may be null into Some(None), leaving None for absent
+fn present_or_null<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// POST /api/settings/{management_token}/rotate response
+#[derive(Debug, Serialize)]
+pub struct TokenRotateResponse {
+    pub success: bool,
+    pub management_token: String,
+    pub my_page_url: String,
 }
 
 /// PUT /api/settings/{management_token} response
 #[derive(Debug, Serialize)]
 pub struct SettingsResponse {
     pub success: bool,
+    /// Rental the settings belong to, for notifying about the change
+    #[serde(skip)]
+    pub username: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookEndpoint>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub nostr_dm: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nip05_relays: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nip46_relays: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub nip05_names: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nip05_pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subdomain: Option<OrderSubdomainRequest>,
+    /// The new signing secret, only after a rotation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_secret: Option<String>,
+    /// Settings fields this request changed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<&'static str>,
 }
 
 #[cfg(test)]
@@ -508,6 +1109,22 @@ mod tests {
         // Bundle price (6500) < sum of individual (2000+5000+1000=8000)
     }
 
+    #[test]
+    fn test_calculate_total_lightning_address() {
+        assert_eq!(Plan::calculate_total(&Plan::ThirtyDays, &[ServiceType::LightningAddress]), 1000);
+        // The bundle covers the three core services; a Lightning Address is added on top
+        let services = vec![
+            ServiceType::Subdomain,
+            ServiceType::EmailForwarding,
+            ServiceType::Nip05,
+            ServiceType::LightningAddress,
+        ];
+        assert_eq!(Plan::calculate_total(&Plan::ThirtyDays, &services), 7500);
+        let mut pricing = default_pricing();
+        pricing.get_mut("30d").unwrap().insert("lightning_address".into(), 1234);
+        assert_eq!(Plan::calculate_total_dynamic(&Plan::ThirtyDays, &services, &pricing), 6500 + 1234);
+    }
+
     #[test]
     fn test_plan_duration_days() {
         assert_eq!(Plan::FiveMinutes.duration_days(), 0);
@@ -536,10 +1153,81 @@ mod tests {
         assert_eq!(status, OrderStatus::Paid);
     }
 
+    #[test]
+    fn test_rental_status_serde_compat() {
+        assert_eq!(serde_json::to_string(&RentalStatus::Grace).unwrap(), "\"grace\"");
+        for (stored, status) in [
+            ("active", RentalStatus::Active),
+            ("expired", RentalStatus::Expired),
+            ("Revoked", RentalStatus::Revoked),
+            ("banned", RentalStatus::Suspended),
+            ("released", RentalStatus::Released),
+            ("something-else", RentalStatus::Expired),
+        ] {
+            let parsed: RentalStatus = serde_json::from_str(&format!("\"{}\"", stored)).unwrap();
+            assert_eq!(parsed, status, "{}", stored);
+        }
+        for status in [RentalStatus::Active, RentalStatus::Suspended, RentalStatus::Released] {
+            let json = serde_json::to_string(&status).unwrap();
+            assert_eq!(json, format!("\"{}\"", status));
+            assert_eq!(serde_json::from_str::<RentalStatus>(&json).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn test_order_status_transitions() {
+        use OrderStatus::*;
+        assert!(WebhookPending.can_transition_to(&Pending));
+        assert!(Pending.can_transition_to(&Paid));
+        assert!(Paid.can_transition_to(&Provisioned));
+        assert!(Provisioned.can_transition_to(&Refunded));
+        assert!(!WebhookPending.can_transition_to(&Paid));
+        assert!(!Pending.can_transition_to(&Provisioned));
+        assert!(!Provisioned.can_transition_to(&Pending));
+        assert!(!Expired.can_transition_to(&Paid));
+        assert!(!Refunded.can_transition_to(&Provisioned));
+        assert!(!Cancelled.can_transition_to(&Pending));
+        assert_eq!(serde_json::to_string(&Cancelled).unwrap(), "\"cancelled\"");
+        assert_eq!(serde_json::to_string(&WebhookPending).unwrap(), format!("\"{}\"", WebhookPending));
+    }
+
+    #[test]
+    fn test_order_transition_records_history() {
+        let mut order: Order = serde_json::from_value(serde_json::json!({
+            "order_id": "ord_1", "username": "alice", "plan": "1d", "amount_sats": 1, "bolt11": "",
+            "status": "pending", "created_at": "2026-02-11T12:00:00.000Z", "expires_at": "2026-02-11T12:15:00.000Z"
+        }))
+        .unwrap();
+        assert!(order.history.is_empty());
+
+        order.transition(OrderStatus::Cancelled, 1_770_811_200_000, Some("by admin")).unwrap();
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        assert_eq!(
+            order.history,
+            vec![OrderTransition {
+                from: OrderStatus::Pending,
+                to: OrderStatus::Cancelled,
+                at: "2026-02-11T12:00:00.000Z".into(),
+                reason: Some("by admin".into()),
+            }]
+        );
+        let err = order.transition(OrderStatus::Paid, 0, None).unwrap_err();
+        assert_eq!(err, "Invalid order transition cancelled -> paid");
+        assert_eq!(order.history.len(), 1);
+
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["history"][0]["to"], "cancelled");
+    }
+
     #[test]
     fn test_pricing_config_default() {
         let config = default_pricing();
         assert_eq!(*config.get("30d").unwrap().get("bundle").unwrap(), 6500);
+        assert_eq!(Plan::ThirtyDays.nip05_name_price_dynamic(&config), 500);
+        // Configs saved before extra names were priced fall back to half the NIP-05 price
+        let mut old = config.clone();
+        old.get_mut("30d").unwrap().remove("nip05_name");
+        assert_eq!(Plan::ThirtyDays.nip05_name_price_dynamic(&old), 500);
         assert_eq!(*config.get("90d").unwrap().get("bundle").unwrap(), 16000);
         assert_eq!(*config.get("365d").unwrap().get("bundle").unwrap(), 50000);
     }
@@ -615,7 +1303,8 @@ mod tests {
     fn test_settings_request_with_webhook_url() {
         let json = r#"{"webhook_url":"https://example.com/hook"}"#;
         let req: SettingsRequest = serde_json::from_str(json).unwrap();
-        assert_eq!(req.webhook_url, Some("https://example.com/hook".to_string()));
+        assert_eq!(req.webhook_url, Some(Some("https://example.com/hook".to_string())));
+        assert!(!req.rotate_signing_secret);
     }
 
     /// Bug 2: SettingsRequest with null webhook_url (disable)
@@ -623,15 +1312,19 @@ mod tests {
     fn test_settings_request_null_webhook_url() {
         let json = r#"{"webhook_url":null}"#;
         let req: SettingsRequest = serde_json::from_str(json).unwrap();
-        assert_eq!(req.webhook_url, None);
+        assert_eq!(req.webhook_url, Some(None));
     }
 
-    /// Bug 2: SettingsRequest with empty body (webhook_url defaults to None)
+    /// Bug 2: SettingsRequest with empty body (webhook_url left unchanged)
     #[test]
     fn test_settings_request_empty_body() {
         let json = r#"{}"#;
         let req: SettingsRequest = serde_json::from_str(json).unwrap();
         assert_eq!(req.webhook_url, None);
+
+        let req: SettingsRequest = serde_json::from_str(r#"{"rotate_signing_secret":true}"#).unwrap();
+        assert_eq!(req.webhook_url, None);
+        assert!(req.rotate_signing_secret);
     }
 
     /// Bug 2: SettingsResponse serialization
@@ -639,16 +1332,32 @@ mod tests {
     fn test_settings_response_serde() {
         let resp = SettingsResponse {
             success: true,
+            username: "alice".to_string(),
             webhook_url: Some("https://example.com".to_string()),
+            webhooks: Vec::new(),
+            nostr_dm: false,
+            owner_pubkey: None,
+            nip05_relays: Vec::new(),
+            nip46_relays: Vec::new(),
+            nip05_names: BTreeMap::new(),
+            nip05_pubkey: None,
+            subdomain: None,
+            signing_secret: None,
+            changed: vec!["webhook_url"],
         };
         let json = serde_json::to_string(&resp).unwrap();
         assert!(json.contains(r#""success":true"#));
         assert!(json.contains(r#""webhook_url":"https://example.com""#));
+        assert!(json.contains(r#""changed":["webhook_url"]"#));
+        assert!(!json.contains("alice"));
 
         // None webhook_url should be omitted
-        let resp2 = SettingsResponse { success: true, webhook_url: None };
+        let resp2 = SettingsResponse { success: true, username: "alice".to_string(), webhook_url: None, webhooks: Vec::new(), nostr_dm: false, owner_pubkey: None, nip05_relays: Vec::new(), nip46_relays: Vec::new(), nip05_names: BTreeMap::new(), nip05_pubkey: None, subdomain: None, signing_secret: None, changed: Vec::new() };
         let json2 = serde_json::to_string(&resp2).unwrap();
         assert!(!json2.contains("webhook_url"));
+        assert!(!json2.contains("webhooks"));
+        assert!(!json2.contains("signing_secret"));
+        assert!(!json2.contains("changed"));
     }
 
     /// Bug 3: skill.md pricing matches default_pricing for non-1d plans
@@ -732,6 +1441,51 @@ mod tests {
         assert!(!is_expired_at("2025-06-01T12:00:01Z", "2025-06-01T12:00:00Z"));
     }
 
+    // === ISO time helpers ===
+
+    #[test]
+    fn test_iso_from_ms_epoch() {
+        assert_eq!(iso_from_ms(0), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_iso_from_ms_matches_js_format() {
+        // new Date(1770811500123).toISOString()
+        assert_eq!(iso_from_ms(1770811500123), "2026-02-11T12:05:00.123Z");
+        // Leap day
+        assert_eq!(iso_from_ms(1709164800000), "2024-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_ms_from_iso_roundtrip() {
+        for ms in [0u64, 1709164800000, 1770811500123, 4102444799999] {
+            assert_eq!(ms_from_iso(&iso_from_ms(ms)), Some(ms));
+        }
+    }
+
+    #[test]
+    fn test_ms_from_iso_without_millis() {
+        assert_eq!(ms_from_iso("2025-01-01T00:00:00Z"), Some(1735689600000));
+        assert_eq!(ms_from_iso("2025-01-01T00:00:00.5Z"), Some(1735689600500));
+    }
+
+    #[test]
+    fn test_ms_from_iso_invalid() {
+        assert_eq!(ms_from_iso(""), None);
+        assert_eq!(ms_from_iso("2025-01-01"), None);
+        assert_eq!(ms_from_iso("2025-13-01T00:00:00Z"), None);
+        assert_eq!(ms_from_iso("2025-01-01T00:00:00+09:00"), None);
+    }
+
+    #[test]
+    fn test_is_expired_ms() {
+        let now = ms_from_iso("2025-06-01T12:00:00.000Z").unwrap();
+        assert!(is_expired_ms("2025-06-01T11:59:59.999Z", now));
+        assert!(is_expired_ms("2025-06-01T12:00:00.000Z", now));
+        assert!(!is_expired_ms("2025-06-01T12:00:00.001Z", now));
+        assert!(!is_expired_ms("garbage", now));
+    }
+
     /// Rental with webhook_url field
     #[test]
     fn test_rental_webhook_url_serde() {
@@ -744,4 +1498,34 @@ mod tests {
         let rental2: Rental = serde_json::from_str(json2).unwrap();
         assert_eq!(rental2.webhook_url, None);
     }
+
+    #[test]
+    fn test_webhook_targets_follow_subscriptions() {
+        let json = r#"{"username":"bob","status":"active","created_at":"2026-01-01T00:00:00Z","expires_at":"2026-02-01T00:00:00Z","plan":"30d","services":{},"webhook_url":"https://example.com/all","webhooks":[{"url":"https://mail.example.com","events":["email_received"]},{"url":"https://billing.example.com","events":["payment_completed","renewed"]},{"url":"https://example.com/all","events":["email_received"]}]}"#;
+        let mut rental: Rental = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            rental.webhook_targets(&[WebhookEvent::EmailReceived]),
+            vec!["https://example.com/all", "https://mail.example.com"]
+        );
+        assert_eq!(
+            rental.webhook_targets(&[WebhookEvent::PaymentCompleted, WebhookEvent::Renewed]),
+            vec!["https://example.com/all", "https://billing.example.com"]
+        );
+
+        rental.webhook_url = None;
+        assert!(rental.webhook_targets(&[WebhookEvent::DnsChanged]).is_empty());
+        assert_eq!(rental.webhook_targets(&[WebhookEvent::Renewed]), vec!["https://billing.example.com"]);
+    }
+
+    #[test]
+    fn test_settings_request_webhooks() {
+        let json = r#"{"webhooks":[{"url":"https://example.com","events":["expiring_soon","dns_changed"]}]}"#;
+        let req: SettingsRequest = serde_json::from_str(json).unwrap();
+        let webhooks = req.webhooks.unwrap();
+        assert_eq!(webhooks[0].events, vec![WebhookEvent::ExpiringSoon, WebhookEvent::DnsChanged]);
+        assert_eq!(req.webhook_url, None);
+
+        let unknown = r#"{"webhooks":[{"url":"https://example.com","events":["everything"]}]}"#;
+        assert!(serde_json::from_str::<SettingsRequest>(unknown).is_err());
+    }
 }
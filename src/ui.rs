@@ -2,3 +2,8 @@
 pub fn landing_page_html() -> &'static str {
     include_str!("ui.html")
 }
+
+/// Escape text for HTML element content and double-quoted attributes
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
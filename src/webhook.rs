@@ -0,0 +1,630 @@
+//! Outbound webhook requests and their `X-Noscha-Signature` header.
+//!
+//! Raw JSON webhooks are signed with the rental's signing secret (bodies in a
+//! third-party service's format, see [`crate::webhook_format`], are not):
+//! `X-Noscha-Signature: t=<unix seconds>,v1=<hex HMAC-SHA256(secret, "<t>." + body)>`.
+//! Receivers check it with [`verify_signature`].
+//!
+//! Deliveries are recorded in R2 and failed ones are retried from the cron with
+//! exponential backoff until they succeed or run out of attempts (dead letter).
+//! Records hold no secrets; retries are signed with the rental's (or, for a
+//! challenge, the order's) current secret. Finished records are pruned by age
+//! and count from the cron.
+
+use crate::hmac::{hmac_sha256_hex, verify_hmac_sha256_hex};
+use crate::http::{HttpClient, HttpRequest};
+use crate::service::ApiError;
+use crate::store::{Store, StoreResult};
+use crate::types::{iso_from_ms, ms_from_iso, DeliveryAttempt, DeliveryStatus, Rental, WebhookDelivery};
+use std::collections::HashMap;
+use crate::webhook_format::{Notification, WebhookFormat};
+
+pub const SIGNATURE_HEADER: &str = "X-Noscha-Signature";
+
+/// Maximum age (and clock skew) accepted by `verify_signature`
+pub const SIGNATURE_TOLERANCE_SECS: u64 = 5 * 60;
+
+fn signed_message(timestamp_secs: u64, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}.", timestamp_secs).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Value of the `X-Noscha-Signature` header for a body sent at `timestamp_secs`
+pub fn signature_header(secret: &str, timestamp_secs: u64, body: &[u8]) -> String {
+    format!(
+        "t={},v1={}",
+        timestamp_secs,
+        hmac_sha256_hex(secret.as_bytes(), &signed_message(timestamp_secs, body))
+    )
+}
+
+/// Verify an `X-Noscha-Signature` header against the raw request body.
+///
+/// ```
+/// use noscha_io::webhook::{signature_header, verify_signature};
+///
+/// let header = signature_header("whsec_abc", 1_700_000_000, b"{}");
+/// assert!(verify_signature("whsec_abc", &header, b"{}", 1_700_000_060).is_ok());
+/// assert!(verify_signature("whsec_abc", &header, b"{ }", 1_700_000_060).is_err());
+/// ```
+pub fn verify_signature(secret: &str, header: &str, body: &[u8], now_secs: u64) -> Result<(), String> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", v)) => timestamp = v.parse::<u64>().ok(),
+            Some(("v1", v)) => signatures.push(v),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or("Missing or invalid timestamp")?;
+    if timestamp.abs_diff(now_secs) > SIGNATURE_TOLERANCE_SECS {
+        return Err("Timestamp outside tolerance".to_string());
+    }
+    let message = signed_message(timestamp, body);
+    if signatures
+        .iter()
+        .any(|sig| verify_hmac_sha256_hex(secret.as_bytes(), &message, sig))
+    {
+        Ok(())
+    } else {
+        Err("Signature mismatch".to_string())
+    }
+}
+
+/// POST request for a webhook, signed unless it goes to a recognized third-party
+/// service or there is no secret
+pub fn build_request(
+    url: &str,
+    content_type: &str,
+    body: String,
+    secret: Option<&str>,
+    now_secs: u64,
+) -> HttpRequest {
+    let mut req = HttpRequest {
+        method: "POST",
+        url: url.to_string(),
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body: None,
+    };
+    if let Some(secret) = secret.filter(|s| !s.is_empty() && WebhookFormat::detect(url).is_signed()) {
+        req = req.header(SIGNATURE_HEADER, signature_header(secret, now_secs, body.as_bytes()));
+    }
+    req.body = Some(body);
+    req
+}
+
+/// Attempts before a delivery is dead-lettered
+pub const MAX_DELIVERY_ATTEMPTS: usize = 8;
+
+/// Delay before the first retry; doubles with every failed attempt
+pub const RETRY_BASE_MS: u64 = 5 * 60 * 1000;
+
+/// Longest delay between two attempts
+pub const RETRY_MAX_MS: u64 = 6 * 60 * 60 * 1000;
+
+/// Most recent deliveries returned to a rental owner
+pub const DELIVERY_LIST_LIMIT: usize = 50;
+
+/// Delivered and dead-lettered records older than this are pruned
+pub const DELIVERY_RETENTION_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Finished records kept per username; older ones are pruned
+pub const DELIVERIES_KEPT_PER_USERNAME: usize = 100;
+
+/// A webhook to send for a rental (or an order for that username)
+#[derive(Debug, Clone)]
+pub struct OutboundWebhook {
+    pub username: String,
+    pub event: String,
+    pub url: String,
+    pub content_type: String,
+    pub body: String,
+    /// Signs the first attempt; not stored with the delivery
+    pub signing_secret: Option<String>,
+    /// Set for an order's challenge, sent before the rental exists
+    pub order_id: Option<String>,
+}
+
+impl OutboundWebhook {
+    /// JSON body, as sent for every event except the browser-flow challenge
+    pub fn json(
+        username: &str,
+        event: &str,
+        url: &str,
+        body: &serde_json::Value,
+        signing_secret: Option<&str>,
+    ) -> Self {
+        Self {
+            username: username.to_string(),
+            event: event.to_string(),
+            url: url.to_string(),
+            content_type: "application/json; charset=utf-8".to_string(),
+            body: body.to_string(),
+            signing_secret: signing_secret.map(str::to_string),
+            order_id: None,
+        }
+    }
+
+    /// A notification rendered for the service behind `url`
+    pub fn notification(username: &str, url: &str, notification: &Notification, signing_secret: Option<&str>) -> Self {
+        let rendered = notification.render(url);
+        Self {
+            username: username.to_string(),
+            event: notification.event.clone(),
+            url: rendered.url,
+            content_type: rendered.content_type.to_string(),
+            body: rendered.body,
+            signing_secret: signing_secret.map(str::to_string),
+            order_id: None,
+        }
+    }
+
+    /// Mark a webhook as an order's challenge, so retries use the order's secret
+    pub fn for_order(mut self, order_id: &str) -> Self {
+        self.order_id = Some(order_id.to_string());
+        self
+    }
+}
+
+/// Delay before the next attempt after `attempts` failed ones
+pub fn retry_delay_ms(attempts: usize) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16) as u32;
+    (RETRY_BASE_MS << doublings).min(RETRY_MAX_MS)
+}
+
+impl WebhookDelivery {
+    pub fn new(id: String, webhook: OutboundWebhook, now_ms: u64) -> Self {
+        Self {
+            id,
+            username: webhook.username,
+            event: webhook.event,
+            url: webhook.url,
+            content_type: webhook.content_type,
+            body: webhook.body,
+            order_id: webhook.order_id,
+            status: DeliveryStatus::Pending,
+            created_at: iso_from_ms(now_ms),
+            next_attempt_at: Some(iso_from_ms(now_ms)),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Whether a pending delivery's next attempt is due
+    pub fn is_due(&self, now_ms: u64) -> bool {
+        self.status == DeliveryStatus::Pending
+            && self.next_attempt_at.as_deref().and_then(ms_from_iso).is_none_or(|at| at <= now_ms)
+    }
+}
+
+/// Current secret for signing a stored delivery: its order's for a challenge,
+/// else the rental's
+async fn delivery_secret<S: Store>(store: &S, delivery: &WebhookDelivery) -> StoreResult<Option<String>> {
+    match delivery.order_id.as_deref() {
+        Some(order_id) => Ok(store.get_order(order_id).await?.and_then(|o| o.signing_secret)),
+        None => Ok(store.get_rental(&delivery.username).await?.and_then(|r| r.signing_secret)),
+    }
+}
+
+/// POST the delivery once and record the attempt; returns whether it succeeded
+async fn attempt<C: HttpClient>(
+    client: &C,
+    delivery: &mut WebhookDelivery,
+    secret: Option<&str>,
+    now_ms: u64,
+) -> bool {
+    let req = build_request(&delivery.url, &delivery.content_type, delivery.body.clone(), secret, now_ms / 1000);
+    let (ok, status_code, error) = match client.send(req).await {
+        Ok(resp) if resp.is_success() => (true, Some(resp.status), None),
+        Ok(resp) => (false, Some(resp.status), Some(resp.body.chars().take(200).collect())),
+        Err(e) => (false, None, Some(e)),
+    };
+    delivery.attempts.push(DeliveryAttempt { at: iso_from_ms(now_ms), status_code, error });
+    ok
+}
+
+/// Move a delivery to its state after an attempt and persist it with its queue entry
+async fn record_outcome<S: Store>(store: &S, delivery: &mut WebhookDelivery, ok: bool, now_ms: u64) -> StoreResult<()> {
+    if ok {
+        delivery.status = DeliveryStatus::Delivered;
+        delivery.next_attempt_at = None;
+    } else if delivery.status == DeliveryStatus::Dead || delivery.attempts.len() >= MAX_DELIVERY_ATTEMPTS {
+        delivery.status = DeliveryStatus::Dead;
+        delivery.next_attempt_at = None;
+    } else {
+        delivery.next_attempt_at = Some(iso_from_ms(now_ms + retry_delay_ms(delivery.attempts.len())));
+    }
+    store.put_delivery(delivery).await?;
+    if delivery.status == DeliveryStatus::Pending {
+        store.put_delivery_queue(delivery).await
+    } else {
+        store.delete_delivery_queue(&delivery.id).await
+    }
+}
+
+/// Record a webhook and attempt it right away; failures are queued for retry
+pub async fn deliver<S: Store, C: HttpClient>(
+    store: &S,
+    client: &C,
+    webhook: OutboundWebhook,
+    id: String,
+    now_ms: u64,
+) -> StoreResult<WebhookDelivery> {
+    let secret = webhook.signing_secret.clone();
+    let mut delivery = WebhookDelivery::new(id, webhook, now_ms);
+    let ok = attempt(client, &mut delivery, secret.as_deref(), now_ms).await;
+    record_outcome(store, &mut delivery, ok, now_ms).await?;
+    Ok(delivery)
+}
+
+/// Outcome of one cron pass over the delivery queue
+#[derive(Debug, Default)]
+pub struct RetryReport {
+    pub delivered: Vec<String>,
+    pub dead: Vec<String>,
+    pub rescheduled: usize,
+}
+
+/// Retry queued deliveries whose backoff has elapsed
+pub async fn retry_due_deliveries<S: Store, C: HttpClient>(
+    store: &S,
+    client: &C,
+    now_ms: u64,
+) -> StoreResult<RetryReport> {
+    let mut report = RetryReport::default();
+    for entry in store.list_queued_deliveries().await? {
+        let mut delivery = match store.get_delivery(&entry.username, &entry.id).await? {
+            Some(d) if d.status == DeliveryStatus::Pending => d,
+            _ => {
+                store.delete_delivery_queue(&entry.id).await?;
+                continue;
+            }
+        };
+        if !delivery.is_due(now_ms) {
+            continue;
+        }
+        let secret = delivery_secret(store, &delivery).await?;
+        let ok = attempt(client, &mut delivery, secret.as_deref(), now_ms).await;
+        record_outcome(store, &mut delivery, ok, now_ms).await?;
+        match delivery.status {
+            DeliveryStatus::Delivered => report.delivered.push(delivery.id),
+            DeliveryStatus::Dead => report.dead.push(delivery.id),
+            DeliveryStatus::Pending => report.rescheduled += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// Admin replay: send a delivery again now, whatever its state. A dead delivery
+/// that fails again stays dead; a pending one keeps its retry schedule
+pub async fn replay_delivery<S: Store, C: HttpClient>(
+    store: &S,
+    client: &C,
+    username: &str,
+    id: &str,
+    now_ms: u64,
+) -> Result<WebhookDelivery, ApiError> {
+    let mut delivery = store
+        .get_delivery(username, id)
+        .await?
+        .ok_or_else(|| ApiError::new(404, "Delivery not found"))?;
+    let secret = delivery_secret(store, &delivery).await?;
+    let ok = attempt(client, &mut delivery, secret.as_deref(), now_ms).await;
+    record_outcome(store, &mut delivery, ok, now_ms).await?;
+    Ok(delivery)
+}
+
+/// Most recent deliveries for a username created at or after `since_ms`, newest first
+pub async fn recent_deliveries<S: Store>(
+    store: &S,
+    username: &str,
+    since_ms: u64,
+    limit: usize,
+) -> StoreResult<Vec<WebhookDelivery>> {
+    let mut deliveries: Vec<WebhookDelivery> = store
+        .list_deliveries(username)
+        .await?
+        .into_iter()
+        .filter(|d| ms_from_iso(&d.created_at).unwrap_or(0) >= since_ms)
+        .collect();
+    deliveries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+    deliveries.truncate(limit);
+    Ok(deliveries)
+}
+
+/// Delete delivered and dead-lettered records past the retention period, and
+/// all but the newest `DELIVERIES_KEPT_PER_USERNAME` of them per username.
+/// Pending deliveries are kept until they finish. Returns how many were deleted
+pub async fn prune_deliveries<S: Store>(store: &S, now_ms: u64) -> StoreResult<usize> {
+    let mut by_username: HashMap<String, Vec<WebhookDelivery>> = HashMap::new();
+    for delivery in store.list_all_deliveries().await? {
+        if delivery.status != DeliveryStatus::Pending {
+            by_username.entry(delivery.username.clone()).or_default().push(delivery);
+        }
+    }
+    let mut pruned = 0;
+    for deliveries in by_username.values_mut() {
+        deliveries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+        for (i, delivery) in deliveries.iter().enumerate() {
+            let created = ms_from_iso(&delivery.created_at).unwrap_or(0);
+            if i >= DELIVERIES_KEPT_PER_USERNAME || created + DELIVERY_RETENTION_MS <= now_ms {
+                store.delete_delivery(&delivery.username, &delivery.id).await?;
+                pruned += 1;
+            }
+        }
+    }
+    Ok(pruned)
+}
+
+/// Deliveries shown to a rental owner; those for a previous rental of the same
+/// username are excluded
+pub async fn list_rental_deliveries<S: Store>(
+    store: &S,
+    rental: &Rental,
+    limit: usize,
+) -> StoreResult<Vec<WebhookDelivery>> {
+    let since = ms_from_iso(&rental.created_at).unwrap_or(0);
+    recent_deliveries(store, &rental.username, since, limit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_760_000_000;
+
+    #[test]
+    fn test_signature_roundtrip() {
+        let header = signature_header("whsec_k", NOW, br#"{"event":"x"}"#);
+        assert!(header.starts_with(&format!("t={},v1=", NOW)));
+        assert_eq!(verify_signature("whsec_k", &header, br#"{"event":"x"}"#, NOW + 10), Ok(()));
+        assert!(verify_signature("whsec_other", &header, br#"{"event":"x"}"#, NOW).is_err());
+        assert!(verify_signature("whsec_k", &header, br#"{"event":"y"}"#, NOW).is_err());
+    }
+
+    #[test]
+    fn test_signature_covers_timestamp() {
+        let header = signature_header("whsec_k", NOW, b"body");
+        let sig = header.split_once(",v1=").unwrap().1;
+        let replayed = format!("t={},v1={}", NOW + 100, sig);
+        assert_eq!(
+            verify_signature("whsec_k", &replayed, b"body", NOW + 100),
+            Err("Signature mismatch".to_string())
+        );
+    }
+
+    #[test]
+    fn test_signature_tolerance() {
+        let header = signature_header("whsec_k", NOW, b"body");
+        assert!(verify_signature("whsec_k", &header, b"body", NOW + SIGNATURE_TOLERANCE_SECS).is_ok());
+        assert!(verify_signature("whsec_k", &header, b"body", NOW + SIGNATURE_TOLERANCE_SECS + 1).is_err());
+        assert!(verify_signature("whsec_k", &header, b"body", NOW - SIGNATURE_TOLERANCE_SECS - 1).is_err());
+        assert!(verify_signature("whsec_k", "v1=00", b"body", NOW).is_err());
+        assert!(verify_signature("whsec_k", "", b"body", NOW).is_err());
+    }
+
+    #[test]
+    fn test_build_request_signing() {
+        let req = build_request("https://example.com/hook", "application/json", "{}".into(), Some("whsec_k"), NOW);
+        let header = req.header_value("x-noscha-signature").unwrap();
+        assert!(verify_signature("whsec_k", header, b"{}", NOW).is_ok());
+        assert_eq!(req.body.as_deref(), Some("{}"));
+
+        let discord = build_request(
+            "https://discord.com/api/webhooks/1/abc",
+            "application/json",
+            "{}".into(),
+            Some("whsec_k"),
+            NOW,
+        );
+        assert_eq!(discord.header_value(SIGNATURE_HEADER), None);
+        let ntfy = build_request("https://ntfy.sh/", "application/json", "{}".into(), Some("whsec_k"), NOW);
+        assert_eq!(ntfy.header_value(SIGNATURE_HEADER), None);
+        let unsigned = build_request("https://example.com/hook", "text/plain", "x".into(), None, NOW);
+        assert_eq!(unsigned.header_value(SIGNATURE_HEADER), None);
+        assert_eq!(unsigned.header_value("content-type"), Some("text/plain"));
+    }
+
+    use crate::http::HttpResponse;
+    use crate::store::{block_on, MemoryStore};
+    use std::cell::RefCell;
+
+    const NOW_MS: u64 = NOW * 1000;
+    const MINUTE: u64 = 60 * 1000;
+
+    /// Answers each request with the next scripted status (200 once exhausted)
+    #[derive(Default)]
+    struct ScriptedClient {
+        statuses: RefCell<Vec<u16>>,
+        sent: RefCell<Vec<HttpRequest>>,
+    }
+
+    impl ScriptedClient {
+        fn failing(times: usize) -> Self {
+            Self { statuses: RefCell::new(vec![500; times]), sent: RefCell::default() }
+        }
+    }
+
+    impl HttpClient for ScriptedClient {
+        async fn send(&self, req: HttpRequest) -> Result<HttpResponse, String> {
+            self.sent.borrow_mut().push(req);
+            let mut statuses = self.statuses.borrow_mut();
+            let status = if statuses.is_empty() { 200 } else { statuses.remove(0) };
+            Ok(HttpResponse { status, body: "nope".into() })
+        }
+    }
+
+    fn outbound(username: &str) -> OutboundWebhook {
+        OutboundWebhook {
+            username: username.into(),
+            event: "payment_completed".into(),
+            url: "https://example.com/hook".into(),
+            content_type: "application/json".into(),
+            body: r#"{"event":"payment_completed"}"#.into(),
+            signing_secret: Some("whsec_k".into()),
+            order_id: None,
+        }
+    }
+
+    fn rental(username: &str, signing_secret: &str) -> Rental {
+        serde_json::from_value(serde_json::json!({
+            "username": username, "status": "active", "created_at": iso_from_ms(NOW_MS),
+            "expires_at": iso_from_ms(NOW_MS + 60 * MINUTE), "plan": "1h", "services": {},
+            "signing_secret": signing_secret
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        assert_eq!(retry_delay_ms(1), 5 * MINUTE);
+        assert_eq!(retry_delay_ms(2), 10 * MINUTE);
+        assert_eq!(retry_delay_ms(4), 40 * MINUTE);
+        assert_eq!(retry_delay_ms(7), 320 * MINUTE);
+        assert_eq!(retry_delay_ms(8), RETRY_MAX_MS);
+        assert_eq!(retry_delay_ms(100), RETRY_MAX_MS);
+    }
+
+    #[test]
+    fn test_deliver_success_is_signed_and_not_queued() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::default();
+        block_on(async {
+            let d = deliver(&store, &client, outbound("ann"), "dlv_1".into(), NOW_MS).await.unwrap();
+            assert_eq!(d.status, DeliveryStatus::Delivered);
+            assert_eq!(d.attempts.len(), 1);
+            assert_eq!(d.attempts[0].status_code, Some(200));
+            assert!(store.list_queued_deliveries().await.unwrap().is_empty());
+            // The secret signs the request but is not stored with the record
+            let stored = store.get(&crate::store::delivery_key("ann", "dlv_1")).await.unwrap().unwrap();
+            assert!(!String::from_utf8(stored).unwrap().contains("whsec_k"));
+
+            let sent = client.sent.borrow();
+            let header = sent[0].header_value(SIGNATURE_HEADER).unwrap();
+            assert!(verify_signature("whsec_k", header, sent[0].body.as_deref().unwrap().as_bytes(), NOW).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_failed_delivery_retries_with_backoff_then_dead_letters() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::failing(MAX_DELIVERY_ATTEMPTS + 1);
+        block_on(async {
+            let d = deliver(&store, &client, outbound("ann"), "dlv_1".into(), NOW_MS).await.unwrap();
+            assert_eq!(d.status, DeliveryStatus::Pending);
+            assert_eq!(d.next_attempt_at, Some(iso_from_ms(NOW_MS + 5 * MINUTE)));
+            assert_eq!(d.attempts[0].error.as_deref(), Some("nope"));
+            assert_eq!(store.list_queued_deliveries().await.unwrap().len(), 1);
+
+            // Not due yet
+            let report = retry_due_deliveries(&store, &client, NOW_MS + MINUTE).await.unwrap();
+            assert_eq!(report.rescheduled, 0);
+            assert_eq!(client.sent.borrow().len(), 1);
+
+            let mut now = NOW_MS;
+            for attempts in 1..MAX_DELIVERY_ATTEMPTS {
+                now += retry_delay_ms(attempts);
+                let report = retry_due_deliveries(&store, &client, now).await.unwrap();
+                if attempts + 1 < MAX_DELIVERY_ATTEMPTS {
+                    assert_eq!(report.rescheduled, 1);
+                } else {
+                    assert_eq!(report.dead, vec!["dlv_1".to_string()]);
+                }
+            }
+            let d = store.get_delivery("ann", "dlv_1").await.unwrap().unwrap();
+            assert_eq!(d.status, DeliveryStatus::Dead);
+            assert_eq!(d.attempts.len(), MAX_DELIVERY_ATTEMPTS);
+            assert!(store.list_queued_deliveries().await.unwrap().is_empty());
+
+            // Replay of a dead delivery: fails once more and stays dead, then succeeds
+            let d = replay_delivery(&store, &client, "ann", "dlv_1", now).await.unwrap();
+            assert_eq!(d.status, DeliveryStatus::Dead);
+            let d = replay_delivery(&store, &client, "ann", "dlv_1", now).await.unwrap();
+            assert_eq!(d.status, DeliveryStatus::Delivered);
+            assert_eq!(d.attempts.len(), MAX_DELIVERY_ATTEMPTS + 2);
+            assert_eq!(replay_delivery(&store, &client, "ann", "dlv_x", now).await.unwrap_err().status, 404);
+        });
+    }
+
+    #[test]
+    fn test_retry_recovers() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::failing(1);
+        block_on(async {
+            store.put_rental(&rental("ann", "whsec_k")).await.unwrap();
+            deliver(&store, &client, outbound("ann"), "dlv_1".into(), NOW_MS).await.unwrap();
+            let report = retry_due_deliveries(&store, &client, NOW_MS + 5 * MINUTE).await.unwrap();
+            assert_eq!(report.delivered, vec!["dlv_1".to_string()]);
+            assert!(store.list_queued_deliveries().await.unwrap().is_empty());
+            // Each attempt is signed with its own timestamp; the retry with the rental's secret
+            let sent = client.sent.borrow();
+            assert_ne!(sent[0].header_value(SIGNATURE_HEADER), sent[1].header_value(SIGNATURE_HEADER));
+            let header = sent[1].header_value(SIGNATURE_HEADER).unwrap();
+            assert!(verify_signature("whsec_k", header, sent[1].body.as_deref().unwrap().as_bytes(), NOW + 300).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_challenge_retry_uses_order_secret() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::failing(1);
+        block_on(async {
+            let order: crate::types::Order = serde_json::from_value(serde_json::json!({
+                "order_id": "ord_1", "username": "ann", "plan": "1h", "amount_sats": 1, "bolt11": "",
+                "status": "webhook_pending", "created_at": iso_from_ms(NOW_MS), "expires_at": iso_from_ms(NOW_MS),
+                "signing_secret": "whsec_order"
+            }))
+            .unwrap();
+            store.put_order(&order).await.unwrap();
+            let webhook = OutboundWebhook { signing_secret: Some("whsec_order".into()), ..outbound("ann") }.for_order("ord_1");
+            deliver(&store, &client, webhook, "dlv_1".into(), NOW_MS).await.unwrap();
+            retry_due_deliveries(&store, &client, NOW_MS + 5 * MINUTE).await.unwrap();
+            let sent = client.sent.borrow();
+            let header = sent[1].header_value(SIGNATURE_HEADER).unwrap();
+            assert!(verify_signature("whsec_order", header, sent[1].body.as_deref().unwrap().as_bytes(), NOW + 300).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_prune_deliveries_by_age_and_count() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::failing(1);
+        block_on(async {
+            // Pending (failed once) deliveries survive any age
+            deliver(&store, &client, outbound("ann"), "dlv_pending".into(), NOW_MS).await.unwrap();
+            deliver(&store, &client, outbound("ann"), "dlv_old".into(), NOW_MS).await.unwrap();
+            for i in 0..DELIVERIES_KEPT_PER_USERNAME + 2 {
+                let at = NOW_MS + DELIVERY_RETENTION_MS + i as u64 * MINUTE;
+                deliver(&store, &client, outbound("bob"), format!("dlv_{:03}", i), at).await.unwrap();
+            }
+
+            let now = NOW_MS + DELIVERY_RETENTION_MS + 200 * MINUTE;
+            assert_eq!(prune_deliveries(&store, now).await.unwrap(), 3);
+            let ann: Vec<String> = store.list_deliveries("ann").await.unwrap().into_iter().map(|d| d.id).collect();
+            assert_eq!(ann, vec!["dlv_pending".to_string()]);
+            let bob = store.list_deliveries("bob").await.unwrap();
+            assert_eq!(bob.len(), DELIVERIES_KEPT_PER_USERNAME);
+            assert!(bob.iter().all(|d| d.id != "dlv_000" && d.id != "dlv_001"));
+        });
+    }
+
+    #[test]
+    fn test_list_rental_deliveries() {
+        let store = MemoryStore::new();
+        let client = ScriptedClient::default();
+        block_on(async {
+            // From a previous rental of the same username
+            deliver(&store, &client, outbound("ann"), "dlv_old".into(), NOW_MS - MINUTE).await.unwrap();
+            deliver(&store, &client, outbound("ann"), "dlv_a".into(), NOW_MS).await.unwrap();
+            deliver(&store, &client, outbound("ann"), "dlv_b".into(), NOW_MS + MINUTE).await.unwrap();
+            deliver(&store, &client, outbound("bob"), "dlv_c".into(), NOW_MS).await.unwrap();
+
+            let rental = rental("ann", "whsec_k");
+            let listed = list_rental_deliveries(&store, &rental, DELIVERY_LIST_LIMIT).await.unwrap();
+            let ids: Vec<&str> = listed.iter().map(|d| d.id.as_str()).collect();
+            assert_eq!(ids, vec!["dlv_b", "dlv_a"]);
+            assert_eq!(list_rental_deliveries(&store, &rental, 1).await.unwrap().len(), 1);
+        });
+    }
+}
@@ -0,0 +1,737 @@
+//! Renders webhook notifications in the receiving service's native format.
+//!
+//! The format is picked from the URL by the recognizers in [`FORMATS`]: Discord
+//! embeds, Slack Block Kit, Telegram `sendMessage`, Matrix hookshot and ntfy
+//! messages. Anything else gets the raw JSON payload, which is the only format
+//! signed with `X-Noscha-Signature`.
+
+use serde_json::{json, Value};
+
+use crate::types::{EmailReceivedNotice, Order, Rental};
+use crate::ui::escape_html;
+
+/// How a webhook body is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookFormat {
+    /// Raw JSON payload for the renter's own receiver
+    Json,
+    Discord,
+    Slack,
+    Telegram,
+    /// Matrix hookshot generic webhook
+    Matrix,
+    Ntfy,
+}
+
+/// Whether a URL belongs to a service
+pub type Recognizer = fn(&UrlParts) -> bool;
+
+/// Recognizers checked in order; URLs no recognizer claims get raw JSON
+pub const FORMATS: &[(WebhookFormat, Recognizer)] = &[
+    (WebhookFormat::Discord, is_discord),
+    (WebhookFormat::Slack, is_slack),
+    (WebhookFormat::Telegram, is_telegram),
+    (WebhookFormat::Matrix, is_matrix_hookshot),
+    (WebhookFormat::Ntfy, is_ntfy),
+];
+
+impl WebhookFormat {
+    pub fn detect(url: &str) -> Self {
+        let Some(parts) = UrlParts::parse(url) else {
+            return WebhookFormat::Json;
+        };
+        FORMATS
+            .iter()
+            .find(|(_, matches)| matches(&parts))
+            .map(|(format, _)| *format)
+            .unwrap_or(WebhookFormat::Json)
+    }
+
+    /// Third-party services ignore the signature header, so only raw JSON is signed
+    pub fn is_signed(&self) -> bool {
+        *self == WebhookFormat::Json
+    }
+}
+
+/// The pieces of a webhook URL the recognizers look at
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlParts {
+    pub scheme: String,
+    /// Lowercase, without port
+    pub host: String,
+    pub path: String,
+    pub query: String,
+}
+
+impl UrlParts {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.trim().split_once("://")?;
+        let (rest, _fragment) = rest.split_once('#').unwrap_or((rest, ""));
+        let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let host = authority.rsplit('@').next()?;
+        let host = match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host.split(':').next().unwrap_or(host),
+        };
+        Some(UrlParts {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            path: path.to_string(),
+            query: query.to_string(),
+        })
+    }
+
+    fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| percent_decode(v))
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b'+', _) => {
+                out.push(b' ');
+                i += 1;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn is_discord(url: &UrlParts) -> bool {
+    let host = url.host.as_str();
+    let discord_host = ["discord.com", "discordapp.com"]
+        .iter()
+        .any(|d| host == *d || host.ends_with(&format!(".{}", d)));
+    discord_host && url.path.starts_with("/api/webhooks/")
+}
+
+fn is_slack(url: &UrlParts) -> bool {
+    url.host == "hooks.slack.com" && url.path.starts_with("/services/")
+}
+
+fn is_telegram(url: &UrlParts) -> bool {
+    url.host == "api.telegram.org" && url.path.starts_with("/bot") && url.path.ends_with("/sendMessage")
+}
+
+fn is_matrix_hookshot(url: &UrlParts) -> bool {
+    (url.host.contains("hookshot") || url.path.contains("/hookshot/")) && url.path.contains("/webhook/")
+}
+
+fn is_ntfy(url: &UrlParts) -> bool {
+    url.host == "ntfy.sh" || url.host.starts_with("ntfy.")
+}
+
+/// A labelled value shown alongside the notification text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+/// A link the notification asks the reader to open
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub label: String,
+    pub url: String,
+}
+
+/// One event, described once and rendered per receiving service
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub event: String,
+    pub title: String,
+    /// Plain-text summary
+    pub text: String,
+    pub color: u32,
+    pub fields: Vec<Field>,
+    pub link: Option<Link>,
+    /// Raw JSON payload for `WebhookFormat::Json`
+    pub data: Value,
+}
+
+/// A rendered webhook request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub format: WebhookFormat,
+    /// Where to POST; differs from the configured URL for ntfy topics
+    pub url: String,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+const JSON: &str = "application/json; charset=utf-8";
+
+fn field(name: &str, value: impl Into<String>, inline: bool) -> Field {
+    Field { name: name.to_string(), value: value.into(), inline }
+}
+
+fn link(label: &str, url: &str) -> Option<Link> {
+    Some(Link { label: label.to_string(), url: url.to_string() })
+}
+
+impl Notification {
+    /// Challenge a new order's webhook URL must confirm before an invoice is issued
+    pub fn webhook_challenge(order_id: &str, username: &str, challenge_url: &str) -> Self {
+        let inner = json!({
+            "event": "webhook_challenge",
+            "challenge_url": challenge_url,
+            "order_id": order_id,
+        });
+        Notification {
+            event: "webhook_challenge".to_string(),
+            title: "🔐 Confirm your noscha.io order".to_string(),
+            text: format!("Open the link to confirm the order for {} and get a Lightning invoice.", username),
+            color: 0x8b5cf6,
+            fields: vec![field("Username", username, true), field("Order", order_id, true)],
+            link: link("Confirm order", challenge_url),
+            // `content` keeps the challenge readable by Discord-compatible receivers
+            data: json!({
+                "content": inner.to_string(),
+                "event": "webhook_challenge",
+                "challenge_url": challenge_url,
+                "order_id": order_id,
+            }),
+        }
+    }
+
+    pub fn payment_completed(
+        order: &Order,
+        rental: &Rental,
+        management_token: &str,
+        my_page_url: &str,
+        is_renewal: bool,
+    ) -> Self {
+        let services = &rental.services;
+        let plan_key = order.plan.period_key();
+        let is_upgrade = order.upgrade_for.is_some();
+        Notification {
+            event: "payment_completed".to_string(),
+            title: if is_renewal {
+                "🔄 Renewal Complete"
+            } else if is_upgrade {
+                "➕ Services Added"
+            } else {
+                "⚡ Payment Complete"
+            }
+            .to_string(),
+            text: format!("{} — {}", rental.username, plan_key),
+            color: 0x00ff88,
+            fields: vec![
+                field("Username", rental.username.as_str(), true),
+                field("Plan", plan_key, true),
+                field("Amount", format!("{} sats", order.amount_sats), true),
+                field("Expires", rental.expires_at.as_str(), false),
+            ],
+            link: link("My Page", my_page_url),
+            data: json!({
+                "event": "payment_completed",
+                "order_id": order.order_id,
+                "username": rental.username,
+                "management_token": management_token,
+                "my_page_url": my_page_url,
+                "expires_at": rental.expires_at,
+                "plan": plan_key,
+                "amount_sats": order.amount_sats,
+                "is_renewal": is_renewal,
+                "is_upgrade": is_upgrade,
+                "services": {
+                    "email": services.email.as_ref().map(|e| e.enabled).unwrap_or(false),
+                    "subdomain": services.subdomain.is_some(),
+                    "nip05": services.nip05.is_some(),
+                    "lightning_address": services.lightning_address.is_some(),
+                },
+                "nip05_npub": services.nip05.as_ref().and_then(|n| crate::nip19::encode_npub(&n.pubkey_hex).ok()),
+            }),
+        }
+    }
+
+    pub fn grace_period_started(rental: &Rental, grace_until: &str, my_page_url: &str) -> Self {
+        Notification {
+            event: "grace_period_started".to_string(),
+            title: "⏳ Rental Expired — Grace Period Started".to_string(),
+            text: format!("{} has expired. Renew before the grace period ends to keep it.", rental.username),
+            color: 0xf59e0b,
+            fields: vec![field("Username", rental.username.as_str(), true), field("Grace Until", grace_until, true)],
+            link: link("Renew", my_page_url),
+            data: json!({
+                "event": "grace_period_started",
+                "username": rental.username,
+                "expires_at": rental.expires_at,
+                "grace_until": grace_until,
+                "my_page_url": my_page_url,
+            }),
+        }
+    }
+
+    /// `threshold_label` is the human form of `threshold_minutes`, e.g. "1 Day"
+    pub fn expiring_soon(
+        rental: &Rental,
+        threshold_minutes: u64,
+        threshold_label: &str,
+        minutes_remaining: u64,
+        my_page_url: &str,
+    ) -> Self {
+        Notification {
+            event: "expiring_soon".to_string(),
+            title: "⏰ Rental Expiring Soon".to_string(),
+            text: format!("{} expires in less than {}", rental.username, threshold_label),
+            color: 0xf59e0b,
+            fields: vec![field("Username", rental.username.as_str(), true), field("Expires", rental.expires_at.as_str(), true)],
+            link: link("Renew", my_page_url),
+            data: json!({
+                "event": "expiring_soon",
+                "username": rental.username,
+                "expires_at": rental.expires_at,
+                "threshold_minutes": threshold_minutes,
+                "minutes_remaining": minutes_remaining,
+                "my_page_url": my_page_url,
+            }),
+        }
+    }
+
+    /// `rotated_by` is "owner" or "admin"
+    /// Carries no token or my-page URL: a rotation usually follows a leak, and
+    /// whoever held the old token may have pointed the webhooks at themselves
+    pub fn token_rotated(rental: &Rental, rotated_by: &str) -> Self {
+        Notification {
+            event: "token_rotated".to_string(),
+            title: "🔑 Management Token Rotated".to_string(),
+            text: format!("{} has a new management token; links with the old one no longer work.", rental.username),
+            color: 0x8b5cf6,
+            fields: vec![field("Username", rental.username.as_str(), true), field("Rotated By", rotated_by, true)],
+            link: None,
+            data: json!({
+                "event": "token_rotated",
+                "username": rental.username,
+                "rotated_by": rotated_by,
+            }),
+        }
+    }
+
+    pub fn settings_changed(rental: &Rental, changed: &[&str], my_page_url: &str) -> Self {
+        let nip05 = rental.services.nip05.as_ref();
+        let subdomain = rental.services.subdomain.as_ref();
+        Notification {
+            event: "settings_changed".to_string(),
+            title: "⚙️ Settings Changed".to_string(),
+            text: format!("{} settings changed: {}", rental.username, changed.join(", ")),
+            color: 0x8b5cf6,
+            fields: vec![field("Username", rental.username.as_str(), true), field("Changed", changed.join(", "), true)],
+            link: link("My Page", my_page_url),
+            data: json!({
+                "event": "settings_changed",
+                "username": rental.username,
+                "changed": changed,
+                "my_page_url": my_page_url,
+                "nip05_npub": nip05.and_then(|n| crate::nip19::encode_npub(&n.pubkey_hex).ok()),
+                "subdomain": subdomain.map(|s| json!({"type": s.record_type, "target": s.target, "proxied": s.proxied})),
+            }),
+        }
+    }
+
+    /// Mail delivered to the rental's address (sent by the JS email handler; built
+    /// here for NIP-17 DMs)
+    pub fn email_received(notice: &EmailReceivedNotice) -> Self {
+        let subject = if notice.subject.is_empty() { "(no subject)" } else { notice.subject.as_str() };
+        Notification {
+            event: "email_received".to_string(),
+            title: "📧 New email".to_string(),
+            text: format!("From {}: {}", notice.from, notice.subject),
+            color: 0x5865f2,
+            fields: vec![
+                field("From", notice.from.as_str(), true),
+                field("To", notice.to.as_str(), true),
+                field("Subject", subject, false),
+            ],
+            link: link("View email", &notice.view_url),
+            data: json!({
+                "event": "email_received",
+                "username": notice.username,
+                "mail_id": notice.mail_id,
+                "from": notice.from,
+                "to": notice.to,
+                "subject": notice.subject,
+                "date": notice.received_at,
+                "view_url": notice.view_url,
+                "received_at": notice.received_at,
+            }),
+        }
+    }
+
+    /// `action` is "removed" or "restored"; None when the rental has no subdomain
+    pub fn dns_changed(rental: &Rental, hostname: &str, action: &str) -> Option<Self> {
+        let sub = rental.services.subdomain.as_ref()?;
+        Some(Notification {
+            event: "dns_changed".to_string(),
+            title: "🌐 DNS Record Changed".to_string(),
+            text: format!("{} {} ({} → {})", hostname, action, sub.record_type, sub.target),
+            color: 0xc2410c,
+            fields: vec![field("Hostname", hostname, true), field("Action", action, true)],
+            link: None,
+            data: json!({
+                "event": "dns_changed",
+                "username": rental.username,
+                "hostname": hostname,
+                "action": action,
+                "record_type": sub.record_type,
+                "target": sub.target,
+                "expires_at": rental.expires_at,
+            }),
+        })
+    }
+
+    /// Render for the service behind `url`
+    pub fn render(&self, url: &str) -> Rendered {
+        let format = WebhookFormat::detect(url);
+        let (url, body) = match format {
+            WebhookFormat::Json => (url.to_string(), self.data.clone()),
+            WebhookFormat::Discord => (url.to_string(), self.discord()),
+            WebhookFormat::Slack => (url.to_string(), self.slack()),
+            WebhookFormat::Telegram => (url.to_string(), self.telegram(url)),
+            WebhookFormat::Matrix => (url.to_string(), self.matrix()),
+            WebhookFormat::Ntfy => ntfy_publish(url, self),
+        };
+        Rendered { format, url, content_type: JSON, body: body.to_string() }
+    }
+
+    fn discord(&self) -> Value {
+        let mut fields: Vec<Value> = self
+            .fields
+            .iter()
+            .map(|f| json!({"name": f.name, "value": f.value, "inline": f.inline}))
+            .collect();
+        if let Some(ref l) = self.link {
+            fields.push(json!({"name": "🔗 Link", "value": format!("[{}]({})", l.label, l.url), "inline": false}));
+        }
+        let mut embed = json!({
+            "title": self.title,
+            "description": self.text,
+            "color": self.color,
+            "fields": fields,
+            "footer": {"text": "noscha.io"},
+        });
+        if let Some(ref l) = self.link {
+            embed["url"] = json!(l.url);
+        }
+        json!({"embeds": [embed]})
+    }
+
+    fn slack(&self) -> Value {
+        let mut blocks = vec![
+            json!({"type": "header", "text": {"type": "plain_text", "text": self.title, "emoji": true}}),
+            json!({"type": "section", "text": {"type": "mrkdwn", "text": escape_slack(&self.text)}}),
+        ];
+        if !self.fields.is_empty() {
+            let fields: Vec<Value> = self
+                .fields
+                .iter()
+                .take(10)
+                .map(|f| json!({"type": "mrkdwn", "text": format!("*{}*\n{}", escape_slack(&f.name), escape_slack(&f.value))}))
+                .collect();
+            blocks.push(json!({"type": "section", "fields": fields}));
+        }
+        if let Some(ref l) = self.link {
+            blocks.push(json!({
+                "type": "actions",
+                "elements": [{"type": "button", "text": {"type": "plain_text", "text": l.label}, "url": l.url}]
+            }));
+        }
+        json!({
+            "text": format!("{}: {}", self.title, self.text),
+            "blocks": blocks,
+            "unfurl_links": false,
+            "unfurl_media": false,
+        })
+    }
+
+    fn telegram(&self, url: &str) -> Value {
+        let mut body = json!({
+            "text": self.html("\n"),
+            "parse_mode": "HTML",
+            "disable_web_page_preview": true,
+        });
+        if let Some(chat_id) = UrlParts::parse(url).and_then(|u| u.query_param("chat_id")) {
+            body["chat_id"] = json!(chat_id);
+        }
+        body
+    }
+
+    fn matrix(&self) -> Value {
+        json!({
+            "text": self.plain_text(),
+            "html": self.html("<br>"),
+            "username": "noscha.io",
+        })
+    }
+
+    /// Title, text, fields and link as plain text lines; also the body of NIP-17 DMs
+    pub fn plain_text(&self) -> String {
+        let mut lines = vec![self.title.clone(), self.text.clone()];
+        lines.extend(self.fields.iter().map(|f| format!("{}: {}", f.name, f.value)));
+        if let Some(ref l) = self.link {
+            lines.push(format!("{}: {}", l.label, l.url));
+        }
+        lines.join("\n")
+    }
+
+    /// The same content as HTML, lines joined with `separator`
+    fn html(&self, separator: &str) -> String {
+        let mut lines = vec![format!("<b>{}</b>", escape_html(&self.title)), escape_html(&self.text)];
+        lines.extend(
+            self.fields
+                .iter()
+                .map(|f| format!("<b>{}:</b> {}", escape_html(&f.name), escape_html(&f.value))),
+        );
+        if let Some(ref l) = self.link {
+            lines.push(format!("<a href=\"{}\">{}</a>", escape_html(&l.url), escape_html(&l.label)));
+        }
+        lines.join(separator)
+    }
+}
+
+/// ntfy takes JSON messages at the server root with the topic in the body
+fn ntfy_publish(url: &str, n: &Notification) -> (String, Value) {
+    let parts = UrlParts::parse(url);
+    let (root, topic) = match parts {
+        Some(ref p) => (
+            format!("{}://{}/", p.scheme, p.host),
+            p.path.trim_matches('/').to_string(),
+        ),
+        None => (url.to_string(), String::new()),
+    };
+    let mut message = vec![n.text.clone()];
+    message.extend(n.fields.iter().map(|f| format!("{}: {}", f.name, f.value)));
+    let mut body = json!({
+        "topic": topic,
+        "title": n.title,
+        "message": message.join("\n"),
+    });
+    if let Some(ref l) = n.link {
+        body["click"] = json!(l.url);
+        body["actions"] = json!([{"action": "view", "label": l.label, "url": l.url}]);
+    }
+    (root, body)
+}
+
+fn escape_slack(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge() -> Notification {
+        Notification::webhook_challenge("ord_1", "alice", "https://noscha.io/api/order/ord_1/confirm/ch")
+    }
+
+    #[test]
+    fn test_detect_formats() {
+        let cases = [
+            ("https://discord.com/api/webhooks/1/abc", WebhookFormat::Discord),
+            ("https://discordapp.com/api/webhooks/1/abc", WebhookFormat::Discord),
+            ("https://canary.discord.com/api/webhooks/1/abc", WebhookFormat::Discord),
+            ("https://DISCORD.com/api/webhooks/1/abc", WebhookFormat::Discord),
+            ("https://hooks.slack.com/services/T0/B0/xyz", WebhookFormat::Slack),
+            ("https://api.telegram.org/bot123:ABC/sendMessage?chat_id=42", WebhookFormat::Telegram),
+            ("https://hookshot.example.org/webhook/5f0c", WebhookFormat::Matrix),
+            ("https://matrix.example.org/hookshot/webhooks/webhook/5f0c", WebhookFormat::Matrix),
+            ("https://ntfy.sh/my-topic", WebhookFormat::Ntfy),
+            ("https://ntfy.example.com/alerts", WebhookFormat::Ntfy),
+            ("https://example.com/hook", WebhookFormat::Json),
+            // Look-alikes stay raw JSON
+            ("https://example.com/discord.com/api/webhooks/1", WebhookFormat::Json),
+            ("https://notdiscord.com/api/webhooks/1/abc", WebhookFormat::Json),
+            ("https://hooks.slack.com/workflows/T0/x", WebhookFormat::Json),
+            ("https://api.telegram.org/bot123:ABC/getUpdates", WebhookFormat::Json),
+            ("https://example.com/webhook/5f0c", WebhookFormat::Json),
+            ("not a url", WebhookFormat::Json),
+        ];
+        for (url, format) in cases {
+            assert_eq!(WebhookFormat::detect(url), format, "{}", url);
+        }
+        assert!(WebhookFormat::Json.is_signed());
+        assert!(!WebhookFormat::Slack.is_signed());
+    }
+
+    #[test]
+    fn test_url_parts() {
+        let parts = UrlParts::parse("https://user@Ntfy.Example.com:8443/topic?x=1#frag").unwrap();
+        assert_eq!(parts.host, "ntfy.example.com");
+        assert_eq!(parts.path, "/topic");
+        assert_eq!(parts.query, "x=1");
+        assert_eq!(UrlParts::parse("https://example.com").unwrap().path, "/");
+        assert_eq!(UrlParts::parse("https://[2001:db8::1]:443/x").unwrap().host, "[2001:db8::1]");
+        assert_eq!(UrlParts::parse("https://[2001:db8::1]/x").unwrap().host, "[2001:db8::1]");
+    }
+
+    #[test]
+    fn test_json_is_the_raw_payload() {
+        let rendered = challenge().render("https://example.com/hook");
+        assert_eq!(rendered.format, WebhookFormat::Json);
+        assert_eq!(rendered.url, "https://example.com/hook");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        assert_eq!(body["event"], "webhook_challenge");
+        assert_eq!(body["challenge_url"], "https://noscha.io/api/order/ord_1/confirm/ch");
+        assert!(body["content"].as_str().unwrap().contains("webhook_challenge"));
+    }
+
+    #[test]
+    fn test_discord_embed() {
+        let rendered = challenge().render("https://discord.com/api/webhooks/1/abc");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        let embed = &body["embeds"][0];
+        assert_eq!(embed["title"], "🔐 Confirm your noscha.io order");
+        assert_eq!(embed["url"], "https://noscha.io/api/order/ord_1/confirm/ch");
+        assert_eq!(embed["fields"][0]["name"], "Username");
+        assert_eq!(embed["fields"][2]["value"], "[Confirm order](https://noscha.io/api/order/ord_1/confirm/ch)");
+        // No bare URL in message content for Discord to crawl
+        assert!(body.get("content").is_none());
+    }
+
+    #[test]
+    fn test_slack_blocks() {
+        let mut n = challenge();
+        n.text = "a <b> & c".into();
+        let rendered = n.render("https://hooks.slack.com/services/T0/B0/xyz");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        assert_eq!(body["unfurl_links"], false);
+        assert_eq!(body["blocks"][0]["type"], "header");
+        assert_eq!(body["blocks"][1]["text"]["text"], "a &lt;b&gt; &amp; c");
+        assert_eq!(body["blocks"][2]["fields"][0]["text"], "*Username*\nalice");
+        assert_eq!(body["blocks"][3]["elements"][0]["url"], "https://noscha.io/api/order/ord_1/confirm/ch");
+    }
+
+    #[test]
+    fn test_telegram_message() {
+        let mut n = challenge();
+        n.fields[0].value = "<alice>".into();
+        let rendered = n.render("https://api.telegram.org/bot123:ABC/sendMessage?chat_id=%40noscha_alerts");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        assert_eq!(body["chat_id"], "@noscha_alerts");
+        assert_eq!(body["parse_mode"], "HTML");
+        assert_eq!(body["disable_web_page_preview"], true);
+        let text = body["text"].as_str().unwrap();
+        assert!(text.starts_with("<b>🔐 Confirm your noscha.io order</b>\n"));
+        assert!(text.contains("<b>Username:</b> &lt;alice&gt;"));
+        assert!(text.ends_with("<a href=\"https://noscha.io/api/order/ord_1/confirm/ch\">Confirm order</a>"));
+
+        let without_chat = n.render("https://api.telegram.org/bot123:ABC/sendMessage");
+        let body: Value = serde_json::from_str(&without_chat.body).unwrap();
+        assert!(body.get("chat_id").is_none());
+    }
+
+    #[test]
+    fn test_matrix_hookshot_message() {
+        let rendered = challenge().render("https://hookshot.example.org/webhook/5f0c");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        assert_eq!(body["username"], "noscha.io");
+        assert!(body["text"].as_str().unwrap().contains("Order: ord_1"));
+        assert!(body["html"].as_str().unwrap().contains("<br><b>Order:</b> ord_1"));
+    }
+
+    #[test]
+    fn test_ntfy_publishes_to_the_root() {
+        let rendered = challenge().render("https://ntfy.sh/noscha-alerts");
+        assert_eq!(rendered.url, "https://ntfy.sh/");
+        let body: Value = serde_json::from_str(&rendered.body).unwrap();
+        assert_eq!(body["topic"], "noscha-alerts");
+        assert_eq!(body["title"], "🔐 Confirm your noscha.io order");
+        assert_eq!(body["click"], "https://noscha.io/api/order/ord_1/confirm/ch");
+        assert!(body["message"].as_str().unwrap().contains("Username: alice"));
+    }
+
+    #[test]
+    fn test_event_payloads() {
+        let rental: Rental = serde_json::from_value(json!({
+            "username": "bob", "status": "active", "created_at": "2026-01-01T00:00:00Z",
+            "expires_at": "2026-02-01T00:00:00Z", "plan": "30d",
+            "services": {"subdomain": {"enabled": true, "type": "CNAME", "target": "example.com"}}
+        }))
+        .unwrap();
+
+        let n = Notification::grace_period_started(&rental, "2026-02-04T00:00:00Z", "https://noscha.io/my/t");
+        assert_eq!(n.data["grace_until"], "2026-02-04T00:00:00Z");
+        let n = Notification::expiring_soon(&rental, 1440, "1 Day", 1432, "https://noscha.io/my/t");
+        assert_eq!(n.text, "bob expires in less than 1 Day");
+        assert_eq!(n.data["minutes_remaining"], 1432);
+        let n = Notification::dns_changed(&rental, "bob.noscha.io", "removed").unwrap();
+        assert_eq!(n.data["record_type"], "CNAME");
+        assert_eq!(n.data["action"], "removed");
+
+        let n = Notification::token_rotated(&rental, "admin");
+        assert_eq!(n.data.get("management_token"), None);
+        assert_eq!(n.data.get("my_page_url"), None);
+        assert_eq!(n.data["rotated_by"], "admin");
+
+        let n = Notification::settings_changed(&rental, &["subdomain", "webhook_url"], "https://noscha.io/my/t");
+        assert_eq!(n.data["changed"], json!(["subdomain", "webhook_url"]));
+        assert_eq!(n.data["subdomain"], json!({"type": "CNAME", "target": "example.com", "proxied": false}));
+        assert_eq!(n.data["nip05_npub"], Value::Null);
+
+        let mut no_sub = rental.clone();
+        no_sub.services.subdomain = None;
+        assert!(Notification::dns_changed(&no_sub, "bob.noscha.io", "removed").is_none());
+
+        let order: Order = serde_json::from_value(json!({
+            "order_id": "ord_1", "username": "bob", "plan": "30d", "amount_sats": 6500, "bolt11": "",
+            "status": "provisioned", "created_at": "2026-01-01T00:00:00Z", "expires_at": "2026-01-01T00:15:00Z"
+        }))
+        .unwrap();
+        let mut with_nip05 = rental.clone();
+        with_nip05.services.nip05 = serde_json::from_value(json!({
+            "enabled": true, "pubkey_hex": "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e"
+        }))
+        .unwrap();
+        let n = Notification::payment_completed(&order, &with_nip05, "mgmt", "https://noscha.io/my/mgmt", false);
+        assert_eq!(n.data["nip05_npub"], "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg");
+        assert_eq!(n.data["is_upgrade"], false);
+
+        let upgrade = Order { upgrade_for: Some("bob".into()), ..order };
+        let n = Notification::payment_completed(&upgrade, &with_nip05, "mgmt", "https://noscha.io/my/mgmt", false);
+        assert_eq!(n.title, "➕ Services Added");
+        assert_eq!(n.data["is_upgrade"], true);
+    }
+
+    #[test]
+    fn test_email_received_plain_text() {
+        let notice = EmailReceivedNotice {
+            username: "bob".into(),
+            mail_id: "a1b2c3d4".into(),
+            from: "alice@example.com".into(),
+            to: "bob@noscha.io".into(),
+            subject: String::new(),
+            view_url: "https://noscha.io/api/mail/bob/a1b2c3d4".into(),
+            received_at: "2026-02-01T00:00:00.000Z".into(),
+        };
+        let n = Notification::email_received(&notice);
+        assert_eq!(n.data["mail_id"], "a1b2c3d4");
+        assert_eq!(
+            n.plain_text(),
+            "📧 New email\nFrom alice@example.com: \nFrom: alice@example.com\nTo: bob@noscha.io\n\
+             Subject: (no subject)\nView email: https://noscha.io/api/mail/bob/a1b2c3d4"
+        );
+    }
+}
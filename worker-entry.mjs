@@ -93,7 +93,8 @@ async function handleAuthVerify(request, env) {
     await bucket.delete(chKey);
 
     // Create session token
-    const token = 'staging_' + Date.now().toString(36) + '_' + Math.random().toString(36).slice(2);
+    const random = crypto.getRandomValues(new Uint8Array(32));
+    const token = 'staging_' + Array.from(random, (b) => b.toString(16).padStart(2, '0')).join('');
     const expiresAt = new Date(Date.now() + 24 * 60 * 60 * 1000).toISOString();
     const session = { pubkey: event.pubkey, created_at: new Date().toISOString(), expires_at: expiresAt };
     await bucket.put(`staging_sessions/${token}.json`, JSON.stringify(session));
@@ -275,6 +276,11 @@ export default {
     const url = new URL(request.url);
     const requireAuth = env.REQUIRE_AUTH === 'true';
 
+    // /internal/ routes are only for calls from the email handler below
+    if (url.pathname.startsWith("/internal/")) {
+      return new Response("Not found", { status: 404 });
+    }
+
     // Handle mail API routes
     if (url.pathname.startsWith("/api/mail/")) {
       const pathParts = url.pathname.split("/").filter(Boolean); // ["api", "mail", ...]
@@ -336,7 +342,9 @@ export default {
   },
 
   async email(message, env, ctx) {
-    return emailShim.email(message, env, ctx);
+    // Lets the email handler call internal Rust routes (e.g. NIP-17 DMs)
+    const dispatch = (request) => new WorkerClass(ctx, env).fetch(request);
+    return emailShim.email(message, env, ctx, dispatch);
   },
 
   async scheduled(controller, env, ctx) {